@@ -1,9 +1,10 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     bike::Bike,
     collision::{Collision, CollisionSide},
-    track::TrackLaneId,
+    track::TrackLanes,
     RacingState,
 };
 
@@ -16,7 +17,7 @@ impl Plugin for ActionsPlugin {
     }
 }
 
-#[derive(Component, PartialEq, Eq, Copy, Clone, Debug)]
+#[derive(Component, Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug)]
 pub enum BikeAction {
     Accelerate,
     Watch,
@@ -33,7 +34,14 @@ pub enum BikeAction {
 }
 
 impl BikeAction {
-    pub fn can_do(&self, bike: &Bike, maybe_collision: Option<&Collision>) -> bool {
+    pub fn can_do(
+        &self,
+        bike: &Bike,
+        maybe_collision: Option<&Collision>,
+        track_lanes: &TrackLanes,
+    ) -> bool {
+        let lane_index = bike.current_lane_id.index();
+        let lanes_to_right = track_lanes.lane_count() - 1 - lane_index;
         match self {
             BikeAction::Accelerate => match maybe_collision {
                 Some(collision) => {
@@ -45,53 +53,34 @@ impl BikeAction {
             BikeAction::Skid => bike.speed > bike.max_speed / 2.0,
             BikeAction::Stop => bike.speed > 0.0,
             BikeAction::Left => {
-                if bike.current_lane_id == TrackLaneId::First {
-                    false
-                } else if let Some(collision) = maybe_collision {
-                    return collision.side != CollisionSide::Left;
-                } else {
-                    return true;
-                }
+                lane_index >= 1 && !is_blocked_on(maybe_collision, CollisionSide::Left)
             }
             BikeAction::LeftLeft => {
-                if bike.current_lane_id == TrackLaneId::First
-                    || bike.current_lane_id == TrackLaneId::Second
-                {
-                    false
-                } else if let Some(collision) = maybe_collision {
-                    return collision.side != CollisionSide::Left;
-                } else {
-                    return true;
-                }
+                lane_index >= 2 && !is_blocked_on(maybe_collision, CollisionSide::Left)
             }
-            BikeAction::LeftElbow => bike.current_lane_id != TrackLaneId::First,
-            BikeAction::LeftHip => bike.current_lane_id != TrackLaneId::First,
+            BikeAction::LeftElbow => lane_index >= 1,
+            BikeAction::LeftHip => lane_index >= 1,
             BikeAction::Right => {
-                if bike.current_lane_id == TrackLaneId::Fourth {
-                    false
-                } else if let Some(collision) = maybe_collision {
-                    return collision.side != CollisionSide::Right;
-                } else {
-                    return true;
-                }
+                lanes_to_right >= 1 && !is_blocked_on(maybe_collision, CollisionSide::Right)
             }
             BikeAction::RightRight => {
-                if bike.current_lane_id == TrackLaneId::Fourth
-                    || bike.current_lane_id == TrackLaneId::Third
-                {
-                    false
-                } else if let Some(collision) = maybe_collision {
-                    return collision.side != CollisionSide::Right;
-                } else {
-                    return true;
-                }
+                lanes_to_right >= 2 && !is_blocked_on(maybe_collision, CollisionSide::Right)
             }
-            BikeAction::RightElbow => bike.current_lane_id != TrackLaneId::Fourth,
-            BikeAction::RightHip => bike.current_lane_id != TrackLaneId::Fourth,
+            BikeAction::RightElbow => lanes_to_right >= 1,
+            BikeAction::RightHip => lanes_to_right >= 1,
         }
     }
 }
 
+/// Marks a rider that chose to watch, so the actions the other riders choose for the
+/// next turn are revealed to them
+#[derive(Component, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Watching;
+
+fn is_blocked_on(maybe_collision: Option<&Collision>, side: CollisionSide) -> bool {
+    maybe_collision.is_some_and(|collision| collision.side == side)
+}
+
 #[derive(Event, Copy, Clone, PartialEq, Eq, Debug)]
 pub struct ActionEvent {
     pub bike_entity: Entity,
@@ -104,21 +93,22 @@ impl ActionEvent {
     }
 }
 
-fn on_action(
+/// Takes up the actions chosen for riders, leaving it to whoever is waiting on them to
+/// start the turn
+pub fn on_action(
     mut action_events: EventReader<ActionEvent>,
     mut commands: Commands,
     q_bikes: Query<(&Bike, Option<&Collision>)>,
-    mut next_state: ResMut<NextState<RacingState>>,
+    track_lanes: Res<TrackLanes>,
 ) {
     for event in action_events.read() {
         if let Ok((bike, maybe_collision)) = q_bikes.get(event.bike_entity) {
-            if event.kind.can_do(bike, maybe_collision) {
+            if event.kind.can_do(bike, maybe_collision, &track_lanes) {
                 commands.entity(event.bike_entity).insert(event.kind);
-                println!(
+                debug!(
                     "Doing action {:?} for bike {:?}",
                     event.kind, event.bike_entity,
                 );
-                next_state.set(RacingState::Simulating);
             }
         }
     }
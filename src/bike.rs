@@ -1,56 +1,226 @@
-use bevy::prelude::*;
+mod contact;
+mod crash;
+
+use bevy::{
+    ecs::{system::EntityCommands, world::EntityRef},
+    prelude::*,
+    utils::HashSet,
+};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    actions::BikeAction,
-    collision::{self, Collision},
-    game::TurnTimer,
+    actions::{BikeAction, Watching},
+    collision::{self, Collision, CollisionEvent},
+    game::{Finished, TurnTimer, TICKS_PER_TURN, TICK_SECONDS},
     loading::BikeTextures,
+    simulation::{TickSet, TurnPhaseSet},
     track::{TrackLaneId, TrackLanes},
     PlayingState, RacingState,
 };
 
+use self::{
+    contact::resolve_contacts,
+    crash::{
+        fall, hide_retired_riders, recover_from_crashes, show_fallen_riders, show_recovered_riders,
+        slide_fallen_bikes,
+    },
+};
+pub use self::{
+    contact::{ContactEvent, ContactKind, ContactOutcome, CONTACT_REACH},
+    crash::{CrashCause, CrashEvent, Crashed},
+};
+
 const TURNING_THRESHOLD: f32 = 0.00003;
+/// Sideways acceleration a bike with a grip of 1.0 can hold on a shale bend
+const CORNERING_ACCELERATION: f32 = 6000.0;
+/// Speed over the limit of a bend that slides a bike out by one lane
+const SLIP_SPEED_PER_LANE: f32 = 400.0;
+/// Speed over the limit of a bend at which a slide becomes a fall
+const SLIP_CRASH_SPEED: f32 = 800.0;
+/// Proportion of its speed a bike keeps while skidding
+const SKID_SPEED_FACTOR: f32 = 0.6;
+/// Seconds from the tapes going up within which a rider getting away has made a flying
+/// start
+const FLYING_START_REACTION: f32 = 0.1;
+/// Speed a perfect start adds to the first turn, as a proportion of the bike's
+/// acceleration
+const FLYING_START_BURST: f32 = 0.3;
+/// Most of the first turn a slow start can cost
+const MAX_START_DELAY: f32 = 0.9;
 
 pub struct BikePlugin;
 
 impl Plugin for BikePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ContactEvent>()
+            .add_event::<CrashEvent>()
+            .add_systems(
+                FixedUpdate,
+                (try_action, change_speed).chain().in_set(TickSet::Act),
+            )
+            .add_systems(
+                FixedUpdate,
+                (move_bikes, slide_fallen_bikes, update_bikes_positions)
+                    .chain()
+                    .in_set(TickSet::Move),
+            )
+            .add_systems(
+                FixedUpdate,
+                (on_collision, fall).chain().in_set(TickSet::React),
+            )
+            .add_systems(
+                OnEnter(RacingState::Commanding),
+                (
+                    recover_from_crashes.in_set(TurnPhaseSet),
+                    update_bikes_positions,
+                )
+                    .chain(),
+            )
+            .add_systems(
+                OnEnter(RacingState::Simulating),
+                (stop_watching, resolve_contacts, check_slip)
+                    .chain()
+                    .in_set(TurnPhaseSet),
+            )
+            .add_systems(OnExit(RacingState::Simulating), on_exit_simulating_state);
+    }
+}
+
+/// Draws the bikes, separately from the race rules in `BikePlugin`
+pub struct BikeSpritesPlugin;
+
+impl Plugin for BikeSpritesPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(
             Update,
-            (on_turning_added, on_turning_removed, update_bikes_positions)
+            (
+                on_turning_added,
+                on_turning_removed,
+                show_fallen_riders,
+                show_recovered_riders,
+            )
                 .run_if(in_state(PlayingState::Racing)),
         )
-        .add_systems(
-            Update,
-            (try_action, change_speed, on_collision, move_bikes)
-                .chain()
-                .run_if(in_state(RacingState::Simulating)),
-        )
-        .add_systems(OnEnter(RacingState::Simulating), check_slip)
-        .add_systems(OnExit(RacingState::Simulating), on_exit_simulating_state);
+        .add_systems(OnEnter(RacingState::Commanding), hide_retired_riders);
+    }
+}
+
+/// Colour of the cover on a rider's helmet, which tells the riders of a race apart
+#[derive(Component, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HelmetColour {
+    Red,
+    Blue,
+    White,
+    Yellow,
+}
+
+impl HelmetColour {
+    pub const ALL: [HelmetColour; 4] = [
+        HelmetColour::Red,
+        HelmetColour::Blue,
+        HelmetColour::White,
+        HelmetColour::Yellow,
+    ];
+
+    /// The colour for the rider starting from `gate`, counting from zero at the inside
+    pub fn for_gate(gate: usize) -> Self {
+        Self::ALL[gate % Self::ALL.len()]
+    }
+
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    /// Name of the colour, as it appears in the names of the bike images
+    pub fn name(&self) -> &'static str {
+        match self {
+            HelmetColour::Red => "red",
+            HelmetColour::Blue => "blue",
+            HelmetColour::White => "white",
+            HelmetColour::Yellow => "yellow",
+        }
     }
 }
 
-#[derive(Component, Debug, Clone, Copy, Default)]
+#[derive(Component, Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
 pub struct Bike {
     pub current_lane_id: TrackLaneId,
     pub distance: f32,
     pub speed: f32,
     pub max_speed: f32,
     pub acceleration: f32,
-    // pub grip: f32,
+    pub grip: f32,
+    /// Seconds the rider takes to get going when the race starts, from the rider's
+    /// profile until the tapes go up
+    #[serde(default)]
+    pub start_reaction: f32,
 }
 
 impl Bike {
-    pub fn new(initial_lane: &TrackLaneId, max_speed: f32, _grip: f32, acceleration: f32) -> Self {
+    pub fn new(initial_lane: &TrackLaneId, max_speed: f32, grip: f32, acceleration: f32) -> Self {
         Self {
             current_lane_id: *initial_lane,
             max_speed,
             acceleration,
-            // grip,
+            grip,
             ..Default::default()
         }
     }
+
+    /// Still waiting on the start line
+    pub fn at_start(&self) -> bool {
+        self.distance == 0.0 && self.speed == 0.0
+    }
+}
+
+/// A bike and everything it is doing in the turn under way, as kept in a saved race
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BikeSnapshot {
+    bike: Bike,
+    action: Option<BikeAction>,
+    change_speed: Option<ChangeSpeed>,
+    change_lane: Option<ChangeLane>,
+    crashed: Option<Crashed>,
+    watching: bool,
+}
+
+impl BikeSnapshot {
+    pub fn take(entity: EntityRef) -> Option<Self> {
+        Some(Self {
+            bike: *entity.get::<Bike>()?,
+            action: entity.get::<BikeAction>().copied(),
+            change_speed: entity.get::<ChangeSpeed>().copied(),
+            change_lane: entity.get::<ChangeLane>().copied(),
+            crashed: entity.get::<Crashed>().copied(),
+            watching: entity.contains::<Watching>(),
+        })
+    }
+
+    /// Puts the bike back as it was, adding and removing components to match
+    pub fn restore(&self, entity: &mut EntityCommands) {
+        entity.insert(self.bike);
+        match self.action {
+            Some(action) => entity.insert(action),
+            None => entity.remove::<BikeAction>(),
+        };
+        match self.change_speed {
+            Some(change_speed) => entity.insert(change_speed),
+            None => entity.remove::<ChangeSpeed>(),
+        };
+        match self.change_lane {
+            Some(change_lane) => entity.insert(change_lane),
+            None => entity.remove::<ChangeLane>(),
+        };
+        match self.crashed {
+            Some(crashed) => entity.insert(crashed),
+            None => entity.remove::<Crashed>(),
+        };
+        if self.watching {
+            entity.insert(Watching);
+        } else {
+            entity.remove::<Watching>();
+        }
+    }
 }
 
 #[derive(Component, Debug, Clone, Copy, Eq, PartialEq)]
@@ -68,29 +238,28 @@ fn try_action(
         Option<&ChangeLane>,
     )>,
     mut commands: Commands,
+    track_lanes: Res<TrackLanes>,
 ) {
     for (entity, bike, maybe_action, maybe_change_speed, maybe_change_lane) in q_bikes.iter() {
         if let Some(action) = maybe_action {
             match action {
-                BikeAction::Accelerate => {
+                BikeAction::Accelerate | BikeAction::Skid | BikeAction::Stop => {
                     if maybe_change_speed.is_none() {
-                        commands.entity(entity).insert(ChangeSpeed {
-                            start_speed: bike.speed,
-                            final_speed: (bike.speed + bike.acceleration).min(bike.max_speed),
-                            instant: false,
-                        });
+                        let change_speed = if bike.at_start() {
+                            start_speed_change(bike, *action)
+                        } else {
+                            ChangeSpeed {
+                                start_speed: bike.speed,
+                                final_speed: speed_after(bike, *action),
+                                instant: false,
+                                delay: 0.0,
+                            }
+                        };
+                        commands.entity(entity).insert(change_speed);
                     }
                 }
-                BikeAction::Watch => {}
-                BikeAction::Skid => {}
-                BikeAction::Stop => {
-                    if maybe_change_speed.is_none() {
-                        commands.entity(entity).insert(ChangeSpeed {
-                            start_speed: bike.speed,
-                            final_speed: 0.0,
-                            instant: false,
-                        });
-                    }
+                BikeAction::Watch => {
+                    commands.entity(entity).insert(Watching);
                 }
                 BikeAction::Left => {
                     if maybe_change_lane.is_none() {
@@ -108,13 +277,11 @@ fn try_action(
                         ));
                     }
                 }
-                BikeAction::LeftElbow => {}
-                BikeAction::LeftHip => {}
                 BikeAction::Right => {
                     if maybe_change_lane.is_none() {
                         commands.entity(entity).insert(ChangeLane::new(
                             bike.current_lane_id,
-                            bike.current_lane_id.right(),
+                            bike.current_lane_id.right(&track_lanes),
                         ));
                     }
                 }
@@ -122,17 +289,66 @@ fn try_action(
                     if maybe_change_lane.is_none() {
                         commands.entity(entity).insert(ChangeLane::new(
                             bike.current_lane_id,
-                            bike.current_lane_id.right_right(),
+                            bike.current_lane_id.right_right(&track_lanes),
                         ));
                     }
                 }
-                BikeAction::RightElbow => {}
-                BikeAction::RightHip => {}
+                // contacts are resolved once when the turn starts, by resolve_contacts
+                BikeAction::LeftElbow
+                | BikeAction::LeftHip
+                | BikeAction::RightElbow
+                | BikeAction::RightHip => {}
             }
         }
     }
 }
 
+/// Getting away from the start line takes as long as the rider's reaction to the tapes,
+/// and a flying start carries the bike on past its usual acceleration
+fn start_speed_change(bike: &Bike, action: BikeAction) -> ChangeSpeed {
+    let delay =
+        (bike.start_reaction / (TICKS_PER_TURN as f32 * TICK_SECONDS)).clamp(0.0, MAX_START_DELAY);
+    let mut final_speed = speed_after(bike, action);
+    if action == BikeAction::Accelerate && bike.start_reaction < FLYING_START_REACTION {
+        let burst = 1.0 - bike.start_reaction.max(0.0) / FLYING_START_REACTION;
+        final_speed =
+            (final_speed + bike.acceleration * FLYING_START_BURST * burst).min(bike.max_speed);
+    }
+    ChangeSpeed {
+        start_speed: bike.speed,
+        final_speed,
+        instant: false,
+        delay,
+    }
+}
+
+/// Speed a bike will have at the end of a turn spent on `action`, leaving aside
+/// contacts, collisions and slips
+pub fn speed_after(bike: &Bike, action: BikeAction) -> f32 {
+    match action {
+        BikeAction::Accelerate => (bike.speed + bike.acceleration).min(bike.max_speed),
+        BikeAction::Skid => bike.speed * SKID_SPEED_FACTOR,
+        BikeAction::Stop => 0.0,
+        _ => bike.speed,
+    }
+}
+
+/// Lane a bike will end a turn spent on `action` in, leaving aside contacts,
+/// collisions and slips
+pub fn lane_after(bike: &Bike, action: BikeAction, track_lanes: &TrackLanes) -> TrackLaneId {
+    let lane_id = bike.current_lane_id;
+    match action {
+        BikeAction::Left => lane_id.left(),
+        BikeAction::LeftLeft => lane_id.left_left(),
+        BikeAction::Right => lane_id.right(track_lanes),
+        BikeAction::RightRight => lane_id.right_right(track_lanes),
+        BikeAction::Skid if track_lanes.track_lane(&lane_id).in_turn(bike.distance) => {
+            lane_id.left()
+        }
+        _ => lane_id,
+    }
+}
+
 fn change_speed(mut q_bikes: Query<(&mut Bike, &ChangeSpeed)>, turn_timer: Res<TurnTimer>) {
     for (mut bike, change_speed) in q_bikes.iter_mut() {
         if change_speed.instant {
@@ -143,20 +359,25 @@ fn change_speed(mut q_bikes: Query<(&mut Bike, &ChangeSpeed)>, turn_timer: Res<T
     }
 }
 
-#[derive(Component, Debug, Clone, Copy)]
+#[derive(Component, Serialize, Deserialize, Debug, Clone, Copy)]
 struct ChangeSpeed {
     start_speed: f32,
     final_speed: f32,
     instant: bool,
+    /// Proportion of the turn that passes before the speed starts to change
+    #[serde(default)]
+    delay: f32,
 }
 
 impl ChangeSpeed {
     fn current_speed(self, turn_proportion_elapsed: f32) -> f32 {
-        self.start_speed + (self.final_speed - self.start_speed) * turn_proportion_elapsed
+        let proportion_changed =
+            ((turn_proportion_elapsed - self.delay) / (1.0 - self.delay)).clamp(0.0, 1.0);
+        self.start_speed + (self.final_speed - self.start_speed) * proportion_changed
     }
 }
 
-#[derive(Component, Debug, Clone, Copy)]
+#[derive(Component, Serialize, Deserialize, Debug, Clone, Copy)]
 struct ChangeLane {
     start_lane_id: TrackLaneId,
     final_lane_id: TrackLaneId,
@@ -168,7 +389,7 @@ struct ChangeLane {
 
 impl ChangeLane {
     fn new(current: TrackLaneId, desired: TrackLaneId) -> Self {
-        let double_lane_change = current.difference(desired) > 1;
+        let double_lane_change = current.difference(desired).abs() > 1;
         Self {
             start_lane_id: current,
             final_lane_id: desired,
@@ -190,25 +411,24 @@ impl ChangeLane {
             if self.current_proportion < 0.4 {
                 self.start_lane_id
             } else if self.current_proportion > 0.9 {
-                return self.final_lane_id;
+                self.final_lane_id
             } else {
-                return self.start_lane_id.between(self.final_lane_id);
+                self.start_lane_id.between(self.final_lane_id)
             }
         } else if self.current_proportion < 0.6 {
-            return self.start_lane_id;
+            self.start_lane_id
         } else {
-            return self.final_lane_id;
+            self.final_lane_id
         }
     }
 }
 
 fn move_bikes(
     mut q_bikes: Query<(&mut Bike, Option<&mut ChangeLane>)>,
-    time: Res<Time>,
     turn_timer: Res<TurnTimer>,
 ) {
     for (mut bike, maybe_change_lane) in q_bikes.iter_mut() {
-        bike.distance += bike.speed * time.delta_seconds();
+        bike.distance += bike.speed * TICK_SECONDS;
         if let Some(mut change_lane) = maybe_change_lane {
             change_lane.update_proportion(turn_timer.proportion_finished());
         }
@@ -216,13 +436,16 @@ fn move_bikes(
 }
 
 fn update_bikes_positions(
-    mut q_bike: Query<(
-        Entity,
-        &Bike,
-        &mut Transform,
-        Option<&BikeTurning>,
-        Option<&ChangeLane>,
-    )>,
+    mut q_bike: Query<
+        (
+            Entity,
+            &Bike,
+            &mut Transform,
+            Option<&BikeTurning>,
+            Option<&ChangeLane>,
+        ),
+        Without<Crashed>,
+    >,
     lanes: Res<TrackLanes>,
     mut commands: Commands,
 ) {
@@ -252,31 +475,46 @@ fn update_bikes_positions(
     }
 }
 
+/// Reacts to collisions as they start, so a collision restored from a saved race isn't
+/// dealt with twice
 fn on_collision(
-    mut q_bike_collisions: Query<
-        (Entity, &Bike, &Collision, Option<&mut ChangeLane>),
-        Added<Collision>,
-    >,
+    mut collision_events: EventReader<CollisionEvent>,
+    mut q_bike_collisions: Query<(&Bike, &Collision, Option<&mut ChangeLane>), Without<Crashed>>,
+    mut crash_events: EventWriter<CrashEvent>,
     mut commands: Commands,
 ) {
-    for (entity, bike, collision, maybe_change_lane) in q_bike_collisions.iter_mut() {
+    let mut handled = HashSet::new();
+    for event in collision_events.read() {
+        let entity = event.bike_entity;
+        if !handled.insert(entity) {
+            continue;
+        }
+        let Ok((bike, collision, maybe_change_lane)) = q_bike_collisions.get_mut(entity) else {
+            continue;
+        };
         match collision.side {
             collision::CollisionSide::Front => {
-                // slow down to other bike's speed
-                commands.entity(entity).insert(ChangeSpeed {
-                    start_speed: bike.speed,
-                    final_speed: collision.other_bike_speed,
-                    instant: true,
-                });
                 let speed_difference = (bike.speed - collision.other_bike_speed).abs();
                 if speed_difference > 10.0 {
-                    println!("CRASH!!!");
+                    crash_events.send(CrashEvent {
+                        bike_entity: entity,
+                        cause: CrashCause::Collision,
+                        impact_speed: speed_difference,
+                    });
+                } else {
+                    // slow down to other bike's speed
+                    commands.entity(entity).insert(ChangeSpeed {
+                        start_speed: bike.speed,
+                        final_speed: collision.other_bike_speed,
+                        instant: true,
+                        delay: 0.0,
+                    });
                 }
             }
             collision::CollisionSide::Left => {
                 if let Some(mut change_lane) = maybe_change_lane {
                     if change_lane.changing_to_left {
-                        println!("Blocked!");
+                        debug!("Blocked!");
                         change_lane.lane_clear = false;
                     }
                 }
@@ -284,7 +522,7 @@ fn on_collision(
             collision::CollisionSide::Right => {
                 if let Some(mut change_lane) = maybe_change_lane {
                     if !change_lane.changing_to_left {
-                        println!("Blocked!");
+                        debug!("Blocked!");
                         change_lane.lane_clear = false;
                     }
                 }
@@ -296,58 +534,139 @@ fn on_collision(
     }
 }
 
+/// Watching only reveals the actions chosen for the turn after the one it was chosen in
+fn stop_watching(q_watching: Query<Entity, With<Watching>>, mut commands: Commands) {
+    for entity in &q_watching {
+        commands.entity(entity).remove::<Watching>();
+    }
+}
+
+/// Fastest a bike can go at a distance along a lane without slipping, or `None` on straights
+pub fn max_turn_speed(
+    bike: &Bike,
+    track_lanes: &TrackLanes,
+    lane_id: &TrackLaneId,
+    distance: f32,
+) -> Option<f32> {
+    track_lanes
+        .track_lane(lane_id)
+        .turn_radius(distance)
+        .map(|radius| {
+            (CORNERING_ACCELERATION * bike.grip * track_lanes.surface.grip() * radius).sqrt()
+        })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SlipRisk {
+    /// The bike will slide outwards by this many lanes. It is carried wide for each
+    /// whole lane, and scrubs off speed for the rest of the slide.
+    Slide { lanes: f32 },
+    /// The bike will fall
+    Crash,
+}
+
+/// What will happen to the bike in the coming turn if it doesn't skid
+pub fn slip_risk(bike: &Bike, track_lanes: &TrackLanes) -> Option<SlipRisk> {
+    let max_turn_speed = max_turn_speed(bike, track_lanes, &bike.current_lane_id, bike.distance)?;
+    let excess_speed = bike.speed - max_turn_speed;
+    if excess_speed > SLIP_CRASH_SPEED {
+        Some(SlipRisk::Crash)
+    } else if excess_speed > 0.0 {
+        Some(SlipRisk::Slide {
+            lanes: excess_speed / SLIP_SPEED_PER_LANE,
+        })
+    } else {
+        None
+    }
+}
+
 fn check_slip(
-    q_bike: Query<(Entity, &Bike, Option<&BikeAction>)>,
+    // riders who have finished are riding off the track, well clear of the racing line
+    q_bike: Query<
+        (Entity, &Bike, Option<&BikeAction>, Option<&ChangeSpeed>),
+        (Without<Crashed>, Without<Finished>),
+    >,
     track_lanes: Res<TrackLanes>,
+    mut crash_events: EventWriter<CrashEvent>,
     mut commands: Commands,
 ) {
-    for (entity, bike, maybe_bike_action) in &q_bike {
-        if let Some(bike_action) = maybe_bike_action {
-            if *bike_action == BikeAction::Skid {
-                return;
-            }
-        }
-        if track_lanes
+    for (entity, bike, maybe_bike_action, maybe_change_speed) in &q_bike {
+        let in_turn = track_lanes
             .track_lane(&bike.current_lane_id)
-            .in_turn(bike.distance)
-        {
-            let max_turn_speed = ((4 - bike.current_lane_id as i32) * 400) as f32;
-            if bike.speed > max_turn_speed {
-                let final_lane_id = if bike.speed - max_turn_speed > 800.0 {
-                    println!("SLIP DOUBLE");
-                    bike.current_lane_id.right_right()
-                } else {
-                    println!("SLIP");
-                    bike.current_lane_id.right()
-                };
+            .in_turn(bike.distance);
+        if maybe_bike_action == Some(&BikeAction::Skid) {
+            // a controlled skid holds the bike in the bend and lets it take the inside line
+            let inside_lane_id = bike.current_lane_id.left();
+            if in_turn && inside_lane_id != bike.current_lane_id {
+                debug!("SKID");
                 commands
                     .entity(entity)
-                    .insert(ChangeLane::new(bike.current_lane_id, final_lane_id));
+                    .insert(ChangeLane::new(bike.current_lane_id, inside_lane_id));
+            }
+            continue;
+        }
+        match slip_risk(bike, &track_lanes) {
+            Some(SlipRisk::Crash) => {
+                debug!("SLIP CRASH");
+                let max_turn_speed =
+                    max_turn_speed(bike, &track_lanes, &bike.current_lane_id, bike.distance)
+                        .unwrap_or(bike.speed);
+                crash_events.send(CrashEvent {
+                    bike_entity: entity,
+                    cause: CrashCause::Slip,
+                    impact_speed: bike.speed - max_turn_speed,
+                });
             }
+            Some(SlipRisk::Slide { lanes }) => {
+                debug!("SLIP {lanes:.2} lanes");
+                // carried wide, towards the outside of the bend, a lane for every whole
+                // lane of the slide that there is room for
+                let mut final_lane_id = bike.current_lane_id;
+                for _ in 0..lanes.floor() as usize {
+                    final_lane_id = track_lanes.outward_lane(final_lane_id, bike.distance);
+                }
+                let lanes_wide = final_lane_id.difference(bike.current_lane_id).abs();
+                if lanes_wide > 0 {
+                    commands
+                        .entity(entity)
+                        .insert(ChangeLane::new(bike.current_lane_id, final_lane_id));
+                }
+                // and the rest of the slide scrubs off speed, on top of any other change
+                let scrubbed_speed = (lanes - lanes_wide as f32) * SLIP_SPEED_PER_LANE;
+                let final_speed =
+                    maybe_change_speed.map_or(bike.speed, |change_speed| change_speed.final_speed);
+                commands.entity(entity).insert(ChangeSpeed {
+                    start_speed: bike.speed,
+                    final_speed: (final_speed - scrubbed_speed).max(0.0),
+                    instant: false,
+                    delay: 0.0,
+                });
+            }
+            None => {}
         }
     }
 }
 
 fn on_turning_added(
-    mut q_bike: Query<(&BikeTurning, &mut Handle<Image>), Added<BikeTurning>>,
+    mut q_bike: Query<(&BikeTurning, &HelmetColour, &mut Handle<Image>), Added<BikeTurning>>,
     bike_textures: Res<BikeTextures>,
 ) {
-    for (turning, mut image_handle) in q_bike.iter_mut() {
+    for (turning, helmet, mut image_handle) in q_bike.iter_mut() {
         match turning {
-            BikeTurning::Left => *image_handle = bike_textures.turn.clone(),
-            // BikeTurning::Right => *image_handle = bike_textures.turn.clone(),
+            BikeTurning::Left => *image_handle = bike_textures.turn(*helmet),
+            // BikeTurning::Right => *image_handle = bike_textures.turn(*helmet),
         }
     }
 }
 
 fn on_turning_removed(
     mut removed_turning: RemovedComponents<BikeTurning>,
-    mut q_bike: Query<&mut Handle<Image>, With<Bike>>,
+    mut q_bike: Query<(&HelmetColour, &mut Handle<Image>), With<Bike>>,
     bike_textures: Res<BikeTextures>,
 ) {
     for entity in removed_turning.read() {
-        if let Ok(mut image_handle) = q_bike.get_mut(entity) {
-            *image_handle = bike_textures.straight.clone();
+        if let Ok((helmet, mut image_handle)) = q_bike.get_mut(entity) {
+            *image_handle = bike_textures.straight(*helmet);
         }
     }
 }
@@ -375,3 +694,32 @@ fn on_exit_simulating_state(
         commands.entity(entity).remove::<ChangeSpeed>();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{profile::RiderProfile, track::TrackDefinition};
+
+    #[test]
+    fn fastest_rider_slides_at_top_speed_on_the_inside_of_a_bend() {
+        let definition =
+            TrackDefinition::from_ron(include_bytes!("../assets/tracks/cycle_speedway.track.ron"))
+                .unwrap();
+        let track_lanes = TrackLanes::new(&definition);
+        let profile =
+            RiderProfile::from_ron(include_bytes!("../assets/riders/ray_duggan.rider.ron"))
+                .unwrap();
+        let lane_id = track_lanes.lane_id(0).unwrap();
+        let lane = track_lanes.track_lane(&lane_id);
+        let mut bike = profile.bike(&lane_id);
+        bike.distance = (0..100)
+            .map(|step| step as f32 * 50.0)
+            .find(|distance| lane.in_arc(*distance))
+            .unwrap();
+        bike.speed = bike.max_speed;
+        assert!(matches!(
+            slip_risk(&bike, &track_lanes),
+            Some(SlipRisk::Slide { .. })
+        ));
+    }
+}
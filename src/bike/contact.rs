@@ -0,0 +1,211 @@
+use bevy::{prelude::*, utils::HashSet};
+
+use crate::{
+    actions::BikeAction,
+    collision::{Collision, CollisionSide},
+    game::Rider,
+    random::Randomness,
+    track::TrackLanes,
+};
+
+use super::{Bike, ChangeLane, ChangeSpeed, Crashed};
+
+/// How far along the track, measured on the attacker's lane, a rider can be reached
+pub const CONTACT_REACH: f32 = 120.0;
+/// Contact score needed to shove the target a lane away
+const SHOVE_THRESHOLD: f32 = 300.0;
+/// Extra leverage of a hip over an elbow
+const HIP_BONUS: f32 = 200.0;
+/// Extra leverage when pushing a rider wide
+const OUTWARD_BONUS: f32 = 150.0;
+const ELBOW_SPEED_LOSS: f32 = 400.0;
+const HIP_SPEED_LOSS: f32 = 200.0;
+/// Speed an attacker loses when the target holds their line
+const REBUFF_SPEED_LOSS: f32 = 300.0;
+/// Widest swing that luck can make to the outcome of a contact
+const CONTACT_LUCK: f32 = 200.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContactKind {
+    Elbow,
+    Hip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContactOutcome {
+    /// The target was pushed a lane wide, away from the attacker
+    Shoved,
+    /// The target held their lane but lost speed
+    Slowed,
+    /// The target held firm and the attacker lost speed
+    Rebuffed,
+    /// There was nobody alongside to make contact with
+    Missed,
+}
+
+#[derive(Event, Debug, Clone, Copy)]
+pub struct ContactEvent {
+    pub attacker: Entity,
+    pub target: Option<Entity>,
+    pub kind: ContactKind,
+    pub outcome: ContactOutcome,
+}
+
+fn contact_for(action: &BikeAction) -> Option<(ContactKind, CollisionSide)> {
+    match action {
+        BikeAction::LeftElbow => Some((ContactKind::Elbow, CollisionSide::Left)),
+        BikeAction::LeftHip => Some((ContactKind::Hip, CollisionSide::Left)),
+        BikeAction::RightElbow => Some((ContactKind::Elbow, CollisionSide::Right)),
+        BikeAction::RightHip => Some((ContactKind::Hip, CollisionSide::Right)),
+        _ => None,
+    }
+}
+
+/// Resolves elbow and hip actions at the start of the simulation of a turn. A rider
+/// can only be shoved wide, towards the outside of the bend or of the track on a
+/// straight. Pushing them the other way at best slows them down.
+pub(super) fn resolve_contacts(
+    q_bikes: Query<
+        (
+            Entity,
+            &Bike,
+            Option<&BikeAction>,
+            Option<&Collision>,
+            &Rider,
+        ),
+        Without<Crashed>,
+    >,
+    track_lanes: Res<TrackLanes>,
+    mut randomness: ResMut<Randomness>,
+    mut contact_events: EventWriter<ContactEvent>,
+    mut commands: Commands,
+) {
+    // a rider only takes the result of one contact each turn
+    let mut affected = HashSet::new();
+    // in order of rider number, so luck falls the same way whenever the race is replayed
+    let mut attackers: Vec<_> = q_bikes.iter().collect();
+    attackers.sort_by_key(|(.., rider)| rider.number());
+    for (entity, bike, maybe_action, maybe_collision, _) in attackers {
+        let Some((kind, side)) = maybe_action.and_then(contact_for) else {
+            continue;
+        };
+        let target = find_target(entity, bike, side, maybe_collision, &q_bikes, &track_lanes);
+        let Some((target_entity, target_bike)) = target else {
+            debug!("Contact {kind:?} from bike {entity:?} missed");
+            contact_events.send(ContactEvent {
+                attacker: entity,
+                target: None,
+                kind,
+                outcome: ContactOutcome::Missed,
+            });
+            continue;
+        };
+        if affected.contains(&entity) || affected.contains(&target_entity) {
+            continue;
+        }
+
+        let shoved_lane = match side {
+            CollisionSide::Left => target_bike.current_lane_id.left(),
+            _ => target_bike.current_lane_id.right(&track_lanes),
+        };
+        let wide = shoved_lane != target_bike.current_lane_id
+            && shoved_lane
+                == track_lanes.outward_lane(target_bike.current_lane_id, target_bike.distance);
+        let mut score =
+            bike.speed - target_bike.speed + (randomness.incidents.f32() - 0.5) * CONTACT_LUCK;
+        if kind == ContactKind::Hip {
+            score += HIP_BONUS;
+        }
+        if wide {
+            score += OUTWARD_BONUS;
+        }
+        let outcome = if wide && score >= SHOVE_THRESHOLD {
+            commands
+                .entity(target_entity)
+                .insert(ChangeLane::new(target_bike.current_lane_id, shoved_lane));
+            ContactOutcome::Shoved
+        } else if score >= 0.0 {
+            let speed_loss = match kind {
+                ContactKind::Elbow => ELBOW_SPEED_LOSS,
+                ContactKind::Hip => HIP_SPEED_LOSS,
+            };
+            commands.entity(target_entity).insert(ChangeSpeed {
+                start_speed: target_bike.speed,
+                final_speed: (target_bike.speed - speed_loss).max(0.0),
+                instant: false,
+                delay: 0.0,
+            });
+            ContactOutcome::Slowed
+        } else {
+            commands.entity(entity).insert(ChangeSpeed {
+                start_speed: bike.speed,
+                final_speed: (bike.speed - REBUFF_SPEED_LOSS).max(0.0),
+                instant: false,
+                delay: 0.0,
+            });
+            ContactOutcome::Rebuffed
+        };
+        debug!("Contact {kind:?} from bike {entity:?} on bike {target_entity:?}: {outcome:?}");
+        affected.insert(entity);
+        affected.insert(target_entity);
+        contact_events.send(ContactEvent {
+            attacker: entity,
+            target: Some(target_entity),
+            kind,
+            outcome,
+        });
+    }
+}
+
+/// The rider touching the attacker on `side`, or otherwise the closest rider in the
+/// adjacent lane on that side that is within reach
+fn find_target(
+    entity: Entity,
+    bike: &Bike,
+    side: CollisionSide,
+    maybe_collision: Option<&Collision>,
+    q_bikes: &Query<
+        (
+            Entity,
+            &Bike,
+            Option<&BikeAction>,
+            Option<&Collision>,
+            &Rider,
+        ),
+        Without<Crashed>,
+    >,
+    track_lanes: &TrackLanes,
+) -> Option<(Entity, Bike)> {
+    if let Some(collision) = maybe_collision.filter(|collision| collision.side == side) {
+        if let Ok((other_entity, other_bike, ..)) = q_bikes.get(collision.other_entity) {
+            return Some((other_entity, *other_bike));
+        }
+    }
+    let adjacent_lane = match side {
+        CollisionSide::Left => bike.current_lane_id.left(),
+        _ => bike.current_lane_id.right(track_lanes),
+    };
+    if adjacent_lane == bike.current_lane_id {
+        return None;
+    }
+    q_bikes
+        .iter()
+        .filter(|(other_entity, other_bike, ..)| {
+            *other_entity != entity && other_bike.current_lane_id == adjacent_lane
+        })
+        .map(|(other_entity, other_bike, ..)| {
+            let distance_on_own_lane = track_lanes.distance_on_adjacent_lane(
+                adjacent_lane,
+                bike.current_lane_id,
+                other_bike.distance,
+            );
+            (
+                other_entity,
+                *other_bike,
+                (distance_on_own_lane - bike.distance).abs(),
+            )
+        })
+        .filter(|(_, _, gap)| *gap <= CONTACT_REACH)
+        .min_by(|(_, _, gap), (_, _, other_gap)| gap.total_cmp(other_gap))
+        .map(|(other_entity, other_bike, _)| (other_entity, other_bike))
+}
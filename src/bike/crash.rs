@@ -0,0 +1,188 @@
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::{prelude::*, utils::HashSet};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    actions::BikeAction,
+    collision::{Collider, Collision},
+    game::{Rider, TICK_SECONDS},
+};
+
+use super::{Bike, ChangeLane, ChangeSpeed};
+
+/// Impact speed above which a crash puts the rider out of the race
+const SERIOUS_CRASH_SPEED: f32 = 1000.0;
+/// Turns a rider spends getting back on their bike after a crash
+const CRASH_TURNS_LOST: usize = 3;
+/// How quickly a fallen bike slides to a stop
+const SLIDE_DECELERATION: f32 = 1500.0;
+/// How far a fallen bike spins round while sliding
+const SLIDE_SPIN: f32 = FRAC_PI_2;
+const CRASHED_COLOR: Color = Color::srgb(1.0, 0.5, 0.5);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrashCause {
+    /// Ran into the back of another rider
+    Collision,
+    /// Took a bend far too fast
+    Slip,
+}
+
+/// Makes a rider fall
+#[derive(Event, Debug, Clone, Copy)]
+pub struct CrashEvent {
+    pub bike_entity: Entity,
+    pub cause: CrashCause,
+    pub impact_speed: f32,
+}
+
+/// A rider that has fallen, and is either out of the race or sitting out some turns.
+/// Their collider is ignored until they are back on their bike.
+#[derive(Component, Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Crashed {
+    pub cause: CrashCause,
+    pub retired: bool,
+    turns_remaining: usize,
+    slide: Slide,
+}
+
+/// The path of a fallen bike, sliding in a straight line until friction stops it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct Slide {
+    start: Vec2,
+    rotation: Quat,
+    speed: f32,
+    elapsed: f32,
+}
+
+impl Slide {
+    fn duration(&self) -> f32 {
+        self.speed / SLIDE_DECELERATION
+    }
+
+    fn length(&self) -> f32 {
+        self.speed * self.speed / (2.0 * SLIDE_DECELERATION)
+    }
+
+    fn position_and_rotation(&self) -> (Vec2, Quat) {
+        let time = self.elapsed.min(self.duration());
+        let travelled = self.speed * time - 0.5 * SLIDE_DECELERATION * time * time;
+        let direction = self.rotation.mul_vec3(Vec3::X).xy();
+        let proportion = if self.speed > 0.0 {
+            travelled / self.length()
+        } else {
+            1.0
+        };
+        (
+            self.start + direction * travelled,
+            self.rotation * Quat::from_rotation_z(SLIDE_SPIN * proportion),
+        )
+    }
+}
+
+/// Knocks riders off their bikes, and notes the crash on their record
+pub(super) fn fall(
+    mut crash_events: EventReader<CrashEvent>,
+    mut q_bikes: Query<(&mut Bike, &Transform, Option<&mut Rider>), Without<Crashed>>,
+    mut commands: Commands,
+) {
+    let mut fallen = HashSet::new();
+    for event in crash_events.read() {
+        if !fallen.insert(event.bike_entity) {
+            continue;
+        }
+        let Ok((mut bike, transform, maybe_rider)) = q_bikes.get_mut(event.bike_entity) else {
+            continue;
+        };
+        let retired = event.impact_speed > SERIOUS_CRASH_SPEED;
+        debug!(
+            "CRASH!!! Bike {:?} fell from {:?} at {}{}",
+            event.bike_entity,
+            event.cause,
+            event.impact_speed,
+            if retired {
+                " and is out of the race"
+            } else {
+                ""
+            }
+        );
+        let slide = Slide {
+            start: transform.translation.xy(),
+            rotation: transform.rotation,
+            speed: bike.speed,
+            elapsed: 0.0,
+        };
+        bike.speed = 0.0;
+        if let Some(mut rider) = maybe_rider {
+            rider.record_crash(event.cause, retired);
+        }
+        commands
+            .entity(event.bike_entity)
+            .remove::<(BikeAction, ChangeSpeed, ChangeLane, Collision)>()
+            .insert(Crashed {
+                cause: event.cause,
+                retired,
+                turns_remaining: CRASH_TURNS_LOST,
+                slide,
+            });
+    }
+}
+
+pub(super) fn slide_fallen_bikes(mut q_crashed: Query<(&mut Crashed, &mut Transform)>) {
+    for (mut crashed, mut transform) in q_crashed.iter_mut() {
+        crashed.slide.elapsed += TICK_SECONDS;
+        let (position, rotation) = crashed.slide.position_and_rotation();
+        transform.translation = position.extend(transform.translation.z);
+        transform.rotation = rotation;
+    }
+}
+
+/// Clears retired riders off the track and puts recovered riders back on their bikes,
+/// at the point along their lane where their bike stopped
+pub(super) fn recover_from_crashes(
+    mut q_crashed: Query<(Entity, &mut Crashed, &mut Bike, Has<Collider>)>,
+    mut commands: Commands,
+) {
+    for (entity, mut crashed, mut bike, has_collider) in q_crashed.iter_mut() {
+        if crashed.retired {
+            if has_collider {
+                commands.entity(entity).remove::<Collider>();
+            }
+            continue;
+        }
+        crashed.turns_remaining = crashed.turns_remaining.saturating_sub(1);
+        if crashed.turns_remaining == 0 {
+            debug!("Bike {entity:?} is back in the race");
+            bike.distance += crashed.slide.length();
+            commands.entity(entity).remove::<Crashed>();
+        }
+    }
+}
+
+/// Colours riders who have fallen
+pub(super) fn show_fallen_riders(mut q_fallen: Query<&mut Sprite, Added<Crashed>>) {
+    for mut sprite in q_fallen.iter_mut() {
+        sprite.color = CRASHED_COLOR;
+    }
+}
+
+pub(super) fn show_recovered_riders(
+    mut recovered: RemovedComponents<Crashed>,
+    mut q_sprites: Query<&mut Sprite, With<Bike>>,
+) {
+    for entity in recovered.read() {
+        if let Ok(mut sprite) = q_sprites.get_mut(entity) {
+            sprite.color = Color::WHITE;
+        }
+    }
+}
+
+/// Retired riders disappear once their bike has stopped sliding
+pub(super) fn hide_retired_riders(mut q_crashed: Query<(&Crashed, &mut Visibility)>) {
+    for (crashed, mut visibility) in q_crashed.iter_mut() {
+        if crashed.retired {
+            *visibility = Visibility::Hidden;
+        }
+    }
+}
@@ -0,0 +1,498 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    actions::BikeAction,
+    bike::{Bike, Crashed},
+    collision::{Collision, CollisionSide},
+    config::Config,
+    game::{Finished, Rider},
+    opponent::generate_possible_actions,
+    simulation::TurnPhaseSet,
+    track::TrackLanes,
+    GameState, RacingState,
+};
+
+/// Longest a bot gets to answer before its rider watches for the turn. Every bot is
+/// asked at once, so they share the wait.
+const BOT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Lets programs outside the game race riders, whatever language they are written in.
+/// Each bot is started as a child process when its rider first has to choose. At the
+/// start of every turn it is sent an `Observation` as one line of JSON on its standard
+/// input, and answers with one line on its standard output naming a `BikeAction`, such
+/// as `"Accelerate"`. A rider whose bot is too slow, or answers with anything but a
+/// legal action, watches instead.
+pub struct ExternalBotPlugin;
+
+impl Plugin for ExternalBotPlugin {
+    fn build(&self, app: &mut App) {
+        let bot_commands = BotCommands::from_config(Config::of(app));
+        app.insert_resource(bot_commands).add_systems(
+            OnEnter(RacingState::Commanding),
+            drive_bots
+                .in_set(TurnPhaseSet)
+                .run_if(in_state(GameState::Playing)),
+        );
+    }
+}
+
+/// How to start a bot
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BotCommand {
+    /// Name of the bot's rider
+    pub name: String,
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+impl BotCommand {
+    fn start(&self) -> std::io::Result<BotProcess> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("stdin of the bot is piped");
+        let stdout = child.stdout.take().expect("stdout of the bot is piped");
+        let (sender, receiver) = mpsc::channel();
+        // replies are read on their own thread, so a bot that says nothing can't hold
+        // up the game
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(BotProcess {
+            child,
+            stdin,
+            replies: Mutex::new(BotReplies {
+                receiver,
+                unanswered: 0,
+            }),
+        })
+    }
+}
+
+/// Bots that take the place of the first opponents on the grid, counting from the
+/// inside lane
+#[derive(Resource, Debug, Clone, Default, PartialEq)]
+pub struct BotCommands(pub Vec<BotCommand>);
+
+impl BotCommands {
+    /// Bots listed as `bots` in the config file
+    pub fn from_config(config: &Config) -> Self {
+        Self(config.bots.clone())
+    }
+}
+
+/// A rider raced by a bot
+#[derive(Component, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExternalBot(pub BotCommand);
+
+/// The running bot of a rider, which is stopped along with the rider
+#[derive(Component)]
+struct BotProcess {
+    child: Child,
+    stdin: ChildStdin,
+    replies: Mutex<BotReplies>,
+}
+
+struct BotReplies {
+    receiver: Receiver<String>,
+    /// Observations sent that the bot has yet to answer. Answers that come too late
+    /// for their turn are thrown away when they arrive.
+    unanswered: usize,
+}
+
+/// Marks a rider whose bot could not be started or has stopped, who watches for the
+/// rest of the race
+#[derive(Component)]
+struct BotStopped;
+
+impl BotProcess {
+    fn ask(&self, observation: &str) -> std::io::Result<()> {
+        let mut stdin = &self.stdin;
+        writeln!(stdin, "{observation}")?;
+        stdin.flush()?;
+        self.replies().unanswered += 1;
+        Ok(())
+    }
+
+    /// The answer to the latest observation
+    fn answer(&self, deadline: Instant) -> Result<String, RecvTimeoutError> {
+        let mut replies = self.replies();
+        loop {
+            let reply = replies
+                .receiver
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))?;
+            replies.unanswered = replies.unanswered.saturating_sub(1);
+            if replies.unanswered == 0 {
+                return Ok(reply);
+            }
+        }
+    }
+
+    fn replies(&self) -> MutexGuard<'_, BotReplies> {
+        self.replies.lock().expect("bot replies are never poisoned")
+    }
+}
+
+impl Drop for BotProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// What a bot is told at the start of every turn
+#[derive(Serialize, Debug)]
+struct Observation<'a> {
+    /// Number of the bot's own rider, which is the lane they started in
+    rider: usize,
+    /// Laps in the race
+    laps: usize,
+    lane_count: usize,
+    /// Actions the bot's rider can take this turn
+    legal_actions: &'a [BikeAction],
+    /// Every bike in the race, by rider number
+    bikes: &'a [BikeObservation<'a>],
+}
+
+#[derive(Serialize, Debug)]
+struct BikeObservation<'a> {
+    rider: usize,
+    name: &'a str,
+    /// Counted from the inside of the track
+    lane: usize,
+    /// Along the lane from the start line
+    distance: f32,
+    /// Laps covered, in the same units whatever the lane
+    race_progress: f32,
+    speed: f32,
+    max_speed: f32,
+    crashed: bool,
+    collision: Option<CollisionObservation>,
+    section: SectionObservation,
+}
+
+#[derive(Serialize, Debug)]
+struct CollisionObservation {
+    other_rider: Option<usize>,
+    side: CollisionSide,
+    other_bike_speed: f32,
+}
+
+#[derive(Serialize, Debug)]
+struct SectionObservation {
+    /// Counted in driving order from the start of the track layout
+    index: usize,
+    bend: bool,
+    turn_radius: Option<f32>,
+    /// Distance to the end of the section
+    remaining: f32,
+}
+
+/// Asks every bot what its rider does this turn, and waits for them all to answer
+fn drive_bots(
+    mut commands: Commands,
+    q_bots: Query<
+        (
+            Entity,
+            &ExternalBot,
+            Option<&BotProcess>,
+            &Rider,
+            &Bike,
+            Option<&Collision>,
+        ),
+        (
+            Without<Crashed>,
+            Without<Finished>,
+            Without<BikeAction>,
+            Without<BotStopped>,
+        ),
+    >,
+    q_stopped: Query<
+        Entity,
+        (
+            With<BotStopped>,
+            Without<Crashed>,
+            Without<Finished>,
+            Without<BikeAction>,
+        ),
+    >,
+    q_bikes: Query<(&Rider, &Bike, Option<&Collision>, Has<Crashed>)>,
+    track_lanes: Res<TrackLanes>,
+) {
+    for entity in &q_stopped {
+        commands.entity(entity).insert(BikeAction::Watch);
+    }
+    let rider_number = |entity: Entity| q_bikes.get(entity).ok().map(|(rider, ..)| rider.number());
+    let mut bikes: Vec<BikeObservation> = q_bikes
+        .iter()
+        .map(|(rider, bike, maybe_collision, crashed)| {
+            let track_lane = track_lanes.track_lane(&bike.current_lane_id);
+            BikeObservation {
+                rider: rider.number(),
+                name: rider.name(),
+                lane: bike.current_lane_id.index(),
+                distance: bike.distance,
+                race_progress: track_lanes.race_progress(&bike.current_lane_id, bike.distance),
+                speed: bike.speed,
+                max_speed: bike.max_speed,
+                crashed,
+                collision: maybe_collision.map(|collision| CollisionObservation {
+                    other_rider: rider_number(collision.other_entity),
+                    side: collision.side,
+                    other_bike_speed: collision.other_bike_speed,
+                }),
+                section: SectionObservation {
+                    index: track_lane.in_track_section(bike.distance).index(),
+                    bend: track_lane.in_turn(bike.distance),
+                    turn_radius: track_lane.turn_radius(bike.distance),
+                    remaining: track_lane.distance_to_end_of_track_section(bike.distance),
+                },
+            }
+        })
+        .collect();
+    bikes.sort_by_key(|bike| bike.rider);
+
+    // bots are asked in order of rider number, all before any answer is waited on
+    let mut bots: Vec<_> = q_bots.iter().collect();
+    bots.sort_by_key(|(_, _, _, rider, ..)| rider.number());
+    // bots are started when their rider first has to choose, and kept running
+    let mut started = Vec::new();
+    for (entity, bot, process, rider, ..) in &bots {
+        if process.is_some() {
+            continue;
+        }
+        match bot.0.start() {
+            Ok(process) => started.push((*entity, process)),
+            Err(error) => {
+                warn!(
+                    "Could not start bot {} for rider {}, who watches from now on: {error}",
+                    bot.0.name,
+                    rider.number()
+                );
+                commands
+                    .entity(*entity)
+                    .insert((BotStopped, BikeAction::Watch));
+            }
+        }
+    }
+    let mut asked = Vec::new();
+    for (entity, bot, process, rider, bike, maybe_collision) in &bots {
+        let legal_actions = generate_possible_actions(bike, *maybe_collision, &track_lanes);
+        let observation = Observation {
+            rider: rider.number(),
+            laps: track_lanes.laps,
+            lane_count: track_lanes.lane_count(),
+            legal_actions: &legal_actions,
+            bikes: &bikes,
+        };
+        let line = serde_json::to_string(&observation).expect("observations can always be written");
+        let Some(running) = running_process(*entity, *process, &started) else {
+            continue;
+        };
+        match running.ask(&line) {
+            Ok(()) => asked.push((*entity, bot, *process, rider.number(), legal_actions)),
+            Err(error) => {
+                warn!(
+                    "Could not ask bot {} for rider {}, who watches from now on: {error}",
+                    bot.0.name,
+                    rider.number()
+                );
+                stop_bot(&mut commands, *entity);
+            }
+        }
+    }
+
+    let deadline = Instant::now() + BOT_TIMEOUT;
+    for (entity, bot, process, number, legal_actions) in asked {
+        let Some(process) = running_process(entity, process, &started) else {
+            continue;
+        };
+        let answer = process.answer(deadline);
+        if answer == Err(RecvTimeoutError::Disconnected) {
+            stop_bot(&mut commands, entity);
+        }
+        let action = answered_action(answer, &legal_actions, &bot.0.name, number);
+        commands.entity(entity).insert(action);
+    }
+    for (entity, process) in started {
+        commands.entity(entity).insert(process);
+    }
+}
+
+/// The action a bot's answer for rider `number` stands for, which is watching unless
+/// it names a legal one in time
+fn answered_action(
+    answer: Result<String, RecvTimeoutError>,
+    legal_actions: &[BikeAction],
+    name: &str,
+    number: usize,
+) -> BikeAction {
+    match answer {
+        Ok(reply) => match serde_json::from_str::<BikeAction>(reply.trim()) {
+            Ok(action) if legal_actions.contains(&action) => action,
+            Ok(action) => {
+                warn!("Bot {name} chose {action:?} for rider {number}, who can't, so watches");
+                BikeAction::Watch
+            }
+            Err(error) => {
+                warn!("Bot {name} answered {reply:?} for rider {number}, who watches: {error}");
+                BikeAction::Watch
+            }
+        },
+        Err(RecvTimeoutError::Timeout) => {
+            warn!("Bot {name} took too long to choose for rider {number}, who watches");
+            BikeAction::Watch
+        }
+        Err(RecvTimeoutError::Disconnected) => {
+            warn!("Bot {name} for rider {number} has stopped, so they watch from now on");
+            BikeAction::Watch
+        }
+    }
+}
+
+/// The bot process of a rider, which may have only just been started
+fn running_process<'a>(
+    entity: Entity,
+    process: Option<&'a BotProcess>,
+    started: &'a [(Entity, BotProcess)],
+) -> Option<&'a BotProcess> {
+    process.or_else(|| {
+        started
+            .iter()
+            .find(|(started, _)| *started == entity)
+            .map(|(_, process)| process)
+    })
+}
+
+fn stop_bot(commands: &mut Commands, entity: Entity) {
+    commands
+        .entity(entity)
+        .remove::<BotProcess>()
+        .insert((BotStopped, BikeAction::Watch));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deadline for bots that are meant to answer, which they never come near
+    const PATIENCE: Duration = Duration::from_secs(5);
+    const LEGAL_ACTIONS: [BikeAction; 3] =
+        [BikeAction::Accelerate, BikeAction::Watch, BikeAction::Left];
+
+    /// A bot that runs `script` in the shell
+    fn shell_bot(script: &str) -> BotCommand {
+        BotCommand {
+            name: "stub".to_string(),
+            program: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+        }
+    }
+
+    fn observation_line() -> String {
+        let bikes = [BikeObservation {
+            rider: 0,
+            name: "Stub",
+            lane: 0,
+            distance: 12.5,
+            race_progress: 0.25,
+            speed: 3.0,
+            max_speed: 10.0,
+            crashed: false,
+            collision: Some(CollisionObservation {
+                other_rider: Some(1),
+                side: CollisionSide::Right,
+                other_bike_speed: 4.0,
+            }),
+            section: SectionObservation {
+                index: 1,
+                bend: true,
+                turn_radius: Some(20.0),
+                remaining: 7.5,
+            },
+        }];
+        serde_json::to_string(&Observation {
+            rider: 0,
+            laps: 4,
+            lane_count: 4,
+            legal_actions: &LEGAL_ACTIONS,
+            bikes: &bikes,
+        })
+        .unwrap()
+    }
+
+    /// What `bot` answers to an observation, given until `timeout` to do so
+    fn ask(bot: &BotCommand, timeout: Duration) -> Result<String, RecvTimeoutError> {
+        let process = bot.start().unwrap();
+        process.ask(&observation_line()).unwrap();
+        process.answer(Instant::now() + timeout)
+    }
+
+    #[test]
+    fn observation_crosses_to_the_bot_as_one_line_of_json() {
+        let echo = BotCommand {
+            name: "echo".to_string(),
+            program: "cat".to_string(),
+            args: Vec::new(),
+        };
+        let line = ask(&echo, PATIENCE).unwrap();
+        assert_eq!(line, observation_line());
+
+        let observation: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(observation["legal_actions"][2], "Left");
+        assert_eq!(observation["bikes"][0]["section"]["turn_radius"], 20.0);
+        assert_eq!(observation["bikes"][0]["collision"]["side"], "Right");
+    }
+
+    #[test]
+    fn legal_answer_is_taken() {
+        let answer = ask(&shell_bot("read line; echo '\"Left\"'"), PATIENCE);
+        assert_eq!(
+            answered_action(answer, &LEGAL_ACTIONS, "stub", 0),
+            BikeAction::Left
+        );
+    }
+
+    #[test]
+    fn slow_bot_watches() {
+        let answer = ask(&shell_bot("read line; sleep 5"), Duration::from_millis(100));
+        assert_eq!(answer, Err(RecvTimeoutError::Timeout));
+        assert_eq!(
+            answered_action(answer, &LEGAL_ACTIONS, "stub", 0),
+            BikeAction::Watch
+        );
+    }
+
+    #[test]
+    fn malformed_or_illegal_answer_watches() {
+        for reply in ["Left", "\"Sideways\"", "\"Skid\""] {
+            let answer = ask(&shell_bot(&format!("read line; echo '{reply}'")), PATIENCE);
+            assert_eq!(answer.as_deref(), Ok(reply));
+            assert_eq!(
+                answered_action(answer, &LEGAL_ACTIONS, "stub", 0),
+                BikeAction::Watch
+            );
+        }
+    }
+}
@@ -1,6 +1,9 @@
 use bevy::prelude::*;
 
-use crate::{player::Player, PlayingState, RacingState};
+use crate::{
+    player::{ActivePlayer, Player, RemotePlayer},
+    PlayingState, RacingState,
+};
 
 const CAMERA_MOVEMENT_SPEED: f32 = 600.0;
 
@@ -11,7 +14,9 @@ impl Plugin for CameraDollyPlugin {
         app.add_systems(Startup, setup)
             .add_systems(
                 Update,
-                move_camera.run_if(in_state(RacingState::Commanding)),
+                (look_at_active_player, move_camera)
+                    .chain()
+                    .run_if(in_state(RacingState::Commanding)),
             )
             .add_systems(
                 Update,
@@ -68,14 +73,31 @@ fn move_camera(
     }
 }
 
+/// Keeps the best placed player at this machine in view
 fn follow_player(
     mut q_camera: Query<&mut Transform, (With<Camera2d>, Without<Player>)>,
-    q_bike: Query<&Transform, With<Player>>,
+    q_bikes: Query<(&Transform, &Player), Without<RemotePlayer>>,
+) {
+    if let Some((player_pos, _)) = q_bikes.iter().min_by_key(|(_, player)| player.position) {
+        for mut camera_transform in q_camera.iter_mut() {
+            camera_transform.translation = view_of(player_pos);
+        }
+    }
+}
+
+/// Moves over to each player as their turn to choose comes round
+fn look_at_active_player(
+    mut q_camera: Query<&mut Transform, (With<Camera2d>, Without<ActivePlayer>)>,
+    q_bike: Query<&Transform, (With<ActivePlayer>, Added<ActivePlayer>)>,
 ) {
     if let Ok(player_pos) = q_bike.get_single() {
-        let ideal_camera_pos = Vec3::ZERO.lerp(player_pos.translation, 0.7);
         for mut camera_transform in q_camera.iter_mut() {
-            camera_transform.translation = ideal_camera_pos;
+            camera_transform.translation = view_of(player_pos);
         }
     }
 }
+
+/// Where the camera goes to show a bike, part of the way in towards the middle of the track
+fn view_of(bike_pos: &Transform) -> Vec3 {
+    Vec3::ZERO.lerp(bike_pos.translation, 0.7)
+}
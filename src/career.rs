@@ -0,0 +1,433 @@
+mod board;
+mod calendar;
+
+use bevy::prelude::*;
+use fastrand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bot::BotCommands,
+    loading::{MeetingAssets, RiderAssets, SeasonAssets, TrackAssets, MEETING_FILES},
+    meeting::{Meeting, MeetingProgramme},
+    opponent::OpponentDifficulties,
+    profile::{BikeStats, RiderProfile},
+    random::{RaceSeed, Randomness},
+    save::{back_up_save, read_save, write_save, SaveError},
+    track::{SelectedTrack, TrackDefinition},
+    GameState,
+};
+
+use self::board::CareerBoardPlugin;
+pub use self::calendar::SeasonCalendar;
+use self::calendar::{Round, SeasonCalendarLoader};
+
+/// Name the career is saved under
+const CAREER_SAVE: &str = "career";
+/// Development points for riding a round of the season
+const ROUND_DEVELOPMENT: usize = 1;
+/// Development points on top for finishing a round in the first three
+const PODIUM_DEVELOPMENT: usize = 1;
+const TOP_SPEED_STEP: f32 = 20.0;
+const MAX_TOP_SPEED: f32 = 1600.0;
+const ACCELERATION_STEP: f32 = 20.0;
+const MAX_ACCELERATION: f32 = 1000.0;
+const GRIP_STEP: f32 = 0.02;
+const MAX_GRIP: f32 = 0.7;
+
+/// Runs a career: seasons of meetings on different tracks, with championship points
+/// for where the riders are placed in each meeting. The player's rider earns
+/// development points as they go, which improve their bike between meetings. The
+/// career is saved after every change, so it carries on where it was left.
+pub struct CareerPlugin;
+
+impl Plugin for CareerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<SeasonCalendar>()
+            .init_asset_loader::<SeasonCalendarLoader>()
+            .add_plugins(CareerBoardPlugin)
+            .add_systems(
+                OnEnter(GameState::Career),
+                (
+                    start_career.run_if(not(resource_exists::<Career>)),
+                    record_round
+                        .run_if(resource_exists::<Career>.and_then(resource_exists::<Meeting>)),
+                )
+                    .chain(),
+            )
+            // a new career once the player has chosen to put an unreadable one aside
+            .add_systems(
+                Update,
+                start_career.run_if(
+                    in_state(GameState::Career)
+                        .and_then(not(resource_exists::<Career>))
+                        .and_then(not(resource_exists::<UnreadableCareer>)),
+                ),
+            )
+            .add_systems(OnEnter(GameState::Menu), end_career);
+    }
+}
+
+/// The career being played, and everything that has happened in it
+#[derive(Resource, Serialize, Deserialize, Debug, Clone)]
+pub struct Career {
+    pub calendar: SeasonCalendar,
+    /// Seasons started, counting the current one
+    pub season: usize,
+    /// The player's rider, whose bike improves over the career
+    pub rider: RiderProfile,
+    /// Development points the player has still to spend on their bike
+    pub development: usize,
+    /// The opponents who ride the season's meetings
+    field: Vec<RiderProfile>,
+    /// How each round of the current season finished, in calendar order
+    rounds: Vec<RoundResult>,
+    /// How each season before the current one finished
+    history: Vec<SeasonSummary>,
+    /// The season's meetings are drawn and raced with seeds from this one
+    seed: u64,
+}
+
+/// The saved career could not be read, for this reason. The player chooses whether to
+/// put it aside and start again.
+#[derive(Resource, Debug, Clone)]
+struct UnreadableCareer(String);
+
+/// How a round of the season finished
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RoundResult {
+    /// Name of the track the round was run on
+    pub track: String,
+    /// Names of the riders in the order the meeting placed them
+    pub classification: Vec<String>,
+}
+
+/// How a season finished
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SeasonSummary {
+    pub season: usize,
+    /// Name of every rider with their championship points, champion first
+    pub standings: Vec<(String, usize)>,
+}
+
+/// One rider's line in the championship
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChampionshipScore {
+    pub points: usize,
+    /// Meetings won
+    pub wins: usize,
+}
+
+/// A part of the bike a development point can be spent on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Improvement {
+    TopSpeed,
+    Acceleration,
+    Grip,
+}
+
+impl Improvement {
+    pub const ALL: [Improvement; 3] = [
+        Improvement::TopSpeed,
+        Improvement::Acceleration,
+        Improvement::Grip,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Improvement::TopSpeed => "Speed",
+            Improvement::Acceleration => "Accel",
+            Improvement::Grip => "Grip",
+        }
+    }
+
+    /// Makes the stat a step better, unless it is as good as a bike gets. Returns
+    /// whether it improved.
+    fn apply(&self, stats: &mut BikeStats) -> bool {
+        let (stat, step, max) = match self {
+            Improvement::TopSpeed => (&mut stats.top_speed, TOP_SPEED_STEP, MAX_TOP_SPEED),
+            Improvement::Acceleration => {
+                (&mut stats.acceleration, ACCELERATION_STEP, MAX_ACCELERATION)
+            }
+            Improvement::Grip => (&mut stats.grip, GRIP_STEP, MAX_GRIP),
+        };
+        if *stat >= max {
+            return false;
+        }
+        *stat = (*stat + step).min(max);
+        true
+    }
+}
+
+impl Career {
+    /// Starts a career in the first season of `calendar`, against `opponents` riders
+    /// drawn from `profiles`
+    pub fn new(
+        calendar: SeasonCalendar,
+        mut profiles: Vec<RiderProfile>,
+        opponents: usize,
+        seed: u64,
+    ) -> Self {
+        Randomness::from_seed(seed).grid.shuffle(&mut profiles);
+        profiles.truncate(opponents);
+        Self {
+            calendar,
+            season: 1,
+            rider: RiderProfile::player(0, 1),
+            development: 0,
+            field: profiles,
+            rounds: Vec::new(),
+            history: Vec::new(),
+            seed,
+        }
+    }
+
+    pub fn load() -> Result<Self, SaveError> {
+        Ok(ron::de::from_str(&read_save(CAREER_SAVE)?)?)
+    }
+
+    pub fn save(&self) -> Result<(), SaveError> {
+        write_save(CAREER_SAVE, &ron::ser::to_string(self)?)
+    }
+
+    /// The round to be run next, or `None` once the season is over
+    pub fn next_round(&self) -> Option<&Round> {
+        self.calendar.rounds.get(self.rounds.len())
+    }
+
+    /// The round run most recently
+    pub fn last_round(&self) -> Option<&RoundResult> {
+        self.rounds.last()
+    }
+
+    /// Rounds run so far this season
+    pub fn rounds_run(&self) -> usize {
+        self.rounds.len()
+    }
+
+    pub fn is_season_over(&self) -> bool {
+        self.next_round().is_none()
+    }
+
+    pub fn history(&self) -> &[SeasonSummary] {
+        &self.history
+    }
+
+    /// Championship points and meeting wins of the rider called `name` this season
+    pub fn score(&self, name: &str) -> ChampionshipScore {
+        let mut score = ChampionshipScore { points: 0, wins: 0 };
+        for round in &self.rounds {
+            if let Some(index) = round.classification.iter().position(|rider| rider == name) {
+                score.points += self.calendar.points_for(index + 1);
+                score.wins += usize::from(index == 0);
+            }
+        }
+        score
+    }
+
+    /// Every rider of the season by championship points, then meetings won. Riders
+    /// level on both keep the player first and then their order in the field.
+    pub fn standings(&self) -> Vec<(String, ChampionshipScore)> {
+        let mut standings: Vec<(String, ChampionshipScore)> = std::iter::once(&self.rider)
+            .chain(&self.field)
+            .map(|profile| (profile.name.clone(), self.score(&profile.name)))
+            .collect();
+        standings.sort_by_key(|(_, score)| {
+            (
+                std::cmp::Reverse(score.points),
+                std::cmp::Reverse(score.wins),
+            )
+        });
+        standings
+    }
+
+    /// The meeting of the next round, to `programme`, with the player and the season's
+    /// field. Career meetings are raced by the computer, without bots.
+    pub fn meeting(
+        &self,
+        programme: MeetingProgramme,
+        opponent_difficulties: &OpponentDifficulties,
+    ) -> Meeting {
+        Meeting::new(
+            programme,
+            self.field.clone(),
+            vec![self.rider.clone()],
+            opponent_difficulties,
+            &BotCommands::default(),
+            self.seed.wrapping_add(self.rounds.len() as u64),
+        )
+    }
+
+    /// Scores the meeting of the round that has just been run at `track`, and rewards
+    /// the player's rider for taking part
+    pub fn record_round(&mut self, meeting: &Meeting, track: String) {
+        let classification: Vec<String> = meeting
+            .classification()
+            .into_iter()
+            .map(|number| meeting.rider(number).profile.name.clone())
+            .collect();
+        let place = classification
+            .iter()
+            .position(|name| *name == self.rider.name);
+        self.development += ROUND_DEVELOPMENT;
+        if place.is_some_and(|index| index < 3) {
+            self.development += PODIUM_DEVELOPMENT;
+        }
+        self.rounds.push(RoundResult {
+            track,
+            classification,
+        });
+    }
+
+    /// Spends a development point on the player's bike. Nothing is spent if there are
+    /// no points left or the stat can't get any better.
+    pub fn improve(&mut self, improvement: Improvement) {
+        if self.development > 0 && improvement.apply(&mut self.rider.bike) {
+            self.development -= 1;
+        }
+    }
+
+    /// Puts the season that has finished in the history and starts the next one. The
+    /// player keeps their bike and any development points left over.
+    pub fn start_next_season(&mut self) {
+        let standings = self
+            .standings()
+            .into_iter()
+            .map(|(name, score)| (name, score.points))
+            .collect();
+        self.history.push(SeasonSummary {
+            season: self.season,
+            standings,
+        });
+        self.season += 1;
+        self.rounds.clear();
+        self.seed = Rng::with_seed(self.seed).u64(..);
+    }
+}
+
+/// Sets up the next round of the career: its track, and its meeting with the player's
+/// rider in the field. Returns `false` if the round's track or meeting programme didn't
+/// load.
+fn start_round(
+    commands: &mut Commands,
+    career: &Career,
+    meeting_assets: &MeetingAssets,
+    programmes: &Assets<MeetingProgramme>,
+    opponent_difficulties: &OpponentDifficulties,
+    track_assets: &TrackAssets,
+    selected_track: &mut SelectedTrack,
+) -> bool {
+    let Some(round) = career.next_round() else {
+        return false;
+    };
+    let programme = MEETING_FILES
+        .iter()
+        .position(|path| *path == round.meeting)
+        .and_then(|index| programmes.get(&meeting_assets.programmes[index]));
+    let track_index = track_assets.index_of(&round.track);
+    let (Some(programme), Some(track_index)) = (programme, track_index) else {
+        return false;
+    };
+    selected_track.index = track_index;
+    commands.insert_resource(career.meeting(programme.clone(), opponent_difficulties));
+    true
+}
+
+/// Picks up the saved career, or starts a new one on the first season calendar if
+/// there is none. A save that can't be read is left for the player to decide about.
+fn start_career(
+    mut commands: Commands,
+    mut game_state: ResMut<NextState<GameState>>,
+    season_assets: Res<SeasonAssets>,
+    calendars: Res<Assets<SeasonCalendar>>,
+    meeting_assets: Res<MeetingAssets>,
+    programmes: Res<Assets<MeetingProgramme>>,
+    rider_assets: Res<RiderAssets>,
+    rider_profiles: Res<Assets<RiderProfile>>,
+    race_seed: Res<RaceSeed>,
+) {
+    let career = match Career::load() {
+        Ok(career) => career,
+        Err(SaveError::NoSave) => {
+            let Some(calendar) = season_assets
+                .calendars
+                .first()
+                .and_then(|handle| calendars.get(handle))
+            else {
+                warn!("Could not start a career: the season calendar did not load");
+                game_state.set(GameState::Menu);
+                return;
+            };
+            // enough opponents to fill the biggest meeting of the season
+            let opponents = meeting_assets
+                .programmes
+                .iter()
+                .filter_map(|handle| programmes.get(handle))
+                .map(|programme| programme.field.saturating_sub(1))
+                .max()
+                .unwrap_or(0);
+            let profiles = rider_assets
+                .profiles
+                .iter()
+                .filter_map(|handle| rider_profiles.get(handle))
+                .cloned()
+                .collect();
+            let seed = race_seed.0.unwrap_or_else(|| fastrand::u64(..));
+            debug!("Career seed {seed}");
+            let career = Career::new(calendar.clone(), profiles, opponents, seed);
+            save_career(&career);
+            career
+        }
+        Err(error @ SaveError::Parse(_)) => {
+            warn!("Could not load the career: {error}");
+            commands.insert_resource(UnreadableCareer(error.to_string()));
+            return;
+        }
+        Err(error) => {
+            warn!("Could not load the career: {error}");
+            game_state.set(GameState::Menu);
+            return;
+        }
+    };
+    commands.insert_resource(career);
+}
+
+/// Moves the unreadable career save aside, so that a new career is started in its place
+fn put_aside_unreadable_career(commands: &mut Commands) -> Result<(), SaveError> {
+    let backup = back_up_save(CAREER_SAVE)?;
+    info!("Kept the unreadable career as {backup}");
+    commands.remove_resource::<UnreadableCareer>();
+    Ok(())
+}
+
+/// Scores the meeting the player has come back from, if it was run to the end. A
+/// meeting left part of the way through is run again.
+fn record_round(
+    mut commands: Commands,
+    mut career: ResMut<Career>,
+    meeting: Res<Meeting>,
+    selected_track: Res<SelectedTrack>,
+    track_assets: Res<TrackAssets>,
+    track_definitions: Res<Assets<TrackDefinition>>,
+) {
+    if meeting.is_over() {
+        let track = selected_track
+            .definition(&track_assets, &track_definitions)
+            .name
+            .clone();
+        career.record_round(&meeting, track);
+        save_career(&career);
+    }
+    commands.remove_resource::<Meeting>();
+}
+
+fn save_career(career: &Career) {
+    if let Err(error) = career.save() {
+        warn!("Could not save the career: {error}");
+    }
+}
+
+/// The career is loaded again from its save when it is next chosen
+fn end_career(mut commands: Commands) {
+    commands.remove_resource::<Career>();
+    commands.remove_resource::<UnreadableCareer>();
+}
@@ -0,0 +1,580 @@
+use bevy::prelude::*;
+
+use crate::{
+    loading::{MeetingAssets, TrackAssets, TRACK_FILES},
+    meeting::MeetingProgramme,
+    opponent::OpponentDifficulties,
+    track::{SelectedTrack, TrackDefinition},
+    GameState,
+};
+
+use super::{
+    put_aside_unreadable_career, record_round, save_career, start_career, start_round, Career,
+    Improvement, UnreadableCareer,
+};
+
+const BUTTON_NORMAL_COLOR: Color = Color::srgb(0.15, 0.15, 0.15);
+const BUTTON_HOVERED_COLOR: Color = Color::srgb(0.25, 0.25, 0.25);
+const BUTTON_PRESSED_COLOR: Color = Color::srgb(0.35, 0.75, 0.35);
+const BUTTON_WIDTH: f32 = 200.0;
+const BUTTON_HEIGHT: f32 = 65.0;
+const BUTTON_FONT_SIZE: f32 = 40.0;
+const BUTTON_FONT_COLOR: Color = Color::srgb(0.9, 0.9, 0.9);
+const PLAYER_COLOR: Color = Color::srgba(0.9, 0.1, 0.1, 0.8);
+const TITLE_FONT_SIZE: f32 = 40.0;
+const HEADING_FONT_SIZE: f32 = 30.0;
+const NOTE_FONT_SIZE: f32 = 22.0;
+const NOTE_COLOR: Color = Color::srgb(1.0, 0.8, 0.3);
+const TABLE_FONT_SIZE: f32 = 22.0;
+/// Widths of the columns of the championship: position, rider, meetings won and points
+const STANDINGS_COLUMN_WIDTHS: [f32; 4] = [40.0, 220.0, 60.0, 80.0];
+/// Widths of the columns of a round: place, rider and championship points
+const ROUND_COLUMN_WIDTHS: [f32; 3] = [40.0, 220.0, 60.0];
+/// Widths of the columns of the bike and of past seasons
+const SUMMARY_COLUMN_WIDTHS: [f32; 3] = [120.0, 160.0, 220.0];
+
+/// The screen shown between the meetings of a career, with the championship, the
+/// player's bike and how past seasons went
+pub struct CareerBoardPlugin;
+
+impl Plugin for CareerBoardPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            OnEnter(GameState::Career),
+            (
+                setup_board
+                    .after(start_career)
+                    .after(record_round)
+                    .run_if(resource_exists::<Career>),
+                setup_unreadable_prompt
+                    .after(start_career)
+                    .run_if(resource_exists::<UnreadableCareer>),
+            ),
+        )
+        .add_systems(OnExit(GameState::Career), teardown)
+        .add_systems(
+            Update,
+            prompt_button_system
+                .run_if(in_state(GameState::Career).and_then(resource_exists::<UnreadableCareer>)),
+        )
+        .add_systems(
+            Update,
+            (
+                button_system,
+                // the board shows the bike as it is improved
+                (teardown, setup_board)
+                    .chain()
+                    .run_if(resource_changed::<Career>),
+            )
+                .chain()
+                .run_if(in_state(GameState::Career).and_then(resource_exists::<Career>)),
+        );
+    }
+}
+
+#[derive(Component)]
+struct CareerBoard;
+
+#[derive(Component, Debug, PartialEq, Eq, Clone, Copy)]
+enum ButtonAction {
+    NextRound,
+    NewSeason,
+    Improve(Improvement),
+    NewCareer,
+    Menu,
+}
+
+fn setup_board(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    career: Res<Career>,
+    track_assets: Res<TrackAssets>,
+    track_definitions: Res<Assets<TrackDefinition>>,
+) {
+    let font_handle = asset_server.load("fonts/FiraSans-Bold.ttf");
+    let text = |text: String, font_size: f32, color: Color| {
+        TextBundle::from_section(
+            text,
+            TextStyle {
+                font_size,
+                color,
+                font: font_handle.clone(),
+            },
+        )
+    };
+    let rider_color = |name: &str| {
+        if name == career.rider.name {
+            PLAYER_COLOR
+        } else {
+            BUTTON_FONT_COLOR
+        }
+    };
+    let track_name = |path: &str| {
+        TRACK_FILES
+            .iter()
+            .position(|track| *track == path)
+            .and_then(|index| track_definitions.get(&track_assets.tracks[index]))
+            .map_or_else(|| path.to_string(), |track| track.name.clone())
+    };
+
+    let standings_title = if career.is_season_over() {
+        "Final standings"
+    } else {
+        "Championship"
+    };
+    let standings_header = vec![
+        String::new(),
+        "Rider".to_string(),
+        "Wins".to_string(),
+        "Points".to_string(),
+    ];
+    let standings_rows =
+        career
+            .standings()
+            .into_iter()
+            .enumerate()
+            .map(|(index, (name, score))| {
+                let color = rider_color(&name);
+                (
+                    vec![
+                        (index + 1).to_string(),
+                        name,
+                        score.wins.to_string(),
+                        score.points.to_string(),
+                    ],
+                    color,
+                )
+            });
+    let bike = career.rider.bike;
+    let bike_rows = [
+        (Improvement::TopSpeed, format!("{:.0}", bike.top_speed)),
+        (
+            Improvement::Acceleration,
+            format!("{:.0}", bike.acceleration),
+        ),
+        (Improvement::Grip, format!("{:.2}", bike.grip)),
+    ]
+    .into_iter()
+    .map(|(improvement, value)| {
+        (
+            vec![improvement.label().to_string(), value, String::new()],
+            BUTTON_FONT_COLOR,
+        )
+    });
+    // the rider's place and points in each past season, and who won it
+    let history_rows = career.history().iter().map(|summary| {
+        let place = summary
+            .standings
+            .iter()
+            .position(|(name, _)| *name == career.rider.name);
+        let (place, points) = match place {
+            Some(index) => (
+                format!("{} of {}", ordinal(index + 1), summary.standings.len()),
+                summary.standings[index].1,
+            ),
+            None => ("-".to_string(), 0),
+        };
+        let champion = summary
+            .standings
+            .first()
+            .map(|(name, _)| format!("Champion {name}"))
+            .unwrap_or_default();
+        (
+            vec![
+                format!("Season {}", summary.season),
+                format!("{place}, {points}"),
+                champion,
+            ],
+            BUTTON_FONT_COLOR,
+        )
+    });
+
+    commands
+        .spawn((
+            CareerBoard,
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn(text(
+                format!("{}, season {}", career.calendar.name, career.season),
+                TITLE_FONT_SIZE,
+                BUTTON_FONT_COLOR,
+            ));
+            parent.spawn(make_row()).with_children(|parent| {
+                parent.spawn(make_column()).with_children(|parent| {
+                    parent.spawn(text(
+                        standings_title.to_string(),
+                        HEADING_FONT_SIZE,
+                        BUTTON_FONT_COLOR,
+                    ));
+                    spawn_table(
+                        parent,
+                        std::iter::once((standings_header, BUTTON_FONT_COLOR))
+                            .chain(standings_rows),
+                        &STANDINGS_COLUMN_WIDTHS,
+                        &font_handle,
+                    );
+                });
+                parent.spawn(make_column()).with_children(|parent| {
+                    if let Some(round) = career.last_round() {
+                        parent.spawn(text(
+                            format!("Round {} at {}", career.rounds_run(), round.track),
+                            HEADING_FONT_SIZE,
+                            BUTTON_FONT_COLOR,
+                        ));
+                        let rows = round
+                            .classification
+                            .iter()
+                            .enumerate()
+                            .map(|(index, name)| {
+                                (
+                                    vec![
+                                        (index + 1).to_string(),
+                                        name.clone(),
+                                        career.calendar.points_for(index + 1).to_string(),
+                                    ],
+                                    rider_color(name),
+                                )
+                            });
+                        spawn_table(parent, rows, &ROUND_COLUMN_WIDTHS, &font_handle);
+                    }
+                    if let Some(round) = career.next_round() {
+                        parent.spawn(text(
+                            format!(
+                                "Next: round {} of {} at {}",
+                                career.rounds_run() + 1,
+                                career.calendar.rounds.len(),
+                                track_name(&round.track)
+                            ),
+                            HEADING_FONT_SIZE,
+                            BUTTON_FONT_COLOR,
+                        ));
+                    }
+                    parent.spawn(text(
+                        format!("Your bike, {} development points", career.development),
+                        HEADING_FONT_SIZE,
+                        BUTTON_FONT_COLOR,
+                    ));
+                    spawn_table(parent, bike_rows, &SUMMARY_COLUMN_WIDTHS, &font_handle);
+                    if !career.history().is_empty() {
+                        parent.spawn(text(
+                            "Past seasons".to_string(),
+                            HEADING_FONT_SIZE,
+                            BUTTON_FONT_COLOR,
+                        ));
+                        spawn_table(parent, history_rows, &SUMMARY_COLUMN_WIDTHS, &font_handle);
+                    }
+                });
+            });
+            if career.development > 0 {
+                parent.spawn(make_row()).with_children(|parent| {
+                    for improvement in Improvement::ALL {
+                        parent
+                            .spawn((ButtonAction::Improve(improvement), make_button()))
+                            .with_children(|parent| {
+                                parent.spawn(make_button_text(
+                                    improvement.label(),
+                                    font_handle.clone(),
+                                ));
+                            });
+                    }
+                });
+            }
+            parent.spawn(make_row()).with_children(|parent| {
+                let (action, label) = if career.is_season_over() {
+                    (ButtonAction::NewSeason, "New season")
+                } else {
+                    (ButtonAction::NextRound, "Next round")
+                };
+                parent
+                    .spawn((action, make_button()))
+                    .with_children(|parent| {
+                        parent.spawn(make_button_text(label, font_handle.clone()));
+                    });
+                parent
+                    .spawn((ButtonAction::Menu, make_button()))
+                    .with_children(|parent| {
+                        parent.spawn(make_button_text("Menu", font_handle.clone()));
+                    });
+            });
+        });
+}
+
+/// Asks the player what to do about a saved career that can't be read
+fn setup_unreadable_prompt(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    unreadable_career: Res<UnreadableCareer>,
+) {
+    let font_handle = asset_server.load("fonts/FiraSans-Bold.ttf");
+    let text = |text: String, font_size: f32, color: Color| {
+        TextBundle::from_section(
+            text,
+            TextStyle {
+                font_size,
+                color,
+                font: font_handle.clone(),
+            },
+        )
+    };
+    commands
+        .spawn((
+            CareerBoard,
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn(text(
+                "Your saved career could not be read".to_string(),
+                TITLE_FONT_SIZE,
+                BUTTON_FONT_COLOR,
+            ));
+            parent.spawn(text(
+                unreadable_career.0.clone(),
+                NOTE_FONT_SIZE,
+                NOTE_COLOR,
+            ));
+            parent.spawn(text(
+                "A new career keeps the old save as a backup".to_string(),
+                NOTE_FONT_SIZE,
+                BUTTON_FONT_COLOR,
+            ));
+            parent.spawn(make_row()).with_children(|parent| {
+                for (action, label) in [
+                    (ButtonAction::NewCareer, "New career"),
+                    (ButtonAction::Menu, "Menu"),
+                ] {
+                    parent
+                        .spawn((action, make_button()))
+                        .with_children(|parent| {
+                            parent.spawn(make_button_text(label, font_handle.clone()));
+                        });
+                }
+            });
+        });
+}
+
+/// A place written out in words, such as 1st or 12th
+fn ordinal(place: usize) -> String {
+    let suffix = match (place % 10, place % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{place}{suffix}")
+}
+
+/// Rows of text, each in its own color, lined up in columns of `widths`
+fn spawn_table(
+    parent: &mut ChildBuilder,
+    rows: impl Iterator<Item = (Vec<String>, Color)>,
+    widths: &[f32],
+    font_handle: &Handle<Font>,
+) {
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Column,
+                margin: UiRect::vertical(Val::Px(10.0)),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            for (cells, color) in rows {
+                parent.spawn(make_row()).with_children(|parent| {
+                    for (cell, width) in cells.into_iter().zip(widths) {
+                        parent.spawn(
+                            TextBundle::from_section(
+                                cell,
+                                TextStyle {
+                                    font_size: TABLE_FONT_SIZE,
+                                    color,
+                                    font: font_handle.clone(),
+                                },
+                            )
+                            .with_style(Style {
+                                width: Val::Px(*width),
+                                ..default()
+                            }),
+                        );
+                    }
+                });
+            }
+        });
+}
+
+fn make_row() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            flex_direction: FlexDirection::Row,
+            ..default()
+        },
+        ..default()
+    }
+}
+
+fn make_column() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            flex_direction: FlexDirection::Column,
+            margin: UiRect::horizontal(Val::Px(20.0)),
+            ..default()
+        },
+        ..default()
+    }
+}
+
+fn make_button() -> ButtonBundle {
+    ButtonBundle {
+        style: Style {
+            width: Val::Px(BUTTON_WIDTH),
+            height: Val::Px(BUTTON_HEIGHT),
+            border: UiRect::all(Val::Px(2.0)),
+            // horizontally center child text
+            justify_content: JustifyContent::Center,
+            // vertically center child text
+            align_items: AlignItems::Center,
+            margin: UiRect::all(Val::Px(20.0)),
+            ..default()
+        },
+        border_color: BorderColor(Color::BLACK),
+        border_radius: BorderRadius::MAX,
+        background_color: BUTTON_NORMAL_COLOR.into(),
+        ..default()
+    }
+}
+
+fn make_button_text(text: &str, font_handle: Handle<Font>) -> TextBundle {
+    TextBundle::from_section(
+        text,
+        TextStyle {
+            font: font_handle,
+            font_size: BUTTON_FONT_SIZE,
+            color: BUTTON_FONT_COLOR,
+        },
+    )
+}
+
+fn teardown(mut commands: Commands, q_elements: Query<Entity, With<CareerBoard>>) {
+    for entity in &q_elements {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn button_system(
+    mut interaction_query: Query<
+        (&ButtonAction, &Interaction, &mut BackgroundColor),
+        (Changed<Interaction>, With<Button>),
+    >,
+    mut commands: Commands,
+    mut game_state: ResMut<NextState<GameState>>,
+    mut career: ResMut<Career>,
+    meeting_assets: Res<MeetingAssets>,
+    programmes: Res<Assets<MeetingProgramme>>,
+    opponent_difficulties: Res<OpponentDifficulties>,
+    track_assets: Res<TrackAssets>,
+    mut selected_track: ResMut<SelectedTrack>,
+) {
+    for (button_action, interaction, mut color) in &mut interaction_query {
+        match *interaction {
+            Interaction::Pressed => {
+                *color = BUTTON_PRESSED_COLOR.into();
+                match button_action {
+                    ButtonAction::NextRound => {
+                        if start_round(
+                            &mut commands,
+                            &career,
+                            &meeting_assets,
+                            &programmes,
+                            &opponent_difficulties,
+                            &track_assets,
+                            &mut selected_track,
+                        ) {
+                            game_state.set(GameState::Meeting);
+                        } else {
+                            warn!(
+                                "Could not start the next round: its track or meeting programme did not load"
+                            );
+                        }
+                    }
+                    ButtonAction::NewSeason => {
+                        career.start_next_season();
+                        save_career(&career);
+                    }
+                    ButtonAction::Improve(improvement) => {
+                        career.improve(*improvement);
+                        save_career(&career);
+                    }
+                    ButtonAction::Menu => {
+                        game_state.set(GameState::Menu);
+                    }
+                    // only on the prompt about an unreadable career
+                    ButtonAction::NewCareer => {}
+                };
+            }
+            Interaction::Hovered => {
+                *color = BUTTON_HOVERED_COLOR.into();
+            }
+            Interaction::None => {
+                *color = BUTTON_NORMAL_COLOR.into();
+            }
+        }
+    }
+}
+
+fn prompt_button_system(
+    mut interaction_query: Query<
+        (&ButtonAction, &Interaction, &mut BackgroundColor),
+        (Changed<Interaction>, With<Button>),
+    >,
+    mut commands: Commands,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    for (button_action, interaction, mut color) in &mut interaction_query {
+        match *interaction {
+            Interaction::Pressed => {
+                *color = BUTTON_PRESSED_COLOR.into();
+                match button_action {
+                    ButtonAction::NewCareer => {
+                        if let Err(error) = put_aside_unreadable_career(&mut commands) {
+                            warn!("Could not keep the unreadable career as a backup: {error}");
+                            game_state.set(GameState::Menu);
+                        }
+                    }
+                    ButtonAction::Menu => {
+                        game_state.set(GameState::Menu);
+                    }
+                    _ => {}
+                }
+            }
+            Interaction::Hovered => {
+                *color = BUTTON_HOVERED_COLOR.into();
+            }
+            Interaction::None => {
+                *color = BUTTON_NORMAL_COLOR.into();
+            }
+        }
+    }
+}
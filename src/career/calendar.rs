@@ -0,0 +1,99 @@
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::loading::{MEETING_FILES, TRACK_FILES};
+
+/// The meetings of a season, loaded from a `.season.ron` file under `assets/seasons/`
+#[derive(Asset, TypePath, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SeasonCalendar {
+    pub name: String,
+    pub rounds: Vec<Round>,
+    /// Championship points for each place in a meeting's classification, from first.
+    /// Riders placed further back score nothing.
+    pub points: Vec<usize>,
+}
+
+/// One meeting of the season
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Round {
+    /// Track file, as listed in `TRACK_FILES`
+    pub track: String,
+    /// Meeting programme file, as listed in `MEETING_FILES`
+    pub meeting: String,
+}
+
+impl SeasonCalendar {
+    /// Reads a season calendar without an asset server
+    pub fn from_ron(bytes: &[u8]) -> Result<Self, SeasonCalendarLoaderError> {
+        let calendar: SeasonCalendar = ron::de::from_bytes(bytes)?;
+        calendar.validate()?;
+        Ok(calendar)
+    }
+
+    /// Championship points for finishing a meeting in `place`, counting from one
+    pub fn points_for(&self, place: usize) -> usize {
+        self.points.get(place - 1).copied().unwrap_or(0)
+    }
+
+    fn validate(&self) -> Result<(), SeasonCalendarLoaderError> {
+        let invalid = |reason: String| Err(SeasonCalendarLoaderError::Invalid(reason));
+        if self.rounds.is_empty() {
+            return invalid("a season needs at least one round".to_string());
+        }
+        for (index, round) in self.rounds.iter().enumerate() {
+            if !TRACK_FILES.contains(&round.track.as_str()) {
+                return invalid(format!(
+                    "round {} is on a track that isn't available: {}",
+                    index + 1,
+                    round.track
+                ));
+            }
+            if !MEETING_FILES.contains(&round.meeting.as_str()) {
+                return invalid(format!(
+                    "round {} is run to a meeting programme that isn't available: {}",
+                    index + 1,
+                    round.meeting
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SeasonCalendarLoaderError {
+    #[error("could not read season calendar: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse season calendar: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("invalid season calendar: {0}")]
+    Invalid(String),
+}
+
+#[derive(Default)]
+pub struct SeasonCalendarLoader;
+
+impl AssetLoader for SeasonCalendarLoader {
+    type Asset = SeasonCalendar;
+    type Settings = ();
+    type Error = SeasonCalendarLoaderError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        SeasonCalendar::from_ron(&bytes)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["season.ron"]
+    }
+}
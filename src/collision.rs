@@ -4,27 +4,43 @@ use bevy::{
     math::bounding::{BoundingCircle, IntersectsVolume},
     prelude::*,
 };
-
-use crate::{bike::Bike, loading::IconTextures, RacingState};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bike::{Bike, Crashed},
+    game::Rider,
+    loading::IconTextures,
+    simulation::TickSet,
+    RacingState,
+};
 
 pub struct CollisionPlugin;
 
 impl Plugin for CollisionPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<CollisionEvent>().add_systems(
+            FixedUpdate,
+            (check_for_bike_collisions, remove_collisions)
+                .chain()
+                .in_set(TickSet::Collide),
+        );
+    }
+}
+
+/// Shows where collisions happen, separately from the rules in `CollisionPlugin`
+pub struct CollisionIndicatorPlugin;
+
+impl Plugin for CollisionIndicatorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
             Update,
-            (
-                check_for_bike_collisions,
-                remove_collisions,
-                on_event_collision,
-                update_collision_indicator,
-            )
+            (on_event_collision, update_collision_indicator)
                 .run_if(in_state(RacingState::Simulating)),
         );
     }
 }
 
-#[derive(Component, Debug, Clone, Copy)]
+#[derive(Component, Serialize, Deserialize, Debug, Clone, Copy)]
 pub struct Collider {
     half_size: Vec2,
 }
@@ -44,7 +60,7 @@ pub struct Collision {
     pub other_bike_speed: f32,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CollisionSide {
     Front,
     Left,
@@ -52,11 +68,12 @@ pub enum CollisionSide {
     Back,
 }
 
+/// Sent when a bike starts touching another one
 #[derive(Event, Debug, Clone, Copy)]
-struct CollisionEvent {
+pub struct CollisionEvent {
     position: Vec2,
     rotation: Quat,
-    bike_entity: Entity,
+    pub bike_entity: Entity,
 }
 
 #[derive(Component)]
@@ -64,13 +81,30 @@ struct CollisionIndicator {
     timer: Timer,
 }
 
+/// Riders who are down, sliding along the track, are left out: nobody runs into them
 fn check_for_bike_collisions(
-    q_colliders: Query<(Entity, &Collider, &Bike, &Transform, Option<&Collision>)>,
+    q_colliders: Query<
+        (
+            Entity,
+            &Collider,
+            &Bike,
+            &Transform,
+            Option<&Collision>,
+            &Rider,
+        ),
+        Without<Crashed>,
+    >,
     mut commands: Commands,
     mut collision_event: EventWriter<CollisionEvent>,
 ) {
-    for (entity, collider, bike, transform, maybe_collision) in &q_colliders {
-        for (other_entity, other_collider, other_bike, other_transform, _) in &q_colliders {
+    // in order of rider number, so a bike touching several others always ends up
+    // colliding with the same one
+    let mut colliders: Vec<_> = q_colliders.iter().collect();
+    colliders.sort_by_key(|(.., rider)| rider.number());
+    for (entity, collider, bike, transform, maybe_collision, _) in colliders.iter().copied() {
+        for (other_entity, other_collider, other_bike, other_transform, ..) in
+            colliders.iter().copied()
+        {
             if entity != other_entity {
                 let collision_exists =
                     find_collision(transform, collider, other_transform, other_collider);
@@ -108,7 +142,7 @@ fn check_for_bike_collisions(
                         rotation: event_rotation,
                         bike_entity: entity,
                     });
-                    println!("Add collision with bike {entity} and {other_entity} on side {collision_side:?}");
+                    debug!("Add collision with bike {entity} and {other_entity} on side {collision_side:?}");
                     commands.entity(entity).insert(Collision {
                         other_entity,
                         side: collision_side,
@@ -122,16 +156,19 @@ fn check_for_bike_collisions(
 
 fn remove_collisions(
     q_collisions: Query<(Entity, &Transform, &Collider, &Collision)>,
-    q_bike_transform: Query<(&Transform, &Collider), With<Bike>>,
+    q_bike_transform: Query<(&Transform, &Collider), (With<Bike>, Without<Crashed>)>,
     mut commands: Commands,
 ) {
     let mut collisions_to_remove = Vec::new();
     for (entity, transform, collider, collision) in q_collisions.iter() {
-        if let Ok((other_transform, other_collider)) = q_bike_transform.get(collision.other_entity)
-        {
-            if !find_collision(transform, collider, other_transform, other_collider) {
-                collisions_to_remove.push(entity);
+        match q_bike_transform.get(collision.other_entity) {
+            Ok((other_transform, other_collider)) => {
+                if !find_collision(transform, collider, other_transform, other_collider) {
+                    collisions_to_remove.push(entity);
+                }
             }
+            // the other bike has been taken off the track, or has fallen
+            Err(_) => collisions_to_remove.push(entity),
         }
     }
     for entity in collisions_to_remove {
@@ -0,0 +1,64 @@
+use std::io::ErrorKind;
+
+use bevy::prelude::*;
+use ron::extensions::Extensions;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{bot::BotCommand, opponent::Difficulty};
+
+/// Optional settings read from the working directory at startup
+const CONFIG_FILE: &str = "config.ron";
+
+/// Settings from the config file, which every feature takes its own fields from. Any
+/// of them can be left out.
+#[derive(Resource, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    /// Seed for every race, unless one is given on the command line
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Difficulty of each opponent on the grid, counting from the inside lane
+    #[serde(default)]
+    pub opponents: Vec<Difficulty>,
+    /// Where network races are hosted, unless given on the command line
+    #[serde(default)]
+    pub address: Option<String>,
+    /// Bots that race the first opponents on the grid
+    #[serde(default)]
+    pub bots: Vec<BotCommand>,
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("could not read {CONFIG_FILE}: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse {CONFIG_FILE}: {0}")]
+    Parse(#[from] ron::error::SpannedError),
+}
+
+impl Config {
+    /// Reads the config file, where having none is the same as an empty one. Optional
+    /// settings are written as they are, such as `seed: 5`, without `Some`.
+    pub fn read() -> Result<Self, ConfigError> {
+        match std::fs::read(CONFIG_FILE) {
+            Ok(bytes) => Ok(ron::Options::default()
+                .with_default_extension(Extensions::IMPLICIT_SOME)
+                .from_bytes(&bytes)?),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// The config of the app, which is read the first time a plugin asks for it. A
+    /// file that can't be read is ignored.
+    pub fn of(app: &mut App) -> &Config {
+        app.world_mut()
+            .get_resource_or_insert_with(|| {
+                Self::read().unwrap_or_else(|error| {
+                    warn!("Ignoring the config file: {error}");
+                    Self::default()
+                })
+            })
+            .into_inner()
+    }
+}
@@ -1,5 +1,6 @@
 mod buttons;
 mod mouse;
+mod watch;
 
 use std::f32::consts::FRAC_PI_2;
 
@@ -10,7 +11,7 @@ use crate::{
     bike::Bike,
     collision::Collision,
     loading::IconTextures,
-    player::Player,
+    player::ActivePlayer,
     track::{TrackLane, TrackLanes},
     RacingState,
 };
@@ -18,6 +19,7 @@ use crate::{
 use self::{
     buttons::{make_button, ActionButton, ActionButtonsPlugin, ButtonRowPositions},
     mouse::MousePlugin,
+    watch::WatchPlugin,
 };
 
 const BIKE_TO_BUTTON_SPACING: f32 = 150.0;
@@ -29,18 +31,28 @@ impl Plugin for ControlsPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(ActionButtonsPlugin)
             .add_plugins(MousePlugin)
-            .add_systems(OnEnter(RacingState::Commanding), on_enter_commanding_state)
+            .add_plugins(WatchPlugin)
+            .add_systems(
+                Update,
+                show_action_buttons.run_if(in_state(RacingState::Commanding)),
+            )
             .add_systems(OnEnter(RacingState::Simulating), on_enter_simulating_state);
     }
 }
 
-fn on_enter_commanding_state(
+/// Puts the buttons for choosing an action in front of the player whose turn it is
+fn show_action_buttons(
     mut commands: Commands,
-    q_player_bike: Query<(&Bike, Option<&Collision>), With<Player>>,
+    q_player_bike: Query<(&Bike, Option<&Collision>), Added<ActivePlayer>>,
+    q_buttons: Query<Entity, With<ActionButton>>,
     icon_textures: Res<IconTextures>,
     track_lanes: Res<TrackLanes>,
 ) {
     for (bike, maybe_collision) in q_player_bike.iter() {
+        // the last player's buttons are cleared away, so their choice stays hidden
+        for entity in &q_buttons {
+            commands.entity(entity).despawn_recursive();
+        }
         let bike_distance = bike.distance;
         let track_lane = track_lanes.track_lane(&bike.current_lane_id);
         let row_0 = button_row_positions(bike_distance, track_lane, 0);
@@ -49,21 +61,21 @@ fn on_enter_commanding_state(
             row_0.left,
             row_0.rotation,
             &icon_textures,
-            BikeAction::LeftHip.can_do(bike, maybe_collision),
+            BikeAction::LeftHip.can_do(bike, maybe_collision, &track_lanes),
         ));
         commands.spawn(make_button(
             BikeAction::Stop,
             row_0.middle,
             row_0.rotation,
             &icon_textures,
-            BikeAction::Stop.can_do(bike, maybe_collision),
+            BikeAction::Stop.can_do(bike, maybe_collision, &track_lanes),
         ));
         commands.spawn(make_button(
             BikeAction::RightHip,
             row_0.right,
             row_0.rotation,
             &icon_textures,
-            BikeAction::RightHip.can_do(bike, maybe_collision),
+            BikeAction::RightHip.can_do(bike, maybe_collision, &track_lanes),
         ));
 
         let row_1 = button_row_positions(bike_distance, track_lane, 1);
@@ -72,21 +84,21 @@ fn on_enter_commanding_state(
             row_1.left,
             row_1.rotation,
             &icon_textures,
-            BikeAction::LeftElbow.can_do(bike, maybe_collision),
+            BikeAction::LeftElbow.can_do(bike, maybe_collision, &track_lanes),
         ));
         commands.spawn(make_button(
             BikeAction::Skid,
             row_1.middle,
             row_1.rotation,
             &icon_textures,
-            BikeAction::Skid.can_do(bike, maybe_collision),
+            BikeAction::Skid.can_do(bike, maybe_collision, &track_lanes),
         ));
         commands.spawn(make_button(
             BikeAction::RightElbow,
             row_1.right,
             row_1.rotation,
             &icon_textures,
-            BikeAction::RightElbow.can_do(bike, maybe_collision),
+            BikeAction::RightElbow.can_do(bike, maybe_collision, &track_lanes),
         ));
 
         let row_2 = button_row_positions(bike_distance, track_lane, 2);
@@ -95,21 +107,21 @@ fn on_enter_commanding_state(
             row_2.left,
             row_2.rotation,
             &icon_textures,
-            BikeAction::LeftLeft.can_do(bike, maybe_collision),
+            BikeAction::LeftLeft.can_do(bike, maybe_collision, &track_lanes),
         ));
         commands.spawn(make_button(
             BikeAction::Watch,
             row_2.middle,
             row_2.rotation,
             &icon_textures,
-            BikeAction::Watch.can_do(bike, maybe_collision),
+            BikeAction::Watch.can_do(bike, maybe_collision, &track_lanes),
         ));
         commands.spawn(make_button(
             BikeAction::RightRight,
             row_2.right,
             row_2.rotation,
             &icon_textures,
-            BikeAction::RightRight.can_do(bike, maybe_collision),
+            BikeAction::RightRight.can_do(bike, maybe_collision, &track_lanes),
         ));
 
         let row_3 = button_row_positions(bike_distance, track_lane, 3);
@@ -118,21 +130,21 @@ fn on_enter_commanding_state(
             row_3.left,
             row_3.rotation,
             &icon_textures,
-            BikeAction::Left.can_do(bike, maybe_collision),
+            BikeAction::Left.can_do(bike, maybe_collision, &track_lanes),
         ));
         commands.spawn(make_button(
             BikeAction::Accelerate,
             row_3.middle,
             row_3.rotation,
             &icon_textures,
-            BikeAction::Accelerate.can_do(bike, maybe_collision),
+            BikeAction::Accelerate.can_do(bike, maybe_collision, &track_lanes),
         ));
         commands.spawn(make_button(
             BikeAction::Right,
             row_3.right,
             row_3.rotation,
             &icon_textures,
-            BikeAction::Right.can_do(bike, maybe_collision),
+            BikeAction::Right.can_do(bike, maybe_collision, &track_lanes),
         ));
     }
 }
@@ -3,7 +3,7 @@ use bevy::prelude::*;
 use crate::{
     actions::{ActionEvent, BikeAction},
     loading::IconTextures,
-    player::Player,
+    player::ActivePlayer,
     RacingState,
 };
 
@@ -87,7 +87,7 @@ fn on_mouse_clicked(
     mut action_event: EventWriter<ActionEvent>,
     q_buttons: Query<&BikeAction, With<MouseOver>>,
     buttons: Res<ButtonInput<MouseButton>>,
-    q_player: Query<Entity, With<Player>>,
+    q_player: Query<Entity, With<ActivePlayer>>,
 ) {
     if buttons.just_released(MouseButton::Left) {
         for action_kind in &q_buttons {
@@ -130,20 +130,7 @@ pub fn make_button(
     icon_textures: &IconTextures,
     enabled: bool,
 ) -> ActionButtonBundle {
-    let texture = match action_kind {
-        BikeAction::Accelerate => icon_textures.accelerate.clone(),
-        BikeAction::Watch => icon_textures.watch.clone(),
-        BikeAction::Skid => icon_textures.skid.clone(),
-        BikeAction::Stop => icon_textures.stop.clone(),
-        BikeAction::Left => icon_textures.left.clone(),
-        BikeAction::LeftLeft => icon_textures.left_left.clone(),
-        BikeAction::LeftElbow => icon_textures.left_elbow.clone(),
-        BikeAction::LeftHip => icon_textures.left_hip.clone(),
-        BikeAction::Right => icon_textures.right.clone(),
-        BikeAction::RightRight => icon_textures.right_right.clone(),
-        BikeAction::RightElbow => icon_textures.right_elbow.clone(),
-        BikeAction::RightHip => icon_textures.right_hip.clone(),
-    };
+    let texture = icon_textures.for_action(action_kind);
     let sprite_alpha = if enabled { 1.0 } else { 0.3 };
     ActionButtonBundle {
         action_button: ActionButton { enabled },
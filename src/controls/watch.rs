@@ -0,0 +1,72 @@
+use bevy::prelude::*;
+
+use crate::{
+    actions::{BikeAction, Watching},
+    loading::IconTextures,
+    player::{ActivePlayer, Player},
+    RacingState,
+};
+
+const REVEALED_ACTION_SCALE: f32 = 0.6;
+
+pub struct WatchPlugin;
+
+impl Plugin for WatchPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            show_revealed_actions.run_if(in_state(RacingState::Commanding)),
+        )
+        .add_systems(OnEnter(RacingState::Simulating), hide_revealed_actions);
+    }
+}
+
+/// Icon above another rider's bike showing the action they chose
+#[derive(Component)]
+struct RevealedAction;
+
+/// Shows the player whose turn it is what the computer riders chose, if they are
+/// watching. Other players' choices are never shown.
+fn show_revealed_actions(
+    q_player: Query<Has<Watching>, With<ActivePlayer>>,
+    q_chosen_actions: Query<(Entity, &BikeAction), Without<Player>>,
+    q_revealed_actions: Query<Entity, With<RevealedAction>>,
+    icon_textures: Res<IconTextures>,
+    mut commands: Commands,
+) {
+    let watching = q_player.get_single().unwrap_or(false);
+    if !watching {
+        for entity in &q_revealed_actions {
+            commands.entity(entity).despawn_recursive();
+        }
+        return;
+    }
+    if !q_revealed_actions.is_empty() {
+        return;
+    }
+    for (entity, action) in &q_chosen_actions {
+        commands
+            .spawn((
+                RevealedAction,
+                SpriteBundle {
+                    transform: Transform {
+                        translation: Vec3::new(0.0, 0.0, 3.0),
+                        scale: Vec3::splat(REVEALED_ACTION_SCALE),
+                        ..default()
+                    },
+                    texture: icon_textures.for_action(*action),
+                    ..default()
+                },
+            ))
+            .set_parent(entity);
+    }
+}
+
+fn hide_revealed_actions(
+    q_revealed_actions: Query<Entity, With<RevealedAction>>,
+    mut commands: Commands,
+) {
+    for entity in &q_revealed_actions {
+        commands.entity(entity).despawn_recursive();
+    }
+}
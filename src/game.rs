@@ -1,88 +1,244 @@
 mod finish_race;
+mod result;
 
-use bevy::{prelude::*, time::Stopwatch};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    bike::Bike,
+    bike::{Bike, CrashCause, HelmetColour},
+    bot::{BotCommand, BotCommands, ExternalBot},
     collision::Collider,
     hud::HudPlugin,
-    loading::{BikeTextures, TrackTexture},
-    opponent::Opponent,
-    player::Player,
-    random::Randomness,
-    track::{Track, TrackLaneId, TrackLanes, LAPS},
-    GameState, PlayingState, RacingState,
+    loading::{BikeTextures, RiderAssets, TrackAssets},
+    meeting::Meeting,
+    opponent::{Difficulty, Opponent, OpponentDifficulties},
+    player::{Player, PlayerCount},
+    profile::RiderProfile,
+    random::{seed_race, Randomness},
+    simulation::{TickSet, TurnPhaseSet},
+    team::Team,
+    track::{
+        setup_track_lanes, spawn_track_surface, SelectedTrack, Track, TrackDefinition, TrackLaneId,
+        TrackLanes,
+    },
+    GameState, InRace, PlayingState, RacingState,
 };
 
 use self::finish_race::FinishRacePlugin;
+pub use self::result::{compare_standing, Exclusion, RaceOutcome, RaceResult};
 
 pub struct GamePlugin;
 
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<TurnTimer>()
-            .add_plugins(HudPlugin)
+        app.add_plugins(HudPlugin)
             .add_plugins(FinishRacePlugin)
-            .add_event::<LapEvent>()
             .add_systems(
                 OnEnter(PlayingState::SetupRace),
-                (setup_track, setup_bikes).before(set_playing_state),
+                draw_starting_grid
+                    .after(seed_race)
+                    .run_if(in_state(GameState::Playing).and_then(not(resource_exists::<Meeting>))),
+            )
+            .add_systems(
+                OnEnter(PlayingState::SetupRace),
+                (setup_track, setup_bikes.after(draw_starting_grid))
+                    .after(teardown)
+                    .after(setup_track_lanes)
+                    .before(set_playing_state),
             )
+            .add_systems(OnEnter(PlayingState::SetupRace), setup_track_lanes)
             .add_systems(OnEnter(PlayingState::SetupRace), set_playing_state)
+            // a race that is restarted is cleared away before it is set up again
+            .add_systems(OnEnter(PlayingState::SetupRace), teardown)
+            .add_systems(OnExit(InRace), teardown)
+            .add_systems(OnEnter(RacingState::Commanding), hide_finished_riders)
+            // riders excluded at the start wheel away before the race is called again
             .add_systems(
                 Update,
-                (tick_turn_timer, update_laps, update_player_position)
-                    .run_if(in_state(RacingState::Simulating)),
+                hide_finished_riders.run_if(in_state(RacingState::Tapes)),
+            );
+    }
+}
+
+/// Turns, laps and finishing positions, without anything to draw them
+pub struct RaceRulesPlugin;
+
+impl Plugin for RaceRulesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TurnTimer>()
+            .insert_resource(Time::<Fixed>::from_seconds(TICK_SECONDS as f64))
+            .add_systems(
+                FixedUpdate,
+                (
+                    (update_laps, (end_race, update_player_position)).chain(),
+                    tick_turn_timer,
+                )
+                    .chain()
+                    .in_set(TickSet::Score),
             )
-            .add_systems(OnEnter(RacingState::Simulating), reset_timer)
-            .add_systems(OnExit(GameState::Playing), teardown);
+            .add_systems(
+                OnEnter(RacingState::Simulating),
+                reset_timer.in_set(TurnPhaseSet),
+            )
+            .add_systems(OnEnter(PlayingState::SetupRace), reset_race_clock)
+            .add_systems(OnEnter(PlayingState::FinishRace), record_race_result);
     }
 }
 
-#[derive(Component)]
-struct Rider {
+#[derive(Component, Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Rider {
+    /// Lane the rider started the race in, which tells riders apart in replays
+    number: usize,
+    #[serde(default)]
+    name: String,
     laps: usize,
+    crashes: Vec<CrashCause>,
+    /// Crashed out of the race
+    retired: bool,
+    /// Race time, in turns, at which the rider crossed the line at the end of each lap
+    #[serde(default)]
+    lap_times: Vec<f32>,
+    /// Race time, in turns, at which the rider crossed the line at the end of the race
+    #[serde(default)]
+    finish_time: Option<f32>,
+    #[serde(default)]
+    exclusion: Option<Exclusion>,
+}
+
+impl Rider {
+    pub fn new(number: usize, name: &str) -> Self {
+        Self {
+            number,
+            name: name.to_string(),
+            ..default()
+        }
+    }
+
+    pub fn number(&self) -> usize {
+        self.number
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn laps(&self) -> usize {
+        self.laps
+    }
+
+    pub fn record_crash(&mut self, cause: CrashCause, retired: bool) {
+        self.crashes.push(cause);
+        self.retired |= retired;
+    }
+
+    pub fn finish_time(&self) -> Option<f32> {
+        self.finish_time
+    }
+
+    pub fn exclude(&mut self, exclusion: Exclusion) {
+        self.exclusion = Some(exclusion);
+    }
+
+    pub fn is_excluded(&self) -> bool {
+        self.exclusion.is_some()
+    }
+
+    /// Still out on track, neither finished nor out of the race
+    pub fn is_racing(&self) -> bool {
+        self.finish_time.is_none() && !self.retired && self.exclusion.is_none()
+    }
+
+    /// Turns taken over the quickest lap completed
+    pub fn best_lap(&self) -> Option<f32> {
+        self.lap_times
+            .iter()
+            .scan(0.0, |lap_start, &lap_end| {
+                let lap = lap_end - *lap_start;
+                *lap_start = lap_end;
+                Some(lap)
+            })
+            .reduce(f32::min)
+    }
+}
+
+/// Marks a rider who has crossed the line on their last lap, or was excluded at the
+/// start, who pulls off the track out of the way of the riders still racing
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Finished;
+
+/// Who starts a race in one of the lanes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RiderSetup {
+    pub lane: usize,
+    pub player: bool,
+    /// How well the computer races the rider, when it isn't the player
+    #[serde(default)]
+    pub difficulty: Difficulty,
+    /// Kept whole, so the race can be replayed however the profile files change
+    #[serde(default)]
+    pub profile: RiderProfile,
+    /// Bot racing the rider in place of the computer
+    #[serde(default)]
+    pub bot: Option<BotCommand>,
+    /// Side the rider races for in a team match
+    #[serde(default)]
+    pub team: Option<Team>,
 }
 
-#[derive(Event)]
-pub struct LapEvent(pub usize);
+/// The riders lined up for the race that is being set up
+#[derive(Resource, Debug, Clone, Default)]
+pub struct StartingGrid(pub Vec<RiderSetup>);
+
+/// Simulation steps in every turn, whatever the frame rate
+pub const TICKS_PER_TURN: u32 = 64;
+/// Race time covered by one simulation step, in seconds
+pub const TICK_SECONDS: f32 = 1.0 / TICKS_PER_TURN as f32;
+/// Turns the riders still racing have to finish after the winner, before they are
+/// excluded
+const FINISH_TIME_LIMIT: f32 = 10.0;
 
-#[derive(Resource)]
+/// Counts the simulation steps of the turn being simulated
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, Copy, Default)]
 pub struct TurnTimer {
-    timer: Timer,
-    stopwatch: Stopwatch,
+    ticks: u32,
+    /// Turns completed since the race started
+    #[serde(default)]
+    turns: u32,
 }
 
 impl TurnTimer {
+    /// Proportion of the turn that will have been simulated by the end of the current step
     pub fn proportion_finished(&self) -> f32 {
-        self.stopwatch.elapsed_secs() / self.timer.duration().as_secs_f32()
+        (self.ticks + 1).min(TICKS_PER_TURN) as f32 / TICKS_PER_TURN as f32
     }
-}
 
-impl Default for TurnTimer {
-    fn default() -> Self {
-        Self {
-            timer: Timer::from_seconds(1.0, TimerMode::Once),
-            stopwatch: Stopwatch::new(),
-        }
+    pub fn in_progress(&self) -> bool {
+        self.ticks < TICKS_PER_TURN
+    }
+
+    /// Race time, in turns, by the end of the current step
+    pub fn race_time(&self) -> f32 {
+        self.turns as f32 + self.proportion_finished()
     }
 }
 
 fn tick_turn_timer(
     mut turn_timer: ResMut<TurnTimer>,
-    time: Res<Time>,
     mut next_state: ResMut<NextState<RacingState>>,
 ) {
-    turn_timer.timer.tick(time.delta());
-    turn_timer.stopwatch.tick(time.delta());
-    if turn_timer.timer.finished() {
+    turn_timer.ticks += 1;
+    if !turn_timer.in_progress() {
+        turn_timer.turns += 1;
         next_state.set(RacingState::Commanding);
     }
 }
 
 fn reset_timer(mut turn_timer: ResMut<TurnTimer>) {
-    turn_timer.timer.reset();
-    turn_timer.stopwatch.reset();
+    turn_timer.ticks = 0;
+}
+
+fn reset_race_clock(mut turn_timer: ResMut<TurnTimer>) {
+    turn_timer.turns = 0;
 }
 
 fn teardown(
@@ -99,95 +255,264 @@ fn teardown(
     }
 }
 
-fn setup_track(mut commands: Commands, track_texture: Res<TrackTexture>) {
-    commands.spawn((
-        Track,
-        SpriteBundle {
-            texture: track_texture.default.clone(),
-            ..default()
-        },
-    ));
+fn setup_track(
+    mut commands: Commands,
+    selected_track: Res<SelectedTrack>,
+    track_assets: Res<TrackAssets>,
+    track_definitions: Res<Assets<TrackDefinition>>,
+    track_lanes: Res<TrackLanes>,
+) {
+    let definition = selected_track.definition(&track_assets, &track_definitions);
+    commands
+        .spawn((Track, SpatialBundle::default()))
+        .with_children(|parent| {
+            if let Some(texture) = &definition.texture {
+                parent.spawn(SpriteBundle {
+                    texture: texture.clone(),
+                    transform: Transform::from_xyz(0.0, 0.0, -1.0),
+                    ..default()
+                });
+            }
+            spawn_track_surface(parent, &track_lanes);
+        });
 }
 
-fn setup_bikes(
+/// Puts a rider in every lane, with the players in random ones and opponents drawn
+/// from the rider profiles. Bots race the first opponents from the inside lane.
+pub fn draw_starting_grid(
     mut commands: Commands,
-    bike_textures: Res<BikeTextures>,
     track_lanes: Res<TrackLanes>,
     mut randomness: ResMut<Randomness>,
+    opponent_difficulties: Res<OpponentDifficulties>,
+    bot_commands: Res<BotCommands>,
+    player_count: Res<PlayerCount>,
+    rider_assets: Res<RiderAssets>,
+    rider_profiles: Res<Assets<RiderProfile>>,
 ) {
-    let lanes = [
-        TrackLaneId::First,
-        TrackLaneId::Second,
-        TrackLaneId::Third,
-        TrackLaneId::Fourth,
-    ];
-    let player_lane_index = randomness.rng.usize(..lanes.len());
-    for (index, lane_id) in lanes.iter().enumerate() {
-        let lane = track_lanes.track_lane(lane_id);
-        let bike = Bike::new(lane_id, 1400.0, 0.5, 800.0);
-        let (position, _) = lane.position_and_rotation(bike.distance);
+    let mut free_lanes: Vec<usize> = track_lanes.lane_ids().map(|id| id.index()).collect();
+    let player_count = player_count.0.min(free_lanes.len());
+    let player_lanes: Vec<usize> = (0..player_count)
+        .map(|_| free_lanes.remove(randomness.grid.usize(..free_lanes.len())))
+        .collect();
+    let mut profiles: Vec<&RiderProfile> = rider_assets
+        .profiles
+        .iter()
+        .filter_map(|handle| rider_profiles.get(handle))
+        .collect();
+    randomness.grid.shuffle(&mut profiles);
+    let riders = track_lanes
+        .lane_ids()
+        .map(|lane_id| {
+            let lane = lane_id.index();
+            // players and opponents are each counted from the inside lane
+            let players_inside = player_lanes.iter().filter(|index| **index < lane).count();
+            let opponent_index = lane - players_inside;
+            let player = player_lanes.contains(&lane);
+            // profiles are only shared out again when there are more opponents than them
+            let mut profile = if player {
+                RiderProfile::player(players_inside, player_count)
+            } else if profiles.is_empty() {
+                RiderProfile::default()
+            } else {
+                profiles[opponent_index % profiles.len()].clone()
+            };
+            let bot = if player {
+                None
+            } else {
+                bot_commands.0.get(opponent_index).cloned()
+            };
+            if let Some(bot) = &bot {
+                profile.name.clone_from(&bot.name);
+            }
+            RiderSetup {
+                lane,
+                player,
+                difficulty: opponent_difficulties.for_opponent(opponent_index),
+                profile,
+                bot,
+                team: None,
+            }
+        })
+        .collect();
+    commands.insert_resource(StartingGrid(riders));
+}
+
+pub fn setup_bikes(
+    mut commands: Commands,
+    bike_textures: Res<BikeTextures>,
+    track_lanes: Res<TrackLanes>,
+    starting_grid: Res<StartingGrid>,
+) {
+    let rider_count = starting_grid.0.len();
+    for rider in &starting_grid.0 {
+        let Some(lane_id) = track_lanes.lane_id(rider.lane) else {
+            continue;
+        };
+        let helmet = match rider.team {
+            // teammates wear their team's colours, in gate order
+            Some(team) => {
+                let teammates_inside = starting_grid
+                    .0
+                    .iter()
+                    .filter(|other| other.team == Some(team) && other.lane < rider.lane)
+                    .count();
+                let colours = team.helmet_colours();
+                colours[teammates_inside % colours.len()]
+            }
+            None => HelmetColour::for_gate(rider.lane),
+        };
         let entity = commands
-            .spawn((
-                bike,
-                Rider { laps: 0 },
-                SpriteBundle {
-                    texture: bike_textures.straight.clone(),
-                    transform: Transform {
-                        translation: position.extend(5.0),
-                        ..default()
-                    },
-                    ..default()
-                },
-                Collider::new(120.0, 60.0),
-            ))
+            .spawn(SpriteBundle {
+                texture: bike_textures.straight(helmet),
+                ..default()
+            })
+            .insert((rider_bundle(&lane_id, &rider.profile, &track_lanes), helmet))
             .id();
-        if player_lane_index == index {
-            commands.entity(entity).insert(Player::new());
+        if let Some(team) = rider.team {
+            commands.entity(entity).insert(team);
+        }
+        if rider.player {
+            commands.entity(entity).insert(Player::new(rider_count));
+        } else if let Some(bot) = &rider.bot {
+            commands.entity(entity).insert(ExternalBot(bot.clone()));
         } else {
-            commands.entity(entity).insert(Opponent);
+            commands.entity(entity).insert(Opponent {
+                difficulty: rider.difficulty,
+                traits: rider.profile.traits,
+            });
         };
     }
 }
 
+/// The components the race rules need for the rider of `profile` starting in `lane_id`
+pub fn rider_bundle(
+    lane_id: &TrackLaneId,
+    profile: &RiderProfile,
+    track_lanes: &TrackLanes,
+) -> (Bike, Rider, Collider, Transform) {
+    let bike = profile.bike(lane_id);
+    let (position, _) = track_lanes
+        .track_lane(lane_id)
+        .position_and_rotation(bike.distance);
+    (
+        bike,
+        Rider::new(lane_id.index(), &profile.name),
+        Collider::new(120.0, 60.0),
+        Transform::from_translation(position.extend(5.0)),
+    )
+}
+
 fn set_playing_state(mut next_state: ResMut<NextState<PlayingState>>) {
     next_state.set(PlayingState::Racing);
 }
 
+/// Counts the laps every rider completes, and takes riders off the track once they
+/// finish
 fn update_laps(
-    mut q_riders: Query<(&mut Rider, &Bike)>,
+    mut commands: Commands,
+    mut q_riders: Query<(Entity, &mut Rider, &Bike)>,
     track_lanes: Res<TrackLanes>,
-    mut lap_event: EventWriter<LapEvent>,
+    turn_timer: Res<TurnTimer>,
+) {
+    for (entity, mut rider, bike) in q_riders.iter_mut() {
+        if !rider.is_racing() {
+            continue;
+        }
+        let current_lap = track_lanes
+            .race_progress(&bike.current_lane_id, bike.distance)
+            .floor() as usize;
+        while rider.laps < current_lap {
+            rider.laps += 1;
+            let time = crossing_time(bike, rider.laps, &track_lanes, &turn_timer);
+            rider.lap_times.push(time);
+        }
+        if rider.laps >= track_lanes.laps {
+            rider.finish_time = rider.lap_times.last().copied();
+            commands
+                .entity(entity)
+                .insert(Finished)
+                .remove::<Collider>();
+        }
+    }
+}
+
+/// Race time at which the bike crossed the line at the end of `lap`, worked back from
+/// how far past the line it has got by the end of the step
+fn crossing_time(bike: &Bike, lap: usize, track_lanes: &TrackLanes, turn_timer: &TurnTimer) -> f32 {
+    let past_line =
+        bike.distance - track_lanes.distance_at_race_progress(&bike.current_lane_id, lap as f32);
+    let seconds_past_line = if bike.speed > 0.0 {
+        (past_line / bike.speed).clamp(0.0, TICK_SECONDS)
+    } else {
+        0.0
+    };
+    turn_timer.race_time() - seconds_past_line / (TICK_SECONDS * TICKS_PER_TURN as f32)
+}
+
+/// Excludes the riders still racing once they have taken too long to follow the winner
+/// home, and ends the race when nobody is left racing
+fn end_race(
+    mut q_riders: Query<&mut Rider>,
+    turn_timer: Res<TurnTimer>,
     mut next_state: ResMut<NextState<PlayingState>>,
 ) {
-    for (mut rider, bike) in q_riders.iter_mut() {
-        let lane = track_lanes.track_lane(&bike.current_lane_id);
-        let current_lap = lane.laps_finished(bike.distance);
-        if rider.laps != current_lap {
-            rider.laps = current_lap;
-            lap_event.send(LapEvent(current_lap));
-            if current_lap >= LAPS {
-                next_state.set(PlayingState::FinishRace);
+    let winning_time = q_riders
+        .iter()
+        .filter_map(|rider| rider.finish_time)
+        .reduce(f32::min);
+    if winning_time.is_some_and(|time| turn_timer.race_time() > time + FINISH_TIME_LIMIT) {
+        for mut rider in q_riders.iter_mut() {
+            if rider.is_racing() {
+                rider.exclude(Exclusion::TimeLimit);
             }
         }
     }
+    if !q_riders.is_empty() && q_riders.iter().all(|rider| !rider.is_racing()) {
+        next_state.set(PlayingState::FinishRace);
+    }
 }
 
 fn update_player_position(
-    q_opponents: Query<&Bike, With<Opponent>>,
-    mut q_player: Query<(&Bike, &mut Player)>,
+    q_riders: Query<(Entity, &Bike, &Rider)>,
+    mut q_players: Query<(Entity, &mut Player)>,
+    track_lanes: Res<TrackLanes>,
 ) {
-    if let Ok((player_bike, mut player)) = q_player.get_single_mut() {
-        let player_distance = player_bike.distance;
-        let mut opponent_distances = Vec::new();
-        for opponent_bike in &q_opponents {
-            opponent_distances.push(opponent_bike.distance);
-        }
-        let mut player_pos = 4;
-        for opponent_distance in opponent_distances {
-            if player_distance > opponent_distance {
-                player_pos -= 1;
-            }
+    let mut standings: Vec<(Entity, &Rider, f32)> = q_riders
+        .iter()
+        .map(|(entity, bike, rider)| {
+            let progress = track_lanes.race_progress(&bike.current_lane_id, bike.distance);
+            (entity, rider, progress)
+        })
+        .collect();
+    standings.sort_by(|(_, a, a_progress), (_, b, b_progress)| {
+        compare_standing((a, *a_progress), (b, *b_progress))
+    });
+    for (player_entity, mut player) in q_players.iter_mut() {
+        if let Some(index) = standings
+            .iter()
+            .position(|(entity, ..)| *entity == player_entity)
+        {
+            player.position = index + 1;
         }
-        player.position = player_pos;
+    }
+}
+
+/// Writes up the result once everyone has finished or is out of the race
+pub fn record_race_result(
+    mut commands: Commands,
+    q_riders: Query<(&Rider, &Bike)>,
+    track_lanes: Res<TrackLanes>,
+) {
+    let riders = q_riders.iter().map(|(rider, bike)| {
+        let progress = track_lanes.race_progress(&bike.current_lane_id, bike.distance);
+        (rider, progress)
+    });
+    commands.insert_resource(RaceResult::new(riders));
+}
+
+/// Finished riders have ridden off the track by the time the next turn starts
+fn hide_finished_riders(mut q_finished: Query<&mut Visibility, With<Finished>>) {
+    for mut visibility in q_finished.iter_mut() {
+        *visibility = Visibility::Hidden;
     }
 }
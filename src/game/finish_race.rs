@@ -1,6 +1,10 @@
 use bevy::prelude::*;
 
-use crate::{player::Player, GameState, PlayingState};
+use crate::{
+    bike::CrashCause, meeting::Meeting, player::Player, random::Randomness, GameState, PlayingState,
+};
+
+use super::{record_race_result, RaceOutcome, RaceResult, Rider};
 
 const BUTTON_NORMAL_COLOR: Color = Color::srgb(0.15, 0.15, 0.15);
 const BUTTON_HOVERED_COLOR: Color = Color::srgb(0.25, 0.25, 0.25);
@@ -9,17 +13,27 @@ const BUTTON_WIDTH: f32 = 150.0;
 const BUTTON_HEIGHT: f32 = 65.0;
 const BUTTON_FONT_SIZE: f32 = 40.0;
 const BUTTON_FONT_COLOR: Color = Color::srgb(0.9, 0.9, 0.9);
+const PLAYER_COLOR: Color = Color::srgba(0.9, 0.1, 0.1, 0.8);
+const TABLE_FONT_SIZE: f32 = 22.0;
+/// Widths of the columns of the results table: position, rider, time, gap and best lap
+const TABLE_COLUMN_WIDTHS: [f32; 5] = [40.0, 220.0, 230.0, 90.0, 90.0];
 
 pub struct FinishRacePlugin;
 
 impl Plugin for FinishRacePlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(PlayingState::FinishRace), setup_position_display)
-            .add_systems(OnExit(PlayingState::FinishRace), teardown)
-            .add_systems(
-                Update,
-                button_system.run_if(in_state(PlayingState::FinishRace)),
-            );
+        app.add_systems(
+            OnEnter(PlayingState::FinishRace),
+            // riders in a meeting go straight back to the scoreboard
+            setup_position_display
+                .after(record_race_result)
+                .run_if(not(resource_exists::<Meeting>)),
+        )
+        .add_systems(OnExit(PlayingState::FinishRace), teardown)
+        .add_systems(
+            Update,
+            button_system.run_if(in_state(PlayingState::FinishRace)),
+        );
     }
 }
 
@@ -32,61 +46,213 @@ enum ButtonAction {
     Quit,
 }
 
+/// Size of the finishing position when one player raced, and when several did
+const POSITION_FONT_SIZES: (f32, f32) = (100.0, 50.0);
+/// Size of the crash summary when one player raced, and when several did
+const CRASH_SUMMARY_FONT_SIZES: (f32, f32) = (40.0, 25.0);
+
 fn setup_position_display(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
-    q_player: Query<&Player>,
+    q_players: Query<&Rider, With<Player>>,
+    race_result: Res<RaceResult>,
+    randomness: Res<Randomness>,
 ) {
-    if let Ok(player) = q_player.get_single() {
-        let position_text = match player.position {
-            1 => "WINNER",
-            2 => "SECOND",
-            3 => "THIRD",
-            4 => "LAST PLACE",
-            _ => "Wow, terrible!",
-        };
-        let font_handle = asset_server.load("fonts/FiraSans-Bold.ttf");
-        commands
-            .spawn((
-                FinishRaceDisplay,
-                NodeBundle {
-                    style: Style {
-                        width: Val::Percent(100.0),
-                        height: Val::Percent(100.0),
-                        flex_direction: FlexDirection::Column,
-                        align_items: AlignItems::Center,
-                        justify_content: JustifyContent::Center,
-                        ..default()
-                    },
+    // a race with no players, such as one between bots, still shows the results and
+    // the way back to the menu
+    let mut players: Vec<_> = q_players.iter().collect();
+    players.sort_by_key(|rider| rider.number());
+    let several_players = players.len() > 1;
+    let (position_font_size, crash_summary_font_size) = if several_players {
+        (POSITION_FONT_SIZES.1, CRASH_SUMMARY_FONT_SIZES.1)
+    } else {
+        (POSITION_FONT_SIZES.0, CRASH_SUMMARY_FONT_SIZES.0)
+    };
+    let rider_count = race_result.0.len();
+    let player_numbers: Vec<usize> = players.iter().map(|rider| rider.number()).collect();
+    let font_handle = asset_server.load("fonts/FiraSans-Bold.ttf");
+    commands
+        .spawn((
+            FinishRaceDisplay,
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
                     ..default()
                 },
-            ))
-            .with_children(|parent| {
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            for rider in players {
+                let Some(position) = race_result.position(rider.number()) else {
+                    continue;
+                };
+                let outcome = race_result.0[position - 1].outcome;
+                let position_text = position_text(outcome, position, rider_count);
+                let position_text = if several_players {
+                    format!("{}: {position_text}", rider.name())
+                } else {
+                    position_text.to_string()
+                };
                 parent.spawn((
                     FinishRaceDisplay,
                     TextBundle::from_sections([TextSection::new(
                         position_text,
                         TextStyle {
-                            font_size: 100.0,
-                            color: Color::srgba(0.9, 0.1, 0.1, 0.8),
+                            font_size: position_font_size,
+                            color: PLAYER_COLOR,
                             font: font_handle.clone(),
                         },
                     )]),
                 ));
+                if !rider.crashes.is_empty() {
+                    parent.spawn((
+                        FinishRaceDisplay,
+                        TextBundle::from_section(
+                            crash_summary(&rider.crashes),
+                            TextStyle {
+                                font_size: crash_summary_font_size,
+                                color: PLAYER_COLOR,
+                                font: font_handle.clone(),
+                            },
+                        ),
+                    ));
+                }
+            }
+            spawn_results_table(parent, &race_result, &player_numbers, &font_handle);
+            parent.spawn((
+                FinishRaceDisplay,
+                TextBundle::from_section(
+                    format!("Seed: {}", randomness.seed()),
+                    TextStyle {
+                        font_size: 30.0,
+                        color: BUTTON_FONT_COLOR,
+                        font: font_handle.clone(),
+                    },
+                ),
+            ));
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Row,
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent
+                        .spawn((ButtonAction::Menu, make_button()))
+                        .with_children(|parent| {
+                            parent.spawn(make_button_text("Menu", font_handle.clone()));
+                        });
+                    parent
+                        .spawn((ButtonAction::Quit, make_button()))
+                        .with_children(|parent| {
+                            parent.spawn(make_button_text("Quit", font_handle.clone()));
+                        });
+                });
+        });
+}
+
+/// Every rider's result, one to a row, with the rows of the players here picked out
+fn spawn_results_table(
+    parent: &mut ChildBuilder,
+    race_result: &RaceResult,
+    player_numbers: &[usize],
+    font_handle: &Handle<Font>,
+) {
+    let header = [
+        String::new(),
+        "Rider".to_string(),
+        "Time".to_string(),
+        "Gap".to_string(),
+        "Best lap".to_string(),
+    ];
+    let rows = race_result.0.iter().enumerate().map(|(index, result)| {
+        let cells = [
+            (index + 1).to_string(),
+            result.name.clone(),
+            result.time_text(),
+            result.gap_text(),
+            result.best_lap_text(),
+        ];
+        let color = if player_numbers.contains(&result.number) {
+            PLAYER_COLOR
+        } else {
+            BUTTON_FONT_COLOR
+        };
+        (cells, color)
+    });
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Column,
+                margin: UiRect::vertical(Val::Px(10.0)),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            for (cells, color) in std::iter::once((header, BUTTON_FONT_COLOR)).chain(rows) {
                 parent
-                    .spawn((ButtonAction::Menu, make_button()))
-                    .with_children(|parent| {
-                        parent.spawn(make_button_text("Menu", font_handle.clone()));
-                    });
-                parent
-                    .spawn((ButtonAction::Quit, make_button()))
+                    .spawn(NodeBundle {
+                        style: Style {
+                            flex_direction: FlexDirection::Row,
+                            ..default()
+                        },
+                        ..default()
+                    })
                     .with_children(|parent| {
-                        parent.spawn(make_button_text("Quit", font_handle.clone()));
+                        for (cell, width) in cells.into_iter().zip(TABLE_COLUMN_WIDTHS) {
+                            parent.spawn(
+                                TextBundle::from_section(
+                                    cell,
+                                    TextStyle {
+                                        font_size: TABLE_FONT_SIZE,
+                                        color,
+                                        font: font_handle.clone(),
+                                    },
+                                )
+                                .with_style(Style {
+                                    width: Val::Px(width),
+                                    ..default()
+                                }),
+                            );
+                        }
                     });
-            });
+            }
+        });
+}
+
+fn position_text(outcome: RaceOutcome, position: usize, rider_count: usize) -> &'static str {
+    match position {
+        _ if matches!(outcome, RaceOutcome::Retired(_)) => "CRASHED OUT",
+        _ if matches!(outcome, RaceOutcome::Excluded(_)) => "EXCLUDED",
+        1 => "WINNER",
+        2 => "SECOND",
+        3 => "THIRD",
+        position if position == rider_count => "LAST PLACE",
+        4 => "FOURTH",
+        5 => "FIFTH",
+        6 => "SIXTH",
+        7 => "SEVENTH",
+        _ => "Wow, terrible!",
     }
 }
 
+fn crash_summary(crashes: &[CrashCause]) -> String {
+    let slips = crashes
+        .iter()
+        .filter(|cause| **cause == CrashCause::Slip)
+        .count();
+    let collisions = crashes.len() - slips;
+    format!("Crashes: {collisions} from collisions, {slips} from slipping")
+}
+
 fn teardown(mut commands: Commands, q_elements: Query<Entity, With<FinishRaceDisplay>>) {
     for entity in &q_elements {
         commands.entity(entity).despawn_recursive();
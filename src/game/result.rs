@@ -0,0 +1,212 @@
+use std::cmp::Ordering;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::bike::CrashCause;
+
+use super::Rider;
+
+/// Why the referee took a rider out of the race
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exclusion {
+    /// Still racing when the time allowed after the winner finished ran out
+    TimeLimit,
+    /// Went before the tapes were up
+    TouchedTapes,
+}
+
+/// How a rider's race ended
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RaceOutcome {
+    Finished,
+    /// Crashed out of the race
+    Retired(CrashCause),
+    Excluded(Exclusion),
+    /// Still racing when the race was stopped short
+    Running,
+}
+
+impl RaceOutcome {
+    /// Short description for the results table
+    pub fn describe(&self) -> &'static str {
+        match self {
+            RaceOutcome::Finished => "Finished",
+            RaceOutcome::Retired(CrashCause::Collision) => "Retired, collision",
+            RaceOutcome::Retired(CrashCause::Slip) => "Retired, fell on a bend",
+            RaceOutcome::Excluded(Exclusion::TimeLimit) => "Excluded, out of time",
+            RaceOutcome::Excluded(Exclusion::TouchedTapes) => "Excluded, touched the tapes",
+            RaceOutcome::Running => "Still racing",
+        }
+    }
+}
+
+/// How one rider did. Times are race time, in turns.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RiderResult {
+    pub number: usize,
+    pub name: String,
+    pub outcome: RaceOutcome,
+    /// Laps completed
+    pub laps: usize,
+    pub time: Option<f32>,
+    pub best_lap: Option<f32>,
+    /// Time behind the winner
+    pub gap: Option<f32>,
+}
+
+impl RiderResult {
+    /// Race time of a rider who finished, or why they didn't
+    pub fn time_text(&self) -> String {
+        match self.time {
+            Some(time) => format!("{time:.2}"),
+            None => self.outcome.describe().to_string(),
+        }
+    }
+
+    /// Time behind the winner, or laps completed by a rider who didn't finish
+    pub fn gap_text(&self) -> String {
+        match (self.gap, self.laps) {
+            (Some(gap), _) if gap > 0.0 => format!("+{gap:.2}"),
+            (Some(_), _) => String::new(),
+            (None, 1) => "1 lap".to_string(),
+            (None, laps) => format!("{laps} laps"),
+        }
+    }
+
+    pub fn best_lap_text(&self) -> String {
+        match self.best_lap {
+            Some(lap) => format!("{lap:.2}"),
+            None => "-".to_string(),
+        }
+    }
+}
+
+/// Every rider of the last race, in finishing order. Riders who didn't finish follow
+/// the ones who did, in order of how far they got.
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct RaceResult(pub Vec<RiderResult>);
+
+impl RaceResult {
+    /// The result of a race from its riders, with how far through the race each got
+    pub fn new<'a>(riders: impl IntoIterator<Item = (&'a Rider, f32)>) -> Self {
+        let mut riders: Vec<(&Rider, f32)> = riders.into_iter().collect();
+        riders.sort_by(|a, b| compare_standing(*a, *b));
+        let winning_time = riders.first().and_then(|(rider, _)| rider.finish_time);
+        Self(
+            riders
+                .into_iter()
+                .map(|(rider, _)| RiderResult {
+                    number: rider.number,
+                    name: rider.name.clone(),
+                    outcome: outcome(rider),
+                    laps: rider.laps,
+                    time: rider.finish_time,
+                    best_lap: rider.best_lap(),
+                    gap: rider
+                        .finish_time
+                        .zip(winning_time)
+                        .map(|(time, winning_time)| time - winning_time),
+                })
+                .collect(),
+        )
+    }
+
+    /// Finishing position of the rider who started in lane `number`, counting from one
+    pub fn position(&self, number: usize) -> Option<usize> {
+        self.0
+            .iter()
+            .position(|result| result.number == number)
+            .map(|index| index + 1)
+    }
+}
+
+fn outcome(rider: &Rider) -> RaceOutcome {
+    match (rider.finish_time, rider.exclusion, rider.crashes.last()) {
+        (Some(_), ..) => RaceOutcome::Finished,
+        (None, Some(exclusion), _) => RaceOutcome::Excluded(exclusion),
+        (None, None, Some(cause)) if rider.retired => RaceOutcome::Retired(*cause),
+        _ => RaceOutcome::Running,
+    }
+}
+
+/// Order of two riders in the race, each with how far through it they have got. Riders
+/// who finished come first, by their time, then those still racing and then those out
+/// of the race, each by how far they have got.
+pub fn compare_standing(a: (&Rider, f32), b: (&Rider, f32)) -> Ordering {
+    let group = |rider: &Rider| match rider.finish_time {
+        Some(_) => 0,
+        None if rider.is_racing() => 1,
+        None => 2,
+    };
+    let (a_rider, a_progress) = a;
+    let (b_rider, b_progress) = b;
+    group(a_rider)
+        .cmp(&group(b_rider))
+        .then_with(|| match (a_rider.finish_time, b_rider.finish_time) {
+            (Some(a_time), Some(b_time)) => a_time.total_cmp(&b_time),
+            _ => b_progress.total_cmp(&a_progress),
+        })
+        .then_with(|| a_rider.number.cmp(&b_rider.number))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finished(number: usize, time: f32) -> Rider {
+        Rider {
+            laps: 4,
+            finish_time: Some(time),
+            ..Rider::new(number, "Finished")
+        }
+    }
+
+    fn crashed(number: usize, cause: CrashCause) -> Rider {
+        let mut rider = Rider::new(number, "Crashed");
+        rider.record_crash(cause, true);
+        rider
+    }
+
+    #[test]
+    fn finishers_lead_then_riders_still_racing_then_those_out_of_the_race() {
+        let mut fell_and_carried_on = finished(4, 41.0);
+        fell_and_carried_on.record_crash(CrashCause::Slip, false);
+        let mut excluded = Rider::new(6, "Excluded");
+        excluded.exclude(Exclusion::TouchedTapes);
+        let riders = [
+            (Rider::new(0, "Behind"), 3.5),
+            (crashed(1, CrashCause::Collision), 3.9),
+            (finished(2, 40.0), 4.0),
+            (Rider::new(3, "Ahead"), 3.8),
+            (fell_and_carried_on, 4.0),
+            (crashed(5, CrashCause::Slip), 1.0),
+            (excluded, 0.0),
+            (finished(7, 38.0), 4.0),
+        ];
+        let result = RaceResult::new(riders.iter().map(|(rider, progress)| (rider, *progress)));
+
+        let order: Vec<(usize, RaceOutcome)> = result
+            .0
+            .iter()
+            .map(|rider| (rider.number, rider.outcome))
+            .collect();
+        assert_eq!(
+            order,
+            [
+                (7, RaceOutcome::Finished),
+                (2, RaceOutcome::Finished),
+                (4, RaceOutcome::Finished),
+                (3, RaceOutcome::Running),
+                (0, RaceOutcome::Running),
+                (1, RaceOutcome::Retired(CrashCause::Collision)),
+                (5, RaceOutcome::Retired(CrashCause::Slip)),
+                (6, RaceOutcome::Excluded(Exclusion::TouchedTapes)),
+            ]
+        );
+        let gaps: Vec<Option<f32>> = result.0.iter().map(|rider| rider.gap).collect();
+        assert_eq!(gaps[..3], [Some(0.0), Some(2.0), Some(3.0)]);
+        assert!(gaps[3..].iter().all(Option::is_none));
+        assert_eq!(result.position(5), Some(7));
+    }
+}
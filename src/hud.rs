@@ -1,11 +1,21 @@
 use bevy::prelude::*;
 
-use crate::{bike::Bike, game::LapEvent, opponent::Opponent, player::Player, PlayingState};
+use crate::{
+    bike::{slip_risk, Bike, ContactEvent, ContactKind, ContactOutcome, SlipRisk},
+    game::Rider,
+    player::{ActivePlayer, Player, RemotePlayer},
+    track::{setup_track_lanes, TrackLanes},
+    PlayingState, RacingState,
+};
 
 const HUD_FONT_SIZE: f32 = 20.0;
 const HUD_TEXT_PADDING: Val = Val::Px(5.0);
 const POSITION_VERTICAL_SPACE: Val = Val::Px(5.0);
 const LAP_VERTICAL_SPACE: Val = Val::Px(45.0);
+const CONTACT_VERTICAL_SPACE: Val = Val::Px(85.0);
+const SLIP_WARNING_VERTICAL_SPACE: Val = Val::Px(125.0);
+/// How long a contact message stays on screen, in seconds
+const CONTACT_MESSAGE_DURATION: f32 = 2.0;
 const TEXT_COLOR: Color = Color::srgb(0.5, 0.5, 1.0);
 const SCORE_COLOR: Color = Color::srgb(1.0, 0.5, 0.5);
 
@@ -13,12 +23,26 @@ pub struct HudPlugin;
 
 impl Plugin for HudPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(PlayingState::SetupRace), setup)
-            .add_systems(OnExit(PlayingState::Racing), teardown)
-            .add_systems(
-                Update,
-                (update_laps, update_position).run_if(in_state(PlayingState::Racing)),
-            );
+        app.add_systems(
+            OnEnter(PlayingState::SetupRace),
+            setup.after(setup_track_lanes),
+        )
+        .add_systems(OnExit(PlayingState::Racing), teardown)
+        .add_systems(
+            Update,
+            show_slip_warning.run_if(in_state(RacingState::Commanding)),
+        )
+        .add_systems(OnEnter(RacingState::Simulating), hide_slip_warning)
+        .add_systems(
+            Update,
+            (
+                update_laps,
+                update_position,
+                show_contact_message,
+                hide_contact_message,
+            )
+                .run_if(in_state(PlayingState::Racing)),
+        );
     }
 }
 
@@ -31,7 +55,15 @@ struct LapDisplay;
 #[derive(Component)]
 struct PositionDisplay;
 
-fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+#[derive(Component)]
+struct SlipWarningDisplay;
+
+#[derive(Component)]
+struct ContactDisplay {
+    timer: Timer,
+}
+
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>, track_lanes: Res<TrackLanes>) {
     let font_handle = asset_server.load("fonts/FiraSans-Bold.ttf");
     // LAPS
     commands.spawn((
@@ -55,7 +87,7 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                 },
             ),
             TextSection::new(
-                "/4",
+                format!("/{}", track_lanes.laps),
                 TextStyle {
                     font_size: HUD_FONT_SIZE,
                     color: TEXT_COLOR,
@@ -97,6 +129,48 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
             ..default()
         }),
     ));
+
+    // Contact messages
+    commands.spawn((
+        HudElement,
+        ContactDisplay {
+            timer: Timer::from_seconds(CONTACT_MESSAGE_DURATION, TimerMode::Once),
+        },
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: HUD_FONT_SIZE,
+                color: SCORE_COLOR,
+                font: font_handle.clone(),
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: CONTACT_VERTICAL_SPACE,
+            left: HUD_TEXT_PADDING,
+            ..default()
+        }),
+    ));
+
+    // Slip warning
+    commands.spawn((
+        HudElement,
+        SlipWarningDisplay,
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: HUD_FONT_SIZE,
+                color: SCORE_COLOR,
+                font: font_handle.clone(),
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: SLIP_WARNING_VERTICAL_SPACE,
+            left: HUD_TEXT_PADDING,
+            ..default()
+        }),
+    ));
 }
 
 fn teardown(mut commands: Commands, q_hud: Query<Entity, With<HudElement>>) {
@@ -105,34 +179,125 @@ fn teardown(mut commands: Commands, q_hud: Query<Entity, With<HudElement>>) {
     }
 }
 
+/// The player the standings are shown for, which is the one choosing an action, or
+/// otherwise the one furthest up the field
+fn focused_player<'a>(
+    q_players: &'a Query<(&Rider, &Player, Has<ActivePlayer>), Without<RemotePlayer>>,
+) -> Option<(&'a Rider, &'a Player)> {
+    q_players
+        .iter()
+        .min_by_key(|(rider, player, active)| (!active, player.position, rider.number()))
+        .map(|(rider, player, _)| (rider, player))
+}
+
 fn update_laps(
-    mut lap_event: EventReader<LapEvent>,
+    q_players: Query<(&Rider, &Player, Has<ActivePlayer>), Without<RemotePlayer>>,
     mut q_lap_display: Query<&mut Text, With<LapDisplay>>,
 ) {
-    for event in lap_event.read() {
-        let mut text = q_lap_display.single_mut();
-        text.sections[1].value = event.0.to_string();
+    if let (Some((rider, _)), Ok(mut text)) =
+        (focused_player(&q_players), q_lap_display.get_single_mut())
+    {
+        let laps = rider.laps().to_string();
+        if text.sections[1].value != laps {
+            text.sections[1].value = laps;
+        }
     }
 }
 
 fn update_position(
-    q_opponents: Query<&Bike, With<Opponent>>,
-    q_player: Query<&Bike, With<Player>>,
+    q_players: Query<(&Rider, &Player, Has<ActivePlayer>), Without<RemotePlayer>>,
     mut q_position_display: Query<&mut Text, With<PositionDisplay>>,
 ) {
-    if let Ok(player_bike) = q_player.get_single() {
-        let player_distance = player_bike.distance;
-        let mut opponent_distances = Vec::new();
-        for opponent_bike in &q_opponents {
-            opponent_distances.push(opponent_bike.distance);
+    if let (Some((_, player)), Ok(mut text)) = (
+        focused_player(&q_players),
+        q_position_display.get_single_mut(),
+    ) {
+        let position = player.position.to_string();
+        if text.sections[1].value != position {
+            text.sections[1].value = position;
         }
-        let mut player_pos = 4;
-        for opponent_distance in opponent_distances {
-            if player_distance > opponent_distance {
-                player_pos -= 1;
+    }
+}
+
+fn show_contact_message(
+    mut contact_events: EventReader<ContactEvent>,
+    q_players: Query<Entity, (With<Player>, Without<RemotePlayer>)>,
+    q_riders: Query<&Rider>,
+    mut q_contact_display: Query<(&mut Text, &mut ContactDisplay)>,
+) {
+    let name = |entity: Option<Entity>| {
+        entity
+            .and_then(|entity| q_riders.get(entity).ok())
+            .map_or("them", |rider| rider.name())
+    };
+    for event in contact_events.read() {
+        // told from the side of the player involved, naming them if several race here
+        let Some(player_entity) = q_players
+            .iter()
+            .find(|entity| *entity == event.attacker || event.target == Some(*entity))
+        else {
+            continue;
+        };
+        let message = if event.attacker == player_entity {
+            let target = name(event.target);
+            match event.outcome {
+                ContactOutcome::Shoved => format!("You shoved {target} wide!"),
+                ContactOutcome::Slowed => format!("You knocked {target} back!"),
+                ContactOutcome::Rebuffed => format!("{target} held their line!"),
+                ContactOutcome::Missed => "Nobody there!".to_string(),
             }
+        } else if event.target == Some(player_entity) {
+            let attacker = name(Some(event.attacker));
+            match (event.outcome, event.kind) {
+                (ContactOutcome::Shoved, _) => format!("Shoved wide by {attacker}!"),
+                (ContactOutcome::Slowed, ContactKind::Elbow) => format!("Elbowed by {attacker}!"),
+                (ContactOutcome::Slowed, ContactKind::Hip) => format!("Hipped by {attacker}!"),
+                (ContactOutcome::Rebuffed, _) => "You held your line!".to_string(),
+                (ContactOutcome::Missed, _) => continue,
+            }
+        } else {
+            continue;
+        };
+        let message = if q_players.iter().count() > 1 {
+            format!("{}: {message}", name(Some(player_entity)))
+        } else {
+            message
+        };
+        let (mut text, mut contact_display) = q_contact_display.single_mut();
+        text.sections[0].value = message;
+        contact_display.timer.reset();
+    }
+}
+
+fn hide_contact_message(
+    mut q_contact_display: Query<(&mut Text, &mut ContactDisplay)>,
+    time: Res<Time>,
+) {
+    for (mut text, mut contact_display) in q_contact_display.iter_mut() {
+        if contact_display.timer.tick(time.delta()).just_finished() {
+            text.sections[0].value.clear();
         }
-        let mut text = q_position_display.single_mut();
-        text.sections[1].value = player_pos.to_string();
+    }
+}
+
+/// Warns each player before they choose an action that they will slip unless they skid
+fn show_slip_warning(
+    q_player: Query<&Bike, (With<ActivePlayer>, Added<ActivePlayer>)>,
+    mut q_slip_warning: Query<&mut Text, With<SlipWarningDisplay>>,
+    track_lanes: Res<TrackLanes>,
+) {
+    let (Ok(bike), Ok(mut text)) = (q_player.get_single(), q_slip_warning.get_single_mut()) else {
+        return;
+    };
+    text.sections[0].value = match slip_risk(bike, &track_lanes) {
+        Some(SlipRisk::Slide { lanes }) => format!("Too fast! Sliding out {lanes:.1} lanes"),
+        Some(SlipRisk::Crash) => "Too fast! You will crash".to_string(),
+        None => String::new(),
+    };
+}
+
+fn hide_slip_warning(mut q_slip_warning: Query<&mut Text, With<SlipWarningDisplay>>) {
+    for mut text in q_slip_warning.iter_mut() {
+        text.sections[0].value.clear();
     }
 }
@@ -1,6 +1,42 @@
-use bevy::prelude::*;
+use bevy::{
+    asset::{LoadState, RecursiveDependencyLoadState},
+    prelude::*,
+};
 
-use crate::GameState;
+use crate::{
+    actions::BikeAction,
+    bike::HelmetColour,
+    career::SeasonCalendar,
+    meeting::MeetingProgramme,
+    profile::RiderProfile,
+    track::{SelectedTrack, TrackDefinition},
+    GameState,
+};
+
+const MESSAGE_FONT_SIZE: f32 = 30.0;
+const MESSAGE_COLOR: Color = Color::srgb(1.0, 0.5, 0.5);
+
+pub const TRACK_FILES: [&str; 4] = [
+    "tracks/cycle_speedway.track.ron",
+    "tracks/ashgrove.track.ron",
+    "tracks/millfield.track.ron",
+    "tracks/brookside.track.ron",
+];
+pub const RIDER_FILES: [&str; 8] = [
+    "riders/tom_hardacre.rider.ron",
+    "riders/nils_ekdahl.rider.ron",
+    "riders/bartek_wozniak.rider.ron",
+    "riders/lena_marsh.rider.ron",
+    "riders/ray_duggan.rider.ron",
+    "riders/mikkel_storm.rider.ron",
+    "riders/jack_pengelly.rider.ron",
+    "riders/sami_koskinen.rider.ron",
+];
+pub const MEETING_FILES: [&str; 2] = [
+    "meetings/individual.meeting.ron",
+    "meetings/team_match.meeting.ron",
+];
+pub const SEASON_FILES: [&str; 1] = ["seasons/league.season.ron"];
 
 pub struct LoadingPlugin;
 
@@ -8,37 +44,143 @@ impl Plugin for LoadingPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(
             OnEnter(GameState::Loading),
-            (load_track_texture, load_bike_textures, load_icon_textures).before(finish_loading),
+            (
+                load_tracks,
+                load_rider_profiles,
+                load_meeting_programmes,
+                load_season_calendars,
+                load_bike_textures,
+                load_icon_textures,
+            ),
         )
-        .add_systems(OnEnter(GameState::Loading), finish_loading);
+        .add_systems(Update, finish_loading.run_if(in_state(GameState::Loading)))
+        .add_systems(OnEnter(GameState::NoTracks), show_no_tracks);
+    }
+}
+
+#[derive(Resource)]
+pub struct TrackAssets {
+    pub tracks: Vec<Handle<TrackDefinition>>,
+    /// Whether each track loaded, which is known once loading has finished
+    loaded: Vec<bool>,
+}
+
+impl TrackAssets {
+    pub fn is_loaded(&self, index: usize) -> bool {
+        self.loaded.get(index).copied().unwrap_or(false)
+    }
+
+    /// Index of the track file `path`, as listed in `TRACK_FILES`, if it loaded
+    pub fn index_of(&self, path: &str) -> Option<usize> {
+        TRACK_FILES
+            .iter()
+            .position(|track| *track == path)
+            .filter(|index| self.is_loaded(*index))
+    }
+
+    /// The next track after `index` that loaded, going round to the first
+    pub fn next_loaded(&self, index: usize) -> usize {
+        (1..=self.tracks.len())
+            .map(|step| (index + step) % self.tracks.len())
+            .find(|index| self.is_loaded(*index))
+            .unwrap_or(index)
+    }
+}
+
+impl FromWorld for TrackAssets {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            tracks: TRACK_FILES
+                .iter()
+                .map(|path| world.load_asset(*path))
+                .collect(),
+            loaded: vec![false; TRACK_FILES.len()],
+        }
+    }
+}
+
+/// The profiles opponents are drawn from
+#[derive(Resource)]
+pub struct RiderAssets {
+    pub profiles: Vec<Handle<RiderProfile>>,
+}
+
+impl FromWorld for RiderAssets {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            profiles: RIDER_FILES
+                .iter()
+                .map(|path| world.load_asset(*path))
+                .collect(),
+        }
+    }
+}
+
+/// The heat cards meetings are run to
+#[derive(Resource)]
+pub struct MeetingAssets {
+    pub programmes: Vec<Handle<MeetingProgramme>>,
+}
+
+impl FromWorld for MeetingAssets {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            programmes: MEETING_FILES
+                .iter()
+                .map(|path| world.load_asset(*path))
+                .collect(),
+        }
     }
 }
 
+/// The calendars career seasons are run to
 #[derive(Resource)]
-pub struct TrackTexture {
-    pub default: Handle<Image>,
+pub struct SeasonAssets {
+    pub calendars: Vec<Handle<SeasonCalendar>>,
 }
 
-impl FromWorld for TrackTexture {
+impl FromWorld for SeasonAssets {
     fn from_world(world: &mut World) -> Self {
         Self {
-            default: world.load_asset("images/track/track.png"),
+            calendars: SEASON_FILES
+                .iter()
+                .map(|path| world.load_asset(*path))
+                .collect(),
         }
     }
 }
 
+/// The bike in every helmet colour, in the order of `HelmetColour::ALL`
 #[derive(Resource)]
 pub struct BikeTextures {
-    pub straight: Handle<Image>,
-    pub turn: Handle<Image>,
+    straight: Vec<Handle<Image>>,
+    turn: Vec<Handle<Image>>,
     // pub turn_extreme: Handle<Image>,
 }
 
+impl BikeTextures {
+    pub fn straight(&self, helmet: HelmetColour) -> Handle<Image> {
+        self.straight[helmet.index()].clone()
+    }
+
+    pub fn turn(&self, helmet: HelmetColour) -> Handle<Image> {
+        self.turn[helmet.index()].clone()
+    }
+}
+
 impl FromWorld for BikeTextures {
     fn from_world(world: &mut World) -> Self {
+        let load = |shape: &str| -> Vec<Handle<Image>> {
+            HelmetColour::ALL
+                .iter()
+                .map(|helmet| {
+                    world.load_asset(format!("images/bike/bike_{shape}_{}.png", helmet.name()))
+                })
+                .collect()
+        };
         Self {
-            straight: world.load_asset("images/bike/bike_straight.png"),
-            turn: world.load_asset("images/bike/bike_turn.png"),
+            straight: load("straight"),
+            turn: load("turn"),
             // turn_extreme: world.load_asset("images/bike/bike_turn_extreme.png"),
         }
     }
@@ -61,6 +203,25 @@ pub struct IconTextures {
     pub collision: Handle<Image>,
 }
 
+impl IconTextures {
+    pub fn for_action(&self, action: BikeAction) -> Handle<Image> {
+        match action {
+            BikeAction::Accelerate => self.accelerate.clone(),
+            BikeAction::Watch => self.watch.clone(),
+            BikeAction::Skid => self.skid.clone(),
+            BikeAction::Stop => self.stop.clone(),
+            BikeAction::Left => self.left.clone(),
+            BikeAction::LeftLeft => self.left_left.clone(),
+            BikeAction::LeftElbow => self.left_elbow.clone(),
+            BikeAction::LeftHip => self.left_hip.clone(),
+            BikeAction::Right => self.right.clone(),
+            BikeAction::RightRight => self.right_right.clone(),
+            BikeAction::RightElbow => self.right_elbow.clone(),
+            BikeAction::RightHip => self.right_hip.clone(),
+        }
+    }
+}
+
 impl FromWorld for IconTextures {
     fn from_world(world: &mut World) -> Self {
         Self {
@@ -81,8 +242,20 @@ impl FromWorld for IconTextures {
     }
 }
 
-fn load_track_texture(mut commands: Commands) {
-    commands.init_resource::<TrackTexture>();
+fn load_tracks(mut commands: Commands) {
+    commands.init_resource::<TrackAssets>();
+}
+
+fn load_rider_profiles(mut commands: Commands) {
+    commands.init_resource::<RiderAssets>();
+}
+
+fn load_meeting_programmes(mut commands: Commands) {
+    commands.init_resource::<MeetingAssets>();
+}
+
+fn load_season_calendars(mut commands: Commands) {
+    commands.init_resource::<SeasonAssets>();
 }
 
 fn load_bike_textures(mut commands: Commands) {
@@ -93,6 +266,96 @@ fn load_icon_textures(mut commands: Commands) {
     commands.init_resource::<IconTextures>();
 }
 
-fn finish_loading(mut game_state: ResMut<NextState<GameState>>) {
-    game_state.set(GameState::Menu);
+fn finish_loading(
+    mut game_state: ResMut<NextState<GameState>>,
+    asset_server: Res<AssetServer>,
+    mut track_assets: ResMut<TrackAssets>,
+    track_definitions: Res<Assets<TrackDefinition>>,
+    mut selected_track: ResMut<SelectedTrack>,
+    rider_assets: Res<RiderAssets>,
+    meeting_assets: Res<MeetingAssets>,
+    season_assets: Res<SeasonAssets>,
+) {
+    // a track that fails to load, or whose texture does, is left out of the track choice
+    let tracks_settled = track_assets.tracks.iter().all(|track| {
+        matches!(asset_server.load_state(track), LoadState::Failed(_))
+            || matches!(
+                asset_server.get_recursive_dependency_load_state(track),
+                Some(RecursiveDependencyLoadState::Loaded | RecursiveDependencyLoadState::Failed)
+            )
+    });
+    // a profile that fails to load is left out of races rather than holding up the game
+    let profiles_settled = rider_assets.profiles.iter().all(|profile| {
+        matches!(
+            asset_server.load_state(profile),
+            LoadState::Loaded | LoadState::Failed(_)
+        )
+    });
+    // likewise a meeting programme that fails to load only means no meetings
+    let programmes_settled = meeting_assets.programmes.iter().all(|programme| {
+        matches!(
+            asset_server.load_state(programme),
+            LoadState::Loaded | LoadState::Failed(_)
+        )
+    });
+    let calendars_settled = season_assets.calendars.iter().all(|calendar| {
+        matches!(
+            asset_server.load_state(calendar),
+            LoadState::Loaded | LoadState::Failed(_)
+        )
+    });
+    if !(tracks_settled && profiles_settled && programmes_settled && calendars_settled) {
+        return;
+    }
+    let loaded: Vec<bool> = track_assets
+        .tracks
+        .iter()
+        .zip(TRACK_FILES)
+        .map(|(track, path)| {
+            if let LoadState::Failed(error) = asset_server.load_state(track) {
+                warn!("Track {path} is left out, because it could not be loaded: {error}");
+            } else if let Some(texture) = track_definitions
+                .get(track)
+                .and_then(|definition| definition.texture.as_ref())
+            {
+                if let LoadState::Failed(error) = asset_server.load_state(texture) {
+                    let texture_path = texture
+                        .path()
+                        .map_or_else(|| "its texture".to_string(), |path| path.to_string());
+                    warn!(
+                        "Track {path} is left out, because {texture_path} could not be \
+                         loaded: {error}"
+                    );
+                }
+            }
+            asset_server.is_loaded_with_dependencies(track)
+        })
+        .collect();
+    track_assets.loaded = loaded;
+    if !track_assets.is_loaded(selected_track.index) {
+        selected_track.index = track_assets.next_loaded(selected_track.index);
+    }
+    if track_assets.is_loaded(selected_track.index) {
+        game_state.set(GameState::Menu);
+    } else {
+        game_state.set(GameState::NoTracks);
+    }
+}
+
+/// There is nothing to race on without a track
+fn show_no_tracks(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn(
+        TextBundle::from_section(
+            "No track could be loaded. Check the files under assets/tracks.",
+            TextStyle {
+                font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                font_size: MESSAGE_FONT_SIZE,
+                color: MESSAGE_COLOR,
+            },
+        )
+        .with_style(Style {
+            margin: UiRect::all(Val::Auto),
+            ..default()
+        }),
+    );
 }
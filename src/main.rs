@@ -6,45 +6,84 @@
 
 mod actions;
 mod bike;
+mod bot;
 mod camera;
+mod career;
 mod collision;
+mod config;
 mod controls;
 mod game;
 mod hud;
 mod loading;
+mod meeting;
 mod menu;
+mod network;
 mod opponent;
 mod path_highlight;
 mod player;
+mod profile;
 mod random;
+mod replay;
+mod save;
+mod simulation;
+mod start_gate;
+mod team;
 mod track;
 
-use actions::ActionsPlugin;
 use bevy::asset::AssetMetaCheck;
 use bevy::prelude::*;
-use bike::BikePlugin;
+use bike::BikeSpritesPlugin;
 use camera::CameraDollyPlugin;
-use collision::CollisionPlugin;
+use career::CareerPlugin;
+use collision::CollisionIndicatorPlugin;
 use controls::ControlsPlugin;
 use game::GamePlugin;
 use loading::LoadingPlugin;
+use meeting::MeetingPlugin;
 use menu::MenuPlugin;
-use opponent::OpponentPlugin;
+use network::NetworkPlugin;
+use opponent::OpponentPlanningPlugin;
 use path_highlight::PathHighlightPlugin;
 use player::PlayerPlugin;
-use random::RandomnessPlugin;
+use profile::RiderProfilePlugin;
+use replay::ReplayPlugin;
+use save::SavePlugin;
+use serde::{Deserialize, Serialize};
+use simulation::RaceSimulationPlugin;
+use start_gate::StartPanelPlugin;
 use track::TrackPlugin;
 
 #[derive(States, Default, PartialEq, Eq, Hash, Clone, Debug)]
 enum GameState {
     #[default]
     Loading,
+    /// None of the tracks could be loaded, so there is nothing to race on
+    NoTracks,
     Menu,
+    /// Gathering players for a network race
+    Lobby,
+    /// Between the heats of a meeting
+    Meeting,
+    /// Between the meetings of a career season
+    Career,
     Playing,
+    Replay,
+}
+
+/// A race is on, either played or watched as a replay
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
+struct InRace;
+
+impl ComputedStates for InRace {
+    type SourceStates = GameState;
+
+    fn compute(sources: GameState) -> Option<Self> {
+        matches!(sources, GameState::Playing | GameState::Replay).then_some(InRace)
+    }
 }
 
 #[derive(SubStates, Default, Debug, Hash, PartialEq, Eq, Clone, Copy)]
-#[source(GameState = GameState::Playing)]
+#[source(InRace = InRace)]
 enum PlayingState {
     #[default]
     SetupRace,
@@ -52,16 +91,22 @@ enum PlayingState {
     FinishRace,
 }
 
-#[derive(SubStates, Default, Debug, Hash, PartialEq, Eq, Clone, Copy)]
+#[derive(SubStates, Serialize, Deserialize, Default, Debug, Hash, PartialEq, Eq, Clone, Copy)]
 #[source(PlayingState = PlayingState::Racing)]
 enum RacingState {
+    /// Riders are under starter's orders, choosing when to go
     #[default]
+    Tapes,
     Commanding,
     Simulating,
 }
 
 fn main() {
     //std::env::set_var("RUST_BACKTRACE", "1");
+    if std::env::args().any(|arg| arg == "--headless") {
+        simulation::run_headless();
+        return;
+    }
     App::new()
         .add_plugins((
             DefaultPlugins.set(AssetPlugin {
@@ -72,20 +117,29 @@ fn main() {
                 ..default()
             }),
             LoadingPlugin,
-            RandomnessPlugin,
+            RaceSimulationPlugin,
             GamePlugin,
             MenuPlugin,
             CameraDollyPlugin,
             TrackPlugin,
-            BikePlugin,
-            CollisionPlugin,
-            ActionsPlugin,
+            BikeSpritesPlugin,
+            CollisionIndicatorPlugin,
             PlayerPlugin,
-            OpponentPlugin,
             ControlsPlugin,
             PathHighlightPlugin,
+            ReplayPlugin,
+            SavePlugin,
+            OpponentPlanningPlugin,
+        ))
+        .add_plugins((
+            RiderProfilePlugin,
+            NetworkPlugin,
+            MeetingPlugin,
+            CareerPlugin,
+            StartPanelPlugin,
         ))
         .init_state::<GameState>()
+        .add_computed_state::<InRace>()
         .add_sub_state::<PlayingState>()
         .add_sub_state::<RacingState>()
         .run();
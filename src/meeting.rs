@@ -0,0 +1,568 @@
+mod board;
+mod programme;
+mod tactics;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bot::BotCommands,
+    game::{record_race_result, setup_bikes, RaceOutcome, RaceResult, RiderSetup, StartingGrid},
+    loading::{MeetingAssets, RiderAssets},
+    opponent::OpponentDifficulties,
+    player::PlayerCount,
+    profile::RiderProfile,
+    random::{seed_race, RaceSeed, Randomness},
+    team::Team,
+    track::TrackLanes,
+    GameState, PlayingState,
+};
+
+use self::board::MeetingBoardPlugin;
+pub use self::programme::MeetingProgramme;
+use self::programme::{Gate, MeetingProgrammeLoader, ProgrammeHeat};
+pub use self::tactics::Tactics;
+
+/// Points for the first three riders home in a heat. Riders further back, or who
+/// don't finish, score nothing.
+const HEAT_POINTS: [usize; 3] = [3, 2, 1];
+
+/// Runs meetings: a card of heats between the same field of riders, who score points
+/// in every heat, followed by the races that decide the meeting. In a team match the
+/// points of each side's riders add up to the match score.
+pub struct MeetingPlugin;
+
+impl Plugin for MeetingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<MeetingProgramme>()
+            .init_asset_loader::<MeetingProgrammeLoader>()
+            .init_resource::<MeetingFormat>()
+            .add_plugins(MeetingBoardPlugin)
+            .add_systems(
+                OnEnter(GameState::Meeting),
+                start_meeting.run_if(not(resource_exists::<Meeting>)),
+            )
+            .add_systems(
+                OnEnter(PlayingState::SetupRace),
+                (seed_heat.after(seed_race), draw_heat_grid)
+                    .chain()
+                    .before(setup_bikes)
+                    .run_if(in_state(GameState::Playing).and_then(resource_exists::<Meeting>)),
+            )
+            .add_systems(
+                OnEnter(PlayingState::FinishRace),
+                record_heat
+                    .after(record_race_result)
+                    .run_if(in_state(GameState::Playing).and_then(resource_exists::<Meeting>)),
+            )
+            .add_systems(OnEnter(GameState::Menu), end_meeting);
+    }
+}
+
+/// Kind of meeting to run when one is chosen from the menu
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeetingFormat {
+    #[default]
+    Individual,
+    TeamMatch,
+}
+
+/// The meeting being run, and how it has gone so far
+#[derive(Resource, Serialize, Deserialize, Debug, Clone)]
+pub struct Meeting {
+    pub programme: MeetingProgramme,
+    /// Every heat's seed comes from this one, so a meeting can be run again exactly
+    seed: u64,
+    /// The field, in their order in the card. Each rider's lane is set as they are
+    /// put in a heat.
+    riders: Vec<RiderSetup>,
+    heats: Vec<HeatRun>,
+    /// Each team's tactics for the next heat, in the order of `Team::BOTH`
+    tactics: [Tactics; 2],
+}
+
+/// A heat that has been run
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HeatRun {
+    /// Card number of the rider in each gate, counting from the inside
+    pub line_up: Vec<usize>,
+    pub result: RaceResult,
+    /// The tactics each team used in the heat
+    pub tactics: [Tactics; 2],
+}
+
+impl HeatRun {
+    /// Card number of every rider, with how their heat ended and the points they
+    /// scored, in finishing order
+    pub fn placings(&self) -> impl Iterator<Item = (usize, RaceOutcome, usize)> + '_ {
+        self.result
+            .0
+            .iter()
+            .enumerate()
+            .filter_map(|(index, result)| {
+                let rider = *self.line_up.get(result.number)?;
+                let points = match result.outcome {
+                    RaceOutcome::Finished => HEAT_POINTS.get(index).copied().unwrap_or(0),
+                    _ => 0,
+                };
+                Some((rider, result.outcome, points))
+            })
+    }
+}
+
+/// One rider's line on the scoreboard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Score {
+    pub points: usize,
+    pub wins: usize,
+}
+
+impl Meeting {
+    /// Draws the field of a meeting. The riders of `players` take random places in the
+    /// card and the rest of the field is drawn from `profiles`, with bots racing the
+    /// first of them. In a team match the players take turns to join the home and away
+    /// teams.
+    pub fn new(
+        programme: MeetingProgramme,
+        mut profiles: Vec<RiderProfile>,
+        players: Vec<RiderProfile>,
+        opponent_difficulties: &OpponentDifficulties,
+        bot_commands: &BotCommands,
+        seed: u64,
+    ) -> Self {
+        let mut draw = Randomness::from_seed(seed).grid;
+        draw.shuffle(&mut profiles);
+        let player_count = players.len().min(programme.field);
+        // the card numbers of each side follow on from the last side's
+        let sides: Vec<(Option<Team>, usize)> = match &programme.teams {
+            Some(teams) => Team::BOTH
+                .iter()
+                .map(|team| (Some(*team), teams.riders))
+                .collect(),
+            None => vec![(None, programme.field)],
+        };
+        let mut riders = Vec::new();
+        let mut opponent_index = 0;
+        for (side, (team, size)) in sides.iter().enumerate() {
+            let side_players = players
+                .iter()
+                .take(player_count)
+                .enumerate()
+                .filter(|(index, _)| index % sides.len() == side);
+            let mut side_riders: Vec<RiderSetup> = side_players
+                .map(|(_, profile)| RiderSetup {
+                    lane: 0,
+                    player: true,
+                    difficulty: default(),
+                    profile: profile.clone(),
+                    bot: None,
+                    team: *team,
+                })
+                .take(*size)
+                .collect();
+            while side_riders.len() < *size {
+                let mut profile = if profiles.is_empty() {
+                    RiderProfile::default()
+                } else {
+                    profiles[opponent_index % profiles.len()].clone()
+                };
+                let bot = bot_commands.0.get(opponent_index).cloned();
+                if let Some(bot) = &bot {
+                    profile.name.clone_from(&bot.name);
+                }
+                side_riders.push(RiderSetup {
+                    lane: 0,
+                    player: false,
+                    difficulty: opponent_difficulties.for_opponent(opponent_index),
+                    profile,
+                    bot,
+                    team: *team,
+                });
+                opponent_index += 1;
+            }
+            draw.shuffle(&mut side_riders);
+            riders.extend(side_riders);
+        }
+        Self {
+            programme,
+            seed,
+            riders,
+            heats: Vec::new(),
+            tactics: default(),
+        }
+    }
+
+    /// The rider with card number `number`, counting from one
+    pub fn rider(&self, number: usize) -> &RiderSetup {
+        &self.riders[number - 1]
+    }
+
+    /// The heat run most recently, with its place in the card
+    pub fn last_heat(&self) -> Option<(&HeatRun, &ProgrammeHeat)> {
+        let run = self.heats.last()?;
+        Some((run, &self.programme.heats[self.heats.len() - 1]))
+    }
+
+    /// The heat to be run next, or `None` once the meeting is over
+    pub fn next_heat(&self) -> Option<&ProgrammeHeat> {
+        self.programme.heats.get(self.heats.len())
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.next_heat().is_none()
+    }
+
+    /// Card numbers of the riders in each gate of the next heat, with any tactical
+    /// substitutes in place of the riders they replace
+    pub fn line_up(&self) -> Vec<usize> {
+        let substitutions: Vec<_> = self
+            .tactics
+            .iter()
+            .filter_map(|tactics| tactics.substitute)
+            .collect();
+        self.card_line_up()
+            .into_iter()
+            .map(|number| {
+                substitutions
+                    .iter()
+                    .find(|substitution| substitution.replaced == number)
+                    .map_or(number, |substitution| substitution.substitute)
+            })
+            .collect()
+    }
+
+    /// Card numbers of the riders in each gate of the next heat, as the card has it. A
+    /// rider the card puts in two gates, say by their standing and by a place in an
+    /// earlier heat, rides from the first, and the best placed rider on the scoreboard
+    /// who isn't in the heat takes the other.
+    fn card_line_up(&self) -> Vec<usize> {
+        let Some(heat) = self.next_heat() else {
+            return Vec::new();
+        };
+        let standings = self.standings();
+        let resolved: Vec<usize> = heat
+            .gates
+            .iter()
+            .filter_map(|gate| match *gate {
+                Gate::Rider(number) => Some(number),
+                Gate::Standing(place) => standings.get(place - 1).copied(),
+                Gate::Placed { heat, place } => self.heats[heat - 1]
+                    .placings()
+                    .nth(place - 1)
+                    .map(|(rider, ..)| rider),
+            })
+            .collect();
+        let mut line_up: Vec<usize> = Vec::with_capacity(resolved.len());
+        for number in &resolved {
+            if !line_up.contains(number) {
+                line_up.push(*number);
+                continue;
+            }
+            warn!("{} puts rider {number} in two gates", heat.name);
+            if let Some(stand_in) = standings
+                .iter()
+                .find(|other| !resolved.contains(other) && !line_up.contains(other))
+            {
+                line_up.push(*stand_in);
+            }
+        }
+        line_up
+    }
+
+    /// Points and heat wins of the rider with card number `number`, from the heats
+    /// that are scored
+    pub fn score(&self, number: usize) -> Score {
+        let mut score = Score { points: 0, wins: 0 };
+        let scored_heats = self
+            .heats
+            .iter()
+            .zip(&self.programme.heats)
+            .filter(|(_, heat)| heat.scored);
+        for (run, _) in scored_heats {
+            for (rider, _, points) in run.placings() {
+                if rider == number {
+                    score.points += points;
+                    score.wins += usize::from(points == HEAT_POINTS[0]);
+                }
+            }
+        }
+        score
+    }
+
+    /// What the rider with card number `number` made of each scored heat they rode in
+    pub fn rides(&self, number: usize) -> Vec<(RaceOutcome, usize)> {
+        self.heats
+            .iter()
+            .zip(&self.programme.heats)
+            .filter(|(_, heat)| heat.scored)
+            .filter_map(|(run, _)| {
+                run.placings()
+                    .find(|(rider, ..)| *rider == number)
+                    .map(|(_, outcome, points)| (outcome, points))
+            })
+            .collect()
+    }
+
+    /// Card numbers of the field by points, then heat wins. Riders level on both keep
+    /// their order in the card.
+    pub fn standings(&self) -> Vec<usize> {
+        let mut numbers: Vec<usize> = (1..=self.riders.len()).collect();
+        numbers.sort_by_key(|number| {
+            let score = self.score(*number);
+            (
+                std::cmp::Reverse(score.points),
+                std::cmp::Reverse(score.wins),
+            )
+        });
+        numbers
+    }
+
+    /// Card numbers of the field in the order the meeting places them. Once a last
+    /// heat that isn't scored has been run, its riders head the order as they finished
+    /// it, and everyone else follows on the scoreboard.
+    pub fn classification(&self) -> Vec<usize> {
+        let deciding_heat = self
+            .heats
+            .last()
+            .filter(|_| self.is_over())
+            .zip(self.programme.heats.last())
+            .filter(|(_, heat)| !heat.scored);
+        let Some((run, _)) = deciding_heat else {
+            return self.standings();
+        };
+        let mut order: Vec<usize> = run.placings().map(|(rider, ..)| rider).collect();
+        for number in self.standings() {
+            if !order.contains(&number) {
+                order.push(number);
+            }
+        }
+        order
+    }
+
+    fn heat_seed(&self) -> u64 {
+        self.seed.wrapping_add(self.heats.len() as u64)
+    }
+}
+
+/// Draws the field when a meeting is chosen from the menu, for the first programme of
+/// the chosen format
+fn start_meeting(
+    mut commands: Commands,
+    mut game_state: ResMut<NextState<GameState>>,
+    meeting_assets: Res<MeetingAssets>,
+    programmes: Res<Assets<MeetingProgramme>>,
+    rider_assets: Res<RiderAssets>,
+    rider_profiles: Res<Assets<RiderProfile>>,
+    player_count: Res<PlayerCount>,
+    opponent_difficulties: Res<OpponentDifficulties>,
+    bot_commands: Res<BotCommands>,
+    race_seed: Res<RaceSeed>,
+    meeting_format: Res<MeetingFormat>,
+) {
+    let team_match = *meeting_format == MeetingFormat::TeamMatch;
+    let Some(programme) = meeting_assets
+        .programmes
+        .iter()
+        .filter_map(|handle| programmes.get(handle))
+        .find(|programme| programme.teams.is_some() == team_match)
+    else {
+        let format = if team_match { "team match" } else { "meeting" };
+        warn!("Could not start a meeting: no {format} programme loaded");
+        game_state.set(GameState::Menu);
+        return;
+    };
+    let profiles = rider_assets
+        .profiles
+        .iter()
+        .filter_map(|handle| rider_profiles.get(handle))
+        .cloned()
+        .collect();
+    let players = (0..player_count.0)
+        .map(|index| RiderProfile::player(index, player_count.0))
+        .collect();
+    let seed = race_seed.0.unwrap_or_else(|| fastrand::u64(..));
+    debug!("Meeting seed {seed}");
+    commands.insert_resource(Meeting::new(
+        programme.clone(),
+        profiles,
+        players,
+        &opponent_difficulties,
+        &bot_commands,
+        seed,
+    ));
+}
+
+/// Every heat is run with its own seed from the meeting's
+fn seed_heat(meeting: Res<Meeting>, mut randomness: ResMut<Randomness>) {
+    *randomness = Randomness::from_seed(meeting.heat_seed());
+}
+
+/// Lines up the riders of the next heat in the gates the card gives them
+fn draw_heat_grid(mut commands: Commands, meeting: Res<Meeting>, track_lanes: Res<TrackLanes>) {
+    let line_up = meeting.line_up();
+    if line_up.len() > track_lanes.lane_count() {
+        warn!(
+            "The track has {} lanes, so only the riders in the first gates of the heat start",
+            track_lanes.lane_count()
+        );
+    }
+    let riders = line_up
+        .into_iter()
+        .take(track_lanes.lane_count())
+        .enumerate()
+        .map(|(lane, number)| RiderSetup {
+            lane,
+            ..meeting.rider(number).clone()
+        })
+        .collect();
+    commands.insert_resource(StartingGrid(riders));
+}
+
+/// Scores the heat that has just been run, and goes back to the scoreboard once the
+/// computer's teams have decided their tactics for the next one
+fn record_heat(
+    mut meeting: ResMut<Meeting>,
+    race_result: Res<RaceResult>,
+    track_lanes: Res<TrackLanes>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    let mut line_up = meeting.line_up();
+    line_up.truncate(track_lanes.lane_count());
+    let tactics = std::mem::take(&mut meeting.tactics);
+    meeting.heats.push(HeatRun {
+        line_up,
+        result: race_result.clone(),
+        tactics,
+    });
+    meeting.plan_tactics();
+    game_state.set(GameState::Meeting);
+}
+
+fn end_meeting(mut commands: Commands) {
+    commands.remove_resource::<Meeting>();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bike::CrashCause, game::Rider};
+
+    fn heat(name: &str, gates: Vec<Gate>, scored: bool) -> ProgrammeHeat {
+        ProgrammeHeat {
+            name: name.to_string(),
+            gates,
+            scored,
+        }
+    }
+
+    /// A meeting of six riders, with three heats run of a card that goes on to `next`
+    fn meeting(next: Vec<ProgrammeHeat>) -> Meeting {
+        let rider = |_| RiderSetup {
+            lane: 0,
+            player: false,
+            difficulty: default(),
+            profile: RiderProfile::default(),
+            bot: None,
+            team: None,
+        };
+        let gates = |riders: [usize; 4]| riders.map(Gate::Rider).to_vec();
+        let mut heats = vec![
+            heat("Heat 1", gates([1, 2, 3, 4]), true),
+            heat("Heat 2", gates([5, 6, 2, 4]), true),
+            heat("Heat 3", gates([3, 4, 5, 1]), true),
+        ];
+        heats.extend(next);
+        let mut meeting = Meeting {
+            programme: MeetingProgramme {
+                name: "Test".to_string(),
+                field: 6,
+                heats,
+                teams: None,
+            },
+            seed: 0,
+            riders: (1..=6).map(rider).collect(),
+            heats: Vec::new(),
+            tactics: default(),
+        };
+        let finished = RaceOutcome::Finished;
+        run(
+            &mut meeting,
+            &[(1, finished), (2, finished), (3, finished), (4, finished)],
+        );
+        run(
+            &mut meeting,
+            &[(6, finished), (2, finished), (4, finished), (5, finished)],
+        );
+        // a rider who doesn't finish scores nothing, whatever their place
+        let retired = RaceOutcome::Retired(CrashCause::Slip);
+        run(
+            &mut meeting,
+            &[(4, finished), (3, finished), (1, retired), (5, finished)],
+        );
+        meeting
+    }
+
+    /// Runs the next heat, with its riders placed in the order given by card number
+    fn run(meeting: &mut Meeting, placings: &[(usize, RaceOutcome)]) {
+        let line_up = meeting.line_up();
+        // riders further up the order have got further round
+        let riders: Vec<(Rider, f32)> = placings
+            .iter()
+            .enumerate()
+            .map(|(place, (number, _))| {
+                let gate = line_up.iter().position(|rider| rider == number).unwrap();
+                (Rider::new(gate, "Rider"), -(place as f32))
+            })
+            .collect();
+        let mut result = RaceResult::new(riders.iter().map(|(rider, progress)| (rider, *progress)));
+        for (rider_result, (_, outcome)) in result.0.iter_mut().zip(placings) {
+            rider_result.outcome = *outcome;
+        }
+        meeting.heats.push(HeatRun {
+            line_up,
+            result,
+            tactics: default(),
+        });
+    }
+
+    #[test]
+    fn standings_go_by_points_then_wins_then_card_order() {
+        let meeting = meeting(Vec::new());
+        assert_eq!(meeting.score(1), Score { points: 3, wins: 1 });
+        assert_eq!(meeting.score(4), Score { points: 4, wins: 1 });
+        assert_eq!(meeting.score(5), Score { points: 0, wins: 0 });
+        // 1 and 6 are level on points and wins
+        assert_eq!(meeting.standings(), [4, 2, 1, 6, 3, 5]);
+        assert_eq!(meeting.classification(), meeting.standings());
+    }
+
+    #[test]
+    fn unscored_final_heads_the_classification() {
+        let standings = (1..=4).map(Gate::Standing).collect();
+        let mut meeting = meeting(vec![heat("Final", standings, false)]);
+        assert_eq!(meeting.line_up(), [4, 2, 1, 6]);
+        let finished = RaceOutcome::Finished;
+        run(
+            &mut meeting,
+            &[(6, finished), (1, finished), (4, finished), (2, finished)],
+        );
+
+        assert!(meeting.is_over());
+        assert_eq!(meeting.standings(), [4, 2, 1, 6, 3, 5]);
+        assert_eq!(meeting.classification(), [6, 1, 4, 2, 3, 5]);
+    }
+
+    #[test]
+    fn rider_in_two_gates_makes_way_for_a_stand_in() {
+        // the winner of heat 1 is also third on the scoreboard
+        let gates = vec![
+            Gate::Placed { heat: 1, place: 1 },
+            Gate::Standing(3),
+            Gate::Standing(1),
+            Gate::Standing(2),
+        ];
+        let meeting = meeting(vec![heat("Semi-final", gates, false)]);
+        // the best placed rider not in the heat is sixth in the card
+        assert_eq!(meeting.card_line_up(), [1, 6, 4, 2]);
+    }
+}
@@ -0,0 +1,491 @@
+use bevy::prelude::*;
+
+use crate::{career::Career, game::RaceOutcome, team::Team, GameState};
+
+use super::{start_meeting, Meeting};
+
+const BUTTON_NORMAL_COLOR: Color = Color::srgb(0.15, 0.15, 0.15);
+const BUTTON_HOVERED_COLOR: Color = Color::srgb(0.25, 0.25, 0.25);
+const BUTTON_PRESSED_COLOR: Color = Color::srgb(0.35, 0.75, 0.35);
+const BUTTON_WIDTH: f32 = 200.0;
+const BUTTON_HEIGHT: f32 = 65.0;
+const BUTTON_FONT_SIZE: f32 = 40.0;
+const BUTTON_FONT_COLOR: Color = Color::srgb(0.9, 0.9, 0.9);
+const PLAYER_COLOR: Color = Color::srgba(0.9, 0.1, 0.1, 0.8);
+const TITLE_FONT_SIZE: f32 = 40.0;
+const HEADING_FONT_SIZE: f32 = 30.0;
+const TABLE_FONT_SIZE: f32 = 22.0;
+/// Widths of the columns of the scoreboard: position or card number, rider, rides and
+/// points
+const SCOREBOARD_COLUMN_WIDTHS: [f32; 4] = [40.0, 220.0, 160.0, 60.0];
+/// Widths of the columns of a heat: gate or place, rider and how they did
+const HEAT_COLUMN_WIDTHS: [f32; 3] = [40.0, 220.0, 220.0];
+
+/// The screen shown between heats, with the scoreboard, the last heat's result and
+/// who is in the next one. A team that is behind in a team match can make its tactical
+/// changes here.
+pub struct MeetingBoardPlugin;
+
+impl Plugin for MeetingBoardPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            OnEnter(GameState::Meeting),
+            setup_board
+                .after(start_meeting)
+                .run_if(resource_exists::<Meeting>),
+        )
+        .add_systems(OnExit(GameState::Meeting), teardown)
+        .add_systems(
+            Update,
+            (
+                button_system,
+                // the board shows the tactics as they are changed
+                (teardown, setup_board)
+                    .chain()
+                    .run_if(resource_changed::<Meeting>),
+            )
+                .chain()
+                .run_if(in_state(GameState::Meeting).and_then(resource_exists::<Meeting>)),
+        );
+    }
+}
+
+#[derive(Component)]
+struct MeetingBoard;
+
+#[derive(Component, Debug, PartialEq, Eq, Clone, Copy)]
+enum ButtonAction {
+    NextHeat,
+    /// Changes the team's tactical substitute
+    Substitute(Team),
+    /// Plays the team's joker on another rider
+    Joker(Team),
+    /// Back to the career, once a meeting of its season is over
+    Season,
+    Menu,
+}
+
+fn setup_board(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    meeting: Res<Meeting>,
+    career: Option<Res<Career>>,
+) {
+    let font_handle = asset_server.load("fonts/FiraSans-Bold.ttf");
+    let text = |text: String, font_size: f32, color: Color| {
+        TextBundle::from_section(
+            text,
+            TextStyle {
+                font_size,
+                color,
+                font: font_handle.clone(),
+            },
+        )
+    };
+    let rider_color = |number: usize| {
+        if meeting.rider(number).player {
+            PLAYER_COLOR
+        } else {
+            BUTTON_FONT_COLOR
+        }
+    };
+    let rider_name = |number: usize| format!("{number}. {}", meeting.rider(number).profile.name);
+
+    let (board_title, order) = if meeting.is_over() {
+        ("Final classification", meeting.classification())
+    } else {
+        ("Scoreboard", meeting.standings())
+    };
+    let scoreboard_row = |number: usize, first_cell: String| {
+        let rides: Vec<String> = meeting
+            .rides(number)
+            .into_iter()
+            .map(|(outcome, points)| ride_text(outcome, points))
+            .collect();
+        let cells = vec![
+            first_cell,
+            rider_name(number),
+            rides.join(" "),
+            meeting.score(number).points.to_string(),
+        ];
+        (cells, rider_color(number))
+    };
+    let scoreboard_header = vec![
+        String::new(),
+        "Rider".to_string(),
+        "Rides".to_string(),
+        "Points".to_string(),
+    ];
+    let match_score = |home_score: usize, away_score: usize| {
+        format!(
+            "{} {home_score} - {away_score} {}",
+            meeting.team_name(Team::Home),
+            meeting.team_name(Team::Away)
+        )
+    };
+    let match_title = if !meeting.is_team_match() {
+        None
+    } else {
+        let (home_score, away_score) = (
+            meeting.team_score(Team::Home),
+            meeting.team_score(Team::Away),
+        );
+        let score = match_score(home_score, away_score);
+        Some(if !meeting.is_over() {
+            score
+        } else if home_score == away_score {
+            format!("Match drawn {score}")
+        } else {
+            let winner = if home_score > away_score {
+                Team::Home
+            } else {
+                Team::Away
+            };
+            format!("{} win: {score}", meeting.team_name(winner))
+        })
+    };
+
+    let last_heat = meeting.last_heat();
+    let next_heat = meeting.next_heat();
+
+    commands
+        .spawn((
+            MeetingBoard,
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn(text(
+                meeting.programme.name.clone(),
+                TITLE_FONT_SIZE,
+                BUTTON_FONT_COLOR,
+            ));
+            if let Some(match_title) = match_title {
+                parent.spawn(text(match_title, HEADING_FONT_SIZE, BUTTON_FONT_COLOR));
+            }
+            parent.spawn(make_row()).with_children(|parent| {
+                parent.spawn(make_column()).with_children(|parent| {
+                    if !meeting.is_team_match() {
+                        parent.spawn(text(
+                            board_title.to_string(),
+                            HEADING_FONT_SIZE,
+                            BUTTON_FONT_COLOR,
+                        ));
+                        let rows = order.iter().enumerate().map(|(index, number)| {
+                            scoreboard_row(*number, (index + 1).to_string())
+                        });
+                        spawn_table(
+                            parent,
+                            std::iter::once((scoreboard_header.clone(), BUTTON_FONT_COLOR))
+                                .chain(rows),
+                            &SCOREBOARD_COLUMN_WIDTHS,
+                            &font_handle,
+                        );
+                        return;
+                    }
+                    // each team's riders in card order
+                    for team in Team::BOTH {
+                        parent.spawn(text(
+                            format!("{} {}", meeting.team_name(team), meeting.team_score(team)),
+                            HEADING_FONT_SIZE,
+                            BUTTON_FONT_COLOR,
+                        ));
+                        let rows = meeting
+                            .team_riders(team)
+                            .into_iter()
+                            .map(|number| scoreboard_row(number, String::new()));
+                        spawn_table(
+                            parent,
+                            std::iter::once((scoreboard_header.clone(), BUTTON_FONT_COLOR))
+                                .chain(rows),
+                            &SCOREBOARD_COLUMN_WIDTHS,
+                            &font_handle,
+                        );
+                    }
+                });
+                parent.spawn(make_column()).with_children(|parent| {
+                    if let Some((run, heat)) = last_heat {
+                        parent.spawn(text(
+                            format!("{} result", heat.name),
+                            HEADING_FONT_SIZE,
+                            BUTTON_FONT_COLOR,
+                        ));
+                        let rows =
+                            run.placings()
+                                .enumerate()
+                                .map(|(index, (number, outcome, points))| {
+                                    let how = match outcome {
+                                        RaceOutcome::Finished if heat.scored => {
+                                            format!("{points} points")
+                                        }
+                                        RaceOutcome::Finished => String::new(),
+                                        outcome => outcome.describe().to_string(),
+                                    };
+                                    (
+                                        vec![(index + 1).to_string(), rider_name(number), how],
+                                        rider_color(number),
+                                    )
+                                });
+                        spawn_table(parent, rows, &HEAT_COLUMN_WIDTHS, &font_handle);
+                        if meeting.is_team_match() && heat.scored {
+                            parent.spawn(text(
+                                format!(
+                                    "Heat score: {}",
+                                    match_score(
+                                        run.team_points(Team::Home, &meeting),
+                                        run.team_points(Team::Away, &meeting)
+                                    )
+                                ),
+                                TABLE_FONT_SIZE,
+                                BUTTON_FONT_COLOR,
+                            ));
+                        }
+                    }
+                    if let Some(heat) = next_heat {
+                        parent.spawn(text(
+                            format!("Next: {}", heat.name),
+                            HEADING_FONT_SIZE,
+                            BUTTON_FONT_COLOR,
+                        ));
+                        // gates are counted from the inside, starting at one
+                        let rows =
+                            meeting
+                                .line_up()
+                                .into_iter()
+                                .enumerate()
+                                .map(|(gate, number)| {
+                                    let score = meeting.score(number);
+                                    (
+                                        vec![
+                                            (gate + 1).to_string(),
+                                            rider_name(number),
+                                            format!(
+                                                "{} points{}",
+                                                score.points,
+                                                tactics_note(&meeting, number)
+                                            ),
+                                        ],
+                                        rider_color(number),
+                                    )
+                                });
+                        spawn_table(parent, rows, &HEAT_COLUMN_WIDTHS, &font_handle);
+                    }
+                });
+            });
+            parent.spawn(make_row()).with_children(|parent| {
+                if next_heat.is_some() {
+                    parent
+                        .spawn((ButtonAction::NextHeat, make_button()))
+                        .with_children(|parent| {
+                            parent.spawn(make_button_text("Next heat", font_handle.clone()));
+                        });
+                }
+                for team in Team::BOTH {
+                    if !meeting.managed_by_players(team) {
+                        continue;
+                    }
+                    if !meeting.substitutions(team).is_empty() {
+                        parent
+                            .spawn((ButtonAction::Substitute(team), make_button()))
+                            .with_children(|parent| {
+                                parent.spawn(make_button_text("Tactical", font_handle.clone()));
+                            });
+                    }
+                    if !meeting.joker_candidates(team).is_empty() {
+                        parent
+                            .spawn((ButtonAction::Joker(team), make_button()))
+                            .with_children(|parent| {
+                                parent.spawn(make_button_text("Joker", font_handle.clone()));
+                            });
+                    }
+                }
+                // a finished career meeting goes back to the season to be scored
+                let (action, label) = if career.is_some() && meeting.is_over() {
+                    (ButtonAction::Season, "Season")
+                } else {
+                    (ButtonAction::Menu, "Menu")
+                };
+                parent
+                    .spawn((action, make_button()))
+                    .with_children(|parent| {
+                        parent.spawn(make_button_text(label, font_handle.clone()));
+                    });
+            });
+        });
+}
+
+/// What a team has in store for the rider with card number `number` in the next heat
+fn tactics_note(meeting: &Meeting, number: usize) -> String {
+    let Some(team) = meeting.rider(number).team else {
+        return String::new();
+    };
+    let tactics = meeting.tactics(team);
+    let mut note = String::new();
+    if let Some(substitution) = tactics
+        .substitute
+        .filter(|substitution| substitution.substitute == number)
+    {
+        note.push_str(&format!(", for {}", substitution.replaced));
+    }
+    if tactics.joker == Some(number) {
+        note.push_str(", joker");
+    }
+    note
+}
+
+/// A rider's points from one heat, or a letter for why they scored none: F for a
+/// fall, X for an exclusion
+fn ride_text(outcome: RaceOutcome, points: usize) -> String {
+    match outcome {
+        RaceOutcome::Retired(_) => "F".to_string(),
+        RaceOutcome::Excluded(_) => "X".to_string(),
+        RaceOutcome::Finished | RaceOutcome::Running => points.to_string(),
+    }
+}
+
+/// Rows of text, each in its own color, lined up in columns of `widths`
+fn spawn_table(
+    parent: &mut ChildBuilder,
+    rows: impl Iterator<Item = (Vec<String>, Color)>,
+    widths: &[f32],
+    font_handle: &Handle<Font>,
+) {
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Column,
+                margin: UiRect::vertical(Val::Px(10.0)),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            for (cells, color) in rows {
+                parent.spawn(make_row()).with_children(|parent| {
+                    for (cell, width) in cells.into_iter().zip(widths) {
+                        parent.spawn(
+                            TextBundle::from_section(
+                                cell,
+                                TextStyle {
+                                    font_size: TABLE_FONT_SIZE,
+                                    color,
+                                    font: font_handle.clone(),
+                                },
+                            )
+                            .with_style(Style {
+                                width: Val::Px(*width),
+                                ..default()
+                            }),
+                        );
+                    }
+                });
+            }
+        });
+}
+
+fn make_row() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            flex_direction: FlexDirection::Row,
+            ..default()
+        },
+        ..default()
+    }
+}
+
+fn make_column() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            flex_direction: FlexDirection::Column,
+            margin: UiRect::horizontal(Val::Px(20.0)),
+            ..default()
+        },
+        ..default()
+    }
+}
+
+fn make_button() -> ButtonBundle {
+    ButtonBundle {
+        style: Style {
+            width: Val::Px(BUTTON_WIDTH),
+            height: Val::Px(BUTTON_HEIGHT),
+            border: UiRect::all(Val::Px(2.0)),
+            // horizontally center child text
+            justify_content: JustifyContent::Center,
+            // vertically center child text
+            align_items: AlignItems::Center,
+            margin: UiRect::all(Val::Px(20.0)),
+            ..default()
+        },
+        border_color: BorderColor(Color::BLACK),
+        border_radius: BorderRadius::MAX,
+        background_color: BUTTON_NORMAL_COLOR.into(),
+        ..default()
+    }
+}
+
+fn make_button_text(text: &str, font_handle: Handle<Font>) -> TextBundle {
+    TextBundle::from_section(
+        text,
+        TextStyle {
+            font: font_handle,
+            font_size: BUTTON_FONT_SIZE,
+            color: BUTTON_FONT_COLOR,
+        },
+    )
+}
+
+fn teardown(mut commands: Commands, q_elements: Query<Entity, With<MeetingBoard>>) {
+    for entity in &q_elements {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn button_system(
+    mut interaction_query: Query<
+        (&ButtonAction, &Interaction, &mut BackgroundColor),
+        (Changed<Interaction>, With<Button>),
+    >,
+    mut game_state: ResMut<NextState<GameState>>,
+    mut meeting: ResMut<Meeting>,
+) {
+    for (button_action, interaction, mut color) in &mut interaction_query {
+        match *interaction {
+            Interaction::Pressed => {
+                *color = BUTTON_PRESSED_COLOR.into();
+                match button_action {
+                    ButtonAction::NextHeat => {
+                        game_state.set(GameState::Playing);
+                    }
+                    ButtonAction::Substitute(team) => {
+                        meeting.cycle_substitute(*team);
+                    }
+                    ButtonAction::Joker(team) => {
+                        meeting.cycle_joker(*team);
+                    }
+                    ButtonAction::Season => {
+                        game_state.set(GameState::Career);
+                    }
+                    ButtonAction::Menu => {
+                        game_state.set(GameState::Menu);
+                    }
+                };
+            }
+            Interaction::Hovered => {
+                *color = BUTTON_HOVERED_COLOR.into();
+            }
+            Interaction::None => {
+                *color = BUTTON_NORMAL_COLOR.into();
+            }
+        }
+    }
+}
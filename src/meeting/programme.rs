@@ -0,0 +1,177 @@
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// The heat card of a meeting, loaded from a `.meeting.ron` file under
+/// `assets/meetings/`
+#[derive(Asset, TypePath, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MeetingProgramme {
+    pub name: String,
+    /// Riders in the meeting, who are numbered from one in the card
+    pub field: usize,
+    pub heats: Vec<ProgrammeHeat>,
+    /// The two sides, when the meeting is a team match
+    #[serde(default)]
+    pub teams: Option<TeamSheet>,
+}
+
+/// The sides of a team match. The first `riders` numbers in the card ride for the home
+/// team, and the rest of the field for the away team.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TeamSheet {
+    pub home: String,
+    pub away: String,
+    pub riders: usize,
+}
+
+/// One race of the meeting
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProgrammeHeat {
+    pub name: String,
+    /// Who starts from each gate, counting from the inside
+    pub gates: Vec<Gate>,
+    /// Whether the heat's points count towards the scoreboard. Semi-finals and finals
+    /// only decide who goes through and who wins.
+    #[serde(default = "scored_by_default")]
+    pub scored: bool,
+}
+
+fn scored_by_default() -> bool {
+    true
+}
+
+/// Who goes in a gate
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gate {
+    /// The rider with this number in the card
+    Rider(usize),
+    /// The rider in this place on the scoreboard once the heats before are run
+    Standing(usize),
+    /// The rider who finished in `place` of an earlier heat, both counting from one
+    Placed { heat: usize, place: usize },
+}
+
+impl MeetingProgramme {
+    /// Reads a meeting programme without an asset server
+    pub fn from_ron(bytes: &[u8]) -> Result<Self, MeetingProgrammeLoaderError> {
+        let programme: MeetingProgramme = ron::de::from_bytes(bytes)?;
+        programme.validate()?;
+        Ok(programme)
+    }
+
+    fn validate(&self) -> Result<(), MeetingProgrammeLoaderError> {
+        let invalid = |reason: String| Err(MeetingProgrammeLoaderError::Invalid(reason));
+        if self.field == 0 {
+            return invalid("the field needs at least one rider".to_string());
+        }
+        if self.heats.is_empty() {
+            return invalid("there must be at least one heat".to_string());
+        }
+        if let Some(teams) = &self.teams {
+            if teams.riders * 2 != self.field {
+                return invalid(format!(
+                    "two teams of {} riders don't make a field of {}",
+                    teams.riders, self.field
+                ));
+            }
+        }
+        for (index, heat) in self.heats.iter().enumerate() {
+            if heat.gates.is_empty() {
+                return invalid(format!("{} has no riders", heat.name));
+            }
+            let repeated = heat
+                .gates
+                .iter()
+                .enumerate()
+                .find(|(gate_index, gate)| heat.gates[..*gate_index].contains(gate));
+            if let Some((_, gate)) = repeated {
+                return invalid(format!("{} puts {gate:?} in two gates", heat.name));
+            }
+            for gate in &heat.gates {
+                let in_field = |number: usize| (1..=self.field).contains(&number);
+                match *gate {
+                    Gate::Rider(number) | Gate::Standing(number) if !in_field(number) => {
+                        return invalid(format!(
+                            "{} refers to rider {number} of a field of {}",
+                            heat.name, self.field
+                        ));
+                    }
+                    Gate::Placed {
+                        heat: earlier,
+                        place,
+                    } if earlier == 0 || earlier > index || place == 0 => {
+                        return invalid(format!(
+                            "{} takes place {place} of heat {earlier}, which isn't run before it",
+                            heat.name
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum MeetingProgrammeLoaderError {
+    #[error("could not read meeting programme: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse meeting programme: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("invalid meeting programme: {0}")]
+    Invalid(String),
+}
+
+#[derive(Default)]
+pub struct MeetingProgrammeLoader;
+
+impl AssetLoader for MeetingProgrammeLoader {
+    type Asset = MeetingProgramme;
+    type Settings = ();
+    type Error = MeetingProgrammeLoaderError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        MeetingProgramme::from_ron(&bytes)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["meeting.ron"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn programme(gates: Vec<Gate>) -> MeetingProgramme {
+        MeetingProgramme {
+            name: "Test".to_string(),
+            field: 4,
+            heats: vec![ProgrammeHeat {
+                name: "Heat 1".to_string(),
+                gates,
+                scored: true,
+            }],
+            teams: None,
+        }
+    }
+
+    #[test]
+    fn rejects_a_rider_in_two_gates() {
+        let repeated = programme(vec![Gate::Rider(1), Gate::Rider(2), Gate::Rider(1)]);
+        assert!(repeated.validate().is_err());
+        let distinct = programme(vec![Gate::Rider(1), Gate::Rider(2), Gate::Rider(3)]);
+        assert!(distinct.validate().is_ok());
+    }
+}
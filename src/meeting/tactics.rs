@@ -0,0 +1,208 @@
+use serde::{Deserialize, Serialize};
+
+use crate::team::Team;
+
+use super::{HeatRun, Meeting};
+
+/// Points a team has to be behind by before it may turn to tactics
+const TACTICAL_DEFICIT: usize = 6;
+
+/// What a team's manager has decided for the next heat
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Tactics {
+    pub substitute: Option<Substitution>,
+    /// Card number of the rider whose points count double in the heat
+    pub joker: Option<usize>,
+}
+
+/// A rider put in a heat in place of a teammate, by their card numbers
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Substitution {
+    pub replaced: usize,
+    pub substitute: usize,
+}
+
+impl HeatRun {
+    /// The points each rider of `team` brought the team in the heat, with a joker's
+    /// counting double
+    pub fn team_points(&self, team: Team, meeting: &Meeting) -> usize {
+        self.placings()
+            .filter(|(rider, ..)| meeting.rider(*rider).team == Some(team))
+            .map(|(rider, _, points)| {
+                if self.tactics[team.index()].joker == Some(rider) {
+                    points * 2
+                } else {
+                    points
+                }
+            })
+            .sum()
+    }
+}
+
+impl Meeting {
+    pub fn is_team_match(&self) -> bool {
+        self.programme.teams.is_some()
+    }
+
+    /// Card numbers of the riders of `team`
+    pub fn team_riders(&self, team: Team) -> Vec<usize> {
+        (1..=self.riders.len())
+            .filter(|number| self.rider(*number).team == Some(team))
+            .collect()
+    }
+
+    pub fn team_name(&self, team: Team) -> &str {
+        match (&self.programme.teams, team) {
+            (Some(teams), Team::Home) => &teams.home,
+            (Some(teams), Team::Away) => &teams.away,
+            (None, _) => "",
+        }
+    }
+
+    /// The team's match score from the heats that are scored
+    pub fn team_score(&self, team: Team) -> usize {
+        self.heats
+            .iter()
+            .zip(&self.programme.heats)
+            .filter(|(_, heat)| heat.scored)
+            .map(|(run, _)| run.team_points(team, self))
+            .sum()
+    }
+
+    /// What `team` has decided for the next heat
+    pub fn tactics(&self, team: Team) -> Tactics {
+        self.tactics[team.index()]
+    }
+
+    /// Whether the players pick the team's tactics rather than the computer
+    pub fn managed_by_players(&self, team: Team) -> bool {
+        self.team_riders(team)
+            .into_iter()
+            .any(|number| self.rider(number).player)
+    }
+
+    /// A team may only make changes for a scored heat, and only while it is well behind
+    pub fn can_use_tactics(&self, team: Team) -> bool {
+        self.is_team_match()
+            && self.next_heat().is_some_and(|heat| heat.scored)
+            && self.team_score(team) + TACTICAL_DEFICIT <= self.team_score(team.opposition())
+    }
+
+    /// Whether the team still has its joker to play. It can be played once a match.
+    pub fn has_joker(&self, team: Team) -> bool {
+        self.heats
+            .iter()
+            .all(|run| run.tactics[team.index()].joker.is_none())
+    }
+
+    /// Every change of rider `team` may make in the next heat. A rider can come in as
+    /// a tactical substitute once a match.
+    pub fn substitutions(&self, team: Team) -> Vec<Substitution> {
+        if !self.can_use_tactics(team) {
+            return Vec::new();
+        }
+        let line_up = self.card_line_up();
+        let team_riders = self.team_riders(team);
+        let in_heat = |number: &usize| line_up.contains(number);
+        let has_substituted = |number: &usize| {
+            self.heats.iter().any(|run| {
+                run.tactics[team.index()]
+                    .substitute
+                    .is_some_and(|substitution| substitution.substitute == *number)
+            })
+        };
+        let mut substitutions = Vec::new();
+        for replaced in team_riders.iter().filter(|number| in_heat(number)) {
+            for substitute in team_riders
+                .iter()
+                .filter(|number| !in_heat(number) && !has_substituted(number))
+            {
+                substitutions.push(Substitution {
+                    replaced: *replaced,
+                    substitute: *substitute,
+                });
+            }
+        }
+        substitutions
+    }
+
+    /// Riders of `team` in the next heat who the joker could be played on
+    pub fn joker_candidates(&self, team: Team) -> Vec<usize> {
+        if !self.can_use_tactics(team) || !self.has_joker(team) {
+            return Vec::new();
+        }
+        self.line_up()
+            .into_iter()
+            .filter(|number| self.rider(*number).team == Some(team))
+            .collect()
+    }
+
+    /// Moves on to the team's next choice of tactical substitute, or none after the last
+    pub fn cycle_substitute(&mut self, team: Team) {
+        let options = self.substitutions(team);
+        let tactics = &mut self.tactics[team.index()];
+        tactics.substitute = next_choice(&options, tactics.substitute);
+        // a rider who has been stood down can't be the joker
+        let joker_replaced = tactics
+            .substitute
+            .zip(tactics.joker)
+            .is_some_and(|(substitution, joker)| substitution.replaced == joker);
+        if joker_replaced {
+            tactics.joker = None;
+        }
+    }
+
+    /// Moves the team's joker on to the next rider in the heat, or off after the last
+    pub fn cycle_joker(&mut self, team: Team) {
+        let options = self.joker_candidates(team);
+        let tactics = &mut self.tactics[team.index()];
+        tactics.joker = next_choice(&options, tactics.joker);
+    }
+
+    /// Average points of the rider with card number `number` from their rides so far
+    fn average(&self, number: usize) -> f32 {
+        let rides = self.rides(number).len();
+        if rides == 0 {
+            return 0.0;
+        }
+        self.score(number).points as f32 / rides as f32
+    }
+
+    /// Decides the next heat's tactics for the teams the computer manages. A trailing
+    /// team brings in its best rider outside the heat for its weakest inside it, if that
+    /// is an improvement, and plays its joker on whoever is then riding best.
+    pub(super) fn plan_tactics(&mut self) {
+        for team in Team::BOTH {
+            if self.managed_by_players(team) || !self.can_use_tactics(team) {
+                continue;
+            }
+            let substitute = self
+                .substitutions(team)
+                .into_iter()
+                .filter(|substitution| {
+                    self.average(substitution.substitute) > self.average(substitution.replaced)
+                })
+                .max_by(|substitution, other| {
+                    let gain = |substitution: &Substitution| {
+                        self.average(substitution.substitute) - self.average(substitution.replaced)
+                    };
+                    gain(substitution).total_cmp(&gain(other))
+                });
+            self.tactics[team.index()].substitute = substitute;
+            let joker = self
+                .joker_candidates(team)
+                .into_iter()
+                .max_by(|number, other| self.average(*number).total_cmp(&self.average(*other)));
+            self.tactics[team.index()].joker = joker;
+        }
+    }
+}
+
+/// The option after `current`, the first if there is no current one, or none after the
+/// last
+fn next_choice<T: Copy + PartialEq>(options: &[T], current: Option<T>) -> Option<T> {
+    match current.and_then(|current| options.iter().position(|option| *option == current)) {
+        Some(index) => options.get(index + 1).copied(),
+        None => options.first().copied(),
+    }
+}
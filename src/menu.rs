@@ -1,14 +1,56 @@
 use bevy::prelude::*;
 
-use crate::GameState;
+use crate::{
+    loading::TrackAssets,
+    meeting::MeetingFormat,
+    network::{NetworkAddress, NetworkClient, NetworkHost},
+    opponent::OpponentDifficulties,
+    player::PlayerCount,
+    random::RaceSeed,
+    replay::{watch_replay, Replay},
+    save::resume_race,
+    track::{SelectedTrack, TrackDefinition},
+    GameState,
+};
+
+/// The opponent, counting from the inside lane, that the Level button sets the
+/// difficulty of
+#[derive(Resource, Debug, Default)]
+struct SelectedOpponent(usize);
+
+impl SelectedOpponent {
+    /// The opponent selected among `opponent_count`. The selection is kept when there
+    /// are fewer opponents, for when there are more again.
+    fn index(&self, opponent_count: usize) -> Option<usize> {
+        opponent_count.checked_sub(1).map(|last| self.0.min(last))
+    }
+}
 
 pub struct MenuPlugin;
 
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(GameState::Menu), setup_menu)
+        app.init_resource::<SelectedOpponent>()
+            .add_systems(OnEnter(GameState::Menu), setup_menu)
             .add_systems(OnExit(GameState::Menu), teardown_menu)
-            .add_systems(Update, button_system.run_if(in_state(GameState::Menu)));
+            .add_systems(
+                Update,
+                (
+                    button_system,
+                    update_track_name.run_if(resource_changed::<SelectedTrack>),
+                    edit_seed,
+                    update_seed_text.run_if(resource_changed::<RaceSeed>),
+                    update_difficulty_text.run_if(
+                        resource_changed::<OpponentDifficulties>
+                            .or_else(resource_changed::<SelectedOpponent>)
+                            .or_else(resource_changed::<SelectedTrack>)
+                            .or_else(resource_changed::<PlayerCount>),
+                    ),
+                    update_players_text.run_if(resource_changed::<PlayerCount>),
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Menu)),
+            );
     }
 }
 
@@ -23,14 +65,63 @@ const BUTTON_FONT_COLOR: Color = Color::srgb(0.9, 0.9, 0.9);
 #[derive(Component)]
 struct MenuItem;
 
+#[derive(Component)]
+struct TrackName;
+
+#[derive(Component)]
+struct SeedText;
+
+#[derive(Component)]
+struct DifficultyText;
+
+#[derive(Component)]
+struct PlayersText;
+
+const DIGIT_KEYS: [KeyCode; 10] = [
+    KeyCode::Digit0,
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+    KeyCode::Digit6,
+    KeyCode::Digit7,
+    KeyCode::Digit8,
+    KeyCode::Digit9,
+];
+
 #[derive(Component, Debug, PartialEq, Eq, Clone, Copy)]
 enum ButtonAction {
     Play,
+    Resume,
+    Meeting,
+    Match,
+    Career,
+    Track,
+    Opponent,
+    Level,
+    Players,
+    Host,
+    Join,
+    Replay,
     Quit,
 }
 
-fn setup_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
+fn setup_menu(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    selected_track: Res<SelectedTrack>,
+    track_assets: Res<TrackAssets>,
+    track_definitions: Res<Assets<TrackDefinition>>,
+    race_seed: Res<RaceSeed>,
+    opponent_difficulties: Res<OpponentDifficulties>,
+    selected_opponent: Res<SelectedOpponent>,
+    player_count: Res<PlayerCount>,
+) {
     let font_handle = asset_server.load("fonts/FiraSans-Bold.ttf");
+    let definition = selected_track.definition(&track_assets, &track_definitions);
+    let track_name = &definition.name;
+    let opponent_count = opponent_count(definition, &player_count);
     commands
         .spawn((
             MenuItem,
@@ -47,11 +138,93 @@ fn setup_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
             },
         ))
         .with_children(|parent| {
+            // buttons are paired up, so the menu still fits on a small window
+            parent.spawn(make_row()).with_children(|parent| {
+                parent
+                    .spawn((ButtonAction::Play, make_button()))
+                    .with_children(|parent| {
+                        parent.spawn(make_button_text("Play", font_handle.clone()));
+                    });
+                parent
+                    .spawn((ButtonAction::Resume, make_button()))
+                    .with_children(|parent| {
+                        parent.spawn(make_button_text("Resume", font_handle.clone()));
+                    });
+            });
+            parent.spawn((TrackName, make_button_text(track_name, font_handle.clone())));
+            parent
+                .spawn((ButtonAction::Track, make_button()))
+                .with_children(|parent| {
+                    parent.spawn(make_button_text("Track", font_handle.clone()));
+                });
+            parent.spawn((
+                SeedText,
+                make_button_text(&seed_text(&race_seed), font_handle.clone()),
+            ));
+            parent.spawn((
+                DifficultyText,
+                make_button_text(
+                    &difficulty_text(&opponent_difficulties, &selected_opponent, opponent_count),
+                    font_handle.clone(),
+                ),
+            ));
+            parent.spawn((
+                PlayersText,
+                make_button_text(&players_text(&player_count), font_handle.clone()),
+            ));
+            parent.spawn(make_row()).with_children(|parent| {
+                parent
+                    .spawn((ButtonAction::Opponent, make_button()))
+                    .with_children(|parent| {
+                        parent.spawn(make_button_text("Opponent", font_handle.clone()));
+                    });
+                parent
+                    .spawn((ButtonAction::Level, make_button()))
+                    .with_children(|parent| {
+                        parent.spawn(make_button_text("Level", font_handle.clone()));
+                    });
+            });
             parent
-                .spawn((ButtonAction::Play, make_button()))
+                .spawn((ButtonAction::Players, make_button()))
                 .with_children(|parent| {
-                    parent.spawn(make_button_text("Play", font_handle.clone()));
+                    parent.spawn(make_button_text("Players", font_handle.clone()));
                 });
+            parent.spawn(make_row()).with_children(|parent| {
+                parent
+                    .spawn((ButtonAction::Host, make_button()))
+                    .with_children(|parent| {
+                        parent.spawn(make_button_text("Host", font_handle.clone()));
+                    });
+                parent
+                    .spawn((ButtonAction::Join, make_button()))
+                    .with_children(|parent| {
+                        parent.spawn(make_button_text("Join", font_handle.clone()));
+                    });
+            });
+            parent.spawn(make_row()).with_children(|parent| {
+                parent
+                    .spawn((ButtonAction::Meeting, make_button()))
+                    .with_children(|parent| {
+                        parent.spawn(make_button_text("Meeting", font_handle.clone()));
+                    });
+                parent
+                    .spawn((ButtonAction::Match, make_button()))
+                    .with_children(|parent| {
+                        parent.spawn(make_button_text("Match", font_handle.clone()));
+                    });
+            });
+            parent.spawn(make_row()).with_children(|parent| {
+                parent
+                    .spawn((ButtonAction::Career, make_button()))
+                    .with_children(|parent| {
+                        parent.spawn(make_button_text("Career", font_handle.clone()));
+                    });
+                parent
+                    .spawn((ButtonAction::Replay, make_button()))
+                    .with_children(|parent| {
+                        parent.spawn(make_button_text("Replay", font_handle.clone()));
+                    });
+            });
             parent
                 .spawn((ButtonAction::Quit, make_button()))
                 .with_children(|parent| {
@@ -60,6 +233,16 @@ fn setup_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
         });
 }
 
+fn make_row() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            flex_direction: FlexDirection::Row,
+            ..default()
+        },
+        ..default()
+    }
+}
+
 fn make_button() -> ButtonBundle {
     ButtonBundle {
         style: Style {
@@ -70,7 +253,7 @@ fn make_button() -> ButtonBundle {
             justify_content: JustifyContent::Center,
             // vertically center child text
             align_items: AlignItems::Center,
-            margin: UiRect::all(Val::Px(20.0)),
+            margin: UiRect::all(Val::Px(10.0)),
             ..default()
         },
         border_color: BorderColor(Color::BLACK),
@@ -102,8 +285,17 @@ fn button_system(
         (&ButtonAction, &Interaction, &mut BackgroundColor),
         (Changed<Interaction>, With<Button>),
     >,
+    mut commands: Commands,
     mut game_state: ResMut<NextState<GameState>>,
     mut app_exit_events: EventWriter<AppExit>,
+    mut selected_track: ResMut<SelectedTrack>,
+    track_assets: Res<TrackAssets>,
+    track_definitions: Res<Assets<TrackDefinition>>,
+    mut opponent_difficulties: ResMut<OpponentDifficulties>,
+    mut selected_opponent: ResMut<SelectedOpponent>,
+    mut player_count: ResMut<PlayerCount>,
+    network_address: Res<NetworkAddress>,
+    mut meeting_format: ResMut<MeetingFormat>,
 ) {
     for (button_action, interaction, mut color) in &mut interaction_query {
         match *interaction {
@@ -113,6 +305,71 @@ fn button_system(
                     ButtonAction::Play => {
                         game_state.set(GameState::Playing);
                     }
+                    ButtonAction::Meeting => {
+                        *meeting_format = MeetingFormat::Individual;
+                        game_state.set(GameState::Meeting);
+                    }
+                    ButtonAction::Match => {
+                        *meeting_format = MeetingFormat::TeamMatch;
+                        game_state.set(GameState::Meeting);
+                    }
+                    ButtonAction::Career => {
+                        game_state.set(GameState::Career);
+                    }
+                    ButtonAction::Resume => {
+                        match resume_race(&mut commands, &mut selected_track, &track_assets) {
+                            Ok(()) => game_state.set(GameState::Playing),
+                            Err(error) => warn!("Could not resume the saved race: {error}"),
+                        }
+                    }
+                    ButtonAction::Track => {
+                        selected_track.index = track_assets.next_loaded(selected_track.index);
+                    }
+                    ButtonAction::Opponent => {
+                        let definition =
+                            selected_track.definition(&track_assets, &track_definitions);
+                        let opponent_count = opponent_count(definition, &player_count);
+                        if let Some(index) = selected_opponent.index(opponent_count) {
+                            selected_opponent.0 = (index + 1) % opponent_count;
+                        }
+                    }
+                    ButtonAction::Level => {
+                        let definition =
+                            selected_track.definition(&track_assets, &track_definitions);
+                        let opponent_count = opponent_count(definition, &player_count);
+                        if let Some(index) = selected_opponent.index(opponent_count) {
+                            opponent_difficulties.cycle(index);
+                        }
+                    }
+                    ButtonAction::Players => {
+                        player_count.cycle();
+                    }
+                    ButtonAction::Host => match NetworkHost::listen(&network_address.0) {
+                        Ok(host) => {
+                            commands.insert_resource(host);
+                            game_state.set(GameState::Lobby);
+                        }
+                        Err(error) => {
+                            warn!("Could not host a race at {}: {error}", network_address.0)
+                        }
+                    },
+                    ButtonAction::Join => match NetworkClient::join(&network_address.0) {
+                        Ok(client) => {
+                            commands.insert_resource(client);
+                            game_state.set(GameState::Lobby);
+                        }
+                        Err(error) => {
+                            warn!("Could not join a race at {}: {error}", network_address.0)
+                        }
+                    },
+                    ButtonAction::Replay => {
+                        match Replay::latest().and_then(|path| {
+                            watch_replay(&path, &mut commands, &mut selected_track, &track_assets)
+                        }) {
+                            Ok(()) => game_state.set(GameState::Replay),
+                            Err(error) => warn!("Could not open the latest replay: {error}"),
+                        }
+                    }
                     ButtonAction::Quit => {
                         app_exit_events.send(AppExit::Success);
                     }
@@ -127,3 +384,97 @@ fn button_system(
         }
     }
 }
+
+fn update_track_name(
+    mut q_track_name: Query<&mut Text, With<TrackName>>,
+    selected_track: Res<SelectedTrack>,
+    track_assets: Res<TrackAssets>,
+    track_definitions: Res<Assets<TrackDefinition>>,
+) {
+    let definition = selected_track.definition(&track_assets, &track_definitions);
+    for mut text in q_track_name.iter_mut() {
+        text.sections[0].value.clone_from(&definition.name);
+    }
+}
+
+/// Opponents that race the players on the selected track, one in each lane left over
+fn opponent_count(definition: &TrackDefinition, player_count: &PlayerCount) -> usize {
+    definition.lane_count.saturating_sub(player_count.0)
+}
+
+fn difficulty_text(
+    opponent_difficulties: &OpponentDifficulties,
+    selected_opponent: &SelectedOpponent,
+    opponent_count: usize,
+) -> String {
+    let Some(index) = selected_opponent.index(opponent_count) else {
+        return "No opponents".to_string();
+    };
+    format!(
+        "Opponent {} of {opponent_count}: {}",
+        index + 1,
+        opponent_difficulties.for_opponent(index).name()
+    )
+}
+
+fn update_difficulty_text(
+    mut q_difficulty_text: Query<&mut Text, With<DifficultyText>>,
+    opponent_difficulties: Res<OpponentDifficulties>,
+    selected_opponent: Res<SelectedOpponent>,
+    selected_track: Res<SelectedTrack>,
+    track_assets: Res<TrackAssets>,
+    track_definitions: Res<Assets<TrackDefinition>>,
+    player_count: Res<PlayerCount>,
+) {
+    let definition = selected_track.definition(&track_assets, &track_definitions);
+    let opponent_count = opponent_count(definition, &player_count);
+    for mut text in q_difficulty_text.iter_mut() {
+        text.sections[0].value =
+            difficulty_text(&opponent_difficulties, &selected_opponent, opponent_count);
+    }
+}
+
+fn players_text(player_count: &PlayerCount) -> String {
+    match player_count.0 {
+        1 => "Players: 1".to_string(),
+        count => format!("Players: {count} taking turns"),
+    }
+}
+
+fn update_players_text(
+    mut q_players_text: Query<&mut Text, With<PlayersText>>,
+    player_count: Res<PlayerCount>,
+) {
+    for mut text in q_players_text.iter_mut() {
+        text.sections[0].value = players_text(&player_count);
+    }
+}
+
+fn seed_text(race_seed: &RaceSeed) -> String {
+    match race_seed.0 {
+        Some(seed) => format!("Seed: {seed}"),
+        None => "Seed: random (type to set)".to_string(),
+    }
+}
+
+/// Typing digits sets the seed for the next races, and backspace removes them again
+fn edit_seed(keyboard_input: Res<ButtonInput<KeyCode>>, mut race_seed: ResMut<RaceSeed>) {
+    for (digit, key) in DIGIT_KEYS.iter().enumerate() {
+        if keyboard_input.just_pressed(*key) {
+            let seed = race_seed.0.unwrap_or(0);
+            race_seed.0 = seed
+                .checked_mul(10)
+                .and_then(|seed| seed.checked_add(digit as u64))
+                .or(Some(seed));
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Backspace) {
+        race_seed.0 = race_seed.0.map(|seed| seed / 10).filter(|seed| *seed > 0);
+    }
+}
+
+fn update_seed_text(mut q_seed_text: Query<&mut Text, With<SeedText>>, race_seed: Res<RaceSeed>) {
+    for mut text in q_seed_text.iter_mut() {
+        text.sections[0].value = seed_text(&race_seed);
+    }
+}
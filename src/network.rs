@@ -0,0 +1,1094 @@
+mod lobby;
+mod protocol;
+
+use std::{
+    collections::VecDeque, io::ErrorKind, net::TcpListener, thread::JoinHandle, time::Duration,
+};
+
+use bevy::prelude::*;
+
+use crate::{
+    actions::{on_action, ActionEvent, BikeAction},
+    bike::Bike,
+    bot::BotCommands,
+    config::Config,
+    game::{draw_starting_grid, Finished, Rider},
+    loading::TrackAssets,
+    opponent::OpponentDifficulties,
+    player::{Player, PlayerCount, RemotePlayer, MAX_PLAYERS},
+    random::{seed_race, RaceSeed, Randomness},
+    simulation::TurnPhaseSet,
+    start_gate::{release_tapes, Launch, StartEvent, StartGate},
+    track::SelectedTrack,
+    GameState, PlayingState, RacingState,
+};
+
+use self::{
+    lobby::LobbyPlugin,
+    protocol::{checksum, Connection, Message, NetworkError, RaceSettings, PROTOCOL_VERSION},
+};
+
+const ADDRESS_ARGUMENT: &str = "--address";
+const DEFAULT_ADDRESS: &str = "127.0.0.1:7878";
+/// How often a client that lost the host tries to reach it again
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Races against people on other machines. One peer hosts: the clients send it the
+/// action their player chooses each turn, and it sends every rider's action back
+/// once it has them all. Launches at the tapes go the same way. Each peer then
+/// simulates the turn for itself, which comes out the same everywhere, so only the
+/// actions cross the network.
+pub struct NetworkPlugin;
+
+impl Plugin for NetworkPlugin {
+    fn build(&self, app: &mut App) {
+        let address = NetworkAddress::from_environment(Config::of(app));
+        app.insert_resource(address)
+            .add_event::<StartNetworkRace>()
+            .add_event::<RaceStarting>()
+            .add_plugins(LobbyPlugin)
+            .add_systems(
+                Update,
+                (
+                    (
+                        poll_host.run_if(resource_exists::<NetworkHost>),
+                        poll_client.run_if(resource_exists::<NetworkClient>),
+                        start_hosted_race.run_if(resource_exists::<NetworkHost>),
+                    ),
+                    begin_race,
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Lobby)),
+            )
+            .add_systems(
+                Update,
+                (
+                    poll_host.run_if(resource_exists::<NetworkHost>),
+                    poll_client.run_if(resource_exists::<NetworkClient>),
+                    check_checksums.run_if(resource_exists::<NetworkHost>),
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Playing).and_then(resource_exists::<NetworkRace>)),
+            )
+            .add_systems(
+                Update,
+                (
+                    take_remote_commands
+                        .after(poll_host)
+                        .before(on_action)
+                        .run_if(resource_exists::<NetworkHost>),
+                    (send_command, start_turn_from_host)
+                        .after(poll_client)
+                        .after(on_action)
+                        .run_if(resource_exists::<NetworkClient>),
+                )
+                    .run_if(
+                        in_state(GameState::Playing)
+                            .and_then(in_state(RacingState::Commanding))
+                            .and_then(resource_exists::<NetworkRace>),
+                    ),
+            )
+            .add_systems(
+                Update,
+                (
+                    take_remote_launches
+                        .after(poll_host)
+                        .before(release_tapes)
+                        .run_if(resource_exists::<NetworkHost>),
+                    (send_launch, take_host_launches)
+                        .after(poll_client)
+                        .before(release_tapes)
+                        .run_if(resource_exists::<NetworkClient>),
+                )
+                    .run_if(
+                        in_state(GameState::Playing)
+                            .and_then(in_state(RacingState::Tapes))
+                            .and_then(resource_exists::<NetworkRace>)
+                            .and_then(resource_exists::<StartGate>),
+                    ),
+            )
+            .add_systems(
+                Update,
+                broadcast_launches.after(release_tapes).run_if(
+                    in_state(GameState::Playing)
+                        .and_then(resource_exists::<NetworkHost>)
+                        .and_then(on_event::<StartEvent>()),
+                ),
+            )
+            .add_systems(
+                OnEnter(PlayingState::SetupRace),
+                seed_network_race
+                    .after(seed_race)
+                    .before(draw_starting_grid)
+                    .run_if(resource_exists::<NetworkRace>),
+            )
+            .add_systems(
+                OnEnter(PlayingState::Racing),
+                seat_players.run_if(resource_exists::<NetworkRace>),
+            )
+            .add_systems(
+                OnEnter(RacingState::Commanding),
+                check_bikes
+                    .after(TurnPhaseSet)
+                    .run_if(in_state(GameState::Playing).and_then(resource_exists::<NetworkRace>)),
+            )
+            .add_systems(
+                OnEnter(RacingState::Simulating),
+                (
+                    broadcast_turn.run_if(resource_exists::<NetworkHost>),
+                    count_turn,
+                )
+                    .chain()
+                    .before(TurnPhaseSet)
+                    .run_if(in_state(GameState::Playing).and_then(resource_exists::<NetworkRace>)),
+            )
+            .add_systems(OnEnter(GameState::Menu), end_session);
+    }
+}
+
+/// Where a race is hosted, given as `--address <host:port>` on the command line, or
+/// else as `address` in the config file
+#[derive(Resource, Debug, Clone, PartialEq, Eq)]
+pub struct NetworkAddress(pub String);
+
+impl NetworkAddress {
+    pub fn from_environment(config: &Config) -> Self {
+        let arguments: Vec<String> = std::env::args().collect();
+        let argument_address = arguments
+            .windows(2)
+            .find(|pair| pair[0] == ADDRESS_ARGUMENT)
+            .map(|pair| pair[1].clone());
+        Self(
+            argument_address
+                .or_else(|| config.address.clone())
+                .unwrap_or_else(|| DEFAULT_ADDRESS.to_string()),
+        )
+    }
+}
+
+/// Hosts a race, deciding when each turn starts. The host's own player sits in seat
+/// zero.
+#[derive(Resource)]
+pub struct NetworkHost {
+    listener: TcpListener,
+    /// Connections that have yet to say who they are
+    arrivals: Vec<Connection>,
+    /// Clients by seat, from seat one. A seat is kept through the race for a client
+    /// that loses its connection, so it can come back.
+    seats: Vec<Option<Connection>>,
+    /// Actions sent by clients, as (seat, turn, action), until their turn comes
+    commands: Vec<(usize, usize, BikeAction)>,
+    /// Launches sent by clients, as (seat, call, launch), until their call comes
+    launch_commands: Vec<(usize, usize, Launch)>,
+    /// Every rider's launch at each call to the tapes so far, to send to clients that
+    /// reconnect
+    launches: Vec<Vec<(usize, Launch)>>,
+    /// Checksums sent by clients, as (seat, turn, checksum), until the host has its own
+    checksums: Vec<(usize, usize, u64)>,
+    /// The host's own checksum for each turn so far
+    own_checksums: Vec<u64>,
+    /// Every rider's action in each turn so far, to send to clients that reconnect
+    turns: Vec<Vec<(usize, BikeAction)>>,
+}
+
+/// Takes part in a race hosted on another machine
+#[derive(Resource)]
+pub struct NetworkClient {
+    address: String,
+    connection: Option<Connection>,
+    seat: Option<usize>,
+    /// Players in the lobby, as last heard from the host
+    players: usize,
+    /// Turns the host has started that have yet to be raced here, as (turn, actions)
+    turns: VecDeque<(usize, Vec<(usize, BikeAction)>)>,
+    /// Every rider's launch at each call to the tapes the host has put the tapes up on
+    launches: Vec<Vec<(usize, Launch)>>,
+    /// The last launch sent, as (call, launch), which is sent again after reconnecting
+    /// in case the host never got it
+    sent_launch: Option<(usize, Launch)>,
+    /// The last action sent, as (turn, action), which is sent again after reconnecting
+    /// in case the host never got it
+    sent_command: Option<(usize, BikeAction)>,
+    reconnect_timer: Timer,
+    /// Attempt to reach the host again, made away from the game so it keeps running
+    connecting: Option<JoinHandle<Result<Connection, NetworkError>>>,
+}
+
+/// The network race under way, whichever end of it this is
+#[derive(Resource, Debug, Clone)]
+pub struct NetworkRace {
+    settings: RaceSettings,
+    /// Seat of the player at this machine, counting the riders of the players from
+    /// the inside lane
+    seat: usize,
+    /// Turn being chosen or simulated, counting from zero
+    turn: usize,
+    /// Settings of this machine's own races, to go back to after this one
+    own_settings: (
+        SelectedTrack,
+        OpponentDifficulties,
+        PlayerCount,
+        BotCommands,
+    ),
+}
+
+/// Asks the host to start the race with everyone in the lobby
+#[derive(Event)]
+pub struct StartNetworkRace;
+
+/// A race starting with these settings, with this machine's player in the seat
+#[derive(Event)]
+struct RaceStarting(RaceSettings, usize);
+
+impl NetworkHost {
+    pub fn listen(address: &str) -> Result<Self, NetworkError> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            arrivals: Vec::new(),
+            seats: Vec::new(),
+            commands: Vec::new(),
+            launch_commands: Vec::new(),
+            launches: Vec::new(),
+            checksums: Vec::new(),
+            own_checksums: Vec::new(),
+            turns: Vec::new(),
+        })
+    }
+
+    /// Players in the race, counting the host
+    pub fn players(&self) -> usize {
+        self.seats.len() + 1
+    }
+
+    /// Seats of the clients that have lost their connection
+    pub fn missing_seats(&self) -> impl Iterator<Item = usize> + '_ {
+        self.seats
+            .iter()
+            .enumerate()
+            .filter(|(_, connection)| connection.is_none())
+            .map(|(index, _)| index + 1)
+    }
+
+    fn broadcast(&mut self, message: &Message) {
+        for (index, slot) in self.seats.iter_mut().enumerate() {
+            if let Some(connection) = slot {
+                if let Err(error) = connection.send(message) {
+                    warn!("Lost player {}: {error}", index + 2);
+                    *slot = None;
+                }
+            }
+        }
+    }
+
+    /// Gives the clients new seats after one leaves the lobby, so there are no gaps
+    fn close_up_seats(&mut self) {
+        self.seats.retain(Option::is_some);
+        for (index, slot) in self.seats.iter_mut().enumerate() {
+            if let Some(connection) = slot {
+                if connection
+                    .send(&Message::Welcome { seat: index + 1 })
+                    .is_err()
+                {
+                    *slot = None;
+                }
+            }
+        }
+        self.seats.retain(Option::is_some);
+        let players = self.players();
+        self.broadcast(&Message::Lobby { players });
+    }
+
+    /// Answers a client that has said who it is. `rest` is what else it sent along
+    /// with saying so.
+    fn greet(
+        &mut self,
+        mut connection: Connection,
+        version: u32,
+        seat: Option<usize>,
+        next_turn: usize,
+        rest: Vec<Message>,
+        in_race: bool,
+    ) {
+        let refusal = if version != PROTOCOL_VERSION {
+            Some(format!(
+                "the host runs version {PROTOCOL_VERSION} of the game's protocol, not {version}"
+            ))
+        } else {
+            match (seat, in_race) {
+                (None, false) if self.players() < MAX_PLAYERS => {
+                    self.seats.push(None);
+                    let seat = self.players() - 1;
+                    self.seat(connection, seat, next_turn, rest);
+                    // lets everyone know how many are here, or gives up the seat again
+                    // if the client went before it could sit down
+                    self.close_up_seats();
+                    return;
+                }
+                (None, false) => Some("the race is full".to_string()),
+                (None, true) => Some("the race has already started".to_string()),
+                (Some(seat), true) if (1..self.players()).contains(&seat) => {
+                    self.seat(connection, seat, next_turn, rest);
+                    return;
+                }
+                (Some(_), _) => Some("that seat isn't in this race".to_string()),
+            }
+        };
+        if let Some(reason) = refusal {
+            // the client is going anyway, so whether it hears why doesn't matter
+            let _ = connection.send(&Message::Refused(reason));
+        }
+    }
+
+    /// Sits a client down, and catches it up on the start and the turns it missed while
+    /// away. `rest` is what it sent after asking for the seat.
+    fn seat(
+        &mut self,
+        mut connection: Connection,
+        seat: usize,
+        next_turn: usize,
+        rest: Vec<Message>,
+    ) {
+        let mut messages = vec![Message::Welcome { seat }];
+        messages.extend(self.launches.iter().enumerate().map(|(call, launches)| {
+            Message::Launches {
+                call,
+                launches: launches.clone(),
+            }
+        }));
+        messages.extend(
+            self.turns
+                .iter()
+                .enumerate()
+                .skip(next_turn)
+                .map(|(turn, actions)| Message::Turn {
+                    turn,
+                    actions: actions.clone(),
+                }),
+        );
+        let sent = messages
+            .iter()
+            .try_for_each(|message| connection.send(message));
+        match sent {
+            Ok(()) => {
+                self.seats[seat - 1] = Some(connection);
+                self.take_messages(seat, rest);
+            }
+            Err(error) => warn!("Could not seat player {}: {error}", seat + 1),
+        }
+    }
+
+    /// Keeps what the client in `seat` sent until the race gets to it
+    fn take_messages(&mut self, seat: usize, messages: Vec<Message>) {
+        for message in messages {
+            match message {
+                Message::Launch { call, launch } => {
+                    self.launch_commands.push((seat, call, launch));
+                }
+                Message::Command { turn, action } => self.commands.push((seat, turn, action)),
+                Message::Checksum { turn, checksum } => {
+                    self.checksums.push((seat, turn, checksum));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Takes in new connections and everything the clients have sent. `in_race` is
+    /// whether the race has started.
+    fn poll(&mut self, in_race: bool) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => match Connection::new(stream) {
+                    Ok(connection) => self.arrivals.push(connection),
+                    Err(error) => warn!("Could not accept a player: {error}"),
+                },
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) => {
+                    warn!("Could not accept a player: {error}");
+                    break;
+                }
+            }
+        }
+        for mut connection in std::mem::take(&mut self.arrivals) {
+            let mut messages = match connection.receive() {
+                Ok(messages) => messages.into_iter(),
+                Err(error) => {
+                    warn!("A player could not join: {error}");
+                    continue;
+                }
+            };
+            match messages.next() {
+                Some(Message::Hello {
+                    version,
+                    seat,
+                    next_turn,
+                }) => {
+                    let rest = messages.collect();
+                    self.greet(connection, version, seat, next_turn, rest, in_race);
+                }
+                Some(message) => {
+                    warn!("Turned away a player whose first message was {message:?}, not Hello");
+                    // the connection is dropped anyway, so whether it hears why doesn't matter
+                    let _ = connection.send(&Message::Refused("say hello first".to_string()));
+                }
+                None => self.arrivals.push(connection),
+            }
+        }
+        let mut lost_anyone = false;
+        for seat in 1..self.players() {
+            let Some(connection) = &mut self.seats[seat - 1] else {
+                continue;
+            };
+            match connection.receive() {
+                Ok(messages) => self.take_messages(seat, messages),
+                Err(error) => {
+                    warn!("Lost player {}: {error}", seat + 1);
+                    lost_anyone = true;
+                    self.seats[seat - 1] = None;
+                }
+            }
+        }
+        if !in_race && lost_anyone {
+            self.close_up_seats();
+        }
+    }
+
+    /// Compares the checksums clients sent with the host's own, and tells everyone the
+    /// race is over at the first that doesn't match. Gives the turn it stopped matching
+    /// in.
+    fn check_checksums(&mut self) -> Option<usize> {
+        let own_checksums = &self.own_checksums;
+        let mismatch = self
+            .checksums
+            .iter()
+            .find(|(_, turn, checksum)| own_checksums.get(*turn).is_some_and(|own| own != checksum))
+            .map(|(seat, turn, _)| (*seat, *turn));
+        self.checksums
+            .retain(|(_, turn, _)| *turn >= own_checksums.len());
+        let (seat, turn) = mismatch?;
+        warn!("Player {}'s race stopped matching in turn {turn}", seat + 1);
+        self.broadcast(&Message::Desync { turn });
+        Some(turn)
+    }
+}
+
+impl NetworkClient {
+    pub fn join(address: &str) -> Result<Self, NetworkError> {
+        let mut connection = Connection::connect(address)?;
+        connection.send(&Message::Hello {
+            version: PROTOCOL_VERSION,
+            seat: None,
+            next_turn: 0,
+        })?;
+        Ok(Self {
+            address: address.to_string(),
+            connection: Some(connection),
+            seat: None,
+            players: 0,
+            turns: VecDeque::new(),
+            launches: Vec::new(),
+            sent_launch: None,
+            sent_command: None,
+            reconnect_timer: Timer::new(RECONNECT_INTERVAL, TimerMode::Repeating),
+            connecting: None,
+        })
+    }
+
+    /// The seat the host gave this client, once it has
+    pub fn seat(&self) -> Option<usize> {
+        self.seat
+    }
+
+    pub fn players(&self) -> usize {
+        self.players
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    fn send(&mut self, message: &Message) {
+        if let Some(connection) = &mut self.connection {
+            if let Err(error) = connection.send(message) {
+                warn!("Lost the host: {error}");
+                self.connection = None;
+            }
+        }
+    }
+}
+
+fn poll_host(mut host: ResMut<NetworkHost>, race: Option<Res<NetworkRace>>) {
+    host.poll(race.is_some());
+}
+
+fn poll_client(
+    mut client: ResMut<NetworkClient>,
+    race: Option<Res<NetworkRace>>,
+    playing_state: Option<Res<State<PlayingState>>>,
+    time: Res<Time>,
+    mut race_starting: EventWriter<RaceStarting>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    let client = client.as_mut();
+    let racing = playing_state.is_some_and(|state| *state.get() == PlayingState::Racing);
+    let Some(connection) = client.connection.as_mut() else {
+        if racing {
+            reconnect(client, race.as_deref(), time.delta());
+        }
+        return;
+    };
+    let messages = match connection.receive() {
+        Ok(messages) => messages,
+        Err(error) => {
+            client.connection = None;
+            if race.is_none() {
+                warn!("Lost the host: {error}");
+                game_state.set(GameState::Menu);
+            } else if racing {
+                warn!("Lost the host, trying to reconnect: {error}");
+            }
+            return;
+        }
+    };
+    for message in messages {
+        match message {
+            Message::Welcome { seat } => {
+                client.seat = Some(seat);
+                // the host may never have got the last launch or action before the
+                // connection went
+                if let (Some(race), Some((call, launch))) = (&race, client.sent_launch) {
+                    if race.turn == 0 && call == client.launches.len() {
+                        client.send(&Message::Launch { call, launch });
+                    }
+                }
+                if let (Some(race), Some((turn, action))) = (&race, client.sent_command) {
+                    if turn == race.turn {
+                        client.send(&Message::Command { turn, action });
+                    }
+                }
+            }
+            Message::Refused(reason) => {
+                warn!("The host turned us away: {reason}");
+                game_state.set(GameState::Menu);
+            }
+            Message::Lobby { players } => client.players = players,
+            Message::Start(settings) => {
+                if let (None, Some(seat)) = (&race, client.seat) {
+                    race_starting.send(RaceStarting(settings, seat));
+                }
+            }
+            // calls sent again after reconnecting may already have arrived
+            Message::Launches { call, launches } if call == client.launches.len() => {
+                client.launches.push(launches);
+            }
+            Message::Turn { turn, actions } => {
+                // turns sent again after reconnecting may already have arrived
+                let next_turn = race.as_ref().map_or(0, |race| race.turn) + client.turns.len();
+                if turn == next_turn {
+                    client.turns.push_back((turn, actions));
+                }
+            }
+            Message::Desync { turn } => {
+                warn!("This race stopped matching the host's in turn {turn}");
+                game_state.set(GameState::Menu);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Tries to reach the host again every so often. Connecting is done on another
+/// thread, so the game carries on while the host is slow to answer.
+fn reconnect(client: &mut NetworkClient, race: Option<&NetworkRace>, delta: Duration) {
+    let Some(seat) = client.seat else {
+        return;
+    };
+    match client.connecting.take() {
+        Some(connecting) if connecting.is_finished() => {
+            // turns raced while connecting count, so they are asked from after them
+            let next_turn = race.map_or(0, |race| race.turn) + client.turns.len();
+            let hello = Message::Hello {
+                version: PROTOCOL_VERSION,
+                seat: Some(seat),
+                next_turn,
+            };
+            let connected = connecting
+                .join()
+                .unwrap_or_else(|_| Err(NetworkError::Closed))
+                .and_then(|mut connection| {
+                    connection.send(&hello)?;
+                    Ok(connection)
+                });
+            match connected {
+                Ok(connection) => client.connection = Some(connection),
+                Err(error) => debug!("Could not reconnect to the host: {error}"),
+            }
+        }
+        Some(connecting) => client.connecting = Some(connecting),
+        None => {
+            if client.reconnect_timer.tick(delta).just_finished() {
+                let address = client.address.clone();
+                client.connecting = Some(std::thread::spawn(move || Connection::connect(&address)));
+            }
+        }
+    }
+}
+
+fn start_hosted_race(
+    mut start_events: EventReader<StartNetworkRace>,
+    mut host: ResMut<NetworkHost>,
+    race_seed: Res<RaceSeed>,
+    selected_track: Res<SelectedTrack>,
+    opponent_difficulties: Res<OpponentDifficulties>,
+    mut race_starting: EventWriter<RaceStarting>,
+) {
+    if start_events.read().last().is_none() {
+        return;
+    }
+    // anyone who left the lobby without being noticed yet gives up their seat
+    if host.missing_seats().next().is_some() {
+        host.close_up_seats();
+    }
+    let settings = RaceSettings {
+        seed: race_seed.0.unwrap_or_else(|| fastrand::u64(..)),
+        track: selected_track.index,
+        opponents: opponent_difficulties.0.clone(),
+        players: host.players(),
+    };
+    host.broadcast(&Message::Start(settings.clone()));
+    race_starting.send(RaceStarting(settings, 0));
+}
+
+/// Takes on the race's settings in place of this machine's own, and starts it
+fn begin_race(
+    mut commands: Commands,
+    mut race_starting: EventReader<RaceStarting>,
+    mut selected_track: ResMut<SelectedTrack>,
+    mut opponent_difficulties: ResMut<OpponentDifficulties>,
+    mut player_count: ResMut<PlayerCount>,
+    mut bot_commands: ResMut<BotCommands>,
+    mut game_state: ResMut<NextState<GameState>>,
+    track_assets: Res<TrackAssets>,
+) {
+    let Some(RaceStarting(settings, seat)) = race_starting.read().last() else {
+        return;
+    };
+    if !track_assets.is_loaded(settings.track) {
+        warn!("Could not join the race: its track did not load on this machine");
+        game_state.set(GameState::Menu);
+        return;
+    }
+    commands.insert_resource(NetworkRace {
+        settings: settings.clone(),
+        seat: *seat,
+        turn: 0,
+        own_settings: (
+            *selected_track,
+            opponent_difficulties.clone(),
+            *player_count,
+            bot_commands.clone(),
+        ),
+    });
+    selected_track.index = settings.track;
+    opponent_difficulties.0.clone_from(&settings.opponents);
+    *player_count = PlayerCount(settings.players);
+    // bots only run on the machine they are set up on, so they sit network races out
+    bot_commands.0.clear();
+    game_state.set(GameState::Playing);
+}
+
+/// Every peer draws the grid and races with the luck of the host's seed
+fn seed_network_race(race: Res<NetworkRace>, mut randomness: ResMut<Randomness>) {
+    *randomness = Randomness::from_seed(race.settings.seed);
+}
+
+/// Marks the riders of the players at the other machines, which are in seat order
+/// from the inside lane
+fn seat_players(
+    mut commands: Commands,
+    race: Res<NetworkRace>,
+    q_players: Query<(Entity, &Rider), With<Player>>,
+) {
+    let mut players: Vec<_> = q_players.iter().collect();
+    players.sort_by_key(|(_, rider)| rider.number());
+    for (seat, (entity, _)) in players.into_iter().enumerate() {
+        if seat != race.seat {
+            commands.entity(entity).insert(RemotePlayer);
+        }
+    }
+}
+
+/// Works out the checksum of the bikes as the turn starts. Clients send theirs to the
+/// host, which compares them with its own.
+fn check_bikes(
+    race: Res<NetworkRace>,
+    q_bikes: Query<(&Rider, &Bike)>,
+    host: Option<ResMut<NetworkHost>>,
+    client: Option<ResMut<NetworkClient>>,
+) {
+    let mut bikes: Vec<(usize, Bike)> = q_bikes
+        .iter()
+        .map(|(rider, bike)| (rider.number(), *bike))
+        .collect();
+    bikes.sort_by_key(|(number, _)| *number);
+    let checksum = checksum(&bikes);
+    if let Some(mut host) = host {
+        host.own_checksums.push(checksum);
+    }
+    if let Some(mut client) = client {
+        client.send(&Message::Checksum {
+            turn: race.turn,
+            checksum,
+        });
+    }
+}
+
+fn check_checksums(mut host: ResMut<NetworkHost>, mut game_state: ResMut<NextState<GameState>>) {
+    if host.check_checksums().is_some() {
+        game_state.set(GameState::Menu);
+    }
+}
+
+/// Hands the launches clients sent for this call to the tapes to their riders
+fn take_remote_launches(
+    mut commands: Commands,
+    mut host: ResMut<NetworkHost>,
+    start_gate: Res<StartGate>,
+    q_players: Query<(Entity, &Rider, Has<Finished>), With<Player>>,
+) {
+    let mut players: Vec<_> = q_players.iter().collect();
+    players.sort_by_key(|(_, rider, _)| rider.number());
+    host.launch_commands.retain(|(seat, call, launch)| {
+        if *call == start_gate.call {
+            // riders excluded at an earlier call have no more starts to make
+            if let Some((entity, _, false)) = players.get(*seat) {
+                commands.entity(*entity).insert(*launch);
+            }
+        }
+        *call > start_gate.call
+    });
+}
+
+/// Sends every rider's launch to the clients as the host puts the tapes up
+fn broadcast_launches(mut host: ResMut<NetworkHost>, mut start_events: EventReader<StartEvent>) {
+    for StartEvent { launches } in start_events.read() {
+        let call = host.launches.len();
+        host.launches.push(launches.clone());
+        host.broadcast(&Message::Launches {
+            call,
+            launches: launches.clone(),
+        });
+    }
+}
+
+/// Sends the host the launch the player here chose
+fn send_launch(
+    mut client: ResMut<NetworkClient>,
+    start_gate: Res<StartGate>,
+    q_player: Query<&Launch, (With<Player>, Without<RemotePlayer>)>,
+) {
+    let Ok(launch) = q_player.get_single() else {
+        return;
+    };
+    if client
+        .sent_launch
+        .is_some_and(|(call, _)| call == start_gate.call)
+    {
+        return;
+    }
+    client.sent_launch = Some((start_gate.call, *launch));
+    client.send(&Message::Launch {
+        call: start_gate.call,
+        launch: *launch,
+    });
+}
+
+/// Gives the riders of the players at the other machines the launches the host sent
+/// for this call to the tapes
+fn take_host_launches(
+    mut commands: Commands,
+    client: Res<NetworkClient>,
+    start_gate: Res<StartGate>,
+    q_remote: Query<(Entity, &Rider), (With<RemotePlayer>, Without<Launch>, Without<Finished>)>,
+) {
+    let Some(launches) = client.launches.get(start_gate.call) else {
+        return;
+    };
+    for (entity, rider) in &q_remote {
+        if let Some((_, launch)) = launches
+            .iter()
+            .find(|(number, _)| *number == rider.number())
+        {
+            commands.entity(entity).insert(*launch);
+        }
+    }
+}
+
+/// Hands the actions clients sent for this turn to their riders
+fn take_remote_commands(
+    mut host: ResMut<NetworkHost>,
+    race: Res<NetworkRace>,
+    q_players: Query<(Entity, &Rider), With<Player>>,
+    mut action_events: EventWriter<ActionEvent>,
+) {
+    let mut players: Vec<_> = q_players.iter().collect();
+    players.sort_by_key(|(_, rider)| rider.number());
+    host.commands.retain(|(seat, turn, action)| {
+        if *turn == race.turn {
+            if let Some((entity, _)) = players.get(*seat) {
+                action_events.send(ActionEvent::new(*entity, *action));
+            }
+        }
+        // actions from clients that are a turn ahead wait for the host to catch up
+        *turn > race.turn
+    });
+}
+
+/// Sends every rider's action to the clients as the host starts the turn
+fn broadcast_turn(
+    mut host: ResMut<NetworkHost>,
+    race: Res<NetworkRace>,
+    q_actions: Query<(&Rider, &BikeAction)>,
+) {
+    let mut actions: Vec<(usize, BikeAction)> = q_actions
+        .iter()
+        .map(|(rider, action)| (rider.number(), *action))
+        .collect();
+    actions.sort_by_key(|(number, _)| *number);
+    host.turns.push(actions.clone());
+    host.broadcast(&Message::Turn {
+        turn: race.turn,
+        actions,
+    });
+}
+
+fn count_turn(mut race: ResMut<NetworkRace>) {
+    race.turn += 1;
+}
+
+/// Sends the host the action the player here chose
+fn send_command(
+    mut client: ResMut<NetworkClient>,
+    race: Res<NetworkRace>,
+    q_player: Query<&BikeAction, (With<Player>, Without<RemotePlayer>)>,
+) {
+    let Ok(action) = q_player.get_single() else {
+        return;
+    };
+    if client
+        .sent_command
+        .is_some_and(|(turn, _)| turn == race.turn)
+    {
+        return;
+    }
+    client.sent_command = Some((race.turn, *action));
+    client.send(&Message::Command {
+        turn: race.turn,
+        action: *action,
+    });
+}
+
+/// Starts the turn once the host has, with the actions it sent for every rider
+fn start_turn_from_host(
+    mut commands: Commands,
+    mut client: ResMut<NetworkClient>,
+    race: Res<NetworkRace>,
+    q_riders: Query<(Entity, &Rider)>,
+    mut next_state: ResMut<NextState<RacingState>>,
+) {
+    let actions = match client.turns.pop_front() {
+        Some((turn, actions)) if turn == race.turn => actions,
+        Some(later) => {
+            client.turns.push_front(later);
+            return;
+        }
+        None => return,
+    };
+    for (number, action) in actions {
+        if let Some((entity, _)) = q_riders.iter().find(|(_, rider)| rider.number() == number) {
+            commands.entity(entity).insert(action);
+        }
+    }
+    next_state.set(RacingState::Simulating);
+}
+
+/// Leaving for the menu ends any network session, and brings back this machine's
+/// own race settings
+fn end_session(
+    mut commands: Commands,
+    race: Option<Res<NetworkRace>>,
+    mut selected_track: ResMut<SelectedTrack>,
+    mut opponent_difficulties: ResMut<OpponentDifficulties>,
+    mut player_count: ResMut<PlayerCount>,
+    mut bot_commands: ResMut<BotCommands>,
+) {
+    if let Some(race) = race {
+        let (track, difficulties, players, bots) = race.own_settings.clone();
+        *selected_track = track;
+        *opponent_difficulties = difficulties;
+        *player_count = players;
+        *bot_commands = bots;
+    }
+    commands.remove_resource::<NetworkRace>();
+    commands.remove_resource::<NetworkHost>();
+    commands.remove_resource::<NetworkClient>();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    /// Longest a test waits for messages to cross the loopback connection
+    const PATIENCE: Duration = Duration::from_secs(5);
+
+    fn host() -> (NetworkHost, String) {
+        let host = NetworkHost::listen("127.0.0.1:0").unwrap();
+        let address = host.listener.local_addr().unwrap().to_string();
+        (host, address)
+    }
+
+    /// Connects to the host and asks for a seat, sending `after` straight after
+    fn join(address: &str, seat: Option<usize>, next_turn: usize, after: &[Message]) -> Connection {
+        let mut connection = Connection::connect(address).unwrap();
+        connection
+            .send(&Message::Hello {
+                version: PROTOCOL_VERSION,
+                seat,
+                next_turn,
+            })
+            .unwrap();
+        for message in after {
+            connection.send(message).unwrap();
+        }
+        connection
+    }
+
+    /// Polls the host until `done`, or gives up
+    fn poll_until(host: &mut NetworkHost, in_race: bool, done: impl Fn(&NetworkHost) -> bool) {
+        let started = Instant::now();
+        while !done(host) && started.elapsed() < PATIENCE {
+            host.poll(in_race);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(done(host), "the host never got there");
+    }
+
+    /// Receives until `count` messages have arrived, or gives up
+    fn receive(connection: &mut Connection, count: usize) -> Vec<Message> {
+        let started = Instant::now();
+        let mut messages = Vec::new();
+        while messages.len() < count && started.elapsed() < PATIENCE {
+            messages.extend(connection.receive().unwrap());
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        messages
+    }
+
+    /// A host with one client sat in the lobby, which has heard it is welcome
+    fn seated() -> (NetworkHost, String, Connection) {
+        let (mut host, address) = host();
+        let mut client = join(&address, None, 0, &[]);
+        poll_until(&mut host, false, |host| host.players() == 2);
+        receive(&mut client, 3);
+        (host, address, client)
+    }
+
+    #[test]
+    fn hello_is_welcomed_to_a_seat() {
+        let (mut host, address) = host();
+        let mut client = join(&address, None, 0, &[]);
+        poll_until(&mut host, false, |host| host.players() == 2);
+        // the seats are closed up after anyone arrives, which welcomes everyone again
+        assert_eq!(
+            receive(&mut client, 3),
+            [
+                Message::Welcome { seat: 1 },
+                Message::Welcome { seat: 1 },
+                Message::Lobby { players: 2 }
+            ]
+        );
+    }
+
+    #[test]
+    fn command_is_answered_with_the_turn() {
+        let (mut host, _, mut client) = seated();
+        client
+            .send(&Message::Command {
+                turn: 0,
+                action: BikeAction::Accelerate,
+            })
+            .unwrap();
+        poll_until(&mut host, true, |host| !host.commands.is_empty());
+        assert_eq!(host.commands, [(1, 0, BikeAction::Accelerate)]);
+
+        let turn = Message::Turn {
+            turn: 0,
+            actions: vec![(0, BikeAction::Watch), (1, BikeAction::Accelerate)],
+        };
+        host.broadcast(&turn);
+        assert_eq!(receive(&mut client, 1), [turn]);
+    }
+
+    #[test]
+    fn messages_sent_along_with_hello_are_kept() {
+        let (mut host, address) = host();
+        let command = Message::Command {
+            turn: 0,
+            action: BikeAction::Left,
+        };
+        let _client = join(&address, None, 0, &[command]);
+        poll_until(&mut host, false, |host| !host.commands.is_empty());
+        assert_eq!(host.commands, [(1, 0, BikeAction::Left)]);
+    }
+
+    #[test]
+    fn reconnecting_client_is_sent_the_turns_it_missed() {
+        let (mut host, address, client) = seated();
+        let launches = vec![(0, Launch::React), (1, Launch::Anticipate(0.4))];
+        host.launches.push(launches.clone());
+        host.turns = [BikeAction::Accelerate, BikeAction::Left, BikeAction::Watch]
+            .into_iter()
+            .map(|action| vec![(0, BikeAction::Accelerate), (1, action)])
+            .collect();
+        drop(client);
+        poll_until(&mut host, true, |host| {
+            host.missing_seats().next().is_some()
+        });
+
+        let mut client = join(&address, Some(1), 1, &[]);
+        poll_until(&mut host, true, |host| {
+            host.missing_seats().next().is_none()
+        });
+        assert_eq!(
+            receive(&mut client, 4),
+            [
+                Message::Welcome { seat: 1 },
+                Message::Launches { call: 0, launches },
+                Message::Turn {
+                    turn: 1,
+                    actions: host.turns[1].clone(),
+                },
+                Message::Turn {
+                    turn: 2,
+                    actions: host.turns[2].clone(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn mismatched_checksum_ends_the_race() {
+        let (mut host, _, mut client) = seated();
+        host.own_checksums = vec![1, 2];
+        for (turn, checksum) in [(0, 1), (1, 3)] {
+            client.send(&Message::Checksum { turn, checksum }).unwrap();
+        }
+        poll_until(&mut host, true, |host| host.checksums.len() == 2);
+        assert_eq!(host.check_checksums(), Some(1));
+        assert_eq!(receive(&mut client, 1), [Message::Desync { turn: 1 }]);
+    }
+}
@@ -0,0 +1,247 @@
+use bevy::prelude::*;
+
+use crate::{
+    actions::BikeAction,
+    player::{Player, RemotePlayer, MAX_PLAYERS},
+    GameState, PlayingState, RacingState,
+};
+
+use super::{NetworkAddress, NetworkClient, NetworkHost, NetworkRace, StartNetworkRace};
+
+const BUTTON_NORMAL_COLOR: Color = Color::srgb(0.15, 0.15, 0.15);
+const BUTTON_HOVERED_COLOR: Color = Color::srgb(0.25, 0.25, 0.25);
+const BUTTON_PRESSED_COLOR: Color = Color::srgb(0.35, 0.75, 0.35);
+const BUTTON_WIDTH: f32 = 150.0;
+const BUTTON_HEIGHT: f32 = 65.0;
+const BUTTON_FONT_SIZE: f32 = 40.0;
+const BUTTON_FONT_COLOR: Color = Color::srgb(0.9, 0.9, 0.9);
+const STATUS_FONT_SIZE: f32 = 20.0;
+const STATUS_COLOR: Color = Color::srgb(1.0, 0.5, 0.5);
+
+/// The screen where players gather before a network race, and news of the other
+/// players during it
+pub struct LobbyPlugin;
+
+impl Plugin for LobbyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Lobby), setup_lobby)
+            .add_systems(OnExit(GameState::Lobby), teardown::<LobbyItem>)
+            .add_systems(
+                Update,
+                (button_system, update_lobby_status).run_if(in_state(GameState::Lobby)),
+            )
+            .add_systems(
+                OnEnter(PlayingState::Racing),
+                setup_race_status.run_if(resource_exists::<NetworkRace>),
+            )
+            .add_systems(OnExit(PlayingState::Racing), teardown::<RaceStatus>)
+            .add_systems(
+                Update,
+                update_race_status.run_if(
+                    in_state(PlayingState::Racing).and_then(resource_exists::<NetworkRace>),
+                ),
+            );
+    }
+}
+
+#[derive(Component)]
+struct LobbyItem;
+
+#[derive(Component)]
+struct LobbyStatus;
+
+#[derive(Component)]
+struct RaceStatus;
+
+#[derive(Component, Debug, PartialEq, Eq, Clone, Copy)]
+enum ButtonAction {
+    Start,
+    Leave,
+}
+
+fn setup_lobby(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    address: Res<NetworkAddress>,
+    host: Option<Res<NetworkHost>>,
+) {
+    let font_handle = asset_server.load("fonts/FiraSans-Bold.ttf");
+    let title = if host.is_some() {
+        format!("Hosting at {}", address.0)
+    } else {
+        format!("Joining {}", address.0)
+    };
+    commands
+        .spawn((
+            LobbyItem,
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn(make_button_text(&title, font_handle.clone()));
+            parent.spawn((LobbyStatus, make_button_text("", font_handle.clone())));
+            if host.is_some() {
+                parent
+                    .spawn((ButtonAction::Start, make_button()))
+                    .with_children(|parent| {
+                        parent.spawn(make_button_text("Start", font_handle.clone()));
+                    });
+            }
+            parent
+                .spawn((ButtonAction::Leave, make_button()))
+                .with_children(|parent| {
+                    parent.spawn(make_button_text("Leave", font_handle.clone()));
+                });
+        });
+}
+
+fn teardown<T: Component>(mut commands: Commands, q_items: Query<Entity, With<T>>) {
+    for entity in &q_items {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn make_button() -> ButtonBundle {
+    ButtonBundle {
+        style: Style {
+            width: Val::Px(BUTTON_WIDTH),
+            height: Val::Px(BUTTON_HEIGHT),
+            border: UiRect::all(Val::Px(2.0)),
+            // horizontally center child text
+            justify_content: JustifyContent::Center,
+            // vertically center child text
+            align_items: AlignItems::Center,
+            margin: UiRect::all(Val::Px(10.0)),
+            ..default()
+        },
+        border_color: BorderColor(Color::BLACK),
+        border_radius: BorderRadius::MAX,
+        background_color: BUTTON_NORMAL_COLOR.into(),
+        ..default()
+    }
+}
+
+fn make_button_text(text: &str, font_handle: Handle<Font>) -> TextBundle {
+    TextBundle::from_section(
+        text,
+        TextStyle {
+            font: font_handle,
+            font_size: BUTTON_FONT_SIZE,
+            color: BUTTON_FONT_COLOR,
+        },
+    )
+}
+
+fn button_system(
+    mut interaction_query: Query<
+        (&ButtonAction, &Interaction, &mut BackgroundColor),
+        (Changed<Interaction>, With<Button>),
+    >,
+    mut start_events: EventWriter<StartNetworkRace>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    for (button_action, interaction, mut color) in &mut interaction_query {
+        match *interaction {
+            Interaction::Pressed => {
+                *color = BUTTON_PRESSED_COLOR.into();
+                match button_action {
+                    ButtonAction::Start => {
+                        start_events.send(StartNetworkRace);
+                    }
+                    ButtonAction::Leave => {
+                        game_state.set(GameState::Menu);
+                    }
+                };
+            }
+            Interaction::Hovered => {
+                *color = BUTTON_HOVERED_COLOR.into();
+            }
+            Interaction::None => {
+                *color = BUTTON_NORMAL_COLOR.into();
+            }
+        }
+    }
+}
+
+fn update_lobby_status(
+    host: Option<Res<NetworkHost>>,
+    client: Option<Res<NetworkClient>>,
+    mut q_status: Query<&mut Text, With<LobbyStatus>>,
+) {
+    let status = match (host, client) {
+        (Some(host), _) => format!("{} of up to {MAX_PLAYERS} players here", host.players()),
+        (None, Some(client)) => match client.seat() {
+            Some(seat) => format!(
+                "You are Player {} of {}, waiting for the host to start",
+                seat + 1,
+                client.players()
+            ),
+            None => "Waiting for the host to let you in".to_string(),
+        },
+        (None, None) => String::new(),
+    };
+    for mut text in q_status.iter_mut() {
+        if text.sections[0].value != status {
+            text.sections[0].value.clone_from(&status);
+        }
+    }
+}
+
+fn setup_race_status(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        RaceStatus,
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: STATUS_FONT_SIZE,
+                color: STATUS_COLOR,
+                font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(5.0),
+            right: Val::Px(5.0),
+            ..default()
+        }),
+    ));
+}
+
+/// Tells the player who the race is waiting for
+fn update_race_status(
+    host: Option<Res<NetworkHost>>,
+    client: Option<Res<NetworkClient>>,
+    racing_state: Res<State<RacingState>>,
+    q_player: Query<Has<BikeAction>, (With<Player>, Without<RemotePlayer>)>,
+    mut q_status: Query<&mut Text, With<RaceStatus>>,
+) {
+    let missing: Vec<String> = host
+        .iter()
+        .flat_map(|host| host.missing_seats())
+        .map(|seat| format!("Player {}", seat + 1))
+        .collect();
+    let chosen = q_player.get_single().unwrap_or(true);
+    let status = if !missing.is_empty() {
+        format!("Waiting for {} to reconnect", missing.join(", "))
+    } else if client.is_some_and(|client| !client.is_connected()) {
+        "Reconnecting to the host".to_string()
+    } else if chosen && *racing_state.get() == RacingState::Commanding {
+        "Waiting for the other players".to_string()
+    } else {
+        String::new()
+    };
+    for mut text in q_status.iter_mut() {
+        if text.sections[0].value != status {
+            text.sections[0].value.clone_from(&status);
+        }
+    }
+}
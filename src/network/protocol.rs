@@ -0,0 +1,203 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{actions::BikeAction, bike::Bike, opponent::Difficulty, start_gate::Launch};
+
+/// Changes whenever peers built from different code could no longer race each other
+pub const PROTOCOL_VERSION: u32 = 2;
+/// Longest a client waits for the host to answer when connecting
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Everything peers say to each other, sent as one line of RON each
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Message {
+    /// A client asking for a seat, or for the one it had back after losing its
+    /// connection, along with the turns from `next_turn` on that it missed
+    Hello {
+        version: u32,
+        seat: Option<usize>,
+        next_turn: usize,
+    },
+    /// The host giving a client its seat
+    Welcome { seat: usize },
+    /// The host turning a client away
+    Refused(String),
+    /// How many players are waiting in the lobby
+    Lobby { players: usize },
+    /// The host starting the race
+    Start(RaceSettings),
+    /// When a client's player chose to go at a call to the tapes
+    Launch { call: usize, launch: Launch },
+    /// Every rider's launch at a call to the tapes, by rider number, which puts the
+    /// tapes up
+    Launches {
+        call: usize,
+        launches: Vec<(usize, Launch)>,
+    },
+    /// The action a client's player chose for a turn
+    Command { turn: usize, action: BikeAction },
+    /// Every rider's action for a turn, by rider number, which starts the turn
+    Turn {
+        turn: usize,
+        actions: Vec<(usize, BikeAction)>,
+    },
+    /// How a client's bikes stood at the start of a turn
+    Checksum { turn: usize, checksum: u64 },
+    /// The host finding that a client's race no longer matches its own
+    Desync { turn: usize },
+}
+
+/// What every peer needs to set up the same race
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RaceSettings {
+    pub seed: u64,
+    /// Index into `TRACK_FILES`
+    pub track: usize,
+    pub opponents: Vec<Difficulty>,
+    pub players: usize,
+}
+
+#[derive(Debug, Error)]
+pub enum NetworkError {
+    #[error("network error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse message: {0}")]
+    Parse(#[from] ron::error::SpannedError),
+    #[error("could not write message: {0}")]
+    Write(#[from] ron::Error),
+    #[error("{0} is not an address")]
+    BadAddress(String),
+    #[error("connection closed")]
+    Closed,
+}
+
+/// A connection to another peer, which never blocks once it is open
+pub struct Connection {
+    stream: TcpStream,
+    received: Vec<u8>,
+    unsent: Vec<u8>,
+}
+
+impl Connection {
+    pub fn connect(address: &str) -> Result<Self, NetworkError> {
+        let socket_address = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| NetworkError::BadAddress(address.to_string()))?;
+        Self::new(TcpStream::connect_timeout(
+            &socket_address,
+            CONNECT_TIMEOUT,
+        )?)
+    }
+
+    pub fn new(stream: TcpStream) -> Result<Self, NetworkError> {
+        stream.set_nonblocking(true)?;
+        // messages are tiny and someone is always waiting on them
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            received: Vec::new(),
+            unsent: Vec::new(),
+        })
+    }
+
+    /// Queues the message and sends as much of what is queued as the network takes
+    pub fn send(&mut self, message: &Message) -> Result<(), NetworkError> {
+        self.unsent
+            .extend_from_slice(ron::ser::to_string(message)?.as_bytes());
+        self.unsent.push(b'\n');
+        self.flush()
+    }
+
+    /// Every whole message that has arrived since last time
+    pub fn receive(&mut self) -> Result<Vec<Message>, NetworkError> {
+        self.flush()?;
+        let mut buffer = [0; 4096];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(NetworkError::Closed),
+                Ok(length) => self.received.extend_from_slice(&buffer[..length]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error.into()),
+            }
+        }
+        let mut messages = Vec::new();
+        while let Some(end) = self.received.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.received.drain(..=end).collect();
+            messages.push(ron::de::from_bytes(&line[..end])?);
+        }
+        Ok(messages)
+    }
+
+    fn flush(&mut self) -> Result<(), NetworkError> {
+        while !self.unsent.is_empty() {
+            match self.stream.write(&self.unsent) {
+                Ok(0) => return Err(NetworkError::Closed),
+                Ok(length) => {
+                    self.unsent.drain(..length);
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error.into()),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fingerprint of every bike, by rider number, which peers compare to find out
+/// whether their races have drifted apart. Floats are written out exactly by RON, so
+/// the slightest difference shows.
+pub fn checksum(bikes: &[(usize, Bike)]) -> u64 {
+    let written = ron::ser::to_string(bikes).expect("bikes can always be written as RON");
+    // 64 bit FNV-1a, which is the same everywhere, unlike the standard library's hasher
+    written.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, time::Instant};
+
+    use super::*;
+
+    /// Longest the test waits for bytes to cross the loopback connection
+    const PATIENCE: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn message_split_across_two_reads_arrives_whole() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut sender = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut connection = Connection::new(listener.accept().unwrap().0).unwrap();
+        let message = Message::Command {
+            turn: 3,
+            action: BikeAction::RightElbow,
+        };
+        let line = format!("{}\n", ron::ser::to_string(&message).unwrap());
+        let (first, second) = line.as_bytes().split_at(line.len() / 2);
+
+        sender.write_all(first).unwrap();
+        let started = Instant::now();
+        while connection.received.len() < first.len() && started.elapsed() < PATIENCE {
+            assert!(connection.receive().unwrap().is_empty());
+        }
+        assert_eq!(connection.received, first);
+
+        sender.write_all(second).unwrap();
+        let started = Instant::now();
+        let mut messages = Vec::new();
+        while messages.is_empty() && started.elapsed() < PATIENCE {
+            messages = connection.receive().unwrap();
+        }
+        assert_eq!(messages, [message]);
+        assert!(connection.received.is_empty());
+    }
+}
@@ -1,13 +1,27 @@
+mod difficulty;
+mod search;
+mod utility;
+
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    actions::{self, BikeAction},
-    bike::Bike,
-    collision::Collision,
+    actions::{self, BikeAction, Watching},
+    bike::{Bike, ContactEvent, ContactOutcome, Crashed},
+    collision::{Collider, Collision},
+    config::Config,
+    game::{Finished, Rider},
+    profile::RiderTraits,
     random::Randomness,
-    RacingState,
+    simulation::TurnPhaseSet,
+    team::{are_teammates, Team},
+    track::TrackLanes,
+    GameState, RacingState,
 };
 
+pub use self::difficulty::{Difficulty, OpponentDifficulties};
+use self::{difficulty::AiSkill, search::plan_ahead, utility::choose_action};
+
 const BIKE_ACTIONS: [actions::BikeAction; 12] = [
     BikeAction::Accelerate,
     BikeAction::Watch,
@@ -23,39 +37,239 @@ const BIKE_ACTIONS: [actions::BikeAction; 12] = [
     BikeAction::RightHip,
 ];
 
+/// How far ahead a watching opponent looks for riders that are about to slow down
+const WATCH_REACTION_DISTANCE: f32 = 400.0;
+/// Turns an opponent leaves alone a rider who held firm against its elbow or hip
+const REBUFF_MEMORY_TURNS: u32 = 3;
+
 pub struct OpponentPlugin;
 
 impl Plugin for OpponentPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(RacingState::Commanding), act);
+        let opponent_difficulties = OpponentDifficulties::from_config(Config::of(app));
+        app.insert_resource(opponent_difficulties)
+            .add_systems(
+                OnEnter(RacingState::Commanding),
+                (act, forget_rebuffs)
+                    .chain()
+                    .in_set(TurnPhaseSet)
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(Update, remember_rebuffs.run_if(on_event::<ContactEvent>()));
     }
 }
 
-#[derive(Component, Debug, Copy, Clone, PartialEq, Eq)]
-pub struct Opponent;
+/// Lets opponents that plan ahead search through simulated races. It is kept out of
+/// the race rules, so that riders in those simulations don't plan in turn.
+pub struct OpponentPlanningPlugin;
+
+impl Plugin for OpponentPlanningPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            OnEnter(RacingState::Commanding),
+            plan_ahead
+                .before(TurnPhaseSet)
+                .run_if(in_state(GameState::Playing)),
+        );
+    }
+}
+
+#[derive(Component, Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Default)]
+pub struct Opponent {
+    pub difficulty: Difficulty,
+    /// The character of the rider, from their profile
+    #[serde(default)]
+    pub traits: RiderTraits,
+}
+
+/// A rider who held firm against the opponent's last elbow or hip, by rider number,
+/// and who it leaves alone for a few turns
+#[derive(Component, Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct Rebuffed {
+    pub by: usize,
+    turns: u32,
+}
+
+impl Opponent {
+    fn skill(&self) -> AiSkill {
+        AiSkill {
+            traits: self.traits,
+            ..self.difficulty.skill()
+        }
+    }
+}
 
 fn act(
-    q_opponents: Query<(Entity, &Bike, Option<&Collision>), With<Opponent>>,
+    q_opponents: Query<
+        (
+            Entity,
+            &Opponent,
+            &Bike,
+            Option<&Collision>,
+            Has<Watching>,
+            Option<&Rebuffed>,
+            &Rider,
+        ),
+        // opponents that planned ahead have chosen already
+        (Without<Crashed>, Without<Finished>, Without<BikeAction>),
+    >,
+    q_bikes: Query<(Entity, &Bike, &Rider), With<Collider>>,
+    q_teams: Query<(Entity, &Team)>,
     mut commands: Commands,
     mut randomness: ResMut<Randomness>,
+    track_lanes: Res<TrackLanes>,
 ) {
-    for (entity, bike, maybe_collision) in &q_opponents {
-        if BikeAction::Accelerate.can_do(bike, maybe_collision) {
-            commands.entity(entity).insert(BikeAction::Accelerate);
-        } else {
-            let possible_actions = generate_possible_actions(bike, maybe_collision);
-            let random_action_index = randomness.rng.usize(0..possible_actions.len());
-            commands
-                .entity(entity)
-                .insert(possible_actions[random_action_index]);
+    // decided in order of rider number, so the same seed always leads to the same choices
+    let mut opponents: Vec<_> = q_opponents.iter().collect();
+    opponents.sort_by_key(|(.., rider)| rider.number());
+    // bikes that can be run into, in a fixed order for the same reason
+    let mut bikes: Vec<_> = q_bikes.iter().collect();
+    bikes.sort_by_key(|(.., rider)| rider.number());
+    let rider_entity = |number: usize| {
+        bikes
+            .iter()
+            .find(|(.., rider)| rider.number() == number)
+            .map(|(entity, ..)| *entity)
+    };
+    let rebuffed_by = |maybe_rebuffed: Option<&Rebuffed>| {
+        maybe_rebuffed.and_then(|rebuffed| rider_entity(rebuffed.by))
+    };
+    let bikes: Vec<(Entity, Bike)> = bikes
+        .iter()
+        .map(|(entity, bike, _)| (*entity, **bike))
+        .collect();
+    let teammates = |entity: Entity| -> Vec<Entity> {
+        let team = q_teams.get(entity).ok().map(|(_, team)| *team);
+        q_teams
+            .iter()
+            .filter(|(other, other_team)| {
+                *other != entity && are_teammates(team, Some(**other_team))
+            })
+            .map(|(other, _)| other)
+            .collect()
+    };
+    let mut chosen_actions = Vec::new();
+    for (entity, opponent, bike, maybe_collision, _, maybe_rebuffed, _) in opponents
+        .iter()
+        .copied()
+        .filter(|(_, _, _, _, watching, ..)| !watching)
+    {
+        let action = choose_action(
+            entity,
+            bike,
+            maybe_collision,
+            &bikes,
+            &teammates(entity),
+            rebuffed_by(maybe_rebuffed),
+            &opponent.skill(),
+            &track_lanes,
+            &mut randomness.ai,
+        );
+        chosen_actions.push((entity, action));
+    }
+    // watching opponents decide last, knowing what the others chose
+    for (entity, opponent, bike, maybe_collision, _, maybe_rebuffed, _) in opponents
+        .iter()
+        .copied()
+        .filter(|(_, _, _, _, watching, ..)| *watching)
+    {
+        let action = react_to_revealed_actions(
+            entity,
+            bike,
+            maybe_collision,
+            &chosen_actions,
+            &bikes,
+            &track_lanes,
+        )
+        .unwrap_or_else(|| {
+            choose_action(
+                entity,
+                bike,
+                maybe_collision,
+                &bikes,
+                &teammates(entity),
+                rebuffed_by(maybe_rebuffed),
+                &opponent.skill(),
+                &track_lanes,
+                &mut randomness.ai,
+            )
+        });
+        chosen_actions.push((entity, action));
+    }
+    for (entity, action) in chosen_actions {
+        commands.entity(entity).insert(action);
+    }
+}
+
+/// Opponents whose elbow or hip was rebuffed leave that rider alone for a while
+fn remember_rebuffs(
+    mut commands: Commands,
+    mut contact_events: EventReader<ContactEvent>,
+    q_opponents: Query<(), With<Opponent>>,
+    q_riders: Query<&Rider>,
+) {
+    for event in contact_events.read() {
+        let Some(target) = event.target else {
+            continue;
+        };
+        if event.outcome != ContactOutcome::Rebuffed || !q_opponents.contains(event.attacker) {
+            continue;
         }
+        if let Ok(rider) = q_riders.get(target) {
+            commands.entity(event.attacker).insert(Rebuffed {
+                by: rider.number(),
+                turns: REBUFF_MEMORY_TURNS,
+            });
+        }
+    }
+}
+
+fn forget_rebuffs(mut commands: Commands, mut q_rebuffed: Query<(Entity, &mut Rebuffed)>) {
+    for (entity, mut rebuffed) in &mut q_rebuffed {
+        rebuffed.turns = rebuffed.turns.saturating_sub(1);
+        if rebuffed.turns == 0 {
+            commands.entity(entity).remove::<Rebuffed>();
+        }
+    }
+}
+
+/// Steers around a rider just ahead that was revealed to be slowing down
+fn react_to_revealed_actions(
+    entity: Entity,
+    bike: &Bike,
+    maybe_collision: Option<&Collision>,
+    revealed_actions: &[(Entity, BikeAction)],
+    bikes: &[(Entity, Bike)],
+    track_lanes: &TrackLanes,
+) -> Option<BikeAction> {
+    let slowing_ahead = bikes.iter().any(|(other_entity, other_bike)| {
+        let gap = other_bike.distance - bike.distance;
+        *other_entity != entity
+            && other_bike.current_lane_id == bike.current_lane_id
+            && gap > 0.0
+            && gap < WATCH_REACTION_DISTANCE
+            && revealed_actions.iter().any(|(revealed_entity, action)| {
+                revealed_entity == other_entity
+                    && matches!(action, BikeAction::Stop | BikeAction::Skid)
+            })
+    });
+    if !slowing_ahead {
+        return None;
     }
+    [BikeAction::Left, BikeAction::Right, BikeAction::Stop]
+        .into_iter()
+        .find(|action| action.can_do(bike, maybe_collision, track_lanes))
 }
 
-fn generate_possible_actions(bike: &Bike, maybe_collision: Option<&Collision>) -> Vec<BikeAction> {
+/// Every action the rider can take this turn
+pub fn generate_possible_actions(
+    bike: &Bike,
+    maybe_collision: Option<&Collision>,
+    track_lanes: &TrackLanes,
+) -> Vec<BikeAction> {
     BIKE_ACTIONS
         .iter()
-        .filter(|e| e.can_do(bike, maybe_collision))
+        .filter(|e| e.can_do(bike, maybe_collision, track_lanes))
         .copied()
         .collect::<Vec<BikeAction>>()
 }
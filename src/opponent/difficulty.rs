@@ -0,0 +1,150 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, profile::RiderTraits};
+
+/// How well a computer controlled rider races
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Difficulty {
+    Novice,
+    #[default]
+    Club,
+    League,
+    GrandPrix,
+    /// Races like a Grand Prix rider, but plans its moves by searching simulated races
+    Champion,
+}
+
+/// The weights an opponent gives to each part of its judgement, in the units of
+/// distance along the track that the scores of actions are measured in
+#[derive(Debug, Clone, Copy)]
+pub struct AiSkill {
+    /// Turns ahead that bends and the value of speed are thought about
+    pub lookahead_turns: usize,
+    /// How much slipping and collisions are feared
+    pub caution: f32,
+    /// How much gaps and the inside line are sought out
+    pub racecraft: f32,
+    /// How keen the rider is to block and barge other riders
+    pub aggression: f32,
+    /// Widest random error in the score of an action
+    pub noise: f32,
+    /// Chance of making any legal move instead of thinking at all
+    pub blunder_chance: f32,
+    /// How long the rider spends searching simulated races for each move, if at all
+    pub thinking_time: Duration,
+    /// What sets the rider apart from others of the same difficulty
+    pub traits: RiderTraits,
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 5] = [
+        Difficulty::Novice,
+        Difficulty::Club,
+        Difficulty::League,
+        Difficulty::GrandPrix,
+        Difficulty::Champion,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Difficulty::Novice => "Novice",
+            Difficulty::Club => "Club",
+            Difficulty::League => "League",
+            Difficulty::GrandPrix => "Grand Prix",
+            Difficulty::Champion => "Champion",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        let index = Self::ALL
+            .iter()
+            .position(|level| level == self)
+            .unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    pub fn plans_ahead(&self) -> bool {
+        !self.skill().thinking_time.is_zero()
+    }
+
+    pub fn skill(&self) -> AiSkill {
+        match self {
+            Difficulty::Novice => AiSkill {
+                lookahead_turns: 0,
+                caution: 0.3,
+                racecraft: 0.2,
+                aggression: 0.0,
+                noise: 400.0,
+                blunder_chance: 0.15,
+                thinking_time: Duration::ZERO,
+                traits: RiderTraits::default(),
+            },
+            Difficulty::Club => AiSkill {
+                lookahead_turns: 1,
+                caution: 0.7,
+                racecraft: 0.6,
+                aggression: 0.3,
+                noise: 200.0,
+                blunder_chance: 0.05,
+                thinking_time: Duration::ZERO,
+                traits: RiderTraits::default(),
+            },
+            Difficulty::League => AiSkill {
+                lookahead_turns: 2,
+                caution: 1.0,
+                racecraft: 0.9,
+                aggression: 0.6,
+                noise: 80.0,
+                blunder_chance: 0.02,
+                thinking_time: Duration::ZERO,
+                traits: RiderTraits::default(),
+            },
+            Difficulty::GrandPrix => AiSkill {
+                lookahead_turns: 3,
+                caution: 1.0,
+                racecraft: 1.0,
+                aggression: 1.0,
+                noise: 20.0,
+                blunder_chance: 0.0,
+                thinking_time: Duration::ZERO,
+                traits: RiderTraits::default(),
+            },
+            Difficulty::Champion => AiSkill {
+                thinking_time: Duration::from_millis(150),
+                ..Difficulty::GrandPrix.skill()
+            },
+        }
+    }
+}
+
+/// Difficulty of each opponent on the starting grid, counting from the inside lane.
+/// Opponents past the end of the list take the last difficulty in it.
+#[derive(Resource, Debug, Clone, PartialEq, Eq)]
+pub struct OpponentDifficulties(pub Vec<Difficulty>);
+
+impl OpponentDifficulties {
+    /// Difficulties listed as `opponents` in the config file, or club riders throughout
+    pub fn from_config(config: &Config) -> Self {
+        Self(config.opponents.clone())
+    }
+
+    pub fn for_opponent(&self, index: usize) -> Difficulty {
+        self.0
+            .get(index)
+            .or(self.0.last())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Moves the opponent at `index` up to the next difficulty, leaving the others as
+    /// they were
+    pub fn cycle(&mut self, index: usize) {
+        if self.0.len() <= index {
+            self.0 = (0..=index).map(|other| self.for_opponent(other)).collect();
+        }
+        self.0[index] = self.0[index].next();
+    }
+}
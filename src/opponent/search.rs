@@ -0,0 +1,271 @@
+use std::time::Duration;
+
+use bevy::{ecs::world::EntityRef, prelude::*, utils::Instant};
+use fastrand::Rng;
+
+use crate::{
+    actions::BikeAction,
+    bike::Crashed,
+    game::{Finished, Rider},
+    random::Randomness,
+    simulation::{RaceSimulation, RiderSnapshot},
+    track::TrackLanes,
+};
+
+use super::{difficulty::AiSkill, utility::rank_actions, Opponent};
+
+/// Turns ahead that a planning opponent looks
+const SEARCH_TURNS: usize = 3;
+/// Time a simulated race is reckoned to take, which turns a rider's thinking time into
+/// the number of races they run. Counting races rather than timing them means the same
+/// seed always leads to the same choices on any machine.
+const PLAYOUT_TIME: Duration = Duration::from_millis(12);
+/// Actions searched from each point, taking those the rider rates best at a glance, as
+/// there is only time to race out a few of them
+const CANDIDATE_ACTIONS: usize = 3;
+/// Balance between trying actions again that did well and finding out about others
+const EXPLORATION: f32 = 1.4;
+/// Lead over another rider, in laps, beyond which being further ahead counts for less
+const CLEAR_LEAD: f32 = 0.5;
+/// Value of being down at the end of a search, as well as the ground lost
+const CRASH_VALUE: f32 = -2.0;
+
+/// Actions tried from one point in the search, with how well they turned out
+#[derive(Default)]
+struct Node {
+    visits: u32,
+    total_value: f32,
+    children: Vec<(BikeAction, Node)>,
+}
+
+impl Node {
+    fn mean_value(&self) -> f32 {
+        self.total_value / self.visits.max(1) as f32
+    }
+
+    /// Upper confidence bound of the action's value, which is high for actions that
+    /// either did well or have hardly been tried
+    fn upper_bound(&self, parent_visits: u32) -> f32 {
+        self.mean_value()
+            + EXPLORATION * ((parent_visits.max(1) as f32).ln() / self.visits.max(1) as f32).sqrt()
+    }
+}
+
+/// Chooses actions for the opponents that plan ahead, before the other rules for the
+/// new turn run, so their simulations start from the same point as the race does
+pub(super) fn plan_ahead(world: &mut World) {
+    let mut q_planners = world
+        .query_filtered::<(Entity, &Opponent, &Rider), (
+        Without<BikeAction>,
+        Without<Crashed>,
+        Without<Finished>,
+    )>();
+    let mut planners: Vec<(Entity, usize, AiSkill)> = q_planners
+        .iter(world)
+        .filter(|(_, opponent, _)| opponent.difficulty.plans_ahead())
+        .map(|(entity, opponent, rider)| (entity, rider.number(), opponent.skill()))
+        .collect();
+    if planners.is_empty() {
+        return;
+    }
+    planners.sort_by_key(|(_, number, _)| *number);
+    let riders = take_snapshots(world);
+    let track_lanes = world.resource::<TrackLanes>().clone();
+    let mut rng = Rng::with_seed(world.resource_mut::<Randomness>().ai.u64(..));
+    let mut simulation = RaceSimulation::from_snapshot(track_lanes, &riders, rng.u64(..));
+    for (entity, number, skill) in planners {
+        if let Some(action) = search(&mut simulation, &riders, number, &skill, &mut rng) {
+            world.entity_mut(entity).insert(action);
+        }
+    }
+}
+
+fn take_snapshots(world: &mut World) -> Vec<RiderSnapshot> {
+    let rider_numbers: Vec<(Entity, usize)> = world
+        .query::<(Entity, &Rider)>()
+        .iter(world)
+        .map(|(entity, rider)| (entity, rider.number()))
+        .collect();
+    let rider_number = |other: Entity| {
+        rider_numbers
+            .iter()
+            .find(|(entity, _)| *entity == other)
+            .map(|(_, number)| *number)
+    };
+    let mut riders: Vec<RiderSnapshot> = world
+        .query_filtered::<EntityRef, With<Rider>>()
+        .iter(world)
+        .filter_map(|entity| RiderSnapshot::take(entity, rider_number))
+        .collect();
+    riders.sort_by_key(|snapshot| snapshot.rider.number());
+    riders
+}
+
+/// Searches the planner's actions for the next few turns with open loop Monte Carlo
+/// tree search. The other riders are raced by the simulation's own opponents with fresh
+/// luck every time, so each line is judged over how they are likely to respond.
+fn search(
+    simulation: &mut RaceSimulation,
+    riders: &[RiderSnapshot],
+    number: usize,
+    skill: &AiSkill,
+    rng: &mut Rng,
+) -> Option<BikeAction> {
+    let started = Instant::now();
+    // every first action is raced out at least once, however short the thinking time
+    let playouts = ((skill.thinking_time.as_millis() / PLAYOUT_TIME.as_millis()) as usize)
+        .max(CANDIDATE_ACTIONS);
+    let mut root = Node::default();
+    for _ in 0..playouts {
+        simulation.restore(riders, rng.u64(..));
+        let entity = simulation.rider_entity(number)?;
+        playout(&mut root, simulation, entity, skill, 0, rng);
+    }
+    debug!(
+        "Rider {number} planned over {playouts} simulated races in {:?}, with {:?} to think",
+        started.elapsed(),
+        skill.thinking_time
+    );
+    root.children
+        .iter()
+        .max_by(|(_, child), (_, other_child)| {
+            child
+                .visits
+                .cmp(&other_child.visits)
+                .then(child.mean_value().total_cmp(&other_child.mean_value()))
+        })
+        .map(|(action, _)| *action)
+}
+
+/// The actions worth searching from where the rider is now in the simulation
+fn candidate_actions(
+    simulation: &mut RaceSimulation,
+    entity: Entity,
+    skill: &AiSkill,
+) -> Vec<BikeAction> {
+    // a fallen rider has nothing to choose
+    if !matches!(simulation.rider_progress(entity), Some((_, false))) {
+        return Vec::new();
+    }
+    let bikes = simulation.bikes();
+    let Some((_, bike)) = bikes.iter().find(|(other, _)| *other == entity) else {
+        return Vec::new();
+    };
+    let teammates = simulation.teammates(entity);
+    let rebuffed_by = simulation.rebuffed_by(entity);
+    let mut actions = rank_actions(
+        entity,
+        bike,
+        simulation.collision(entity).as_ref(),
+        &bikes,
+        &teammates,
+        rebuffed_by,
+        skill,
+        simulation.track_lanes(),
+    );
+    actions.truncate(CANDIDATE_ACTIONS);
+    actions
+}
+
+/// Follows the tree down by the most promising actions, tries a new one at the end of
+/// it, and races on from there. Returns the value of the race at the end.
+fn playout(
+    node: &mut Node,
+    simulation: &mut RaceSimulation,
+    entity: Entity,
+    skill: &AiSkill,
+    depth: usize,
+    rng: &mut Rng,
+) -> f32 {
+    let value = if depth == SEARCH_TURNS || simulation.is_finished() {
+        evaluate(simulation, entity)
+    } else {
+        let candidates = candidate_actions(simulation, entity, skill);
+        let untried_actions: Vec<BikeAction> = candidates
+            .iter()
+            .copied()
+            .filter(|action| !node.children.iter().any(|(tried, _)| tried == action))
+            .collect();
+        if !untried_actions.is_empty() {
+            let action = untried_actions[rng.usize(..untried_actions.len())];
+            simulation.step_turn(&[(entity, action)]);
+            let value = roll_out(simulation, entity, depth + 1);
+            node.children.push((
+                action,
+                Node {
+                    visits: 1,
+                    total_value: value,
+                    children: Vec::new(),
+                },
+            ));
+            value
+        } else {
+            let parent_visits = node.visits;
+            // the candidates depend on how the race has gone, since other riders
+            // don't always do the same
+            let chosen = node
+                .children
+                .iter_mut()
+                .filter(|(action, _)| candidates.contains(action))
+                .max_by(|(_, child), (_, other_child)| {
+                    child
+                        .upper_bound(parent_visits)
+                        .total_cmp(&other_child.upper_bound(parent_visits))
+                });
+            match chosen {
+                Some((action, child)) => {
+                    simulation.step_turn(&[(entity, *action)]);
+                    playout(child, simulation, entity, skill, depth + 1, rng)
+                }
+                // none of the actions tried before are worth making from here
+                None => roll_out(simulation, entity, depth),
+            }
+        }
+    };
+    node.visits += 1;
+    node.total_value += value;
+    value
+}
+
+/// Races on to the end of the search with every rider choosing their own actions
+fn roll_out(simulation: &mut RaceSimulation, entity: Entity, depth: usize) -> f32 {
+    for _ in depth..SEARCH_TURNS {
+        if simulation.is_finished() {
+            break;
+        }
+        simulation.step_turn(&[]);
+    }
+    evaluate(simulation, entity)
+}
+
+/// How well placed the rider is against each of their rivals, and how far they have
+/// got. Teammates are no rivals, and their own places against the rivals count for the
+/// rider too.
+fn evaluate(simulation: &mut RaceSimulation, entity: Entity) -> f32 {
+    let Some((progress, crashed)) = simulation.rider_progress(entity) else {
+        return CRASH_VALUE;
+    };
+    let teammates = simulation.teammates(entity);
+    let (teammate_progress, rival_progress): (Vec<(Entity, f32)>, Vec<(Entity, f32)>) = simulation
+        .bikes()
+        .into_iter()
+        .filter(|(other_entity, _)| *other_entity != entity)
+        .filter_map(|(other_entity, _)| {
+            let (other_progress, _) = simulation.rider_progress(other_entity)?;
+            Some((other_entity, other_progress))
+        })
+        .partition(|(other_entity, _)| teammates.contains(other_entity));
+    let standing_against_rivals = |progress: f32| -> f32 {
+        rival_progress
+            .iter()
+            .map(|(_, rival_progress)| ((progress - rival_progress) / CLEAR_LEAD).tanh())
+            .sum()
+    };
+    let standing = standing_against_rivals(progress)
+        + teammate_progress
+            .iter()
+            .map(|(_, teammate_progress)| standing_against_rivals(*teammate_progress))
+            .sum::<f32>();
+    let crash_value = if crashed { CRASH_VALUE } else { 0.0 };
+    standing + progress + crash_value
+}
@@ -0,0 +1,329 @@
+use bevy::prelude::*;
+use fastrand::Rng;
+
+use crate::{
+    actions::BikeAction,
+    bike::{lane_after, max_turn_speed, slip_risk, speed_after, Bike, SlipRisk, CONTACT_REACH},
+    collision::Collision,
+    game::{TICKS_PER_TURN, TICK_SECONDS},
+    track::{TrackLaneId, TrackLanes},
+};
+
+use super::{difficulty::AiSkill, generate_possible_actions};
+
+const TURN_SECONDS: f32 = TICKS_PER_TURN as f32 * TICK_SECONDS;
+/// Score lost to a fall, which costs several turns or the whole race
+const CRASH_PENALTY: f32 = 5000.0;
+/// Score lost for every lane a slip carries the bike out
+const SLIDE_PENALTY_PER_LANE: f32 = 300.0;
+/// Closest two bikes in a lane can be without touching
+const BIKE_LENGTH: f32 = 130.0;
+/// Speed above a bike ahead at which running into it brings a rider down
+const RAM_SPEED: f32 = 10.0;
+/// Furthest ahead open track is counted as worth having
+const GAP_HORIZON: f32 = 600.0;
+/// Score of a clear lane ahead, per unit of distance that is clear
+const GAP_WEIGHT: f32 = 0.2;
+/// How far behind a rider can be and still be held up by a bike in their lane
+const BLOCK_RANGE: f32 = 300.0;
+const BLOCK_BONUS: f32 = 150.0;
+/// Score of being a lane nearer the rider's preferred line
+const LINE_BONUS: f32 = 60.0;
+const CONTACT_BONUS: f32 = 200.0;
+/// Furthest behind a rival can be for a rider to hold them up for a teammate ahead
+const SHEPHERD_RANGE: f32 = 500.0;
+/// Share of the value of getting on that a rider shepherding a teammate still sees
+const SHEPHERD_PACE: f32 = 0.5;
+/// Score lost by an elbow or hip that finds nobody to hit
+const MISSED_CONTACT_PENALTY: f32 = 50.0;
+
+/// Another bike in the race, as the rider sees it
+struct OtherBike {
+    bike: Bike,
+    /// Races for the same team as the rider
+    teammate: bool,
+    /// Held firm against the rider's last elbow or hip
+    rebuffed: bool,
+}
+
+/// The bikes other than the rider's own, with their teammates and the rider who last
+/// rebuffed them picked out
+fn other_bikes(
+    entity: Entity,
+    others: &[(Entity, Bike)],
+    teammates: &[Entity],
+    rebuffed_by: Option<Entity>,
+) -> Vec<OtherBike> {
+    others
+        .iter()
+        .filter(|(other_entity, _)| *other_entity != entity)
+        .map(|(other_entity, other_bike)| OtherBike {
+            bike: *other_bike,
+            teammate: teammates.contains(other_entity),
+            rebuffed: rebuffed_by == Some(*other_entity),
+        })
+        .collect()
+}
+
+/// Where a bike is expected to be at the end of the coming turn
+struct Outlook {
+    lane_id: TrackLaneId,
+    distance: f32,
+    speed: f32,
+}
+
+/// Scores every legal action by looking ahead along the track, and picks the best.
+/// `teammates` are the riders racing for the same team as the rider, and `rebuffed_by`
+/// the rider who held firm against their last elbow or hip, if they still remember it.
+pub(super) fn choose_action(
+    entity: Entity,
+    bike: &Bike,
+    maybe_collision: Option<&Collision>,
+    others: &[(Entity, Bike)],
+    teammates: &[Entity],
+    rebuffed_by: Option<Entity>,
+    skill: &AiSkill,
+    track_lanes: &TrackLanes,
+    rng: &mut Rng,
+) -> BikeAction {
+    let possible_actions = generate_possible_actions(bike, maybe_collision, track_lanes);
+    if rng.f32() < skill.blunder_chance {
+        return possible_actions[rng.usize(0..possible_actions.len())];
+    }
+    let others = other_bikes(entity, others, teammates, rebuffed_by);
+    possible_actions
+        .into_iter()
+        .map(|action| {
+            let score = score_action(bike, action, &others, skill, track_lanes)
+                + (rng.f32() - 0.5) * skill.noise;
+            (action, score)
+        })
+        .max_by(|(_, score), (_, other_score)| score.total_cmp(other_score))
+        .map(|(action, _)| action)
+        .unwrap_or(BikeAction::Watch)
+}
+
+/// Every legal action, best first, as the rider would judge them without any noise
+pub(super) fn rank_actions(
+    entity: Entity,
+    bike: &Bike,
+    maybe_collision: Option<&Collision>,
+    others: &[(Entity, Bike)],
+    teammates: &[Entity],
+    rebuffed_by: Option<Entity>,
+    skill: &AiSkill,
+    track_lanes: &TrackLanes,
+) -> Vec<BikeAction> {
+    let others = other_bikes(entity, others, teammates, rebuffed_by);
+    let mut scored_actions: Vec<(BikeAction, f32)> =
+        generate_possible_actions(bike, maybe_collision, track_lanes)
+            .into_iter()
+            .map(|action| {
+                let score = score_action(bike, action, &others, skill, track_lanes);
+                (action, score)
+            })
+            .collect();
+    scored_actions.sort_by(|(_, score), (_, other_score)| other_score.total_cmp(score));
+    scored_actions
+        .into_iter()
+        .map(|(action, _)| action)
+        .collect()
+}
+
+fn score_action(
+    bike: &Bike,
+    action: BikeAction,
+    others: &[OtherBike],
+    skill: &AiSkill,
+    track_lanes: &TrackLanes,
+) -> f32 {
+    let outlook = predict(bike, action, track_lanes);
+    // progress measured along the current lane, so a shorter line counts for more
+    let progress = track_lanes.distance_on_adjacent_lane(
+        outlook.lane_id,
+        bike.current_lane_id,
+        outlook.distance,
+    ) - bike.distance;
+    let pace = if is_shepherding(bike, others, track_lanes) {
+        SHEPHERD_PACE
+    } else {
+        1.0
+    };
+    let mut score = pace * (progress + outlook.speed * TURN_SECONDS * skill.lookahead_turns as f32);
+    score -= skill.caution * slip_penalty(bike, action, &outlook, skill, track_lanes)
+        / skill.traits.risk_tolerance;
+    score -= skill.caution * traffic_penalty(&outlook, others, track_lanes);
+    score += skill.racecraft * lane_value(&outlook, others, skill, track_lanes);
+    score += skill.aggression * blocking_value(&outlook, others, track_lanes);
+    score += skill.aggression
+        * skill.traits.aggression
+        * contact_value(bike, action, others, track_lanes);
+    score
+}
+
+fn predict(bike: &Bike, action: BikeAction, track_lanes: &TrackLanes) -> Outlook {
+    let speed = speed_after(bike, action);
+    let distance = bike.distance + (bike.speed + speed) / 2.0 * TURN_SECONDS;
+    let lane_id = lane_after(bike, action, track_lanes);
+    Outlook {
+        lane_id,
+        distance: track_lanes.distance_on_adjacent_lane(bike.current_lane_id, lane_id, distance),
+        speed,
+    }
+}
+
+/// Where another bike will be at the end of the turn if it carries on as it is, measured
+/// along `lane_id`
+fn project(other: &Bike, lane_id: TrackLaneId, track_lanes: &TrackLanes) -> f32 {
+    track_lanes.distance_on_adjacent_lane(
+        other.current_lane_id,
+        lane_id,
+        other.distance + other.speed * TURN_SECONDS,
+    )
+}
+
+fn slip_cost(risk: Option<SlipRisk>) -> f32 {
+    match risk {
+        Some(SlipRisk::Crash) => CRASH_PENALTY,
+        Some(SlipRisk::Slide { lanes }) => lanes * SLIDE_PENALTY_PER_LANE,
+        None => 0.0,
+    }
+}
+
+/// Slips are checked as a turn starts, so only a skid saves a bike that is already too
+/// fast for its bend. Bends further ahead can still be slowed for, at the cost of the
+/// speed that has to be shed, which is more pressing the closer the bend is.
+fn slip_penalty(
+    bike: &Bike,
+    action: BikeAction,
+    outlook: &Outlook,
+    skill: &AiSkill,
+    track_lanes: &TrackLanes,
+) -> f32 {
+    let mut penalty = if action == BikeAction::Skid {
+        0.0
+    } else {
+        slip_cost(slip_risk(bike, track_lanes))
+    };
+    for turns_ahead in 0..skill.lookahead_turns {
+        let distance = outlook.distance + outlook.speed * TURN_SECONDS * turns_ahead as f32;
+        if let Some(max_speed) = max_turn_speed(bike, track_lanes, &outlook.lane_id, distance) {
+            penalty += (outlook.speed - max_speed).max(0.0) / (turns_ahead + 1) as f32;
+        }
+    }
+    penalty
+}
+
+/// Whether the rider is following a teammate home ahead of every rival, with a rival
+/// close enough behind to be held up. The rider then rides to keep the rival back
+/// rather than to get on.
+fn is_shepherding(bike: &Bike, others: &[OtherBike], track_lanes: &TrackLanes) -> bool {
+    // how far ahead of the rider another bike is, along the rider's lane
+    let lead = |other: &OtherBike| {
+        track_lanes.distance_on_adjacent_lane(
+            other.bike.current_lane_id,
+            bike.current_lane_id,
+            other.bike.distance,
+        ) - bike.distance
+    };
+    let teammate_ahead = others
+        .iter()
+        .any(|other| other.teammate && lead(other) > 0.0);
+    let rival_ahead = others
+        .iter()
+        .any(|other| !other.teammate && lead(other) > 0.0);
+    let rival_close_behind = others
+        .iter()
+        .any(|other| !other.teammate && (-SHEPHERD_RANGE..=0.0).contains(&lead(other)));
+    teammate_ahead && !rival_ahead && rival_close_behind
+}
+
+/// Running into the back of a slower bike, or ending up on top of one
+fn traffic_penalty(outlook: &Outlook, others: &[OtherBike], track_lanes: &TrackLanes) -> f32 {
+    others
+        .iter()
+        .map(|other| &other.bike)
+        .filter(|other| other.current_lane_id == outlook.lane_id)
+        .map(|other| {
+            let gap = project(other, outlook.lane_id, track_lanes) - outlook.distance;
+            if gap.abs() >= BIKE_LENGTH {
+                0.0
+            } else if gap >= 0.0 && outlook.speed > other.speed + RAM_SPEED {
+                CRASH_PENALTY
+            } else {
+                SLIDE_PENALTY_PER_LANE
+            }
+        })
+        .sum()
+}
+
+/// Open track ahead, and the line the rider likes to take
+fn lane_value(
+    outlook: &Outlook,
+    others: &[OtherBike],
+    skill: &AiSkill,
+    track_lanes: &TrackLanes,
+) -> f32 {
+    let clear_ahead = others
+        .iter()
+        .map(|other| &other.bike)
+        .filter(|other| other.current_lane_id == outlook.lane_id)
+        .map(|other| project(other, outlook.lane_id, track_lanes) - outlook.distance)
+        .filter(|gap| *gap >= 0.0)
+        .fold(GAP_HORIZON, f32::min);
+    let line_index = skill
+        .traits
+        .preferred_line
+        .lane_index(track_lanes.lane_count());
+    let lanes_off_line = outlook.lane_id.index().abs_diff(line_index) as f32;
+    clear_ahead * GAP_WEIGHT - lanes_off_line * LINE_BONUS
+}
+
+/// Sitting in front of a rival close behind holds them up
+fn blocking_value(outlook: &Outlook, others: &[OtherBike], track_lanes: &TrackLanes) -> f32 {
+    let mut rivals = others
+        .iter()
+        .filter(|other| !other.teammate)
+        .map(|other| &other.bike);
+    let blocks_someone = rivals.any(|other| {
+        let gap = outlook.distance - project(other, outlook.lane_id, track_lanes);
+        other.current_lane_id == outlook.lane_id && gap > 0.0 && gap < BLOCK_RANGE
+    });
+    if blocks_someone {
+        BLOCK_BONUS
+    } else {
+        0.0
+    }
+}
+
+/// Elbows and hips are worth throwing at a rival alongside who is no faster, and never
+/// at a teammate
+fn contact_value(
+    bike: &Bike,
+    action: BikeAction,
+    others: &[OtherBike],
+    track_lanes: &TrackLanes,
+) -> f32 {
+    let adjacent_lane_id = match action {
+        BikeAction::LeftElbow | BikeAction::LeftHip => bike.current_lane_id.left(),
+        BikeAction::RightElbow | BikeAction::RightHip => bike.current_lane_id.right(track_lanes),
+        _ => return 0.0,
+    };
+    let target = others
+        .iter()
+        .filter(|other| other.bike.current_lane_id == adjacent_lane_id)
+        .find(|other| {
+            let level_distance = track_lanes.distance_on_adjacent_lane(
+                adjacent_lane_id,
+                bike.current_lane_id,
+                other.bike.distance,
+            );
+            (level_distance - bike.distance).abs() <= CONTACT_REACH
+        });
+    match target {
+        Some(target) if target.teammate || target.rebuffed => -CONTACT_BONUS,
+        Some(target) if bike.speed >= target.bike.speed => CONTACT_BONUS,
+        Some(_) => -CONTACT_BONUS,
+        None => -MISSED_CONTACT_PENALTY,
+    }
+}
@@ -3,14 +3,19 @@ use bevy_prototype_lyon::{
     draw::Stroke, entity::ShapeBundle, path::PathBuilder, plugin::ShapePlugin,
 };
 
-use crate::{bike::Bike, player::Player, track::TrackLanes, RacingState};
+use crate::{bike::Bike, player::ActivePlayer, track::TrackLanes, RacingState};
+
+const CURVE_STEP_LENGTH: f32 = 20.0;
 
 pub struct PathHighlightPlugin;
 
 impl Plugin for PathHighlightPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(ShapePlugin)
-            .add_systems(OnEnter(RacingState::Commanding), show_path_highlight)
+            .add_systems(
+                Update,
+                show_path_highlight.run_if(in_state(RacingState::Commanding)),
+            )
             .add_systems(OnEnter(RacingState::Simulating), hide_path_highlight);
     }
 }
@@ -18,12 +23,17 @@ impl Plugin for PathHighlightPlugin {
 #[derive(Component)]
 struct PathHighlight;
 
+/// Shows how far the player whose turn it is will go at their current speed
 fn show_path_highlight(
-    bikes: Query<&Bike, With<Player>>,
+    bikes: Query<&Bike, Added<ActivePlayer>>,
+    q_path_highlights: Query<Entity, With<PathHighlight>>,
     mut commands: Commands,
     track_lanes: Res<TrackLanes>,
 ) {
     for bike in bikes.iter() {
+        for entity in &q_path_highlights {
+            commands.entity(entity).despawn();
+        }
         let lane = track_lanes.track_lane(&bike.current_lane_id);
         let (pos, _) = lane.position_and_rotation(bike.distance);
         let mut path_builder = PathBuilder::new();
@@ -36,13 +46,23 @@ fn show_path_highlight(
             let section_end_distance = lane.distance_to_end_of_track_section(path_marker);
             let path_section_end_distance = section_end_distance.min(path_length_remaining);
             let end_distance_along_track = path_marker + path_section_end_distance;
-            if lane.in_turn(path_marker) {
+            if lane.in_arc(path_marker) {
                 // draw turn
                 // let start_dist = bike.distance + path_section_end_distance;
                 // let end_dist = path_marker + path_section_end_distance;
                 let (center, radii, sweep_angle, x_rotation) =
                     lane.turn_curve_components(path_marker, end_distance_along_track);
                 path_builder.arc(center, radii, sweep_angle, x_rotation);
+            } else if lane.in_turn(path_marker) {
+                // draw curves without a single centre as short lines
+                let steps = (path_section_end_distance / CURVE_STEP_LENGTH)
+                    .ceil()
+                    .max(1.0);
+                for step in 1..=steps as usize {
+                    let step_distance = path_section_end_distance * step as f32 / steps;
+                    let (step_pos, _) = lane.position_and_rotation(path_marker + step_distance);
+                    path_builder.line_to(step_pos);
+                }
             } else {
                 // draw straightaway
                 let (end_pos, _) = lane.position_and_rotation(end_distance_along_track);
@@ -1,42 +1,150 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use crate::{PlayingState, RacingState};
+use crate::{
+    actions::{on_action, BikeAction},
+    bike::Crashed,
+    game::{Finished, Rider},
+    network::{NetworkClient, NetworkRace},
+    GameState, RacingState,
+};
+
+/// Most people that can take turns racing on one machine
+pub const MAX_PLAYERS: usize = 4;
 
 pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            Update,
-            (toggle_simulating_state).run_if(in_state(PlayingState::Racing)),
-        );
+        app.init_resource::<PlayerCount>()
+            .add_systems(
+                Update,
+                confirm_orders.run_if(
+                    in_state(GameState::Playing)
+                        .and_then(in_state(RacingState::Commanding))
+                        // turns of network races start together on every machine
+                        .and_then(not(resource_exists::<NetworkRace>))
+                        // and with players taking turns, once they have all chosen
+                        .and_then(single_player),
+                ),
+            )
+            .add_systems(
+                Update,
+                (
+                    hand_over_turn,
+                    // the host of a network race starts the turns of its clients
+                    start_turn.run_if(not(resource_exists::<NetworkClient>)),
+                )
+                    .chain()
+                    .after(on_action)
+                    .run_if(
+                        in_state(GameState::Playing).and_then(in_state(RacingState::Commanding)),
+                    ),
+            )
+            .add_systems(OnExit(RacingState::Commanding), end_player_turn);
     }
 }
 
-#[derive(Component, Debug, Copy, Clone, PartialEq)]
+#[derive(Component, Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
 pub struct Player {
     pub position: usize,
 }
 
 impl Player {
-    pub fn new() -> Self {
-        Self { position: 4 }
+    /// Starts the race in last place out of `rider_count` riders
+    pub fn new(rider_count: usize) -> Self {
+        Self {
+            position: rider_count,
+        }
+    }
+}
+
+/// People taking turns to race on this machine, who each get a rider
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerCount(pub usize);
+
+impl Default for PlayerCount {
+    fn default() -> Self {
+        Self(1)
+    }
+}
+
+impl PlayerCount {
+    pub fn cycle(&mut self) {
+        self.0 = self.0 % MAX_PLAYERS + 1;
+    }
+}
+
+/// The player choosing an action, who the controls and camera are for
+#[derive(Component, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ActivePlayer;
+
+/// A player racing on another machine, whose actions arrive over the network
+#[derive(Component, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RemotePlayer;
+
+/// Passes the controls to the next player here who still has to choose, in lane order
+/// from the inside
+fn hand_over_turn(
+    mut commands: Commands,
+    q_active: Query<(Entity, Has<BikeAction>), With<ActivePlayer>>,
+    q_waiting: Query<
+        (Entity, &Rider),
+        (
+            With<Player>,
+            Without<RemotePlayer>,
+            Without<BikeAction>,
+            Without<Crashed>,
+            Without<Finished>,
+        ),
+    >,
+) {
+    if let Ok((entity, chosen)) = q_active.get_single() {
+        if !chosen {
+            return;
+        }
+        commands.entity(entity).remove::<ActivePlayer>();
+    }
+    if let Some((entity, _)) = q_waiting.iter().min_by_key(|(_, rider)| rider.number()) {
+        commands.entity(entity).insert(ActivePlayer);
+    }
+}
+
+/// Starts the turn once every player has chosen
+fn start_turn(
+    q_waiting: Query<
+        (),
+        (
+            With<Player>,
+            Without<BikeAction>,
+            Without<Crashed>,
+            Without<Finished>,
+        ),
+    >,
+    mut next_state: ResMut<NextState<RacingState>>,
+) {
+    if q_waiting.is_empty() {
+        next_state.set(RacingState::Simulating);
     }
 }
 
-fn toggle_simulating_state(
+fn single_player(player_count: Res<PlayerCount>) -> bool {
+    player_count.0 <= 1
+}
+
+fn end_player_turn(mut commands: Commands, q_active: Query<Entity, With<ActivePlayer>>) {
+    for entity in &q_active {
+        commands.entity(entity).remove::<ActivePlayer>();
+    }
+}
+
+/// Space or Enter starts the turn with the orders given so far. A turn that has
+/// started always runs in full.
+fn confirm_orders(
     mut next_state: ResMut<NextState<RacingState>>,
-    state: Res<State<RacingState>>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
 ) {
     if keyboard_input.any_just_pressed([KeyCode::Space, KeyCode::Enter]) {
-        match state.get() {
-            RacingState::Simulating => {
-                next_state.set(RacingState::Commanding);
-            }
-            RacingState::Commanding => {
-                next_state.set(RacingState::Simulating);
-            }
-        }
+        next_state.set(RacingState::Simulating);
     }
 }
@@ -0,0 +1,199 @@
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    bike::Bike,
+    game::{TICKS_PER_TURN, TICK_SECONDS},
+    track::TrackLaneId,
+};
+
+/// Longest a rider can take to react to the start, which is a whole turn
+const MAX_START_REACTION: f32 = TICKS_PER_TURN as f32 * TICK_SECONDS;
+
+pub struct RiderProfilePlugin;
+
+impl Plugin for RiderProfilePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<RiderProfile>()
+            .init_asset_loader::<RiderProfileLoader>();
+    }
+}
+
+/// A named rider loaded from a `.rider.ron` file under `assets/riders/`
+#[derive(Asset, TypePath, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RiderProfile {
+    pub name: String,
+    pub bike: BikeStats,
+    #[serde(default)]
+    pub traits: RiderTraits,
+}
+
+/// How the rider's machine performs
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct BikeStats {
+    pub top_speed: f32,
+    pub acceleration: f32,
+    pub grip: f32,
+}
+
+/// How the rider races, which computer controlled riders act on
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct RiderTraits {
+    /// Keenness to use elbows and hips, relative to other riders of the same difficulty
+    pub aggression: f32,
+    /// Willingness to carry speed into bends, relative to other riders of the same
+    /// difficulty
+    pub risk_tolerance: f32,
+    pub preferred_line: RacingLine,
+    /// Seconds the rider takes to get going when the race starts
+    pub start_reaction: f32,
+}
+
+/// The part of the track a rider likes to race on
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RacingLine {
+    #[default]
+    Inside,
+    Middle,
+    Outside,
+}
+
+impl Default for RiderProfile {
+    fn default() -> Self {
+        Self {
+            name: "Rider".to_string(),
+            bike: BikeStats::default(),
+            traits: RiderTraits::default(),
+        }
+    }
+}
+
+impl Default for BikeStats {
+    fn default() -> Self {
+        Self {
+            top_speed: 1400.0,
+            acceleration: 800.0,
+            grip: 0.5,
+        }
+    }
+}
+
+impl Default for RiderTraits {
+    fn default() -> Self {
+        Self {
+            aggression: 1.0,
+            risk_tolerance: 1.0,
+            preferred_line: RacingLine::Inside,
+            start_reaction: 0.0,
+        }
+    }
+}
+
+impl RacingLine {
+    /// Index of the lane the line follows, on a track with `lane_count` lanes
+    pub fn lane_index(&self, lane_count: usize) -> usize {
+        match self {
+            RacingLine::Inside => 0,
+            RacingLine::Middle => lane_count / 2,
+            RacingLine::Outside => lane_count.saturating_sub(1),
+        }
+    }
+}
+
+impl RiderProfile {
+    /// The profile of the rider raced by player `index`, counting from zero, out of
+    /// `player_count` taking turns on this machine
+    pub fn player(index: usize, player_count: usize) -> Self {
+        let name = if player_count > 1 {
+            format!("Player {}", index + 1)
+        } else {
+            "You".to_string()
+        };
+        Self { name, ..default() }
+    }
+
+    /// Reads a rider profile without an asset server
+    pub fn from_ron(bytes: &[u8]) -> Result<Self, RiderProfileLoaderError> {
+        let profile: RiderProfile = ron::de::from_bytes(bytes)?;
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    /// The rider's bike, waiting to start in `lane_id`
+    pub fn bike(&self, lane_id: &TrackLaneId) -> Bike {
+        Bike {
+            start_reaction: self.traits.start_reaction,
+            ..Bike::new(
+                lane_id,
+                self.bike.top_speed,
+                self.bike.grip,
+                self.bike.acceleration,
+            )
+        }
+    }
+
+    fn validate(&self) -> Result<(), RiderProfileLoaderError> {
+        let invalid = |reason: String| Err(RiderProfileLoaderError::Invalid(reason));
+        let stats = [
+            ("top speed", self.bike.top_speed),
+            ("acceleration", self.bike.acceleration),
+            ("grip", self.bike.grip),
+            ("risk tolerance", self.traits.risk_tolerance),
+        ];
+        if let Some((stat, value)) = stats.iter().find(|(_, value)| *value <= 0.0) {
+            return invalid(format!("{stat} must be positive, got {value}"));
+        }
+        if self.traits.aggression < 0.0 {
+            return invalid(format!(
+                "aggression can't be negative, got {}",
+                self.traits.aggression
+            ));
+        }
+        if !(0.0..MAX_START_REACTION).contains(&self.traits.start_reaction) {
+            return invalid(format!(
+                "start reaction must be from 0 to under {MAX_START_REACTION} seconds, got {}",
+                self.traits.start_reaction
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RiderProfileLoaderError {
+    #[error("could not read rider profile: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse rider profile: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("invalid rider profile: {0}")]
+    Invalid(String),
+}
+
+#[derive(Default)]
+pub struct RiderProfileLoader;
+
+impl AssetLoader for RiderProfileLoader {
+    type Asset = RiderProfile;
+    type Settings = ();
+    type Error = RiderProfileLoaderError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        RiderProfile::from_ron(&bytes)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["rider.ron"]
+    }
+}
@@ -1,15 +1,126 @@
 use bevy::prelude::*;
 use fastrand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, GameState, PlayingState};
+
+const SEED_ARGUMENT: &str = "--seed";
+
+// Each stream gets its own seed, so drawing more numbers from one doesn't shift the others
+const GRID_STREAM: u64 = 1;
+const AI_STREAM: u64 = 2;
+const INCIDENTS_STREAM: u64 = 3;
+const START_STREAM: u64 = 4;
 
 pub struct RandomnessPlugin;
 
 impl Plugin for RandomnessPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<Randomness>();
+        let race_seed = RaceSeed::from_environment(Config::of(app));
+        app.insert_resource(race_seed)
+            .init_resource::<Randomness>()
+            .add_systems(
+                OnEnter(PlayingState::SetupRace),
+                seed_race.run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+/// The seed the next race should use, or `None` to pick a new one for every race
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RaceSeed(pub Option<u64>);
+
+impl RaceSeed {
+    /// A seed given as `--seed <number>` on the command line, or else in the config file
+    pub fn from_environment(config: &Config) -> Self {
+        let arguments: Vec<String> = std::env::args().collect();
+        let argument_seed = arguments
+            .windows(2)
+            .find(|pair| pair[0] == SEED_ARGUMENT)
+            .and_then(|pair| pair[1].parse().ok());
+        Self(argument_seed.or(config.seed))
     }
 }
 
-#[derive(Resource, Default)]
+/// Random numbers for a race, split into independent streams that all come from
+/// one seed, so any race can be played again exactly
+#[derive(Resource)]
 pub struct Randomness {
-    pub rng: Rng,
+    seed: u64,
+    /// Draws the starting grid
+    pub grid: Rng,
+    /// Decisions of computer controlled riders
+    pub ai: Rng,
+    /// Luck in contacts and other incidents on track
+    pub incidents: Rng,
+    /// When the tapes go up
+    pub start: Rng,
+}
+
+impl Randomness {
+    pub fn from_seed(seed: u64) -> Self {
+        Self {
+            seed,
+            grid: Rng::with_seed(stream_seed(seed, GRID_STREAM)),
+            ai: Rng::with_seed(stream_seed(seed, AI_STREAM)),
+            incidents: Rng::with_seed(stream_seed(seed, INCIDENTS_STREAM)),
+            start: Rng::with_seed(stream_seed(seed, START_STREAM)),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Where every stream has got to, so a race can carry on with the same luck
+    pub fn state(&self) -> RandomnessState {
+        RandomnessState {
+            seed: self.seed,
+            grid: self.grid.get_seed(),
+            ai: self.ai.get_seed(),
+            incidents: self.incidents.get_seed(),
+            start: self.start.get_seed(),
+        }
+    }
+
+    pub fn from_state(state: &RandomnessState) -> Self {
+        Self {
+            seed: state.seed,
+            grid: Rng::with_seed(state.grid),
+            ai: Rng::with_seed(state.ai),
+            incidents: Rng::with_seed(state.incidents),
+            start: Rng::with_seed(state.start),
+        }
+    }
+}
+
+/// The seed of a race and the position of each of its random streams
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RandomnessState {
+    seed: u64,
+    grid: u64,
+    ai: u64,
+    incidents: u64,
+    #[serde(default)]
+    start: u64,
+}
+
+impl Default for Randomness {
+    fn default() -> Self {
+        Self::from_seed(fastrand::u64(..))
+    }
+}
+
+/// Mixes the stream number into the race seed with SplitMix64
+fn stream_seed(seed: u64, stream: u64) -> u64 {
+    let mut z = seed.wrapping_add(stream.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+pub fn seed_race(mut randomness: ResMut<Randomness>, race_seed: Res<RaceSeed>) {
+    let seed = race_seed.0.unwrap_or_else(|| fastrand::u64(..));
+    debug!("Race seed {seed}");
+    *randomness = Randomness::from_seed(seed);
 }
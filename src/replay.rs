@@ -0,0 +1,243 @@
+mod viewer;
+
+use std::path::{Path, PathBuf};
+
+use bevy::{prelude::*, utils::SystemTime};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    actions::BikeAction,
+    game::{draw_starting_grid, Rider, RiderSetup, StartingGrid},
+    loading::{TrackAssets, TRACK_FILES},
+    random::Randomness,
+    simulation::TurnPhaseSet,
+    start_gate::{Launch, StartEvent},
+    track::SelectedTrack,
+    GameState, PlayingState, RacingState,
+};
+
+pub use self::viewer::watch_replay;
+use self::viewer::ReplayViewerPlugin;
+
+/// Folder in the working directory that replays are saved to
+const REPLAY_DIRECTORY: &str = "replays";
+const REPLAY_EXTENSION: &str = ".replay.ron";
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(ReplayViewerPlugin)
+            .add_systems(
+                OnEnter(PlayingState::SetupRace),
+                start_recording
+                    .after(draw_starting_grid)
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(Update, record_launches.run_if(resource_exists::<Recording>))
+            .add_systems(
+                OnEnter(RacingState::Simulating),
+                record_actions
+                    .in_set(TurnPhaseSet)
+                    .run_if(resource_exists::<Recording>),
+            )
+            .add_systems(
+                OnExit(PlayingState::Racing),
+                save_recording.run_if(resource_exists::<Recording>),
+            );
+    }
+}
+
+/// Everything needed to play a race again: the seed and track it was raced with, who
+/// started where, when every rider went at the start and the action every rider chose
+/// in every turn
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Replay {
+    pub seed: u64,
+    /// Track file, as listed in `TRACK_FILES`
+    pub track: String,
+    pub riders: Vec<RiderSetup>,
+    /// Launches chosen at each call to the tapes, by rider number
+    #[serde(default)]
+    pub starts: Vec<Vec<(usize, Launch)>>,
+    pub turns: Vec<ReplayTurn>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ReplayTurn {
+    /// Actions chosen this turn, by rider number
+    pub actions: Vec<(usize, BikeAction)>,
+}
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error("could not access replay file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse replay: {0}")]
+    Parse(#[from] ron::error::SpannedError),
+    #[error("could not write replay: {0}")]
+    Write(#[from] ron::Error),
+    #[error("no replays have been saved yet")]
+    NoReplays,
+    #[error("replay is of a track that isn't available: {0}")]
+    UnknownTrack(String),
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> Result<Self, ReplayError> {
+        let bytes = std::fs::read(path)?;
+        Ok(ron::de::from_bytes(&bytes)?)
+    }
+
+    /// Writes the replay to a new file in the replay folder, named after the time it
+    /// was saved
+    pub fn save(&self) -> Result<PathBuf, ReplayError> {
+        self.save_in(Path::new(REPLAY_DIRECTORY))
+    }
+
+    fn save_in(&self, directory: &Path) -> Result<PathBuf, ReplayError> {
+        let seconds = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        std::fs::create_dir_all(directory)?;
+        let path = directory.join(format!("race_{seconds}{REPLAY_EXTENSION}"));
+        std::fs::write(&path, ron::ser::to_string(self)?)?;
+        Ok(path)
+    }
+
+    /// The most recently saved replay
+    pub fn latest() -> Result<PathBuf, ReplayError> {
+        std::fs::read_dir(REPLAY_DIRECTORY)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.ends_with(REPLAY_EXTENSION))
+            })
+            .max()
+            .ok_or(ReplayError::NoReplays)
+    }
+
+    /// Index of the replay's track in `TRACK_FILES`, as long as it loaded
+    pub fn track_index(&self, track_assets: &TrackAssets) -> Result<usize, ReplayError> {
+        track_assets
+            .index_of(&self.track)
+            .ok_or_else(|| ReplayError::UnknownTrack(self.track.clone()))
+    }
+}
+
+/// The replay of the race being played, which grows by a turn at a time
+#[derive(Resource)]
+pub struct Recording(pub Replay);
+
+fn start_recording(
+    mut commands: Commands,
+    randomness: Res<Randomness>,
+    selected_track: Res<SelectedTrack>,
+    starting_grid: Res<StartingGrid>,
+) {
+    commands.insert_resource(Recording(Replay {
+        seed: randomness.seed(),
+        track: TRACK_FILES[selected_track.index].to_string(),
+        riders: starting_grid.0.clone(),
+        starts: Vec::new(),
+        turns: Vec::new(),
+    }));
+}
+
+fn record_launches(mut recording: ResMut<Recording>, mut start_events: EventReader<StartEvent>) {
+    for event in start_events.read() {
+        recording.0.starts.push(event.launches.clone());
+    }
+}
+
+fn record_actions(mut recording: ResMut<Recording>, q_actions: Query<(&Rider, &BikeAction)>) {
+    let mut actions: Vec<(usize, BikeAction)> = q_actions
+        .iter()
+        .map(|(rider, action)| (rider.number(), *action))
+        .collect();
+    actions.sort_by_key(|(number, _)| *number);
+    recording.0.turns.push(ReplayTurn { actions });
+}
+
+fn save_recording(mut commands: Commands, recording: Res<Recording>) {
+    match recording.0.save() {
+        Ok(path) => info!("Saved replay to {}", path.display()),
+        Err(error) => warn!("Could not save replay: {error}"),
+    }
+    commands.remove_resource::<Recording>();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        simulation::RaceSimulation,
+        track::{TrackDefinition, TrackLanes},
+    };
+
+    const SEED: u64 = 11;
+    /// Turns after which a race that hasn't finished fails the test
+    const MAX_TURNS: usize = 1000;
+
+    fn simulation() -> RaceSimulation {
+        let definition =
+            TrackDefinition::from_ron(include_bytes!("../assets/tracks/cycle_speedway.track.ron"))
+                .unwrap();
+        RaceSimulation::new(TrackLanes::new(&definition), &[], &Config::default(), SEED)
+    }
+
+    /// Steps the turn with actions given by rider number
+    fn step(simulation: &mut RaceSimulation, actions: &[(usize, BikeAction)]) {
+        let actions: Vec<(Entity, BikeAction)> = actions
+            .iter()
+            .filter_map(|(number, action)| Some((simulation.rider_entity(*number)?, *action)))
+            .collect();
+        simulation.step_turn(&actions);
+    }
+
+    #[test]
+    fn saved_race_plays_back_to_the_same_result() {
+        // the inside rider sticks to their own plan rather than the computer's
+        let plan = [
+            BikeAction::Accelerate,
+            BikeAction::Accelerate,
+            BikeAction::Right,
+            BikeAction::Watch,
+            BikeAction::Left,
+        ];
+        let mut raced = simulation();
+        let mut replay = Replay {
+            seed: SEED,
+            track: TRACK_FILES[0].to_string(),
+            riders: Vec::new(),
+            starts: Vec::new(),
+            turns: Vec::new(),
+        };
+        while !raced.is_finished() && replay.turns.len() < MAX_TURNS {
+            let mut actions = raced.chosen_actions();
+            if let Some((_, action)) = actions.iter_mut().find(|(number, _)| *number == 0) {
+                *action = plan[replay.turns.len() % plan.len()];
+            }
+            step(&mut raced, &actions);
+            replay.turns.push(ReplayTurn { actions });
+        }
+        assert!(raced.is_finished());
+
+        let directory = std::env::temp_dir().join(format!("replay_test_{}", std::process::id()));
+        let path = replay.save_in(&directory).unwrap();
+        let loaded = Replay::load(&path);
+        std::fs::remove_dir_all(&directory).unwrap();
+        let loaded = loaded.unwrap();
+
+        let mut played_back = simulation();
+        for turn in &loaded.turns {
+            step(&mut played_back, &turn.actions);
+        }
+        assert!(played_back.is_finished());
+        assert_eq!(played_back.result(), raced.result());
+    }
+}
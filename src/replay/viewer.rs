@@ -0,0 +1,291 @@
+use std::path::Path;
+
+use bevy::prelude::*;
+
+use crate::{
+    bike::Crashed,
+    game::{Finished, Rider, StartingGrid},
+    loading::TrackAssets,
+    random::Randomness,
+    start_gate::{release_tapes, Launch},
+    track::SelectedTrack,
+    GameState, PlayingState, RacingState,
+};
+
+use super::{Replay, ReplayError};
+
+const REPLAY_ARGUMENT: &str = "--replay";
+/// How many times faster than normal a replay runs while catching up with a turn
+/// that was scrubbed to
+const CATCH_UP_SPEED: f32 = 16.0;
+/// Turns skipped by page up and page down
+const SCRUB_TURNS: usize = 10;
+const REPLAY_FONT_SIZE: f32 = 20.0;
+const REPLAY_TEXT_COLOR: Color = Color::srgb(1.0, 0.8, 0.3);
+const REPLAY_HELP: &str =
+    "Space: play/pause   Right: step   Left/PgUp: back   PgDn: forward   Esc: menu";
+
+/// Watches a replay by racing it again, turn by turn, with the choices it recorded
+pub(super) struct ReplayViewerPlugin;
+
+impl Plugin for ReplayViewerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Menu), open_replay_argument)
+            .add_systems(OnEnter(GameState::Replay), setup_replay_display)
+            .add_systems(
+                OnEnter(PlayingState::SetupRace),
+                restart_replay.run_if(in_state(GameState::Replay)),
+            )
+            .add_systems(
+                Update,
+                (
+                    replay_controls,
+                    play_replay_launches
+                        .before(release_tapes)
+                        .run_if(in_state(RacingState::Tapes)),
+                    play_replay_turn.run_if(in_state(RacingState::Commanding)),
+                    set_replay_speed,
+                    update_replay_display.run_if(resource_changed::<ReplayPlayback>),
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Replay)),
+            )
+            .add_systems(OnExit(GameState::Replay), teardown);
+    }
+}
+
+/// The replay being watched, and how far through it the race has got
+#[derive(Resource)]
+struct ReplayPlayback {
+    replay: Replay,
+    /// Turns played so far
+    turn: usize,
+    /// Calls to the tapes played so far
+    start: usize,
+    paused: bool,
+    /// Plays one more turn while paused
+    step: bool,
+    /// Turn to race through to as quickly as possible, after scrubbing
+    catch_up_to: Option<usize>,
+}
+
+impl ReplayPlayback {
+    fn turn_count(&self) -> usize {
+        self.replay.turns.len()
+    }
+}
+
+#[derive(Component)]
+struct ReplayDisplay;
+
+/// Sets up a replay from a file to be watched when the game enters `GameState::Replay`
+pub fn watch_replay(
+    path: &Path,
+    commands: &mut Commands,
+    selected_track: &mut SelectedTrack,
+    track_assets: &TrackAssets,
+) -> Result<(), ReplayError> {
+    let replay = Replay::load(path)?;
+    selected_track.index = replay.track_index(track_assets)?;
+    commands.insert_resource(StartingGrid(replay.riders.clone()));
+    commands.insert_resource(ReplayPlayback {
+        replay,
+        turn: 0,
+        start: 0,
+        paused: false,
+        step: false,
+        catch_up_to: None,
+    });
+    info!("Watching replay {}", path.display());
+    Ok(())
+}
+
+/// Goes straight to the replay given as `--replay <file>` on the command line, the
+/// first time the menu opens
+fn open_replay_argument(
+    mut commands: Commands,
+    mut selected_track: ResMut<SelectedTrack>,
+    track_assets: Res<TrackAssets>,
+    mut game_state: ResMut<NextState<GameState>>,
+    mut opened: Local<bool>,
+) {
+    if *opened {
+        return;
+    }
+    *opened = true;
+    let arguments: Vec<String> = std::env::args().collect();
+    let Some(path) = arguments
+        .windows(2)
+        .find(|pair| pair[0] == REPLAY_ARGUMENT)
+        .map(|pair| Path::new(&pair[1]).to_path_buf())
+    else {
+        return;
+    };
+    match watch_replay(&path, &mut commands, &mut selected_track, &track_assets) {
+        Ok(()) => game_state.set(GameState::Replay),
+        Err(error) => warn!("Could not open replay {}: {error}", path.display()),
+    }
+}
+
+/// Starts the race from the first turn, with the same luck as when it was recorded
+fn restart_replay(mut playback: ResMut<ReplayPlayback>, mut randomness: ResMut<Randomness>) {
+    playback.turn = 0;
+    playback.start = 0;
+    *randomness = Randomness::from_seed(playback.replay.seed);
+}
+
+fn replay_controls(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut playback: ResMut<ReplayPlayback>,
+    mut playing_state: ResMut<NextState<PlayingState>>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        game_state.set(GameState::Menu);
+        return;
+    }
+    if keyboard_input.just_pressed(KeyCode::Space) {
+        playback.paused = !playback.paused;
+    }
+    if keyboard_input.just_pressed(KeyCode::ArrowRight) {
+        playback.paused = true;
+        playback.step = true;
+    }
+    let target = playback.catch_up_to.unwrap_or(playback.turn);
+    if keyboard_input.just_pressed(KeyCode::PageDown) {
+        playback.catch_up_to = Some((target + SCRUB_TURNS).min(playback.turn_count()));
+    }
+    let scrub_back = if keyboard_input.just_pressed(KeyCode::ArrowLeft) {
+        1
+    } else if keyboard_input.just_pressed(KeyCode::PageUp) {
+        SCRUB_TURNS
+    } else {
+        0
+    };
+    if scrub_back > 0 {
+        // turns can't be undone, so the race is run again up to the earlier turn
+        playback.paused = true;
+        playback.catch_up_to = Some(target.saturating_sub(scrub_back));
+        playing_state.set(PlayingState::SetupRace);
+    }
+}
+
+/// Hands every rider at the tapes the launch they chose at the next recorded call.
+/// Riders the replay has no launch for wait for the tapes.
+fn play_replay_launches(
+    mut commands: Commands,
+    mut playback: ResMut<ReplayPlayback>,
+    q_riders: Query<(Entity, &Rider), (Without<Launch>, Without<Finished>)>,
+) {
+    if q_riders.is_empty() {
+        return;
+    }
+    let launches = playback
+        .replay
+        .starts
+        .get(playback.start)
+        .cloned()
+        .unwrap_or_default();
+    for (entity, rider) in &q_riders {
+        let launch = launches
+            .iter()
+            .find(|(number, _)| *number == rider.number())
+            .map_or(Launch::React, |(_, launch)| *launch);
+        commands.entity(entity).insert(launch);
+    }
+    playback.start += 1;
+}
+
+/// Hands every rider the action they chose in the next recorded turn, and simulates it
+fn play_replay_turn(
+    mut commands: Commands,
+    mut playback: ResMut<ReplayPlayback>,
+    q_riders: Query<(Entity, &Rider), Without<Crashed>>,
+    mut next_state: ResMut<NextState<RacingState>>,
+) {
+    if playback
+        .catch_up_to
+        .is_some_and(|target| playback.turn >= target)
+    {
+        playback.catch_up_to = None;
+    }
+    if playback.paused && !playback.step && playback.catch_up_to.is_none() {
+        return;
+    }
+    playback.step = false;
+    let Some(turn) = playback.replay.turns.get(playback.turn) else {
+        playback.paused = true;
+        return;
+    };
+    for (number, action) in &turn.actions {
+        if let Some((entity, _)) = q_riders.iter().find(|(_, rider)| rider.number() == *number) {
+            commands.entity(entity).insert(*action);
+        }
+    }
+    playback.turn += 1;
+    next_state.set(RacingState::Simulating);
+}
+
+fn set_replay_speed(playback: Res<ReplayPlayback>, mut time: ResMut<Time<Virtual>>) {
+    let speed = if playback.catch_up_to.is_some() {
+        CATCH_UP_SPEED
+    } else {
+        1.0
+    };
+    if time.relative_speed() != speed {
+        time.set_relative_speed(speed);
+    }
+}
+
+fn setup_replay_display(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let text_style = TextStyle {
+        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+        font_size: REPLAY_FONT_SIZE,
+        color: REPLAY_TEXT_COLOR,
+    };
+    commands.spawn((
+        ReplayDisplay,
+        TextBundle::from_sections([
+            TextSection::new("", text_style.clone()),
+            TextSection::new(format!("\n{REPLAY_HELP}"), text_style),
+        ])
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(5.0),
+            left: Val::Px(5.0),
+            ..default()
+        }),
+    ));
+}
+
+fn update_replay_display(
+    playback: Res<ReplayPlayback>,
+    mut q_display: Query<&mut Text, With<ReplayDisplay>>,
+) {
+    let status = if playback.catch_up_to.is_some() {
+        "catching up"
+    } else if playback.paused {
+        "paused"
+    } else {
+        "playing"
+    };
+    for mut text in q_display.iter_mut() {
+        text.sections[0].value = format!(
+            "REPLAY  turn {} of {}  ({status})",
+            playback.turn,
+            playback.turn_count()
+        );
+    }
+}
+
+fn teardown(
+    mut commands: Commands,
+    q_display: Query<Entity, With<ReplayDisplay>>,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    for entity in q_display.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<ReplayPlayback>();
+    time.set_relative_speed(1.0);
+}
@@ -0,0 +1,387 @@
+use bevy::{ecs::world::EntityRef, input::common_conditions::input_just_pressed, prelude::*};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    career::Career,
+    game::{Rider, TurnTimer},
+    loading::{TrackAssets, TRACK_FILES},
+    meeting::Meeting,
+    random::{Randomness, RandomnessState},
+    replay::{Recording, Replay},
+    simulation::{RiderSnapshot, TurnPhaseSet},
+    track::SelectedTrack,
+    GameState, PlayingState, RacingState,
+};
+
+/// Name the race in progress is saved under
+const RACE_SAVE: &str = "race";
+
+/// Keeps the race in progress saved as it goes, so it can be picked up again after the
+/// game is closed. The race is saved at the start of every turn, when F5 is pressed
+/// and when the game exits, so a browser tab that is closed loses at most one turn.
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.configure_sets(
+            OnEnter(RacingState::Commanding),
+            TurnPhaseSet.run_if(not(resource_exists::<ResumedRace>)),
+        )
+        .configure_sets(
+            OnEnter(RacingState::Simulating),
+            TurnPhaseSet.run_if(not(resource_exists::<ResumedRace>)),
+        )
+        .add_systems(
+            OnEnter(PlayingState::SetupRace),
+            queue_resumed_state.run_if(resource_exists::<ResumedRace>),
+        )
+        .add_systems(
+            OnEnter(PlayingState::Racing),
+            restore_race.run_if(resource_exists::<ResumedRace>),
+        )
+        .add_systems(
+            OnEnter(RacingState::Commanding),
+            save_race
+                .after(TurnPhaseSet)
+                .run_if(in_state(GameState::Playing)),
+        )
+        .add_systems(
+            Update,
+            (
+                save_race.run_if(input_just_pressed(KeyCode::F5)),
+                finish_resuming.run_if(resource_exists::<ResumedRace>),
+            )
+                .run_if(in_state(GameState::Playing).and_then(in_state(PlayingState::Racing))),
+        )
+        .add_systems(
+            Last,
+            save_race.run_if(
+                on_event::<AppExit>()
+                    .and_then(in_state(GameState::Playing))
+                    .and_then(in_state(PlayingState::Racing)),
+            ),
+        )
+        .add_systems(
+            OnEnter(PlayingState::FinishRace),
+            delete_saved_race.run_if(in_state(GameState::Playing)),
+        );
+    }
+}
+
+/// Everything about a race in progress, down to how far through a turn it is
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SavedRace {
+    /// Track file, as listed in `TRACK_FILES`
+    track: String,
+    racing_state: RacingState,
+    turn_timer: TurnTimer,
+    randomness: RandomnessState,
+    riders: Vec<RiderSnapshot>,
+    /// The race so far, if it is being recorded
+    replay: Option<Replay>,
+    /// The meeting the race is a heat of, if any
+    #[serde(default)]
+    meeting: Option<Meeting>,
+    /// The career the meeting is a round of, if any
+    #[serde(default)]
+    career: Option<Career>,
+}
+
+#[derive(Debug, Error)]
+pub enum SaveError {
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("could not access save: {0}")]
+    Io(#[from] std::io::Error),
+    #[cfg(target_arch = "wasm32")]
+    #[error("browser storage is not available")]
+    Storage,
+    #[error("could not parse save: {0}")]
+    Parse(#[from] ron::error::SpannedError),
+    #[error("could not write save: {0}")]
+    Write(#[from] ron::Error),
+    #[error("nothing has been saved")]
+    NoSave,
+    #[error("saved race is on a track that isn't available: {0}")]
+    UnknownTrack(String),
+}
+
+impl SavedRace {
+    pub fn load() -> Result<Self, SaveError> {
+        Ok(ron::de::from_str(&read_save(RACE_SAVE)?)?)
+    }
+
+    pub fn save(&self) -> Result<(), SaveError> {
+        write_save(RACE_SAVE, &ron::ser::to_string(self)?)
+    }
+}
+
+/// File in the working directory that the save called `name` is kept in
+#[cfg(not(target_arch = "wasm32"))]
+fn save_file(name: &str) -> String {
+    format!("{name}.save.ron")
+}
+
+/// Reads the save called `name`
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read_save(name: &str) -> Result<String, SaveError> {
+    match std::fs::read_to_string(save_file(name)) {
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Err(SaveError::NoSave),
+        result => Ok(result?),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn write_save(name: &str, text: &str) -> Result<(), SaveError> {
+    Ok(std::fs::write(save_file(name), text)?)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn delete_save(name: &str) -> Result<(), SaveError> {
+    match std::fs::remove_file(save_file(name)) {
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+/// Moves the save called `name` aside, where it is kept but no longer read, without
+/// overwriting any earlier backup. Returns where it went.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn back_up_save(name: &str) -> Result<String, SaveError> {
+    let backup = (1..)
+        .map(|number| format!("{}.{number}.bak", save_file(name)))
+        .find(|path| !std::path::Path::new(path).exists())
+        .expect("there is always a free backup name");
+    std::fs::rename(save_file(name), &backup)?;
+    Ok(backup)
+}
+
+/// Key in the browser's local storage that the save called `name` is kept under
+#[cfg(target_arch = "wasm32")]
+fn save_key(name: &str) -> String {
+    format!("cycle-speedway-{name}")
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Result<web_sys::Storage, SaveError> {
+    web_sys::window()
+        .and_then(|window| window.local_storage().ok().flatten())
+        .ok_or(SaveError::Storage)
+}
+
+/// Reads the save called `name`
+#[cfg(target_arch = "wasm32")]
+pub fn read_save(name: &str) -> Result<String, SaveError> {
+    local_storage()?
+        .get_item(&save_key(name))
+        .map_err(|_| SaveError::Storage)?
+        .ok_or(SaveError::NoSave)
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn write_save(name: &str, text: &str) -> Result<(), SaveError> {
+    local_storage()?
+        .set_item(&save_key(name), text)
+        .map_err(|_| SaveError::Storage)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn delete_save(name: &str) -> Result<(), SaveError> {
+    local_storage()?
+        .remove_item(&save_key(name))
+        .map_err(|_| SaveError::Storage)
+}
+
+/// Moves the save called `name` aside, where it is kept but no longer read, without
+/// overwriting any earlier backup. Returns where it went.
+#[cfg(target_arch = "wasm32")]
+pub fn back_up_save(name: &str) -> Result<String, SaveError> {
+    let storage = local_storage()?;
+    let text = read_save(name)?;
+    let backup = (1..)
+        .map(|number| format!("{}-backup-{number}", save_key(name)))
+        .find(|key| matches!(storage.get_item(key), Ok(None)))
+        .expect("there is always a free backup key");
+    storage
+        .set_item(&backup, &text)
+        .map_err(|_| SaveError::Storage)?;
+    delete_save(name)?;
+    Ok(backup)
+}
+
+/// A saved race that is being set up again
+#[derive(Resource)]
+struct ResumedRace(SavedRace);
+
+/// Loads the saved race to be carried on with when the game enters `GameState::Playing`,
+/// along with the meeting and career it is part of
+pub fn resume_race(
+    commands: &mut Commands,
+    selected_track: &mut SelectedTrack,
+    track_assets: &TrackAssets,
+) -> Result<(), SaveError> {
+    let saved_race = SavedRace::load()?;
+    selected_track.index = track_assets
+        .index_of(&saved_race.track)
+        .ok_or_else(|| SaveError::UnknownTrack(saved_race.track.clone()))?;
+    if let Some(meeting) = &saved_race.meeting {
+        commands.insert_resource(meeting.clone());
+    }
+    if let Some(career) = &saved_race.career {
+        commands.insert_resource(career.clone());
+    }
+    commands.insert_resource(ResumedRace(saved_race));
+    Ok(())
+}
+
+fn save_race(
+    q_riders: Query<EntityRef, With<Rider>>,
+    racing_state: Res<State<RacingState>>,
+    turn_timer: Res<TurnTimer>,
+    randomness: Res<Randomness>,
+    selected_track: Res<SelectedTrack>,
+    recording: Option<Res<Recording>>,
+    meeting: Option<Res<Meeting>>,
+    career: Option<Res<Career>>,
+) {
+    let rider_number = |entity: Entity| {
+        q_riders
+            .get(entity)
+            .ok()
+            .and_then(|entity| entity.get::<Rider>())
+            .map(|rider| rider.number())
+    };
+    let mut riders: Vec<RiderSnapshot> = q_riders
+        .iter()
+        .filter_map(|entity| RiderSnapshot::take(entity, rider_number))
+        .collect();
+    riders.sort_by_key(|saved| saved.rider.number());
+    let saved_race = SavedRace {
+        track: TRACK_FILES[selected_track.index].to_string(),
+        racing_state: *racing_state.get(),
+        turn_timer: *turn_timer,
+        randomness: randomness.state(),
+        riders,
+        replay: recording.map(|recording| recording.0.clone()),
+        meeting: meeting.map(|meeting| meeting.clone()),
+        career: career.map(|career| career.clone()),
+    };
+    if let Err(error) = saved_race.save() {
+        warn!("Could not save race: {error}");
+    }
+}
+
+/// Carries on in the part of the turn the race was saved in
+fn queue_resumed_state(
+    resumed_race: Res<ResumedRace>,
+    mut next_state: ResMut<NextState<RacingState>>,
+) {
+    next_state.set(resumed_race.0.racing_state);
+}
+
+/// Puts every rider of the freshly set up race back where the saved race had them
+fn restore_race(
+    mut commands: Commands,
+    resumed_race: Res<ResumedRace>,
+    q_riders: Query<(Entity, &Rider)>,
+    mut turn_timer: ResMut<TurnTimer>,
+    mut randomness: ResMut<Randomness>,
+) {
+    let saved_race = &resumed_race.0;
+    let rider_entity = |number: usize| {
+        q_riders
+            .iter()
+            .find(|(_, rider)| rider.number() == number)
+            .map(|(entity, _)| entity)
+    };
+    for saved in &saved_race.riders {
+        let Some(entity) = rider_entity(saved.rider.number()) else {
+            continue;
+        };
+        saved.restore(&mut commands.entity(entity), rider_entity);
+    }
+    *turn_timer = saved_race.turn_timer;
+    *randomness = Randomness::from_state(&saved_race.randomness);
+    match &saved_race.replay {
+        Some(replay) => commands.insert_resource(Recording(replay.clone())),
+        None => commands.remove_resource::<Recording>(),
+    }
+}
+
+/// The rules skipped while the race was being restored apply again from the next turn
+fn finish_resuming(mut commands: Commands) {
+    commands.remove_resource::<ResumedRace>();
+}
+
+fn delete_saved_race() {
+    if let Err(error) = delete_save(RACE_SAVE) {
+        warn!("Could not delete saved race: {error}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        simulation::RaceSimulation,
+        track::{TrackDefinition, TrackLanes},
+    };
+
+    const SEED: u64 = 5;
+    /// Turns raced before saving, enough for the riders to be away from the tapes
+    const TURNS_BEFORE_SAVING: usize = 4;
+
+    fn track_lanes() -> TrackLanes {
+        let definition =
+            TrackDefinition::from_ron(include_bytes!("../assets/tracks/cycle_speedway.track.ron"))
+                .unwrap();
+        TrackLanes::new(&definition)
+    }
+
+    #[test]
+    fn restored_race_has_the_same_bikes_and_luck() {
+        let mut raced = RaceSimulation::new(track_lanes(), &[], &Config::default(), SEED);
+        for _ in 0..TURNS_BEFORE_SAVING {
+            raced.step_turn(&[]);
+        }
+        let saved_race = SavedRace {
+            track: TRACK_FILES[0].to_string(),
+            racing_state: RacingState::Commanding,
+            turn_timer: TurnTimer::default(),
+            randomness: raced.randomness(),
+            riders: raced.snapshots(),
+            replay: None,
+            meeting: None,
+            career: None,
+        };
+        let text = ron::ser::to_string(&saved_race).unwrap();
+        let loaded: SavedRace = ron::de::from_str(&text).unwrap();
+
+        let mut restored = RaceSimulation::from_snapshot(track_lanes(), &loaded.riders, SEED);
+        assert_eq!(restored.bikes(), raced.bikes());
+        let randomness = Randomness::from_state(&loaded.randomness);
+        assert_eq!(randomness.state(), raced.randomness());
+    }
+
+    #[test]
+    fn backing_up_never_overwrites_an_earlier_backup() {
+        let name = format!("back_up_test_{}", std::process::id());
+        write_save(&name, "first").unwrap();
+        let first = back_up_save(&name).unwrap();
+        write_save(&name, "second").unwrap();
+        let second = back_up_save(&name).unwrap();
+        let contents = [
+            std::fs::read_to_string(&first),
+            std::fs::read_to_string(&second),
+        ];
+        for backup in [&first, &second] {
+            std::fs::remove_file(backup).unwrap();
+        }
+
+        assert_ne!(first, second);
+        assert_eq!(contents[0].as_deref().unwrap(), "first");
+        assert_eq!(contents[1].as_deref().unwrap(), "second");
+        assert!(matches!(read_save(&name), Err(SaveError::NoSave)));
+    }
+}
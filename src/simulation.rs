@@ -0,0 +1,677 @@
+use std::time::Duration;
+
+use bevy::{
+    ecs::{system::EntityCommands, world::EntityRef},
+    log::LogPlugin,
+    prelude::*,
+    state::app::StatesPlugin,
+    time::TimeUpdateStrategy,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    actions::{ActionsPlugin, BikeAction},
+    bike::{Bike, BikePlugin, BikeSnapshot, Crashed},
+    bot::{ExternalBot, ExternalBotPlugin},
+    collision::{Collider, Collision, CollisionPlugin, CollisionSide},
+    config::Config,
+    game::{rider_bundle, Finished, RaceResult, RaceRulesPlugin, Rider, TurnTimer, TICKS_PER_TURN},
+    loading::{RIDER_FILES, TRACK_FILES},
+    opponent::{Opponent, OpponentDifficulties, OpponentPlugin, Rebuffed},
+    player::Player,
+    profile::{RiderProfile, RiderProfileLoaderError},
+    random::{RaceSeed, RandomnessPlugin},
+    start_gate::StartGatePlugin,
+    team::{are_teammates, Team},
+    track::{TrackDefinition, TrackLanes},
+    GameState, InRace, PlayingState, RacingState,
+};
+
+/// Length of a frame when stepping a simulation, so that every frame runs one tick
+const SIMULATION_FRAME: Duration = Duration::from_nanos(1_000_000_000 / TICKS_PER_TURN as u64);
+/// Length of a frame when planning ahead, so that every frame runs a whole turn. Turns
+/// play out as they would a tick at a time, except that the last one runs to its end.
+const PLANNING_FRAME: Duration =
+    Duration::from_nanos(SIMULATION_FRAME.as_nanos() as u64 * TICKS_PER_TURN as u64);
+/// Turns after which a headless race is abandoned
+const MAX_HEADLESS_TURNS: usize = 1000;
+
+/// Steps of every simulation tick, run in this order on `FixedUpdate`
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TickSet {
+    /// Carry out the chosen actions
+    Act,
+    /// Move the bikes along the track
+    Move,
+    /// Find which bikes are touching
+    Collide,
+    /// Deal with the consequences of collisions
+    React,
+    /// Count laps and positions, and end the turn
+    Score,
+}
+
+/// Rules that run as a turn moves between commanding and simulating. A race resumed
+/// from a save has already been through them, so they are skipped while it starts up.
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TurnPhaseSet;
+
+/// The race rules, without anything to draw or any input, so they can run under
+/// `MinimalPlugins`
+pub struct RaceSimulationPlugin;
+
+impl Plugin for RaceSimulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.configure_sets(
+            FixedUpdate,
+            (
+                TickSet::Act,
+                TickSet::Move,
+                TickSet::Collide,
+                TickSet::React,
+                TickSet::Score,
+            )
+                .chain()
+                .run_if(in_state(RacingState::Simulating).and_then(turn_in_progress)),
+        )
+        .add_plugins((
+            RandomnessPlugin,
+            RaceRulesPlugin,
+            BikePlugin,
+            CollisionPlugin,
+            ActionsPlugin,
+            OpponentPlugin,
+            ExternalBotPlugin,
+            StartGatePlugin,
+        ));
+    }
+}
+
+/// Stops a turn from running on for extra ticks when a frame runs several at once
+fn turn_in_progress(turn_timer: Res<TurnTimer>) -> bool {
+    turn_timer.in_progress()
+}
+
+/// A rider as they are at one moment of a race, which the race can be put back to
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RiderSnapshot {
+    pub rider: Rider,
+    pub player: Option<Player>,
+    pub opponent: Option<Opponent>,
+    #[serde(default)]
+    bot: Option<ExternalBot>,
+    #[serde(default)]
+    team: Option<Team>,
+    bike: BikeSnapshot,
+    collision: Option<CollisionSnapshot>,
+    #[serde(default)]
+    rebuffed: Option<Rebuffed>,
+    /// Retired riders have been taken off the track and no longer collide
+    collider: Option<Collider>,
+    position: Vec3,
+    rotation: Quat,
+}
+
+/// A collision, with the other bike given by its rider number
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct CollisionSnapshot {
+    other_rider: usize,
+    side: CollisionSide,
+    other_bike_speed: f32,
+}
+
+impl RiderSnapshot {
+    /// `rider_number` tells which rider another bike belongs to
+    pub fn take(entity: EntityRef, rider_number: impl Fn(Entity) -> Option<usize>) -> Option<Self> {
+        let transform = entity.get::<Transform>()?;
+        Some(Self {
+            rider: entity.get::<Rider>()?.clone(),
+            player: entity.get::<Player>().copied(),
+            opponent: entity.get::<Opponent>().copied(),
+            bot: entity.get::<ExternalBot>().cloned(),
+            team: entity.get::<Team>().copied(),
+            bike: BikeSnapshot::take(entity)?,
+            collision: entity.get::<Collision>().and_then(|collision| {
+                Some(CollisionSnapshot {
+                    other_rider: rider_number(collision.other_entity)?,
+                    side: collision.side,
+                    other_bike_speed: collision.other_bike_speed,
+                })
+            }),
+            rebuffed: entity.get::<Rebuffed>().copied(),
+            collider: entity.get::<Collider>().copied(),
+            position: transform.translation,
+            rotation: transform.rotation,
+        })
+    }
+
+    /// Puts the rider back as they were. `rider_entity` finds the bike of another rider
+    /// from their number.
+    pub fn restore(
+        &self,
+        entity: &mut EntityCommands,
+        rider_entity: impl Fn(usize) -> Option<Entity>,
+    ) {
+        entity.insert((
+            self.rider.clone(),
+            Transform::from_translation(self.position).with_rotation(self.rotation),
+        ));
+        self.bike.restore(entity);
+        match self.player {
+            Some(player) => entity.insert(player),
+            None => entity.remove::<Player>(),
+        };
+        match self.opponent {
+            Some(opponent) => entity.insert(opponent),
+            None => entity.remove::<Opponent>(),
+        };
+        if self.rider.finish_time().is_some() || self.rider.is_excluded() {
+            entity.insert(Finished);
+        } else {
+            entity.remove::<Finished>();
+        }
+        match &self.bot {
+            Some(bot) => entity.insert(bot.clone()),
+            None => entity.remove::<ExternalBot>(),
+        };
+        match self.team {
+            Some(team) => entity.insert(team),
+            None => entity.remove::<Team>(),
+        };
+        match self.collision.and_then(|collision| {
+            Some(Collision {
+                other_entity: rider_entity(collision.other_rider)?,
+                side: collision.side,
+                other_bike_speed: collision.other_bike_speed,
+            })
+        }) {
+            Some(collision) => entity.insert(collision),
+            None => entity.remove::<Collision>(),
+        };
+        match self.rebuffed {
+            Some(rebuffed) => entity.insert(rebuffed),
+            None => entity.remove::<Rebuffed>(),
+        };
+        match self.collider {
+            Some(collider) => entity.insert(collider),
+            None => entity.remove::<Collider>(),
+        };
+    }
+}
+
+/// A race run in its own headless app, stepped a whole turn at a time
+pub struct RaceSimulation {
+    app: App,
+}
+
+impl RaceSimulation {
+    fn app(seed: u64, frame: Duration, config: Config) -> App {
+        let mut app = App::new();
+        app.insert_resource(config)
+            .add_plugins((MinimalPlugins, StatesPlugin, RaceSimulationPlugin))
+            .insert_resource(TimeUpdateStrategy::ManualDuration(frame))
+            .insert_resource(RaceSeed(Some(seed)))
+            .init_state::<GameState>()
+            .add_computed_state::<InRace>()
+            .add_sub_state::<PlayingState>()
+            .add_sub_state::<RacingState>();
+        app.world_mut()
+            .resource_mut::<Time<Virtual>>()
+            .set_max_delta(frame);
+        app
+    }
+
+    /// Starts a race with an opponent in every lane, ready for the first turn. Riders
+    /// take the profiles in lane order, from the inside, and the bots in the config
+    /// race the first of them.
+    pub fn new(
+        track_lanes: TrackLanes,
+        profiles: &[RiderProfile],
+        config: &Config,
+        seed: u64,
+    ) -> Self {
+        let bots = &config.bots;
+        let mut app = Self::app(seed, SIMULATION_FRAME, config.clone());
+        // shows what goes wrong in the race, such as bots that fail to answer
+        app.add_plugins(LogPlugin::default());
+        let opponent_difficulties = app.world().resource::<OpponentDifficulties>().clone();
+        for lane_id in track_lanes.lane_ids() {
+            let mut profile = profiles.get(lane_id.index()).cloned().unwrap_or_default();
+            let mut rider = app.world_mut().spawn_empty();
+            match bots.get(lane_id.index()) {
+                Some(bot) => {
+                    profile.name.clone_from(&bot.name);
+                    rider.insert(ExternalBot(bot.clone()));
+                }
+                None => {
+                    rider.insert(Opponent {
+                        difficulty: opponent_difficulties.for_opponent(lane_id.index()),
+                        traits: profile.traits,
+                    });
+                }
+            }
+            rider.insert(rider_bundle(&lane_id, &profile, &track_lanes));
+        }
+        app.insert_resource(track_lanes);
+        let mut simulation = Self { app };
+        simulation.start_race(true);
+        simulation
+    }
+
+    /// Carries on a race from a moment between turns, for planning ahead. Every rider
+    /// is raced by the computer, with players racing as club riders, and luck comes
+    /// from `seed` rather than the race being carried on.
+    pub fn from_snapshot(track_lanes: TrackLanes, riders: &[RiderSnapshot], seed: u64) -> Self {
+        // settings from the config file are for the race being planned for, which has
+        // already taken them up
+        let mut app = Self::app(seed, PLANNING_FRAME, Config::default());
+        for snapshot in riders {
+            let Some(lane_id) = track_lanes.lane_id(snapshot.rider.number()) else {
+                continue;
+            };
+            // the profile is replaced by the rider's own as the snapshot is restored
+            app.world_mut().spawn(rider_bundle(
+                &lane_id,
+                &RiderProfile::default(),
+                &track_lanes,
+            ));
+        }
+        app.insert_resource(track_lanes);
+        let mut simulation = Self { app };
+        simulation.restore(riders, seed);
+        simulation
+    }
+
+    /// Puts the riders back as they were in the snapshot, and starts the coming turn
+    /// again with luck from `seed`
+    pub fn restore(&mut self, riders: &[RiderSnapshot], seed: u64) {
+        let world = self.app.world_mut();
+        let rider_entities: Vec<(usize, Entity)> = world
+            .query::<(Entity, &Rider)>()
+            .iter(world)
+            .map(|(entity, rider)| (rider.number(), entity))
+            .collect();
+        let rider_entity = |number: usize| {
+            rider_entities
+                .iter()
+                .find(|(rider_number, _)| *rider_number == number)
+                .map(|(_, entity)| *entity)
+        };
+        let mut commands = world.commands();
+        for snapshot in riders {
+            let Some(entity) = rider_entity(snapshot.rider.number()) else {
+                continue;
+            };
+            let mut entity_commands = commands.entity(entity);
+            snapshot.restore(&mut entity_commands, rider_entity);
+            if snapshot.opponent.is_none() {
+                entity_commands.insert(Opponent::default());
+            }
+            entity_commands.remove::<(Player, ExternalBot)>();
+        }
+        world.flush();
+        world.insert_resource(RaceSeed(Some(seed)));
+        // the riders in the snapshot are away from the tapes already
+        self.start_race(false);
+    }
+
+    /// Sets up the race and enters the first turn, with the riders as they are, after
+    /// starting them from the tapes if `at_tapes`
+    fn start_race(&mut self, at_tapes: bool) {
+        let world = self.app.world_mut();
+        if world.resource::<State<GameState>>().get() == &GameState::Playing {
+            world
+                .resource_mut::<NextState<PlayingState>>()
+                .set(PlayingState::SetupRace);
+        } else {
+            world
+                .resource_mut::<NextState<GameState>>()
+                .set(GameState::Playing);
+        }
+        self.app.update();
+        let world = self.app.world_mut();
+        world
+            .resource_mut::<NextState<PlayingState>>()
+            .set(PlayingState::Racing);
+        if !at_tapes {
+            world
+                .resource_mut::<NextState<RacingState>>()
+                .set(RacingState::Commanding);
+        }
+        self.app.update();
+        // computer riders choose when to go straight away, but a call may be rerun
+        while self.racing_state() == Some(RacingState::Tapes) {
+            self.app.update();
+        }
+    }
+
+    /// Plays out one turn and returns every bike as it is at the end of it. Riders
+    /// choose their own actions, unless `actions` gives them a legal one.
+    pub fn step_turn(&mut self, actions: &[(Entity, BikeAction)]) -> Vec<(Entity, Bike)> {
+        if self.is_finished() {
+            return self.bikes();
+        }
+        for (entity, action) in actions {
+            if self.can_do(*entity, *action) {
+                self.app.world_mut().entity_mut(*entity).insert(*action);
+            }
+        }
+        self.app
+            .world_mut()
+            .resource_mut::<NextState<RacingState>>()
+            .set(RacingState::Simulating);
+        self.app.update();
+        while self.racing_state() == Some(RacingState::Simulating) {
+            self.app.update();
+        }
+        self.bikes()
+    }
+
+    /// Whether the race is over, which is once nobody is left racing: every rider has
+    /// finished, retired or been excluded
+    pub fn is_finished(&self) -> bool {
+        self.racing_state().is_none()
+    }
+
+    /// Every bike in the race, in the order they were spawned
+    pub fn bikes(&mut self) -> Vec<(Entity, Bike)> {
+        let mut bikes: Vec<(Entity, Bike)> = self
+            .app
+            .world_mut()
+            .query::<(Entity, &Bike)>()
+            .iter(self.app.world())
+            .map(|(entity, bike)| (entity, *bike))
+            .collect();
+        bikes.sort_by_key(|(entity, _)| *entity);
+        bikes
+    }
+
+    /// How the riders stand, which is the result of the race once it is finished
+    pub fn result(&mut self) -> RaceResult {
+        let world = self.app.world_mut();
+        let mut q_riders = world.query::<(&Rider, &Bike)>();
+        let track_lanes = world.resource::<TrackLanes>();
+        RaceResult::new(q_riders.iter(world).map(|(rider, bike)| {
+            let progress = track_lanes.race_progress(&bike.current_lane_id, bike.distance);
+            (rider, progress)
+        }))
+    }
+
+    pub fn track_lanes(&self) -> &TrackLanes {
+        self.app.world().resource::<TrackLanes>()
+    }
+
+    fn racing_state(&self) -> Option<RacingState> {
+        self.app
+            .world()
+            .get_resource::<State<RacingState>>()
+            .map(|state| *state.get())
+    }
+
+    /// How far through the race a rider has got, in laps, and whether they are down
+    pub fn rider_progress(&self, entity: Entity) -> Option<(f32, bool)> {
+        let world = self.app.world();
+        let bike = world.get::<Bike>(entity)?;
+        let progress = self
+            .track_lanes()
+            .race_progress(&bike.current_lane_id, bike.distance);
+        Some((progress, world.get::<Crashed>(entity).is_some()))
+    }
+
+    /// The bike of the rider who started in lane `number`
+    pub fn rider_entity(&mut self, number: usize) -> Option<Entity> {
+        self.app
+            .world_mut()
+            .query::<(Entity, &Rider)>()
+            .iter(self.app.world())
+            .find(|(_, rider)| rider.number() == number)
+            .map(|(entity, _)| entity)
+    }
+
+    /// The other riders in the same team as the rider of `entity`
+    pub fn teammates(&mut self, entity: Entity) -> Vec<Entity> {
+        let world = self.app.world_mut();
+        let team = world.get::<Team>(entity).copied();
+        world
+            .query_filtered::<(Entity, Option<&Team>), With<Rider>>()
+            .iter(world)
+            .filter(|(other, other_team)| {
+                *other != entity && are_teammates(team, other_team.copied())
+            })
+            .map(|(other, _)| other)
+            .collect()
+    }
+
+    /// The rider who last held firm against the elbow or hip of the opponent of
+    /// `entity`, while it still leaves them alone
+    pub fn rebuffed_by(&mut self, entity: Entity) -> Option<Entity> {
+        let number = self.app.world().get::<Rebuffed>(entity)?.by;
+        self.rider_entity(number)
+    }
+
+    /// The action each rider has chosen for the coming turn, by rider number
+    #[cfg(test)]
+    pub fn chosen_actions(&mut self) -> Vec<(usize, BikeAction)> {
+        let world = self.app.world_mut();
+        let mut actions: Vec<(usize, BikeAction)> = world
+            .query::<(&Rider, &BikeAction)>()
+            .iter(world)
+            .map(|(rider, action)| (rider.number(), *action))
+            .collect();
+        actions.sort_by_key(|(number, _)| *number);
+        actions
+    }
+
+    /// Every rider as they are now, by rider number
+    #[cfg(test)]
+    pub fn snapshots(&mut self) -> Vec<RiderSnapshot> {
+        let world = self.app.world_mut();
+        let rider_numbers: Vec<(Entity, usize)> = world
+            .query::<(Entity, &Rider)>()
+            .iter(world)
+            .map(|(entity, rider)| (entity, rider.number()))
+            .collect();
+        let rider_number = |entity: Entity| {
+            rider_numbers
+                .iter()
+                .find(|(rider_entity, _)| *rider_entity == entity)
+                .map(|(_, number)| *number)
+        };
+        let mut snapshots: Vec<RiderSnapshot> = world
+            .query_filtered::<EntityRef, With<Rider>>()
+            .iter(world)
+            .filter_map(|entity| RiderSnapshot::take(entity, rider_number))
+            .collect();
+        snapshots.sort_by_key(|snapshot| snapshot.rider.number());
+        snapshots
+    }
+
+    /// Where the random streams of the race have got to
+    #[cfg(test)]
+    pub fn randomness(&self) -> crate::random::RandomnessState {
+        self.app
+            .world()
+            .resource::<crate::random::Randomness>()
+            .state()
+    }
+
+    pub fn collision(&self, entity: Entity) -> Option<Collision> {
+        self.app.world().get::<Collision>(entity).copied()
+    }
+
+    fn can_do(&self, entity: Entity, action: BikeAction) -> bool {
+        let world = self.app.world();
+        match (
+            world.get::<Bike>(entity),
+            world.get::<Crashed>(entity),
+            world.get::<Finished>(entity),
+        ) {
+            (Some(bike), None, None) => {
+                action.can_do(bike, world.get::<Collision>(entity), self.track_lanes())
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Races opponents, and any bots in the config file, against each other on the first
+/// track without opening a window, and prints the result. Problems go to stderr, so
+/// only the result is on stdout.
+pub fn run_headless() {
+    let path = format!("assets/{}", TRACK_FILES[0]);
+    let definition = match std::fs::read(&path)
+        .map_err(|error| error.to_string())
+        .and_then(|bytes| TrackDefinition::from_ron(&bytes).map_err(|error| error.to_string()))
+    {
+        Ok(definition) => definition,
+        Err(error) => {
+            eprintln!("Could not load {path}: {error}");
+            return;
+        }
+    };
+    let config = Config::read().unwrap_or_else(|error| {
+        eprintln!("Ignoring the config file: {error}");
+        Config::default()
+    });
+    let seed = RaceSeed::from_environment(&config)
+        .0
+        .unwrap_or_else(|| fastrand::u64(..));
+    let profiles: Vec<RiderProfile> = RIDER_FILES
+        .iter()
+        .filter_map(|file| {
+            let path = format!("assets/{file}");
+            match std::fs::read(&path)
+                .map_err(RiderProfileLoaderError::from)
+                .and_then(|bytes| RiderProfile::from_ron(&bytes))
+            {
+                Ok(profile) => Some(profile),
+                Err(error) => {
+                    eprintln!("Could not load {path}: {error}");
+                    None
+                }
+            }
+        })
+        .collect();
+    let mut simulation =
+        RaceSimulation::new(TrackLanes::new(&definition), &profiles, &config, seed);
+    let mut turns = 0;
+    while !simulation.is_finished() && turns < MAX_HEADLESS_TURNS {
+        simulation.step_turn(&[]);
+        turns += 1;
+    }
+    println!("{} after {turns} turns, with seed {seed}", definition.name);
+    for (index, result) in simulation.result().0.iter().enumerate() {
+        let time = format!("{} {}", result.time_text(), result.gap_text());
+        println!(
+            "{}. {}: {}, best lap {}",
+            index + 1,
+            result.name,
+            time.trim_end(),
+            result.best_lap_text()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::RaceOutcome;
+
+    const SEED: u64 = 7;
+
+    fn simulation(seed: u64) -> RaceSimulation {
+        let definition =
+            TrackDefinition::from_ron(include_bytes!("../assets/tracks/cycle_speedway.track.ron"))
+                .unwrap();
+        RaceSimulation::new(TrackLanes::new(&definition), &[], &Config::default(), seed)
+    }
+
+    fn bike(simulation: &mut RaceSimulation, entity: Entity) -> Bike {
+        simulation
+            .bikes()
+            .into_iter()
+            .find(|(other, _)| *other == entity)
+            .map(|(_, bike)| bike)
+            .unwrap()
+    }
+
+    /// `action` for the rider of `entity`, with everyone else waiting on the line
+    fn alone(simulation: &mut RaceSimulation, entity: Entity, action: BikeAction) {
+        let actions: Vec<(Entity, BikeAction)> = simulation
+            .bikes()
+            .into_iter()
+            .map(|(other, _)| {
+                let other_action = if other == entity {
+                    action
+                } else {
+                    BikeAction::Watch
+                };
+                (other, other_action)
+            })
+            .collect();
+        simulation.step_turn(&actions);
+    }
+
+    #[test]
+    fn accelerating_raises_speed_up_to_max_speed() {
+        let mut simulation = simulation(SEED);
+        let entity = simulation.rider_entity(0).unwrap();
+        let mut speed = bike(&mut simulation, entity).speed;
+        for _ in 0..10 {
+            if speed == bike(&mut simulation, entity).max_speed {
+                break;
+            }
+            alone(&mut simulation, entity, BikeAction::Accelerate);
+            let bike = bike(&mut simulation, entity);
+            assert!(bike.speed > speed);
+            assert!(bike.speed <= bike.max_speed);
+            speed = bike.speed;
+        }
+        assert_eq!(speed, bike(&mut simulation, entity).max_speed);
+    }
+
+    #[test]
+    fn changing_lane_ends_in_the_adjacent_lane() {
+        let mut simulation = simulation(SEED);
+        let entity = simulation.rider_entity(0).unwrap();
+        // clear of the riders left on the line
+        alone(&mut simulation, entity, BikeAction::Accelerate);
+        alone(&mut simulation, entity, BikeAction::Accelerate);
+        let lane_id = bike(&mut simulation, entity).current_lane_id;
+        let right = lane_id.right(simulation.track_lanes());
+        alone(&mut simulation, entity, BikeAction::Right);
+        assert_ne!(right, lane_id);
+        assert_eq!(bike(&mut simulation, entity).current_lane_id, right);
+    }
+
+    #[test]
+    fn same_seed_races_the_same() {
+        let mut first = simulation(SEED);
+        let mut second = simulation(SEED);
+        for turn in 0..20 {
+            assert_eq!(
+                first.step_turn(&[]),
+                second.step_turn(&[]),
+                "bikes differ after turn {turn}"
+            );
+        }
+    }
+
+    #[test]
+    fn race_ends_with_a_complete_result() {
+        let mut simulation = simulation(SEED);
+        let mut turns = 0;
+        while !simulation.is_finished() && turns < MAX_HEADLESS_TURNS {
+            simulation.step_turn(&[]);
+            turns += 1;
+        }
+        assert!(simulation.is_finished());
+        let result = simulation.result();
+        assert_eq!(result.0.len(), simulation.track_lanes().lane_count());
+        assert_eq!(result.0[0].outcome, RaceOutcome::Finished);
+        assert!(result
+            .0
+            .iter()
+            .all(|rider| rider.outcome != RaceOutcome::Running));
+    }
+}
@@ -0,0 +1,195 @@
+mod panel;
+
+use bevy::prelude::*;
+use fastrand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bike::Bike,
+    bot::ExternalBot,
+    collision::Collider,
+    game::{Exclusion, Finished, Rider},
+    opponent::Opponent,
+    profile::RiderTraits,
+    random::Randomness,
+    GameState, RacingState,
+};
+
+pub use self::panel::StartPanelPlugin;
+
+/// Earliest the tapes go up, in seconds after the riders come under orders
+const TAPES_EARLIEST: f32 = 0.5;
+/// Latest the tapes go up, in seconds after the riders come under orders
+const TAPES_LATEST: f32 = 1.5;
+/// Seconds it takes to see the tapes go up, before the rider's own start reaction
+const SIGHT_REACTION: f32 = 0.12;
+/// Times after coming under orders that riders may go at without waiting for the tapes
+const ANTICIPATIONS: [f32; 3] = [1.25, 1.35, 1.45];
+/// Chance of a computer controlled rider with the usual risk tolerance trying to beat
+/// the tapes
+const ANTICIPATION_CHANCE: f32 = 0.3;
+
+/// Puts the riders under starter's orders before the first turn, and lets them away
+/// once the tapes go up on a clean start
+pub struct StartGatePlugin;
+
+impl Plugin for StartGatePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<StartEvent>()
+            .add_systems(OnEnter(RacingState::Tapes), call_riders_to_tapes)
+            .add_systems(
+                Update,
+                (
+                    choose_launches.run_if(in_state(GameState::Playing)),
+                    release_tapes,
+                )
+                    .chain()
+                    .run_if(in_state(RacingState::Tapes).and_then(resource_exists::<StartGate>)),
+            );
+    }
+}
+
+/// When a rider goes at the start
+#[derive(Component, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Launch {
+    /// Waits to see the tapes go up
+    React,
+    /// Goes this many seconds after coming under orders, whether the tapes are up or not
+    Anticipate(f32),
+}
+
+impl Launch {
+    /// Seconds between the tapes going up, `rise` seconds after the call, and the rider
+    /// getting away, or `None` if they went before the tapes were up. A rider who goes
+    /// too late to beat their own reaction gets away as if they had waited.
+    pub fn reaction(&self, start_reaction: f32, rise: f32) -> Option<f32> {
+        let seen = SIGHT_REACTION + start_reaction;
+        match *self {
+            Launch::React => Some(seen),
+            Launch::Anticipate(time) if time < rise => None,
+            Launch::Anticipate(time) => Some((time - rise).min(seen)),
+        }
+    }
+}
+
+/// The riders are under starter's orders
+#[derive(Resource, Debug, Clone)]
+pub struct StartGate {
+    /// Seconds after the call that the tapes go up
+    rise: f32,
+    /// Which call to the tapes this is, counting from zero
+    pub call: usize,
+    /// Riders excluded for touching the tapes at earlier calls
+    pub excluded: Vec<String>,
+}
+
+/// Every launch chosen at a call to the tapes, by rider number
+#[derive(Event, Debug, Clone)]
+pub struct StartEvent {
+    pub launches: Vec<(usize, Launch)>,
+}
+
+fn call_riders_to_tapes(mut commands: Commands, mut randomness: ResMut<Randomness>) {
+    commands.insert_resource(StartGate {
+        rise: draw_rise(&mut randomness.start),
+        call: 0,
+        excluded: Vec::new(),
+    });
+}
+
+fn draw_rise(rng: &mut Rng) -> f32 {
+    TAPES_EARLIEST + rng.f32() * (TAPES_LATEST - TAPES_EARLIEST)
+}
+
+/// Computer controlled riders sometimes try to beat the tapes, the more often the more
+/// risks they take. Bots wait for the tapes. Players choose on the start panel, each at
+/// their own machine in a network race.
+fn choose_launches(
+    mut commands: Commands,
+    q_riders: Query<
+        (Entity, &Rider, Option<&Opponent>, Has<ExternalBot>),
+        (Without<Launch>, Without<Finished>),
+    >,
+    mut randomness: ResMut<Randomness>,
+) {
+    // decided in order of rider number, so the same seed always leads to the same choices
+    let mut riders: Vec<_> = q_riders.iter().collect();
+    riders.sort_by_key(|(_, rider, ..)| rider.number());
+    for (entity, _, maybe_opponent, bot) in riders {
+        let launch = match maybe_opponent {
+            Some(opponent) => choose_launch(&opponent.traits, &mut randomness.ai),
+            None if bot => Launch::React,
+            // players choose for themselves
+            None => continue,
+        };
+        commands.entity(entity).insert(launch);
+    }
+}
+
+fn choose_launch(traits: &RiderTraits, rng: &mut Rng) -> Launch {
+    if rng.f32() < ANTICIPATION_CHANCE * traits.risk_tolerance {
+        Launch::Anticipate(ANTICIPATIONS[rng.usize(..ANTICIPATIONS.len())])
+    } else {
+        Launch::React
+    }
+}
+
+/// Puts the tapes up once every rider has chosen when to go. Riders who went before
+/// the tapes were up are excluded and the start is called again without them, unless
+/// nobody would be left. Otherwise everyone is away with the reaction they managed.
+pub fn release_tapes(
+    mut commands: Commands,
+    mut q_riders: Query<(Entity, &mut Rider, &mut Bike, Option<&Launch>), Without<Finished>>,
+    mut start_gate: ResMut<StartGate>,
+    mut randomness: ResMut<Randomness>,
+    mut start_events: EventWriter<StartEvent>,
+    mut next_state: ResMut<NextState<RacingState>>,
+) {
+    if q_riders.iter().any(|(.., launch)| launch.is_none()) {
+        return;
+    }
+    let mut launches: Vec<(usize, Launch)> = q_riders
+        .iter()
+        .filter_map(|(_, rider, _, launch)| Some((rider.number(), *launch?)))
+        .collect();
+    launches.sort_by_key(|(number, _)| *number);
+    start_events.send(StartEvent { launches });
+
+    let rise = start_gate.rise;
+    let reaction = |bike: &Bike, launch: &Launch| launch.reaction(bike.start_reaction, rise);
+    let touched = q_riders
+        .iter()
+        .filter(|(_, _, bike, launch)| {
+            launch.is_some_and(|launch| reaction(bike, launch).is_none())
+        })
+        .count();
+    if touched > 0 {
+        let everyone = touched == q_riders.iter().count();
+        for (entity, mut rider, bike, launch) in q_riders.iter_mut() {
+            let went_early = launch.is_some_and(|launch| reaction(&bike, launch).is_none());
+            if went_early && !everyone {
+                debug!("{} touched the tapes", rider.name());
+                rider.exclude(Exclusion::TouchedTapes);
+                start_gate.excluded.push(rider.name().to_string());
+                commands
+                    .entity(entity)
+                    .insert(Finished)
+                    .remove::<Collider>();
+            }
+            commands.entity(entity).remove::<Launch>();
+        }
+        start_gate.rise = draw_rise(&mut randomness.start);
+        start_gate.call += 1;
+        return;
+    }
+
+    for (entity, rider, mut bike, launch) in q_riders.iter_mut() {
+        if let Some(reaction) = launch.and_then(|launch| reaction(&bike, launch)) {
+            debug!("{} away from the tapes in {reaction:.2}s", rider.name());
+            bike.start_reaction = reaction;
+        }
+        commands.entity(entity).remove::<Launch>();
+    }
+    commands.remove_resource::<StartGate>();
+    next_state.set(RacingState::Commanding);
+}
@@ -0,0 +1,221 @@
+use bevy::prelude::*;
+
+use crate::{
+    game::{Finished, Rider},
+    player::{Player, RemotePlayer},
+    GameState, RacingState,
+};
+
+use super::{release_tapes, Launch, StartGate, ANTICIPATIONS, TAPES_EARLIEST, TAPES_LATEST};
+
+const BUTTON_NORMAL_COLOR: Color = Color::srgb(0.15, 0.15, 0.15);
+const BUTTON_HOVERED_COLOR: Color = Color::srgb(0.25, 0.25, 0.25);
+const BUTTON_PRESSED_COLOR: Color = Color::srgb(0.35, 0.75, 0.35);
+const BUTTON_WIDTH: f32 = 220.0;
+const BUTTON_HEIGHT: f32 = 50.0;
+const BUTTON_FONT_SIZE: f32 = 26.0;
+const BUTTON_FONT_COLOR: Color = Color::srgb(0.9, 0.9, 0.9);
+const TITLE_FONT_SIZE: f32 = 36.0;
+const NOTE_FONT_SIZE: f32 = 22.0;
+const NOTE_COLOR: Color = Color::srgb(1.0, 0.8, 0.3);
+
+/// Asks each player here in turn when to go at the start
+pub struct StartPanelPlugin;
+
+impl Plugin for StartPanelPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (button_system, update_panel)
+                .chain()
+                .after(release_tapes)
+                .run_if(
+                    in_state(GameState::Playing)
+                        .and_then(in_state(RacingState::Tapes))
+                        .and_then(resource_exists::<StartGate>),
+                ),
+        )
+        .add_systems(OnExit(RacingState::Tapes), teardown);
+    }
+}
+
+/// The panel for the player choosing when to go
+#[derive(Component)]
+struct StartPanel {
+    rider: Entity,
+}
+
+#[derive(Component, Debug, PartialEq, Clone, Copy)]
+struct LaunchButton(Launch);
+
+/// Shows the panel to the next player here who still has to choose, in lane order from
+/// the inside, and again whenever the start is called again
+fn update_panel(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    start_gate: Res<StartGate>,
+    q_panel: Query<(Entity, &StartPanel)>,
+    q_waiting: Query<
+        (Entity, &Rider),
+        (
+            With<Player>,
+            Without<RemotePlayer>,
+            Without<Launch>,
+            Without<Finished>,
+        ),
+    >,
+) {
+    let waiting = q_waiting.iter().min_by_key(|(_, rider)| rider.number());
+    let showing = q_panel.get_single().ok().map(|(_, panel)| panel.rider);
+    if showing == waiting.map(|(entity, _)| entity) && !start_gate.is_changed() {
+        return;
+    }
+    for (entity, _) in &q_panel {
+        commands.entity(entity).despawn_recursive();
+    }
+    let Some((rider_entity, rider)) = waiting else {
+        return;
+    };
+
+    let font_handle = asset_server.load("fonts/FiraSans-Bold.ttf");
+    let text = |text: String, font_size: f32, color: Color| {
+        TextBundle::from_section(
+            text,
+            TextStyle {
+                font_size,
+                color,
+                font: font_handle.clone(),
+            },
+        )
+    };
+    commands
+        .spawn((
+            StartPanel {
+                rider: rider_entity,
+            },
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    bottom: Val::Px(40.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn(text(
+                format!("{} under starter's orders", rider.name()),
+                TITLE_FONT_SIZE,
+                BUTTON_FONT_COLOR,
+            ));
+            if !start_gate.excluded.is_empty() {
+                parent.spawn(text(
+                    format!(
+                        "Rerun, with {} excluded for touching the tapes",
+                        start_gate.excluded.join(" and ")
+                    ),
+                    NOTE_FONT_SIZE,
+                    NOTE_COLOR,
+                ));
+            }
+            parent.spawn(text(
+                format!(
+                    "The tapes go up between {TAPES_EARLIEST} and {TAPES_LATEST} seconds \
+                     after the call"
+                ),
+                NOTE_FONT_SIZE,
+                BUTTON_FONT_COLOR,
+            ));
+            parent.spawn(make_row()).with_children(|parent| {
+                let launches = std::iter::once(Launch::React)
+                    .chain(ANTICIPATIONS.into_iter().map(Launch::Anticipate));
+                for launch in launches {
+                    let label = match launch {
+                        Launch::React => "Wait for the tapes".to_string(),
+                        Launch::Anticipate(time) => format!("Go at {time:.2}s"),
+                    };
+                    parent
+                        .spawn((make_button(), LaunchButton(launch)))
+                        .with_children(|parent| {
+                            parent.spawn(make_button_text(&label, font_handle.clone()));
+                        });
+                }
+            });
+        });
+}
+
+fn make_row() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            flex_direction: FlexDirection::Row,
+            ..default()
+        },
+        ..default()
+    }
+}
+
+fn make_button() -> ButtonBundle {
+    ButtonBundle {
+        style: Style {
+            width: Val::Px(BUTTON_WIDTH),
+            height: Val::Px(BUTTON_HEIGHT),
+            border: UiRect::all(Val::Px(2.0)),
+            // horizontally center child text
+            justify_content: JustifyContent::Center,
+            // vertically center child text
+            align_items: AlignItems::Center,
+            margin: UiRect::all(Val::Px(10.0)),
+            ..default()
+        },
+        border_color: BorderColor(Color::BLACK),
+        border_radius: BorderRadius::MAX,
+        background_color: BUTTON_NORMAL_COLOR.into(),
+        ..default()
+    }
+}
+
+fn make_button_text(text: &str, font_handle: Handle<Font>) -> TextBundle {
+    TextBundle::from_section(
+        text,
+        TextStyle {
+            font: font_handle,
+            font_size: BUTTON_FONT_SIZE,
+            color: BUTTON_FONT_COLOR,
+        },
+    )
+}
+
+fn teardown(mut commands: Commands, q_panel: Query<Entity, With<StartPanel>>) {
+    for entity in &q_panel {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn button_system(
+    mut commands: Commands,
+    mut interaction_query: Query<
+        (&LaunchButton, &Interaction, &mut BackgroundColor),
+        (Changed<Interaction>, With<Button>),
+    >,
+    q_panel: Query<&StartPanel>,
+) {
+    for (launch_button, interaction, mut color) in &mut interaction_query {
+        match *interaction {
+            Interaction::Pressed => {
+                *color = BUTTON_PRESSED_COLOR.into();
+                if let Ok(panel) = q_panel.get_single() {
+                    commands.entity(panel.rider).insert(launch_button.0);
+                }
+            }
+            Interaction::Hovered => {
+                *color = BUTTON_HOVERED_COLOR.into();
+            }
+            Interaction::None => {
+                *color = BUTTON_NORMAL_COLOR.into();
+            }
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::bike::HelmetColour;
+
+/// Side a rider races for in a team match
+#[derive(Component, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Team {
+    Home,
+    Away,
+}
+
+impl Team {
+    pub const BOTH: [Team; 2] = [Team::Home, Team::Away];
+
+    /// Position of the team in `BOTH`
+    pub fn index(&self) -> usize {
+        match self {
+            Team::Home => 0,
+            Team::Away => 1,
+        }
+    }
+
+    pub fn opposition(&self) -> Team {
+        match self {
+            Team::Home => Team::Away,
+            Team::Away => Team::Home,
+        }
+    }
+
+    /// Helmet colours of the team's riders in a heat, in gate order. The home team
+    /// wears red and blue, and the away team white and yellow.
+    pub fn helmet_colours(&self) -> [HelmetColour; 2] {
+        match self {
+            Team::Home => [HelmetColour::Red, HelmetColour::Blue],
+            Team::Away => [HelmetColour::White, HelmetColour::Yellow],
+        }
+    }
+}
+
+/// Whether two riders race for the same team. Riders outside any team are rivals of
+/// everyone.
+pub fn are_teammates(team: Option<Team>, other_team: Option<Team>) -> bool {
+    team.is_some() && team == other_team
+}
@@ -1,51 +1,148 @@
-use std::f32::consts::{FRAC_PI_2, PI};
+mod definition;
+mod geometry;
+mod surface;
+
+use std::f32::consts::FRAC_PI_2;
 
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::loading::TrackAssets;
+
+use self::{
+    definition::TrackDefinitionLoader,
+    geometry::{bracketing_samples, left_normal, EdgeSection, EdgeShape, TrackGeometry},
+};
+pub use self::{
+    definition::{TrackDefinition, TrackSurface},
+    surface::spawn_track_surface,
+};
 
-pub const LAPS: usize = 4;
-const STRAIGHT_DISTANCE: f32 = 2000.0;
-const TURN_RADIUS: f32 = 620.0;
-const LANE_WIDTH: f32 = 100.0;
+/// Spacing of the points of track outlines used for drawing
+const OUTLINE_STEP_LENGTH: f32 = 10.0;
 
 pub struct TrackPlugin;
 
 impl Plugin for TrackPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<TrackLanes>();
+        app.init_asset::<TrackDefinition>()
+            .init_asset_loader::<TrackDefinitionLoader>()
+            .init_resource::<SelectedTrack>();
+    }
+}
+
+/// Index into the loaded track definitions of the track to race on
+#[derive(Resource, Default, Debug, Clone, Copy)]
+pub struct SelectedTrack {
+    pub index: usize,
+}
+
+impl SelectedTrack {
+    pub fn definition<'a>(
+        &self,
+        track_assets: &TrackAssets,
+        track_definitions: &'a Assets<TrackDefinition>,
+    ) -> &'a TrackDefinition {
+        track_definitions
+            .get(&track_assets.tracks[self.index])
+            .expect("tracks are loaded before leaving the loading state")
     }
 }
 
-#[derive(Resource, Copy, Clone, Debug)]
+/// Builds the lanes of the selected track for the race that is being set up
+pub fn setup_track_lanes(
+    mut commands: Commands,
+    selected_track: Res<SelectedTrack>,
+    track_assets: Res<TrackAssets>,
+    track_definitions: Res<Assets<TrackDefinition>>,
+) {
+    let definition = selected_track.definition(&track_assets, &track_definitions);
+    commands.insert_resource(TrackLanes::new(definition));
+}
+
+#[derive(Resource, Clone, Debug)]
 pub struct TrackLanes {
-    first: TrackLane,
-    second: TrackLane,
-    third: TrackLane,
-    fourth: TrackLane,
+    pub laps: usize,
+    pub surface: TrackSurface,
+    /// Lanes ordered from the inner edge of the track outwards
+    lanes: Vec<TrackLane>,
+    /// Centre line of the track, used to measure race progress independently of lanes
+    reference: TrackLane,
+    geometry: TrackGeometry,
+    lane_width: f32,
 }
 
 impl TrackLanes {
-    pub fn track_lane(&self, id: &TrackLaneId) -> &TrackLane {
-        match id {
-            TrackLaneId::First => &self.first,
-            TrackLaneId::Second => &self.second,
-            TrackLaneId::Third => &self.third,
-            TrackLaneId::Fourth => &self.fourth,
+    pub fn new(definition: &TrackDefinition) -> Self {
+        let track_width = definition.lane_width * definition.lane_count as f32;
+        let lanes = (0..definition.lane_count)
+            .map(|index| TrackLane::new(definition, &TrackLaneId(index)))
+            .collect();
+        Self {
+            laps: definition.laps,
+            surface: definition.surface,
+            lanes,
+            reference: TrackLane::from_inner_edge(&definition.geometry, track_width / 2.0),
+            geometry: definition.geometry.clone(),
+            lane_width: definition.lane_width,
         }
     }
-}
 
-impl Default for TrackLanes {
-    fn default() -> Self {
-        Self {
-            first: TrackLane::new(&TrackLaneId::First),
-            second: TrackLane::new(&TrackLaneId::Second),
-            third: TrackLane::new(&TrackLaneId::Third),
-            fourth: TrackLane::new(&TrackLaneId::Fourth),
+    pub fn lane_width(&self) -> f32 {
+        self.lane_width
+    }
+
+    pub fn track_width(&self) -> f32 {
+        self.lane_width * self.lane_count() as f32
+    }
+
+    /// Points around one lap of a loop at a fixed distance outwards from the inner
+    /// edge of the track, starting level with the start/finish line. Negative
+    /// distances give loops inside the inner edge.
+    pub fn outline(&self, length_from_inner_edge: f32) -> Vec<Vec2> {
+        let loop_lane = TrackLane::from_inner_edge(&self.geometry, length_from_inner_edge);
+        let steps = (loop_lane.lap_distance / OUTLINE_STEP_LENGTH).ceil() as usize;
+        (0..steps)
+            .map(|step| {
+                let distance = loop_lane.lap_distance * step as f32 / steps as f32;
+                loop_lane.position_and_rotation(distance).0
+            })
+            .collect()
+    }
+
+    pub fn track_lane(&self, id: &TrackLaneId) -> &TrackLane {
+        &self.lanes[id.0]
+    }
+
+    pub fn lane_count(&self) -> usize {
+        self.lanes.len()
+    }
+
+    /// Ids of all lanes, from the inner edge of the track outwards
+    pub fn lane_ids(&self) -> impl Iterator<Item = TrackLaneId> {
+        (0..self.lane_count()).map(TrackLaneId)
+    }
+
+    /// Id of the lane at `index`, if the track has that many lanes
+    pub fn lane_id(&self, index: usize) -> Option<TrackLaneId> {
+        (index < self.lane_count()).then_some(TrackLaneId(index))
+    }
+
+    pub fn outermost_lane_id(&self) -> TrackLaneId {
+        TrackLaneId(self.lane_count() - 1)
+    }
+
+    /// The lane next to `lane_id` towards the outside of the bend at `distance`, or
+    /// towards the outer edge of the track on a straight. At the edge of the track
+    /// that is the lane itself.
+    pub fn outward_lane(&self, lane_id: TrackLaneId, distance: f32) -> TrackLaneId {
+        if self.track_lane(&lane_id).bends_right(distance) {
+            lane_id.left()
+        } else {
+            lane_id.right(self)
         }
     }
-}
 
-impl TrackLanes {
     pub fn pos_and_rot_between_lanes(
         &self,
         lane_id_1: TrackLaneId,
@@ -66,237 +163,250 @@ impl TrackLanes {
         (pos_lerp, rot_lerp)
     }
 
-    // TODO: The problem is that going from one lane's distance to another lane's distance
-    // is wildly different, ESPECIALLY as the race goes on and more laps are finished, as
-    // it compounds over time. I need to have the distance automatically adjusted so that
-    // if I switch from an inner lane to an outer lane, it won't result in the bike slowing
-    // down or even going backwards. Likewise when going inwards, the bike will jump forward
     pub fn distance_on_adjacent_lane(
         &self,
         lane_id_1: TrackLaneId,
         lane_id_2: TrackLaneId,
         distance: f32,
     ) -> f32 {
-        let lane_1 = self.track_lane(&lane_id_1);
-        let num_laps_completed = lane_1.laps_finished(distance);
-        let current_lap_distance = lane_1.current_lap_distance(distance);
-        let track_section = lane_1.in_track_section(current_lap_distance);
-        let track_section_total_distance = lane_1.track_section_total_distance(&track_section);
-        let track_section_remaining_distance =
-            lane_1.distance_to_end_of_track_section(current_lap_distance);
-        let track_section_proportion =
-            1.0 - track_section_remaining_distance / track_section_total_distance;
-        let lane_2 = self.track_lane(&lane_id_2);
-        let lane_2_current_lap_distance = lane_2
-            .lap_distance_at_track_section_proportion(&track_section, track_section_proportion);
-        (lane_2.lap_distance * num_laps_completed as f32) + lane_2_current_lap_distance
+        let race_progress = self.race_progress(&lane_id_1, distance);
+        self.distance_at_race_progress(&lane_id_2, race_progress)
+    }
+
+    /// Lane-independent measure of how far through the race a bike at `distance` in
+    /// the given lane is. The whole part is the number of finished laps and the
+    /// fractional part is the proportion of the current lap, measured along the
+    /// centre line of the track.
+    pub fn race_progress(&self, lane_id: &TrackLaneId, distance: f32) -> f32 {
+        let lane = self.track_lane(lane_id);
+        let laps_finished = lane.laps_finished(distance);
+        let reference_lap_distance = self
+            .reference
+            .matching_lap_distance(lane, lane.current_lap_distance(distance));
+        laps_finished as f32 + reference_lap_distance / self.reference.lap_distance
+    }
+
+    /// Inverse of `race_progress`: the distance in the given lane that corresponds
+    /// to the given race progress.
+    pub fn distance_at_race_progress(&self, lane_id: &TrackLaneId, race_progress: f32) -> f32 {
+        let lane = self.track_lane(lane_id);
+        let laps_finished = race_progress.floor();
+        let reference_lap_distance = race_progress.fract() * self.reference.lap_distance;
+        let lap_distance = lane.matching_lap_distance(&self.reference, reference_lap_distance);
+        (lane.lap_distance * laps_finished) + lap_distance
     }
 }
 
 #[derive(Component)]
 pub struct Track;
 
-#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
-pub enum TrackLaneId {
-    /// Inner track
-    #[default]
-    First,
-    /// Next to innermost track
-    Second,
-    /// Next to outermost track
-    Third,
-    /// Outer track
-    Fourth,
-}
+/// Index of a lane, counting outwards from the innermost lane at 0.
+/// Only valid for the `TrackLanes` it was obtained from.
+#[derive(
+    Serialize, Deserialize, Default, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub struct TrackLaneId(usize);
 
 impl TrackLaneId {
-    fn length_from_inner_edge(&self) -> f32 {
-        let factor = match self {
-            TrackLaneId::First => 0,
-            TrackLaneId::Second => 1,
-            TrackLaneId::Third => 2,
-            TrackLaneId::Fourth => 3,
-        };
-        (LANE_WIDTH / 2.0) + (LANE_WIDTH * factor as f32)
+    pub fn index(&self) -> usize {
+        self.0
+    }
+
+    fn length_from_inner_edge(&self, lane_width: f32) -> f32 {
+        (lane_width / 2.0) + (lane_width * self.0 as f32)
     }
 
     pub fn left(&self) -> TrackLaneId {
-        match self {
-            TrackLaneId::First => TrackLaneId::First,
-            TrackLaneId::Second => TrackLaneId::First,
-            TrackLaneId::Third => TrackLaneId::Second,
-            TrackLaneId::Fourth => TrackLaneId::Third,
-        }
+        TrackLaneId(self.0.saturating_sub(1))
     }
 
     pub fn left_left(&self) -> TrackLaneId {
         self.left().left()
     }
 
-    pub fn right(&self) -> TrackLaneId {
-        match self {
-            TrackLaneId::First => TrackLaneId::Second,
-            TrackLaneId::Second => TrackLaneId::Third,
-            TrackLaneId::Third => TrackLaneId::Fourth,
-            TrackLaneId::Fourth => TrackLaneId::Fourth,
-        }
+    pub fn right(&self, track_lanes: &TrackLanes) -> TrackLaneId {
+        TrackLaneId((self.0 + 1).min(track_lanes.outermost_lane_id().0))
     }
 
-    pub fn right_right(&self) -> TrackLaneId {
-        self.right().right()
+    pub fn right_right(&self, track_lanes: &TrackLanes) -> TrackLaneId {
+        self.right(track_lanes).right(track_lanes)
     }
 
+    /// The lane next to this one in the direction of `other`
     pub fn between(&self, other: TrackLaneId) -> TrackLaneId {
-        let self_index = *self as u8;
-        let other_index = other as u8;
-        if other_index > self_index {
-            self.right()
-        } else {
-            self.left()
+        match other.0.cmp(&self.0) {
+            std::cmp::Ordering::Greater => TrackLaneId(self.0 + 1),
+            std::cmp::Ordering::Less => self.left(),
+            std::cmp::Ordering::Equal => *self,
         }
     }
 
     pub fn difference(&self, other: TrackLaneId) -> i32 {
-        let self_index = *self as i32;
-        let other_index = other as i32;
-        self_index - other_index
+        self.0 as i32 - other.0 as i32
     }
 
     pub fn is_to_right_of(&self, other: TrackLaneId) -> bool {
-        let self_index = *self as i32;
-        let other_index = other as i32;
-        self_index - other_index > 0
+        self.difference(other) > 0
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum TrackSection {
-    FirstStraightawayAfterFinishLine,
-    FirstTurn,
-    SecondStraightaway,
-    SecondTurn,
-    FirstStraightawayBeforeFinishLine,
+/// One piece of the track layout with a single shape, counted in driving order
+/// from the start of the layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrackSection(usize);
+
+impl TrackSection {
+    pub fn index(&self) -> usize {
+        self.0
+    }
 }
 
-#[derive(Component, Debug, Clone, Copy)]
+/// A lane, as an offset curve of the inner edge of the track. Distances along the
+/// lane are measured from the start/finish line, and are matched up with other
+/// lanes through the parameter of the inner edge they are level with.
+#[derive(Component, Debug, Clone)]
 pub struct TrackLane {
-    length_from_inner_edge: f32,
     lap_distance: f32,
-    half_straight_dist: f32,
-    turn_radius: f32,
-    vertical_offset: f32,
-    first_straightaway_after_finish_line_dist: f32,
-    first_turn_dist: f32,
-    second_straightaway_dist: f32,
-    second_turn_dist: f32,
+    /// Distance from the start of the layout to the start/finish line
+    start_distance: f32,
+    sections: Vec<LaneSection>,
+}
+
+#[derive(Debug, Clone)]
+struct LaneSection {
+    /// Distance from the start of the layout
+    start_distance: f32,
+    length: f32,
+    start_parameter: f32,
+    parameter_length: f32,
+    shape: LaneShape,
+}
+
+#[derive(Debug, Clone)]
+enum LaneShape {
+    Line {
+        start: Vec2,
+        heading: f32,
+    },
+    Arc {
+        centre: Vec2,
+        radius: f32,
+        start_angle: f32,
+        /// 1.0 when turning left, -1.0 when turning right
+        turn: f32,
+    },
+    Sampled(Vec<LaneSample>),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LaneSample {
+    /// Distance from the start of the section
+    distance: f32,
+    /// Parameter relative to the start of the section
+    parameter: f32,
+    position: Vec2,
+    heading: f32,
 }
 
 impl TrackLane {
-    pub fn new(lane: &TrackLaneId) -> Self {
-        let length_from_inner_edge = lane.length_from_inner_edge();
-        let semicircle_circumfrence = PI * (TURN_RADIUS + length_from_inner_edge);
-        let lap_distance = (STRAIGHT_DISTANCE + semicircle_circumfrence) * 2.0;
-        let half_straight_dist = STRAIGHT_DISTANCE / 2.0;
-        let turn_radius = TURN_RADIUS + length_from_inner_edge;
-        let vertical_offset = turn_radius;
-        let first_straightaway_after_finish_line_dist = half_straight_dist;
-        let first_turn_dist = first_straightaway_after_finish_line_dist + semicircle_circumfrence;
-        let second_straightaway_dist = first_turn_dist + STRAIGHT_DISTANCE;
-        let second_turn_dist = second_straightaway_dist + semicircle_circumfrence;
-        TrackLane {
-            length_from_inner_edge,
-            lap_distance,
-            half_straight_dist,
-            turn_radius,
-            vertical_offset,
-            first_straightaway_after_finish_line_dist,
-            first_turn_dist,
-            second_straightaway_dist,
-            second_turn_dist,
+    pub fn new(definition: &TrackDefinition, lane: &TrackLaneId) -> Self {
+        Self::from_inner_edge(
+            &definition.geometry,
+            lane.length_from_inner_edge(definition.lane_width),
+        )
+    }
+
+    fn from_inner_edge(geometry: &TrackGeometry, length_from_inner_edge: f32) -> Self {
+        let mut sections = Vec::with_capacity(geometry.sections.len());
+        let mut start_distance = 0.0;
+        for edge_section in &geometry.sections {
+            let section = LaneSection::new(edge_section, start_distance, length_from_inner_edge);
+            start_distance += section.length;
+            sections.push(section);
         }
+        let mut lane = TrackLane {
+            lap_distance: start_distance,
+            start_distance: 0.0,
+            sections,
+        };
+        lane.start_distance = lane.layout_distance_at_parameter(geometry.start_parameter);
+        lane
     }
 
+    /// Distance into the lap counted by `laps_finished`. Worked out from the laps
+    /// finished rather than with `%`, which can disagree with it right on the line.
     pub fn current_lap_distance(&self, distance: f32) -> f32 {
-        distance % self.lap_distance
+        distance - self.laps_finished(distance) as f32 * self.lap_distance
     }
 
-    pub fn in_track_section(&self, distance: f32) -> TrackSection {
-        let current_lap_distance = self.current_lap_distance(distance);
-        if current_lap_distance < self.first_straightaway_after_finish_line_dist {
-            TrackSection::FirstStraightawayAfterFinishLine
-        } else if current_lap_distance <= self.first_turn_dist {
-            TrackSection::FirstTurn
-        } else if current_lap_distance <= self.second_straightaway_dist {
-            TrackSection::SecondStraightaway
-        } else if current_lap_distance <= self.second_turn_dist {
-            TrackSection::SecondTurn
-        } else {
-            TrackSection::FirstStraightawayBeforeFinishLine
-        }
+    /// Distance from the start of the layout, rather than from the start/finish line
+    fn layout_distance(&self, distance: f32) -> f32 {
+        wrap(distance + self.start_distance, self.lap_distance)
     }
 
-    pub fn track_section_total_distance(&self, track_section: &TrackSection) -> f32 {
-        match track_section {
-            TrackSection::FirstStraightawayAfterFinishLine => {
-                self.first_straightaway_after_finish_line_dist
-            }
-            TrackSection::FirstTurn => {
-                self.first_turn_dist - self.first_straightaway_after_finish_line_dist
-            }
-            TrackSection::SecondStraightaway => {
-                self.second_straightaway_dist - self.first_turn_dist
-            }
-            TrackSection::SecondTurn => self.second_turn_dist - self.second_straightaway_dist,
-            TrackSection::FirstStraightawayBeforeFinishLine => {
-                self.lap_distance - self.second_turn_dist
-            }
-        }
+    fn section_index_at(&self, layout_distance: f32) -> usize {
+        self.sections
+            .partition_point(|section| section.start_distance <= layout_distance)
+            .saturating_sub(1)
+    }
+
+    pub fn in_track_section(&self, distance: f32) -> TrackSection {
+        TrackSection(self.section_index_at(self.layout_distance(distance)))
     }
 
     pub fn in_turn(&self, distance: f32) -> bool {
-        matches!(
-            self.in_track_section(distance),
-            TrackSection::FirstTurn | TrackSection::SecondTurn
-        )
+        let section = &self.sections[self.in_track_section(distance).0];
+        !matches!(section.shape, LaneShape::Line { .. })
     }
 
-    pub fn track_section_end_distance(&self, track_section: &TrackSection) -> f32 {
-        match track_section {
-            TrackSection::FirstStraightawayAfterFinishLine => {
-                self.first_straightaway_after_finish_line_dist
-            }
-            TrackSection::FirstTurn => self.first_turn_dist,
-            TrackSection::SecondStraightaway => self.second_straightaway_dist,
-            TrackSection::SecondTurn => self.second_turn_dist,
-            TrackSection::FirstStraightawayBeforeFinishLine => self.lap_distance,
-        }
+    /// Whether the track is a circular arc at this distance, so `turn_curve_components` applies
+    pub fn in_arc(&self, distance: f32) -> bool {
+        let section = &self.sections[self.in_track_section(distance).0];
+        matches!(section.shape, LaneShape::Arc { .. })
     }
 
-    pub fn track_section_start_distance(&self, track_section: &TrackSection) -> f32 {
-        match track_section {
-            TrackSection::FirstStraightawayAfterFinishLine => 0.0,
-            TrackSection::FirstTurn => self.first_straightaway_after_finish_line_dist,
-            TrackSection::SecondStraightaway => self.first_turn_dist,
-            TrackSection::SecondTurn => self.second_straightaway_dist,
-            TrackSection::FirstStraightawayBeforeFinishLine => self.second_turn_dist,
-        }
+    /// Radius of the bend at this distance, or `None` on a straight
+    pub fn turn_radius(&self, distance: f32) -> Option<f32> {
+        let layout_distance = self.layout_distance(distance);
+        let section = &self.sections[self.section_index_at(layout_distance)];
+        section.radius_at(layout_distance - section.start_distance)
     }
 
-    pub fn lap_distance_at_track_section_proportion(
-        &self,
-        track_section: &TrackSection,
-        proportion: f32,
-    ) -> f32 {
-        let initial_dist = self.track_section_start_distance(track_section);
-        let section_dist = self.track_section_total_distance(track_section) * proportion;
-        initial_dist + section_dist
+    /// Whether the track turns right at this distance, towards its outer edge, so
+    /// that the outside of the bend is on the side of the inner edge
+    pub fn bends_right(&self, distance: f32) -> bool {
+        let layout_distance = self.layout_distance(distance);
+        let section = &self.sections[self.section_index_at(layout_distance)];
+        section.turn_at(layout_distance - section.start_distance) < 0.0
+    }
+
+    /// Parameter of the inner edge level with a distance along this lane
+    fn parameter_at(&self, distance: f32) -> f32 {
+        let layout_distance = self.layout_distance(distance);
+        let section = &self.sections[self.section_index_at(layout_distance)];
+        section.start_parameter + section.parameter_at(layout_distance - section.start_distance)
+    }
+
+    fn layout_distance_at_parameter(&self, parameter: f32) -> f32 {
+        let index = self
+            .sections
+            .partition_point(|section| section.start_parameter <= parameter)
+            .saturating_sub(1);
+        let section = &self.sections[index];
+        section.start_distance + section.distance_at(parameter - section.start_parameter)
+    }
+
+    /// The lap distance in this lane that is level with `other_lap_distance` in the other lane
+    fn matching_lap_distance(&self, other: &TrackLane, other_lap_distance: f32) -> f32 {
+        let layout_distance =
+            self.layout_distance_at_parameter(other.parameter_at(other_lap_distance));
+        wrap(layout_distance - self.start_distance, self.lap_distance)
     }
 
     pub fn distance_to_end_of_track_section(&self, distance: f32) -> f32 {
-        let current_lap_distance = self.current_lap_distance(distance);
-        let current_section = self.in_track_section(current_lap_distance);
-        let current_section_end_distance = self.track_section_end_distance(&current_section);
-        let distance_to_end = current_section_end_distance - current_lap_distance;
+        let layout_distance = self.layout_distance(distance);
+        let section = &self.sections[self.section_index_at(layout_distance)];
+        let distance_to_end = section.start_distance + section.length - layout_distance;
         if distance_to_end.abs() < 0.005 {
             0.0
         } else {
@@ -304,79 +414,261 @@ impl TrackLane {
         }
     }
 
-    /// Designed to be used for building an arc path
+    /// Designed to be used for building an arc path, for distances where `in_arc` holds.
+    /// Other sections have no single centre, so a zero sized arc is returned for them.
     /// Returns a tuple of (center: Vec2, radii: Vec2, sweep_angle: f32, x_rotation: f32)
     pub fn turn_curve_components(
         &self,
         start_distance: f32,
         end_distance: f32,
     ) -> (Vec2, Vec2, f32, f32) {
-        let section = self.in_track_section(start_distance);
-        let dist_from_section_start = start_distance - self.track_section_start_distance(&section);
-        let radius = TURN_RADIUS + self.length_from_inner_edge;
-        let radii = Vec2::new(radius, radius);
-        let x_rotation_offset = dist_from_section_start / TURN_RADIUS;
-        let sweep_angle = (end_distance - start_distance) / TURN_RADIUS;
-        let (center, x_rotation) = if matches!(section, TrackSection::FirstTurn) {
-            let center = Vec2::new(self.half_straight_dist, 0.0);
-            let x_rotation = -PI / 2.0 + x_rotation_offset;
-            (center, x_rotation)
-        } else {
-            let center = Vec2::new(-self.half_straight_dist, 0.0);
-            let x_rotation = PI / 2.0 + x_rotation_offset;
-            (center, x_rotation)
-        };
-        (center, radii, sweep_angle, x_rotation)
+        let layout_distance = self.layout_distance(start_distance);
+        let section = &self.sections[self.section_index_at(layout_distance)];
+        match section.shape {
+            LaneShape::Arc {
+                centre,
+                radius,
+                start_angle,
+                turn,
+            } => {
+                let dist_from_section_start = layout_distance - section.start_distance;
+                let x_rotation = start_angle + turn * dist_from_section_start / radius;
+                let sweep_angle = turn * (end_distance - start_distance) / radius;
+                (centre, Vec2::splat(radius), sweep_angle, x_rotation)
+            }
+            _ => {
+                let (position, _) = self.position_and_rotation(start_distance);
+                (position, Vec2::ZERO, 0.0, 0.0)
+            }
+        }
     }
 
     /// Determine the position and rotation at a specified distance
     /// from the starting position of 0.0.
     pub fn position_and_rotation(&self, distance: f32) -> (Vec2, Quat) {
-        let current_lap_distance = self.current_lap_distance(distance);
-        match self.in_track_section(distance) {
-            TrackSection::FirstStraightawayAfterFinishLine => {
-                let horizontal = current_lap_distance;
-                let vertical = -self.vertical_offset;
-                let rot = Quat::from_rotation_z(0.0);
-                (Vec2::new(horizontal, vertical), rot)
+        let layout_distance = self.layout_distance(distance);
+        let section = &self.sections[self.section_index_at(layout_distance)];
+        let (position, heading) = section.pose_at(layout_distance - section.start_distance);
+        (position, Quat::from_rotation_z(heading))
+    }
+
+    pub fn laps_finished(&self, distance: f32) -> usize {
+        (distance / self.lap_distance).floor() as usize
+    }
+}
+
+impl LaneSection {
+    fn new(edge_section: &EdgeSection, start_distance: f32, length_from_inner_edge: f32) -> Self {
+        let outward = -left_normal(edge_section.start_heading) * length_from_inner_edge;
+        let (length, shape) = match &edge_section.shape {
+            EdgeShape::Line => (
+                edge_section.length,
+                LaneShape::Line {
+                    start: edge_section.start + outward,
+                    heading: edge_section.start_heading,
+                },
+            ),
+            EdgeShape::Arc { radius, angle } => {
+                let turn = angle.signum();
+                // lanes are further out than the inner edge, so bigger for left hand bends
+                let lane_radius = radius + turn * length_from_inner_edge;
+                (
+                    lane_radius * angle.abs(),
+                    LaneShape::Arc {
+                        centre: edge_section.arc_centre(*radius, turn),
+                        radius: lane_radius,
+                        start_angle: edge_section.start_heading - turn * FRAC_PI_2,
+                        turn,
+                    },
+                )
             }
-            TrackSection::FirstTurn => {
-                let circle_dist =
-                    current_lap_distance - self.first_straightaway_after_finish_line_dist;
-                let position_angle_offset = circle_dist / self.turn_radius;
-                let position_angle = 3.0 * PI / 2.0 + position_angle_offset;
-                let horizontal = self.half_straight_dist + self.turn_radius * position_angle.cos();
-                let vertical = self.turn_radius * position_angle.sin();
-                let rot = Quat::from_rotation_z(position_angle + FRAC_PI_2);
-                (Vec2::new(horizontal, vertical), rot)
+            EdgeShape::Sampled(edge_samples) => {
+                let mut samples: Vec<LaneSample> = Vec::with_capacity(edge_samples.len());
+                for edge_sample in edge_samples {
+                    let position = edge_sample.position
+                        - left_normal(edge_sample.heading) * length_from_inner_edge;
+                    let distance = samples.last().map_or(0.0, |previous| {
+                        previous.distance + previous.position.distance(position)
+                    });
+                    samples.push(LaneSample {
+                        distance,
+                        parameter: edge_sample.parameter,
+                        position,
+                        heading: edge_sample.heading,
+                    });
+                }
+                let length = samples.last().map_or(0.0, |sample| sample.distance);
+                (length, LaneShape::Sampled(samples))
             }
-            TrackSection::SecondStraightaway => {
-                let horizontal =
-                    self.half_straight_dist - (current_lap_distance - self.first_turn_dist);
-                let vertical = self.vertical_offset;
-                let rot = Quat::from_rotation_z(PI);
-                (Vec2::new(horizontal, vertical), rot)
+        };
+        Self {
+            start_distance,
+            length,
+            start_parameter: edge_section.start_parameter,
+            parameter_length: edge_section.length,
+            shape,
+        }
+    }
+
+    /// Position and heading at a distance from the start of the section
+    fn pose_at(&self, distance: f32) -> (Vec2, f32) {
+        match &self.shape {
+            LaneShape::Line { start, heading } => {
+                (*start + Vec2::from_angle(*heading) * distance, *heading)
             }
-            TrackSection::SecondTurn => {
-                let circle_dist = current_lap_distance - self.second_straightaway_dist;
-                let position_angle_offset = circle_dist / self.turn_radius;
-                let position_angle = PI / 2.0 + position_angle_offset;
-                let horizontal = -self.half_straight_dist + self.turn_radius * position_angle.cos();
-                let vertical = self.turn_radius * position_angle.sin();
-                let rot = Quat::from_rotation_z(position_angle + PI / 2.0);
-                (Vec2::new(horizontal, vertical), rot)
+            LaneShape::Arc {
+                centre,
+                radius,
+                start_angle,
+                turn,
+            } => {
+                let position_angle = start_angle + turn * distance / radius;
+                (
+                    *centre + Vec2::from_angle(position_angle) * *radius,
+                    position_angle + turn * FRAC_PI_2,
+                )
             }
-            TrackSection::FirstStraightawayBeforeFinishLine => {
-                let horizontal =
-                    -self.half_straight_dist + (current_lap_distance - self.second_turn_dist);
-                let vertical = -self.vertical_offset;
-                let rot = Quat::from_rotation_z(0.0);
-                (Vec2::new(horizontal, vertical), rot)
+            LaneShape::Sampled(samples) => {
+                let (before, after, proportion) =
+                    bracketing_samples(samples, distance, |sample| sample.distance);
+                (
+                    before.position.lerp(after.position, proportion),
+                    before.heading + (after.heading - before.heading) * proportion,
+                )
             }
         }
     }
 
-    pub fn laps_finished(&self, distance: f32) -> usize {
-        (distance / self.lap_distance).floor() as usize
+    /// Radius of curvature at a distance from the start of the section
+    fn radius_at(&self, distance: f32) -> Option<f32> {
+        match &self.shape {
+            LaneShape::Line { .. } => None,
+            LaneShape::Arc { radius, .. } => Some(*radius),
+            LaneShape::Sampled(samples) => {
+                let (before, after, _) =
+                    bracketing_samples(samples, distance, |sample| sample.distance);
+                let heading_change = (after.heading - before.heading).abs();
+                (heading_change > f32::EPSILON)
+                    .then(|| (after.distance - before.distance) / heading_change)
+            }
+        }
+    }
+
+    /// Direction of the turn at a distance from the start of the section, positive to
+    /// the left, negative to the right and zero on a straight
+    fn turn_at(&self, distance: f32) -> f32 {
+        match &self.shape {
+            LaneShape::Line { .. } => 0.0,
+            LaneShape::Arc { turn, .. } => *turn,
+            LaneShape::Sampled(samples) => {
+                let (before, after, _) =
+                    bracketing_samples(samples, distance, |sample| sample.distance);
+                after.heading - before.heading
+            }
+        }
+    }
+
+    /// Parameter relative to the start of the section at a distance from its start
+    fn parameter_at(&self, distance: f32) -> f32 {
+        match &self.shape {
+            LaneShape::Line { .. } | LaneShape::Arc { .. } => {
+                distance * self.parameter_length / self.length
+            }
+            LaneShape::Sampled(samples) => {
+                let (before, after, proportion) =
+                    bracketing_samples(samples, distance, |sample| sample.distance);
+                before.parameter + (after.parameter - before.parameter) * proportion
+            }
+        }
+    }
+
+    /// Distance from the start of the section at a parameter relative to its start
+    fn distance_at(&self, parameter: f32) -> f32 {
+        match &self.shape {
+            LaneShape::Line { .. } | LaneShape::Arc { .. } => {
+                parameter * self.length / self.parameter_length
+            }
+            LaneShape::Sampled(samples) => {
+                let (before, after, proportion) =
+                    bracketing_samples(samples, parameter, |sample| sample.parameter);
+                before.distance + (after.distance - before.distance) * proportion
+            }
+        }
+    }
+}
+
+/// Wraps a distance into a single lap
+fn wrap(distance: f32, lap_distance: f32) -> f32 {
+    let wrapped = distance.rem_euclid(lap_distance);
+    // rem_euclid can round up to the divisor itself for tiny negative distances
+    if wrapped >= lap_distance {
+        0.0
+    } else {
+        wrapped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Furthest apart positions that count as the same, allowing for sampled sections
+    const TOLERANCE: f32 = 0.5;
+
+    fn track_lanes() -> TrackLanes {
+        let definition =
+            TrackDefinition::from_ron(include_bytes!("../assets/tracks/ashgrove.track.ron"))
+                .unwrap();
+        TrackLanes::new(&definition)
+    }
+
+    #[test]
+    fn riders_level_across_the_track_have_equal_race_progress() {
+        let track_lanes = track_lanes();
+        let inside = track_lanes.lane_id(0).unwrap();
+        let inside_lane = track_lanes.track_lane(&inside);
+        let distances = (0..40).map(|step| inside_lane.lap_distance * step as f32 / 13.0);
+        for distance in distances {
+            let (inside_position, rotation) = inside_lane.position_and_rotation(distance);
+            let race_progress = track_lanes.race_progress(&inside, distance);
+            for lane_id in track_lanes.lane_ids().skip(1) {
+                let lane = track_lanes.track_lane(&lane_id);
+                let lane_distance = track_lanes.distance_at_race_progress(&lane_id, race_progress);
+                // level means straight out from the inside lane, square to its heading
+                let across = lane.position_and_rotation(lane_distance).0 - inside_position;
+                let heading = (rotation * Vec3::X).truncate();
+                assert!(
+                    across.dot(heading).abs() < TOLERANCE,
+                    "lane {} isn't level with the inside at {distance}",
+                    lane_id.index()
+                );
+                let expected = lane_id.index() as f32 * track_lanes.lane_width();
+                assert!((across.length() - expected).abs() < TOLERANCE);
+                let lane_progress = track_lanes.race_progress(&lane_id, lane_distance);
+                assert!(
+                    (lane_progress - race_progress).abs() < 1e-4,
+                    "{lane_progress} in lane {} against {race_progress}",
+                    lane_id.index()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn distance_at_race_progress_inverts_race_progress() {
+        let track_lanes = track_lanes();
+        for lane_id in track_lanes.lane_ids() {
+            let lap_distance = track_lanes.track_lane(&lane_id).lap_distance;
+            for step in 0..40 {
+                let distance = lap_distance * step as f32 / 11.0;
+                let race_progress = track_lanes.race_progress(&lane_id, distance);
+                let inverted = track_lanes.distance_at_race_progress(&lane_id, race_progress);
+                assert!(
+                    (inverted - distance).abs() < TOLERANCE,
+                    "lane {} came back at {inverted}, not {distance}",
+                    lane_id.index()
+                );
+            }
+        }
     }
 }
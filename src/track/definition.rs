@@ -0,0 +1,156 @@
+use std::ops::RangeInclusive;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+};
+use serde::Deserialize;
+use thiserror::Error;
+
+use super::geometry::{TrackGeometry, TrackLayout};
+
+/// Range of lane counts a track can have
+const SUPPORTED_LANE_COUNTS: RangeInclusive<usize> = 3..=8;
+
+/// A track layout loaded from a `.track.ron` file under `assets/tracks/`
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct TrackDefinition {
+    pub name: String,
+    /// Shape of the inner edge of the track
+    pub geometry: TrackGeometry,
+    pub lane_count: usize,
+    pub lane_width: f32,
+    pub laps: usize,
+    pub surface: TrackSurface,
+    /// Optional artwork drawn underneath the track surface
+    #[dependency]
+    pub texture: Option<Handle<Image>>,
+}
+
+/// The on-disk representation of a `TrackDefinition`
+#[derive(Deserialize, Debug)]
+struct TrackDefinitionFile {
+    name: String,
+    layout: TrackLayout,
+    lane_count: usize,
+    lane_width: f32,
+    start_finish_offset: f32,
+    laps: usize,
+    #[serde(default)]
+    surface: TrackSurface,
+    #[serde(default)]
+    texture: Option<String>,
+}
+
+/// What the racing surface is made of, which decides how well tyres grip it
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TrackSurface {
+    #[default]
+    Shale,
+    Cinder,
+    Concrete,
+    Grass,
+}
+
+impl TrackSurface {
+    /// Grip relative to a shale track
+    pub fn grip(&self) -> f32 {
+        match self {
+            TrackSurface::Shale => 1.0,
+            TrackSurface::Cinder => 0.9,
+            TrackSurface::Concrete => 1.15,
+            TrackSurface::Grass => 0.75,
+        }
+    }
+}
+
+impl TrackDefinition {
+    /// Reads a track definition without an asset server, so without its texture
+    pub fn from_ron(bytes: &[u8]) -> Result<Self, TrackDefinitionLoaderError> {
+        TrackDefinitionFile::parse(bytes)?.into_definition(None)
+    }
+}
+
+impl TrackDefinitionFile {
+    fn parse(bytes: &[u8]) -> Result<Self, TrackDefinitionLoaderError> {
+        let file: TrackDefinitionFile = ron::de::from_bytes(bytes)?;
+        file.validate()?;
+        Ok(file)
+    }
+
+    fn into_definition(
+        self,
+        texture: Option<Handle<Image>>,
+    ) -> Result<TrackDefinition, TrackDefinitionLoaderError> {
+        let track_width = self.lane_width * self.lane_count as f32;
+        let geometry = TrackGeometry::new(&self.layout, self.start_finish_offset, track_width)
+            .map_err(TrackDefinitionLoaderError::Invalid)?;
+        Ok(TrackDefinition {
+            name: self.name,
+            geometry,
+            lane_count: self.lane_count,
+            lane_width: self.lane_width,
+            laps: self.laps,
+            surface: self.surface,
+            texture,
+        })
+    }
+
+    fn validate(&self) -> Result<(), TrackDefinitionLoaderError> {
+        let invalid = |reason: String| Err(TrackDefinitionLoaderError::Invalid(reason));
+        if !SUPPORTED_LANE_COUNTS.contains(&self.lane_count) {
+            return invalid(format!(
+                "tracks must have {} to {} lanes, got {}",
+                SUPPORTED_LANE_COUNTS.start(),
+                SUPPORTED_LANE_COUNTS.end(),
+                self.lane_count
+            ));
+        }
+        if self.lane_width <= 0.0 {
+            return invalid(format!(
+                "lane width must be positive, got {}",
+                self.lane_width
+            ));
+        }
+        if self.laps == 0 {
+            return invalid("a race needs at least one lap".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum TrackDefinitionLoaderError {
+    #[error("could not read track definition: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse track definition: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("invalid track definition: {0}")]
+    Invalid(String),
+}
+
+#[derive(Default)]
+pub struct TrackDefinitionLoader;
+
+impl AssetLoader for TrackDefinitionLoader {
+    type Asset = TrackDefinition;
+    type Settings = ();
+    type Error = TrackDefinitionLoaderError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let file = TrackDefinitionFile::parse(&bytes)?;
+        let texture = file.texture.clone().map(|path| load_context.load(path));
+        file.into_definition(texture)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["track.ron"]
+    }
+}
@@ -0,0 +1,541 @@
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+/// Spacing along the inner edge between samples of curves that have no closed form
+const SAMPLE_SPACING: f32 = 5.0;
+/// How far the end of a layout may be from its start and still count as a closed loop
+const CLOSING_DISTANCE_TOLERANCE: f32 = 1.0;
+const CLOSING_HEADING_TOLERANCE: f32 = 0.01;
+/// Width of the kerb drawn inside the inner edge
+pub const KERB_WIDTH: f32 = 40.0;
+/// Gap between the outer edge of the outermost lane and the safety fence
+pub const FENCE_GAP: f32 = 30.0;
+pub const FENCE_WIDTH: f32 = 16.0;
+
+/// How the inner edge of a track is laid out, as written in a track definition file
+#[derive(Deserialize, Debug, Clone)]
+pub enum TrackLayout {
+    /// Two straights joined by two bends, driven anticlockwise starting along the
+    /// bottom straight. `straight_length` is the distance between the centres of
+    /// the bends. The start/finish offset is measured from the middle of the home
+    /// straight.
+    Oval {
+        straight_length: f32,
+        bend_radii: [f32; 2],
+    },
+    /// Segments in driving order. The start/finish offset is measured along the
+    /// inner edge from the start of the first segment.
+    Segments(Vec<TrackSegment>),
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub enum TrackSegment {
+    Line {
+        length: f32,
+    },
+    /// Circular bend, where a positive angle turns left towards the inside of the track
+    Arc {
+        radius: f32,
+        degrees: f32,
+    },
+    /// Transition with a curvature that changes linearly over its length,
+    /// where positive curvature turns left
+    Clothoid {
+        length: f32,
+        start_curvature: f32,
+        end_curvature: f32,
+    },
+    /// Cubic Bézier curve with points relative to the end of the previous segment,
+    /// where x points in the direction of travel and y to the left
+    Bezier {
+        control_1: [f32; 2],
+        control_2: [f32; 2],
+        end: [f32; 2],
+    },
+}
+
+/// The inner edge of a track, split into sections with a single shape each.
+/// Distances along the inner edge are called parameters, since every lane is
+/// an offset of the inner edge and can be matched up with it through them.
+#[derive(Debug, Clone)]
+pub struct TrackGeometry {
+    pub sections: Vec<EdgeSection>,
+    /// Parameter of the start/finish line
+    pub start_parameter: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct EdgeSection {
+    pub start_parameter: f32,
+    pub length: f32,
+    pub start: Vec2,
+    pub start_heading: f32,
+    pub shape: EdgeShape,
+}
+
+#[derive(Debug, Clone)]
+pub enum EdgeShape {
+    Line,
+    /// Arc with a signed angle, where positive angles turn left
+    Arc {
+        radius: f32,
+        angle: f32,
+    },
+    /// Curves without a closed form, sampled along the inner edge
+    Sampled(Vec<EdgeSample>),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EdgeSample {
+    /// Parameter relative to the start of the section
+    pub parameter: f32,
+    pub position: Vec2,
+    pub heading: f32,
+}
+
+impl TrackLayout {
+    /// Segments in driving order, the heading of the first one and the parameter
+    /// of the start/finish line
+    fn segments(&self, start_finish_offset: f32) -> Result<(Vec<TrackSegment>, f32, f32), String> {
+        match self {
+            TrackLayout::Oval {
+                straight_length,
+                bend_radii: [first_radius, second_radius],
+            } => {
+                if *straight_length <= 0.0 || *first_radius <= 0.0 || *second_radius <= 0.0 {
+                    return Err(format!("oval dimensions must be positive, got {self:?}"));
+                }
+                let radius_difference = first_radius - second_radius;
+                if radius_difference.abs() >= *straight_length {
+                    return Err(format!(
+                        "bend radii {first_radius} and {second_radius} are too different for bends {straight_length} apart"
+                    ));
+                }
+                // The straights are the outer tangents of the two bends, so they lean
+                // towards the smaller bend
+                let lean = (radius_difference / straight_length).asin();
+                let tangent_length = (straight_length.powi(2) - radius_difference.powi(2)).sqrt();
+                if start_finish_offset.abs() >= tangent_length / 2.0 {
+                    return Err(format!(
+                        "start/finish offset {start_finish_offset} is not on the home straight"
+                    ));
+                }
+                let segments = vec![
+                    TrackSegment::Line {
+                        length: tangent_length,
+                    },
+                    TrackSegment::Arc {
+                        radius: *first_radius,
+                        degrees: (PI + 2.0 * lean).to_degrees(),
+                    },
+                    TrackSegment::Line {
+                        length: tangent_length,
+                    },
+                    TrackSegment::Arc {
+                        radius: *second_radius,
+                        degrees: (PI - 2.0 * lean).to_degrees(),
+                    },
+                ];
+                Ok((segments, -lean, tangent_length / 2.0 + start_finish_offset))
+            }
+            TrackLayout::Segments(segments) => {
+                if segments.is_empty() {
+                    return Err("a track needs at least one segment".to_string());
+                }
+                Ok((segments.clone(), 0.0, start_finish_offset))
+            }
+        }
+    }
+}
+
+impl TrackGeometry {
+    /// Lays out the segments end to end, checks that they form a closed loop that
+    /// every lane, the kerb and the fence can follow, and centres the track on the
+    /// origin.
+    pub fn new(
+        layout: &TrackLayout,
+        start_finish_offset: f32,
+        track_width: f32,
+    ) -> Result<Self, String> {
+        let (segments, start_heading, start_parameter) = layout.segments(start_finish_offset)?;
+        let mut sections = Vec::with_capacity(segments.len());
+        let mut parameter = 0.0;
+        let mut position = Vec2::ZERO;
+        let mut heading = start_heading;
+        let fence_extent = track_width + FENCE_GAP + FENCE_WIDTH;
+        for segment in segments {
+            let section = EdgeSection::new(&segment, parameter, position, heading, fence_extent)?;
+            (position, heading) = section.pose_at(section.length);
+            parameter += section.length;
+            sections.push(section);
+        }
+
+        let closing_distance = position.length();
+        let closing_heading = (heading - start_heading + PI).rem_euclid(TAU) - PI;
+        if closing_distance > CLOSING_DISTANCE_TOLERANCE
+            || closing_heading.abs() > CLOSING_HEADING_TOLERANCE
+        {
+            return Err(format!(
+                "segments do not form a closed loop, the end is {closing_distance} away from the start and {} degrees off",
+                closing_heading.to_degrees()
+            ));
+        }
+
+        let mut geometry = Self {
+            sections,
+            start_parameter: start_parameter.rem_euclid(parameter),
+        };
+        geometry.centre_on_origin();
+        Ok(geometry)
+    }
+
+    fn centre_on_origin(&mut self) {
+        let mut min = Vec2::splat(f32::INFINITY);
+        let mut max = Vec2::splat(f32::NEG_INFINITY);
+        for section in &self.sections {
+            let steps = (section.length / SAMPLE_SPACING).ceil().max(1.0) as usize;
+            for step in 0..=steps {
+                let (position, _) = section.pose_at(section.length * step as f32 / steps as f32);
+                min = min.min(position);
+                max = max.max(position);
+            }
+        }
+        let centre = (min + max) / 2.0;
+        for section in self.sections.iter_mut() {
+            section.start -= centre;
+            if let EdgeShape::Sampled(samples) = &mut section.shape {
+                for sample in samples.iter_mut() {
+                    sample.position -= centre;
+                }
+            }
+        }
+    }
+}
+
+impl EdgeSection {
+    fn new(
+        segment: &TrackSegment,
+        start_parameter: f32,
+        start: Vec2,
+        start_heading: f32,
+        fence_extent: f32,
+    ) -> Result<Self, String> {
+        let (length, shape) = match *segment {
+            TrackSegment::Line { length } => {
+                if length <= 0.0 {
+                    return Err(format!("line length must be positive, got {length}"));
+                }
+                (length, EdgeShape::Line)
+            }
+            TrackSegment::Arc { radius, degrees } => {
+                if radius <= 0.0 || degrees == 0.0 {
+                    return Err(format!(
+                        "arcs need a positive radius and a non-zero angle, got {segment:?}"
+                    ));
+                }
+                let curvature = degrees.signum() / radius;
+                check_bend(segment, curvature, curvature, fence_extent)?;
+                let angle = degrees.to_radians();
+                (radius * angle.abs(), EdgeShape::Arc { radius, angle })
+            }
+            TrackSegment::Clothoid {
+                length,
+                start_curvature,
+                end_curvature,
+            } => {
+                if length <= 0.0 {
+                    return Err(format!("clothoid length must be positive, got {length}"));
+                }
+                check_bend(
+                    segment,
+                    start_curvature.min(end_curvature),
+                    start_curvature.max(end_curvature),
+                    fence_extent,
+                )?;
+                let samples =
+                    sample_clothoid(length, start_curvature, end_curvature, start, start_heading);
+                (length, EdgeShape::Sampled(samples))
+            }
+            TrackSegment::Bezier {
+                control_1,
+                control_2,
+                end,
+            } => {
+                let rotation = Vec2::from_angle(start_heading);
+                let points = [
+                    start,
+                    start + rotation.rotate(Vec2::from(control_1)),
+                    start + rotation.rotate(Vec2::from(control_2)),
+                    start + rotation.rotate(Vec2::from(end)),
+                ];
+                let samples = sample_bezier(points, start_heading);
+                let length = samples.last().map_or(0.0, |sample| sample.parameter);
+                if length <= 0.0 {
+                    return Err(format!("Bézier curve {segment:?} has no length"));
+                }
+                let (sharpest_right, sharpest_left) = curvature_range(&samples);
+                check_bend(segment, sharpest_right, sharpest_left, fence_extent)?;
+                (length, EdgeShape::Sampled(samples))
+            }
+        };
+        Ok(Self {
+            start_parameter,
+            length,
+            start,
+            start_heading,
+            shape,
+        })
+    }
+
+    /// Position and heading on the inner edge at a parameter relative to the start of the section
+    pub fn pose_at(&self, parameter: f32) -> (Vec2, f32) {
+        match &self.shape {
+            EdgeShape::Line => (
+                self.start + Vec2::from_angle(self.start_heading) * parameter,
+                self.start_heading,
+            ),
+            EdgeShape::Arc { radius, angle } => {
+                let turn = angle.signum();
+                let centre = self.arc_centre(*radius, turn);
+                let position_angle =
+                    self.start_heading - turn * FRAC_PI_2 + turn * parameter / radius;
+                (
+                    centre + Vec2::from_angle(position_angle) * *radius,
+                    self.start_heading + turn * parameter / radius,
+                )
+            }
+            EdgeShape::Sampled(samples) => {
+                let (before, after, proportion) =
+                    bracketing_samples(samples, parameter, |sample| sample.parameter);
+                (
+                    before.position.lerp(after.position, proportion),
+                    before.heading + (after.heading - before.heading) * proportion,
+                )
+            }
+        }
+    }
+
+    /// Centre of an arc section of the given radius, turning left for a positive `turn`
+    pub fn arc_centre(&self, radius: f32, turn: f32) -> Vec2 {
+        self.start + left_normal(self.start_heading) * radius * turn
+    }
+}
+
+/// Checks that a segment curving as much as `sharpest_right` and `sharpest_left`,
+/// where positive curvature turns left, never folds the fence or the kerb over
+/// themselves
+fn check_bend(
+    segment: &TrackSegment,
+    sharpest_right: f32,
+    sharpest_left: f32,
+    fence_extent: f32,
+) -> Result<(), String> {
+    if sharpest_right * fence_extent <= -1.0 {
+        Err(format!(
+            "{segment:?} bends right too tightly for a fence {fence_extent} outside the inner edge"
+        ))
+    } else if sharpest_left * KERB_WIDTH >= 1.0 {
+        Err(format!(
+            "{segment:?} bends left too tightly for a kerb {KERB_WIDTH} wide"
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// The lowest and highest curvature between neighbouring samples
+fn curvature_range(samples: &[EdgeSample]) -> (f32, f32) {
+    samples
+        .windows(2)
+        .filter(|pair| pair[1].parameter > pair[0].parameter)
+        .map(|pair| (pair[1].heading - pair[0].heading) / (pair[1].parameter - pair[0].parameter))
+        .fold((0.0, 0.0), |(lowest, highest), curvature| {
+            (curvature.min(lowest), curvature.max(highest))
+        })
+}
+
+/// Unit vector pointing to the left of the given heading, towards the inside of the track
+pub fn left_normal(heading: f32) -> Vec2 {
+    Vec2::from_angle(heading).perp()
+}
+
+/// The two samples either side of `value` and how far between them it is
+pub fn bracketing_samples<T: Copy>(
+    samples: &[T],
+    value: f32,
+    key: impl Fn(&T) -> f32,
+) -> (T, T, f32) {
+    let after_index = samples
+        .partition_point(|sample| key(sample) <= value)
+        .clamp(1, samples.len() - 1);
+    let before = samples[after_index - 1];
+    let after = samples[after_index];
+    let span = key(&after) - key(&before);
+    let proportion = if span > 0.0 {
+        ((value - key(&before)) / span).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    (before, after, proportion)
+}
+
+fn sample_clothoid(
+    length: f32,
+    start_curvature: f32,
+    end_curvature: f32,
+    start: Vec2,
+    start_heading: f32,
+) -> Vec<EdgeSample> {
+    let heading_at = |parameter: f32| {
+        start_heading
+            + start_curvature * parameter
+            + (end_curvature - start_curvature) * parameter.powi(2) / (2.0 * length)
+    };
+    let steps = (length / SAMPLE_SPACING).ceil().max(1.0) as usize;
+    let step_length = length / steps as f32;
+    let mut samples = Vec::with_capacity(steps + 1);
+    let mut position = start;
+    samples.push(EdgeSample {
+        parameter: 0.0,
+        position,
+        heading: start_heading,
+    });
+    for step in 1..=steps {
+        let parameter = step_length * step as f32;
+        // midpoint rule: follow the heading halfway through the step
+        position += Vec2::from_angle(heading_at(parameter - step_length / 2.0)) * step_length;
+        samples.push(EdgeSample {
+            parameter,
+            position,
+            heading: heading_at(parameter),
+        });
+    }
+    samples
+}
+
+fn sample_bezier(points: [Vec2; 4], start_heading: f32) -> Vec<EdgeSample> {
+    let [p0, p1, p2, p3] = points;
+    let control_polygon_length = p0.distance(p1) + p1.distance(p2) + p2.distance(p3);
+    let steps = ((control_polygon_length / SAMPLE_SPACING).ceil() as usize).max(16);
+    let position_at = |t: f32| {
+        let u = 1.0 - t;
+        p0 * u.powi(3) + p1 * 3.0 * u.powi(2) * t + p2 * 3.0 * u * t.powi(2) + p3 * t.powi(3)
+    };
+    let tangent_at = |t: f32| {
+        let u = 1.0 - t;
+        (p1 - p0) * 3.0 * u.powi(2) + (p2 - p1) * 6.0 * u * t + (p3 - p2) * 3.0 * t.powi(2)
+    };
+    let mut samples: Vec<EdgeSample> = Vec::with_capacity(steps + 1);
+    let mut previous_heading = start_heading;
+    for step in 0..=steps {
+        let t = step as f32 / steps as f32;
+        let position = position_at(t);
+        let tangent = tangent_at(t);
+        // keep headings continuous so they can be interpolated
+        let heading = if tangent.length_squared() > 0.0 {
+            let raw_heading = tangent.y.atan2(tangent.x);
+            previous_heading + (raw_heading - previous_heading + PI).rem_euclid(TAU) - PI
+        } else {
+            previous_heading
+        };
+        let parameter = samples.last().map_or(0.0, |previous| {
+            previous.parameter + previous.position.distance(position)
+        });
+        samples.push(EdgeSample {
+            parameter,
+            position,
+            heading,
+        });
+        previous_heading = heading;
+    }
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::track::TrackDefinition;
+
+    /// Width of the four lane tracks that ship with the game
+    const TRACK_WIDTH: f32 = 400.0;
+
+    fn brookside() -> TrackGeometry {
+        TrackDefinition::from_ron(include_bytes!("../../assets/tracks/brookside.track.ron"))
+            .unwrap()
+            .geometry
+    }
+
+    fn heading_difference(first: f32, second: f32) -> f32 {
+        (first - second + PI).rem_euclid(TAU) - PI
+    }
+
+    fn loop_of(segment: TrackSegment) -> Result<TrackGeometry, String> {
+        // the segment is the first of an otherwise comfortable layout, which only has
+        // to get as far as checking it
+        let layout = TrackLayout::Segments(vec![segment, TrackSegment::Line { length: 100.0 }]);
+        TrackGeometry::new(&layout, 0.0, TRACK_WIDTH)
+    }
+
+    #[test]
+    fn closed_loop_ends_at_its_start_pose() {
+        let geometry = brookside();
+        let first = &geometry.sections[0];
+        let last = geometry.sections.last().unwrap();
+        let (end, end_heading) = last.pose_at(last.length);
+        assert!(end.distance(first.start) <= CLOSING_DISTANCE_TOLERANCE);
+        assert!(
+            heading_difference(end_heading, first.start_heading).abs() <= CLOSING_HEADING_TOLERANCE
+        );
+    }
+
+    #[test]
+    fn pose_is_continuous_across_segment_joins() {
+        let geometry = brookside();
+        for pair in geometry.sections.windows(2) {
+            let (end, end_heading) = pair[0].pose_at(pair[0].length);
+            let (start, start_heading) = pair[1].pose_at(0.0);
+            assert!(end.distance(start) < 0.01, "{:?} ends apart", pair[0].shape);
+            assert!(
+                heading_difference(end_heading, start_heading).abs() < 1e-3,
+                "{:?} ends at another heading",
+                pair[0].shape
+            );
+            assert_eq!(
+                pair[0].start_parameter + pair[0].length,
+                pair[1].start_parameter
+            );
+        }
+    }
+
+    #[test]
+    fn too_tight_bends_are_rejected() {
+        let fence_extent = TRACK_WIDTH + FENCE_GAP + FENCE_WIDTH;
+        let too_tight = [
+            // clear of the outer edge of the track, but not of the fence
+            TrackSegment::Arc {
+                radius: fence_extent - 10.0,
+                degrees: -90.0,
+            },
+            TrackSegment::Arc {
+                radius: KERB_WIDTH / 2.0,
+                degrees: 90.0,
+            },
+            TrackSegment::Clothoid {
+                length: 200.0,
+                start_curvature: 0.0,
+                end_curvature: -1.0 / (fence_extent - 10.0),
+            },
+            TrackSegment::Bezier {
+                control_1: [100.0, 0.0],
+                control_2: [200.0, -200.0],
+                end: [100.0, -300.0],
+            },
+        ];
+        for segment in too_tight {
+            let error = loop_of(segment).unwrap_err();
+            assert!(error.contains("too tightly"), "{segment:?}: {error}");
+        }
+    }
+}
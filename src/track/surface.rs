@@ -0,0 +1,89 @@
+use bevy::prelude::*;
+use bevy_prototype_lyon::{
+    draw::{Fill, Stroke},
+    entity::ShapeBundle,
+    path::{Path, PathBuilder},
+};
+
+use super::{
+    geometry::{FENCE_GAP, FENCE_WIDTH, KERB_WIDTH},
+    TrackLanes,
+};
+
+const SURFACE_COLOR: Color = Color::srgb(0.45, 0.27, 0.21);
+const INFIELD_COLOR: Color = Color::srgb(0.1, 0.38, 0.21);
+const KERB_COLOR: Color = Color::srgb(0.25, 0.5, 0.38);
+const LANE_MARKING_COLOR: Color = Color::srgba(1.0, 1.0, 1.0, 0.15);
+const FENCE_COLOR: Color = Color::srgb(0.85, 0.85, 0.8);
+const START_FINISH_COLOR: Color = Color::WHITE;
+const LANE_MARKING_WIDTH: f32 = 4.0;
+const START_FINISH_WIDTH: f32 = 8.0;
+
+/// Draws the track from its lanes, as children of the track entity. Everything is
+/// drawn between z of 0 and 1, so that path highlights and bikes stay on top.
+pub fn spawn_track_surface(parent: &mut ChildBuilder, track_lanes: &TrackLanes) {
+    let track_width = track_lanes.track_width();
+    // the surface runs out to the fence, and the infield and kerb are drawn over its inside
+    parent.spawn((
+        make_shape(
+            closed_path(&track_lanes.outline(track_width + FENCE_GAP)),
+            0.0,
+        ),
+        Fill::color(SURFACE_COLOR),
+    ));
+    parent.spawn((
+        make_shape(closed_path(&track_lanes.outline(0.0)), 0.1),
+        Fill::color(INFIELD_COLOR),
+    ));
+    parent.spawn((
+        make_shape(closed_path(&track_lanes.outline(-KERB_WIDTH / 2.0)), 0.2),
+        Stroke::new(KERB_COLOR, KERB_WIDTH),
+    ));
+    for lane_index in 1..track_lanes.lane_count() {
+        let marking_offset = track_lanes.lane_width() * lane_index as f32;
+        parent.spawn((
+            make_shape(closed_path(&track_lanes.outline(marking_offset)), 0.3),
+            Stroke::new(LANE_MARKING_COLOR, LANE_MARKING_WIDTH),
+        ));
+    }
+    parent.spawn((
+        make_shape(
+            closed_path(&track_lanes.outline(track_width + FENCE_GAP + FENCE_WIDTH / 2.0)),
+            0.4,
+        ),
+        Stroke::new(FENCE_COLOR, FENCE_WIDTH),
+    ));
+
+    let inner_start = track_lanes.outline(0.0)[0];
+    let outer_start = track_lanes.outline(track_width + FENCE_GAP)[0];
+    let mut path_builder = PathBuilder::new();
+    path_builder.move_to(inner_start);
+    path_builder.line_to(outer_start);
+    parent.spawn((
+        make_shape(path_builder.build(), 0.5),
+        Stroke::new(START_FINISH_COLOR, START_FINISH_WIDTH),
+    ));
+}
+
+fn closed_path(points: &[Vec2]) -> Path {
+    let mut path_builder = PathBuilder::new();
+    if let Some((first, rest)) = points.split_first() {
+        path_builder.move_to(*first);
+        for point in rest {
+            path_builder.line_to(*point);
+        }
+        path_builder.close();
+    }
+    path_builder.build()
+}
+
+fn make_shape(path: Path, z: f32) -> ShapeBundle {
+    ShapeBundle {
+        path,
+        spatial: SpatialBundle {
+            transform: Transform::from_xyz(0.0, 0.0, z),
+            ..default()
+        },
+        ..default()
+    }
+}